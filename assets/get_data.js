@@ -4,18 +4,198 @@ var dataTable = anychart.data.table("x");
 var mapping = dataTable.mapAs({ open: "open", high: "high", low: "low", close: "close" });
 var ohlcSeries = chart.plot(0).ohlc(mapping);
 
+// Cumulative net flow (buy volume - sell volume) beneath the candles.
+var flowMapping = dataTable.mapAs({ value: "flow" });
+var flowPlot = chart.plot(1);
+flowPlot.height("25%");
+flowPlot.line(flowMapping).name("Net flow");
+
+var flowToggle = document.getElementById("net-flow-toggle");
+flowToggle.onchange = function () {
+  flowPlot.enabled(flowToggle.checked);
+}
+
+// Net flow of candles before the last one and the last candle timestamp.
+// Candles arrive in time order, the last one may be updated many times.
+var flowBeforeLast = 0;
+var lastFlow = 0;
+var lastTimestamp = null;
+
+// Server URLs, loaded from its config.
+var httpBaseUrl;
+var wsBaseUrl;
+
 var socket;
 var token;
 var tokenName;
 
-const maxChartDataLen = 100;
+// Tokens overlaid on the chart for comparison, by mint.
+// Each one has its own data table, line series and WebSocket. While there are any,
+// prices are shown as percent change from the window start, so different scales are comparable.
+var comparisons = new Map();
+
+const maxChartDataLen = 300;
 
 var resolutionSelector = document.getElementById("resolution-select");
 resolutionSelector.onchange = function () {
   drawChart();
 }
 
-fetch("http://localhost:33987/tokens")
+// Delays of new tokens WebSocket reconnection.
+const minReconnectDelayMs = 1000;
+const maxReconnectDelayMs = 30000;
+var reconnectDelayMs = minReconnectDelayMs;
+
+// Tokens received from WebSocket are added to the list in batches to avoid UI churn.
+const tokensFlushDelayMs = 500;
+var pendingTokens = new Map();
+var tokensFlushTimer = null;
+
+// Tokens list search text, searched on server after typing stops.
+const searchDelayMs = 300;
+const searchPageSize = 100;
+var searchQuery = "";
+var searchTimer = null;
+
+var tokenSearch = document.getElementById("token-search");
+tokenSearch.oninput = function () {
+  clearTimeout(searchTimer);
+  searchTimer = setTimeout(function () {
+    searchQuery = tokenSearch.value.trim();
+    document.getElementById("tokens").replaceChildren();
+    if (searchQuery == "") {
+      refreshTokens();
+    } else {
+      searchTokens(searchQuery);
+    }
+  }, searchDelayMs);
+}
+
+function tokenText(mint, metadata) {
+  var text = mint;
+
+  if (metadata.symbol != 'NAN') {
+    text = metadata.symbol;
+  }
+
+  if (metadata.name != 'unknown') {
+    text = text + " | " + metadata.name;
+  }
+
+  if (metadata.completed_at) {
+    text = text + " [migrated]";
+  }
+
+  return text;
+}
+
+// Add token to the list or update its text if it's already there.
+function upsertToken(mint, metadata, prepend) {
+  let li = document.getElementById(mint);
+  if (li != null) {
+    li.textContent = tokenText(mint, metadata);
+    return;
+  }
+
+  li = document.createElement('li');
+  li.textContent = tokenText(mint, metadata);
+  li.id = mint;
+
+  li.onclick = function (event) {
+    if ((event.ctrlKey || event.metaKey) && token != null && token != this.id) {
+      toggleComparison(this.id, this.textContent);
+      return;
+    }
+    token = this.id;
+    tokenName = this.textContent;
+    drawChart();
+  }
+
+  let list = document.getElementById("tokens");
+  if (prepend) {
+    list.prepend(li);
+  } else {
+    list.appendChild(li);
+  }
+}
+
+function flushPendingTokens() {
+  tokensFlushTimer = null;
+  for (const [mint, metadata] of pendingTokens) {
+    // New tokens may not match the search, only update listed ones.
+    if (searchQuery == "" || document.getElementById(mint) != null) {
+      upsertToken(mint, metadata, true);
+    }
+  }
+  pendingTokens.clear();
+}
+
+function refreshTokens() {
+  fetch(httpBaseUrl + "/tokens")
+    .then((response) => {
+      if (!response.ok) {
+        throw new Error(`HTTP error! status: ${response.status}`);
+      }
+
+      return response.json();
+    })
+    .then((data) => {
+      for (var i = 0; i < data.length; i++) {
+        upsertToken(data[i][0], data[i][1], false);
+      }
+    })
+    .catch((error) => console.error("Fetch error:", error));
+}
+
+function searchTokens(query) {
+  fetch(httpBaseUrl + "/tokens?query=" + encodeURIComponent(query) + "&limit=" + searchPageSize)
+    .then((response) => {
+      if (!response.ok) {
+        throw new Error(`HTTP error! status: ${response.status}`);
+      }
+
+      return response.json();
+    })
+    .then((page) => {
+      // Ignore results of a stale search.
+      if (query != searchQuery) {
+        return;
+      }
+      for (var i = 0; i < page.tokens.length; i++) {
+        upsertToken(page.tokens[i][0], page.tokens[i][1], false);
+      }
+    })
+    .catch((error) => console.error("Fetch error:", error));
+}
+
+function subscribeTokens() {
+  var tokensSocket = new WebSocket(wsBaseUrl + "/new_tokens_ws");
+
+  tokensSocket.onopen = function () {
+    reconnectDelayMs = minReconnectDelayMs;
+    // Catch up with tokens created while disconnected.
+    if (searchQuery == "") {
+      refreshTokens();
+    }
+  };
+
+  tokensSocket.onmessage = function (event) {
+    var data = JSON.parse(event.data);
+    pendingTokens.set(data[0], data[1]);
+    if (tokensFlushTimer == null) {
+      tokensFlushTimer = setTimeout(flushPendingTokens, tokensFlushDelayMs);
+    }
+  };
+
+  tokensSocket.onclose = function () {
+    console.log("New tokens socket closed, reconnecting in " + reconnectDelayMs + "ms");
+    setTimeout(subscribeTokens, reconnectDelayMs);
+    reconnectDelayMs = Math.min(reconnectDelayMs * 2, maxReconnectDelayMs);
+  };
+}
+
+// Config is served next to the page, so it's found behind path-prefixing proxies too.
+fetch("config")
   .then((response) => {
     if (!response.ok) {
       throw new Error(`HTTP error! status: ${response.status}`);
@@ -23,37 +203,102 @@ fetch("http://localhost:33987/tokens")
 
     return response.json();
   })
-  .then((data) => {
-    for (var i = 0; i < data.length; i++) {
-      let li = document.createElement('li');
-      var text = data[i][0];
+  .then((config) => {
+    httpBaseUrl = config.http_base_url;
+    wsBaseUrl = config.ws_base_url;
+    subscribeTokens();
+  })
+  .catch((error) => console.error("Config fetch error:", error));
 
-      if (data[i][1].symbol != 'NAN') {
-        text = data[i][1].symbol;
-      }
+function selectedResolution() {
+  return resolutionSelector.options[resolutionSelector.selectedIndex].value;
+}
 
-      if (data[i][1].name != 'unknown') {
-        text = text + " | " + data[i][1].name;
-      }
+// Keep at most `maxChartDataLen` rows in the table.
+function trimTable(table, mapping) {
+  var selectable = mapping.createSelectable();
+  selectable.selectAll();
 
-      li.textContent = text;
-      li.id = data[i][0];
+  var iterator = selectable.getIterator();
+  var items_count = 0;
+  while (iterator.advance()) {
+    items_count++;
+  }
 
-      li.onclick = function () {
-        token = this.id;
-        tokenName = this.textContent;
-        drawChart();
-      }
-      document.getElementById("tokens").appendChild(li);
+  if (items_count > maxChartDataLen) {
+    table.removeFirst(items_count - maxChartDataLen);
+  }
+}
+
+function toggleComparison(mint, name) {
+  var comparison = comparisons.get(mint);
+  if (comparison != null) {
+    comparison.socket.close();
+    chart.plot(0).removeSeries(comparison.series.id());
+    comparisons.delete(mint);
+  } else {
+    var table = anychart.data.table("x");
+    var mapping = table.mapAs({ value: "close" });
+    var series = chart.plot(0).line(mapping);
+    series.name(name);
+    comparison = { table: table, mapping: mapping, series: series, socket: null };
+    comparisons.set(mint, comparison);
+    streamComparison(mint, comparison);
+  }
+
+  document.getElementById(mint).classList.toggle("compared", comparisons.has(mint));
+  chart.plot(0).yScale().comparisonMode(comparisons.size > 0 ? "percent" : "none");
+  chart.draw();
+}
+
+// (Re)open comparison token WebSocket with the selected resolution.
+function streamComparison(mint, comparison) {
+  if (comparison.socket != null) {
+    comparison.socket.close();
+  }
+  comparison.table.remove();
+
+  comparison.socket = new WebSocket(wsBaseUrl + "/chart_data_ws/" + mint + "/" + selectedResolution());
+  comparison.socket.onmessage = function (event) {
+    var data = JSON.parse(event.data);
+    trimTable(comparison.table, comparison.mapping);
+    comparison.table.addData([{ x: new Date(data.timestamp), close: data.candle.close }]);
+    chart.draw();
+  };
+}
+
+// Background tabs may miss updates, so history is requested again when the page is shown.
+// Refreshed history replaces the buffered candles.
+document.addEventListener("visibilitychange", function () {
+  if (document.visibilityState != "visible") {
+    return;
+  }
+
+  if (socket != null && socket.readyState == WebSocket.OPEN) {
+    dataTable.remove();
+    flowBeforeLast = 0;
+    lastFlow = 0;
+    lastTimestamp = null;
+    socket.send(JSON.stringify({ action: "refresh" }));
+  }
+  for (const comparison of comparisons.values()) {
+    if (comparison.socket.readyState == WebSocket.OPEN) {
+      comparison.table.remove();
+      comparison.socket.send(JSON.stringify({ action: "refresh" }));
     }
-  })
-  .catch((error) => console.error("Fetch error:", error));
+  }
+});
 
 function drawChart() {
   if (socket != null) {
     socket.close();
   }
 
+  // Charted token is not compared with itself.
+  if (comparisons.has(token)) {
+    toggleComparison(token, tokenName);
+  }
+
   console.log(token);
 
   document.getElementById("myChart").hidden = false;
@@ -61,33 +306,33 @@ function drawChart() {
   ohlcSeries.name(token);
   chart.title(tokenName + " | " + token);
 
-  var resolutionSelector = document.getElementById("resolution-select");
-  const resolution = resolutionSelector.options[resolutionSelector.selectedIndex].value;
-  socket = new WebSocket("ws://localhost:33987/chart_data_ws/" + token + "/" + resolution);
+  socket = new WebSocket(wsBaseUrl + "/chart_data_ws/" + token + "/" + selectedResolution());
+  for (const [mint, comparison] of comparisons) {
+    streamComparison(mint, comparison);
+  }
 
   dataTable.remove();
+  flowBeforeLast = 0;
+  lastFlow = 0;
+  lastTimestamp = null;
 
   socket.onmessage = function (event) {
     var data = JSON.parse(event.data);
 
-    selectable = mapping.createSelectable();
-    selectable.selectAll();
-
-    var iterator = selectable.getIterator();
-    var items_count = 0;
+    trimTable(dataTable, mapping);
 
+    var candle = data.candle;
+    const date = new Date(data.timestamp);
+    candle.x = date;
 
-    while (iterator.advance()) {
-      items_count++;
+    if (lastTimestamp != null && data.timestamp > lastTimestamp) {
+      flowBeforeLast += lastFlow;
     }
-
-    if (items_count > maxChartDataLen) {
-      dataTable.removeFirst(items_count - maxChartDataLen);
+    if (lastTimestamp == null || data.timestamp >= lastTimestamp) {
+      lastTimestamp = data.timestamp;
+      lastFlow = candle.buy_volume - candle.sell_volume;
     }
-
-    var candle = data.candle;
-    const date = new Date(data.timestamp * 1000);
-    candle.x = date;
+    candle.flow = flowBeforeLast + lastFlow;
 
     dataTable.addData([candle]);
 
@@ -0,0 +1,3 @@
+-- Add migration script here
+
+ALTER TABLE trades ADD COLUMN trade_count INT8 NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add migration script here
+
+ALTER TABLE trades ADD COLUMN buy_volume FLOAT8 NOT NULL DEFAULT 0;
+ALTER TABLE trades ADD COLUMN sell_volume FLOAT8 NOT NULL DEFAULT 0;
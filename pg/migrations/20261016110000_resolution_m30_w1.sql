@@ -0,0 +1,6 @@
+-- no-transaction
+-- Enum values can't be used in the transaction they were added in, so it runs without one.
+-- IF NOT EXISTS makes it safe to rerun after partial failure.
+
+ALTER TYPE resolution ADD VALUE IF NOT EXISTS 'M30' AFTER 'M15';
+ALTER TYPE resolution ADD VALUE IF NOT EXISTS 'W1' AFTER 'D1';
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- Volumes are sums of integer token base units, which exceed exact f64 range (2^53) for busy candles.
+ALTER TABLE trades ALTER COLUMN volume TYPE NUMERIC USING volume::NUMERIC;
+ALTER TABLE trades ALTER COLUMN buy_volume TYPE NUMERIC USING buy_volume::NUMERIC;
+ALTER TABLE trades ALTER COLUMN sell_volume TYPE NUMERIC USING sell_volume::NUMERIC;
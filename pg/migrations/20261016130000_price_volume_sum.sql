@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Sum of price × volume of trades, i.e. SOL amount, for VWAP. NULL for candles created before,
+-- which VWAP can't be reconstructed for.
+ALTER TABLE trades ADD COLUMN price_volume_sum NUMERIC;
+ALTER TABLE trades ALTER COLUMN price_volume_sum SET DEFAULT 0;
@@ -0,0 +1,8 @@
+-- Indexer runs, so data gaps can be correlated with downtime.
+-- `ended_at` is NULL for the running session and ones ended without graceful shutdown.
+CREATE TABLE sessions (
+    id BIGSERIAL PRIMARY KEY,
+    started_at TIMESTAMP NOT NULL,
+    ended_at TIMESTAMP,
+    last_event_at TIMESTAMP
+);
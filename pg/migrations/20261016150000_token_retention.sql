@@ -0,0 +1,5 @@
+-- Per-token retention overriding the global one, e.g. for long-lived tokens.
+CREATE TABLE token_retention (
+    mint VARCHAR PRIMARY KEY REFERENCES token (mint) ON DELETE CASCADE,
+    retention_secs BIGINT NOT NULL CHECK (retention_secs > 0)
+);
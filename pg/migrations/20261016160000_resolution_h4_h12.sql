@@ -0,0 +1,6 @@
+-- no-transaction
+-- Enum values can't be used in the transaction they were added in, so it runs without one.
+-- IF NOT EXISTS makes it safe to rerun after partial failure.
+
+ALTER TYPE resolution ADD VALUE IF NOT EXISTS 'H4' AFTER 'H1';
+ALTER TYPE resolution ADD VALUE IF NOT EXISTS 'H12' AFTER 'H4';
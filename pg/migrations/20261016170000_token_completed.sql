@@ -0,0 +1,2 @@
+-- Time token bonding curve was completed and token migrated to AMM, NULL while trading on the curve.
+ALTER TABLE token ADD COLUMN completed_at TIMESTAMP;
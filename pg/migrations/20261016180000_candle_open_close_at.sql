@@ -0,0 +1,5 @@
+-- Time of the trades candle open and close prices are from, so trades arriving out of order
+-- don't replace them. NULL means bucket start, e.g. for candles stored before or without trades:
+-- their open price is never replaced and any trade replaces their close price.
+ALTER TABLE trades ADD COLUMN open_at TIMESTAMP;
+ALTER TABLE trades ADD COLUMN close_at TIMESTAMP;
@@ -0,0 +1,4 @@
+-- Token decimals from its mint account, for prices in SOL per whole token.
+-- NULL if unknown, e.g. for tokens stored before: their prices stay raw base units ratios.
+-- Set only when token is inserted, so prices of a token keep one scale.
+ALTER TABLE token ADD COLUMN decimals SMALLINT;
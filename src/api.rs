@@ -0,0 +1,341 @@
+//! REST and WebSocket APIs of the server.
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::http::header::AUTHORIZATION;
+use axum::middleware;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use std::sync::Arc;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::sensitive_headers::SetSensitiveRequestHeadersLayer;
+use tower_http::services::ServeDir;
+use tower_http::trace::{DefaultMakeSpan, TraceLayer};
+
+use crate::backfill::MetadataBackfill;
+use crate::config::{Config, TlsPaths};
+use crate::connections::Connections;
+use crate::indexer::Indexer;
+use crate::maintenance::DbMaintenance;
+use crate::market_stats::MarketStats;
+use crate::metrics::Metrics;
+use crate::model::{OpenPolicy, Resolution};
+use crate::prices::LastPrices;
+use crate::pump_handler::IngestionControl;
+use crate::rpc_pool::RpcPool;
+use crate::sessions::Sessions;
+use crate::storage::Storage;
+use crate::tokens_cache::TokensCache;
+
+mod admin;
+mod alerts;
+mod charts;
+mod static_files;
+mod status;
+mod tokens;
+mod ws;
+
+/// State shared between app clients.
+pub struct AppState {
+    pub storage: Storage,
+    pub backfill: MetadataBackfill,
+    pub db_maintenance: DbMaintenance,
+    pub last_prices: LastPrices,
+    pub tokens_cache: TokensCache,
+    pub rpc: RpcPool,
+    pub market_stats: MarketStats,
+    pub connections: Connections,
+    pub sessions: Sessions,
+    pub metrics: Arc<Metrics>,
+    /// History points override for all resolutions.
+    pub history_points: Option<usize>,
+    /// Default definition of chart candles open price.
+    pub open_policy: OpenPolicy,
+    /// API key required by admin endpoints.
+    pub admin_api_key: Option<String>,
+    /// Base URL clients reach the server at.
+    pub public_base_url: Option<String>,
+    /// Whether the server is served over TLS.
+    pub tls: bool,
+    pub ingestion: IngestionControl,
+    /// Kept to capture events as long as the server runs.
+    pub indexer: Indexer,
+}
+
+/// Build router of all endpoints and static assets.
+pub fn router(state: Arc<AppState>, config: &Config) -> Router {
+    // CORS are not required for test task.
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    // Precompressed `.br`/`.gz` variants are served if present next to the files.
+    let serve_dir = ServeDir::new("assets")
+        .precompressed_br()
+        .precompressed_gzip();
+    let static_router = Router::new()
+        .nest_service("/assets", serve_dir.clone())
+        .fallback_service(serve_dir)
+        .layer(middleware::from_fn(static_files::static_headers));
+
+    // Admin endpoints are not mounted without API key, so they never run unauthenticated.
+    let admin_router = if config.admin_api_key.is_some() {
+        Router::new()
+            .route("/admin/backfill_metadata", post(admin::backfill_metadata))
+            .route("/admin/db_maintenance", post(admin::run_db_maintenance))
+            .route("/admin/pause", post(admin::pause_ingestion))
+            .route("/admin/resume", post(admin::resume_ingestion))
+            .route(
+                "/admin/drop_token_series/{mint}",
+                post(admin::drop_token_series),
+            )
+            .route("/admin/tokens/{mint}", delete(admin::delete_token))
+            .route(
+                "/admin/tokens/{mint}/raw_metadata",
+                get(admin::get_raw_metadata),
+            )
+            .route(
+                "/admin/tokens/{mint}/retention",
+                put(admin::set_token_retention),
+            )
+            .route("/debug/connections", get(admin::get_connections))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin_key,
+            ))
+    } else {
+        tracing::warn!("ADMIN_API_KEY is not set, admin endpoints are disabled.");
+        Router::new()
+    };
+
+    // REST responses may be compressed, streaming ones are not.
+    let rest_router = Router::new()
+        .route("/tokens", get(tokens::get_tokens))
+        .route("/tokens/by_pressure", get(tokens::get_tokens_by_pressure))
+        .route("/tokens/active", get(tokens::get_active_tokens))
+        .route("/tokens/{mint}", get(tokens::get_token))
+        .route("/tokens/{mint}/candles", get(tokens::get_token_candles))
+        .route("/tokens/{mint}/range", get(tokens::get_candle_time_range))
+        .route(
+            "/chart_data/{token}/{resolution}",
+            get(charts::get_chart_data),
+        )
+        .route("/candles/{token}/{resolution}", get(charts::get_candles))
+        .route("/config", get(status::get_client_config))
+        .route("/health", get(status::health))
+        .route("/ready", get(status::ready))
+        .route("/metrics", get(status::get_metrics))
+        .route("/sessions", get(status::get_sessions))
+        .route("/version", get(status::get_version))
+        .merge(admin_router);
+
+    let rest_router = compressed(rest_router, config.http_compression);
+
+    let router = Router::new()
+        .route(
+            "/chart_data_ws/{token}/{resolution}",
+            get(ws::chart_data_ws),
+        )
+        .route("/alerts_ws", get(ws::alerts_ws))
+        .route("/new_tokens_ws", get(ws::new_tokens_ws))
+        .route("/market_stats_ws", get(ws::market_stats_ws))
+        .merge(rest_router)
+        .merge(static_router)
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::default().include_headers(true)),
+        )
+        .layer(cors)
+        // Keep API key out of traces.
+        .layer(SetSensitiveRequestHeadersLayer::new([
+            admin::ADMIN_API_KEY_HEADER,
+            AUTHORIZATION,
+        ]))
+        .with_state(state);
+
+    router.layer(TraceLayer::new_for_http())
+}
+
+/// Load TLS certificate and private key PEM files.
+pub async fn load_tls(paths: &TlsPaths) -> anyhow::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&paths.cert, &paths.key)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {:?} and key {:?}",
+                paths.cert, paths.key
+            )
+        })
+}
+
+/// Compress responses if enabled and requested by `Accept-Encoding`.
+/// Compression costs CPU, so it may be disabled.
+fn compressed<S: Clone + Send + Sync + 'static>(router: Router<S>, enabled: bool) -> Router<S> {
+    if enabled {
+        router.layer(CompressionLayer::new())
+    } else {
+        router
+    }
+}
+
+/// Response to a request of disabled resolution candles.
+fn disabled_resolution_response(resolution: Resolution) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(format!("Resolution {resolution} is disabled.")),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use sqlx::PgPool;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    use crate::db::Db;
+    use crate::hot_candles::HotCandles;
+    use crate::metadata_breaker::MetadataBreaker;
+    use crate::pump_handler::PauseMode;
+
+    /// State over test DB, with unreachable Redis, so reads fall back to DB.
+    pub(super) async fn test_state(pool: PgPool) -> Arc<AppState> {
+        Arc::new(app_state(pool).await)
+    }
+
+    /// State over test DB to be altered by a test before sharing.
+    pub(super) async fn app_state(pool: PgPool) -> AppState {
+        let metrics = Arc::new(Metrics::default());
+        let db = Db::from_pool(pool.clone(), metrics.clone(), Duration::from_secs(10));
+        let storage =
+            Storage::for_tests(pool, "redis://127.0.0.1:1", HotCandles::new(None, 0)).await;
+        let rpc = RpcPool::new(&[]);
+        AppState {
+            backfill: MetadataBackfill::new(
+                storage.clone(),
+                MetadataBreaker::new(metrics.clone()),
+                rpc.clone(),
+                10,
+            ),
+            storage,
+            db_maintenance: DbMaintenance::new(db.clone(), false),
+            last_prices: LastPrices::default(),
+            tokens_cache: TokensCache::new(Duration::from_secs(1)),
+            rpc,
+            market_stats: MarketStats::default(),
+            connections: Connections::default(),
+            sessions: Sessions::start(db).await.unwrap(),
+            metrics,
+            history_points: None,
+            open_policy: OpenPolicy::default(),
+            admin_api_key: None,
+            public_base_url: None,
+            tls: false,
+            ingestion: IngestionControl::new(PauseMode::Buffer, 4),
+            indexer: Indexer::new().unwrap(),
+        }
+    }
+
+    /// Config from required variables and given ones.
+    pub(super) fn test_config(vars: &[(&str, &str)]) -> Config {
+        let required = [
+            ("POSTGRES_CONN_STR", "postgres://localhost/pumpfun"),
+            ("REDIS_CONN_STR", "redis://127.0.0.1:1"),
+        ];
+        Config::from_vars(
+            required
+                .iter()
+                .chain(vars)
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        )
+        .unwrap()
+    }
+
+    /// Serve router on a free local port.
+    pub(super) async fn serve(router: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        addr
+    }
+
+    /// Encoding of a large tokens list response requested with gzip accepted.
+    async fn tokens_encoding(compression: bool) -> Option<String> {
+        let tokens = vec!["So11111111111111111111111111111111111111112"; 1000];
+        let router = compressed(
+            Router::new().route("/tokens", get(move || async move { Json(tokens) })),
+            compression,
+        );
+        let request = Request::get("/tokens")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn large_response_is_compressed_when_requested() {
+        assert_eq!(tokens_encoding(true).await.as_deref(), Some("gzip"));
+    }
+
+    #[tokio::test]
+    async fn compression_can_be_disabled() {
+        assert_eq!(tokens_encoding(false).await, None);
+    }
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn server_is_served_over_tls() {
+        let paths = TlsPaths {
+            cert: fixture("localhost.crt"),
+            key: fixture("localhost.key"),
+        };
+        let tls = load_tls(&paths).await.unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(axum_server::from_tcp_rustls(listener, tls).serve(router.into_make_service()));
+
+        let cert = std::fs::read(&paths.cert).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://localhost:{port}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn missing_certificate_fails_to_load() {
+        let paths = TlsPaths {
+            cert: fixture("missing.crt"),
+            key: fixture("localhost.key"),
+        };
+        let e = load_tls(&paths).await.unwrap_err();
+        assert!(e.to_string().contains("missing.crt"), "{e}");
+    }
+}
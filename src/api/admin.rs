@@ -0,0 +1,378 @@
+use axum::Json;
+use axum::extract::{Path, Request, State};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::AppState;
+use crate::error::NotFound;
+use crate::model::Mint;
+use crate::pump_handler::PumpHandler;
+
+/// Pause ingestion request handler.
+pub(super) async fn pause_ingestion(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.ingestion.set_paused(true);
+    tracing::info!("Ingestion paused.");
+    Json("Ingestion paused.")
+}
+
+/// Resume ingestion request handler.
+pub(super) async fn resume_ingestion(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.ingestion.set_paused(false);
+    tracing::info!("Ingestion resumed.");
+    Json("Ingestion resumed.")
+}
+
+/// Header with admin API key.
+pub(super) const ADMIN_API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Reject admin requests without valid API key with 401.
+pub(super) async fn require_admin_key(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let provided = req
+        .headers()
+        .get(ADMIN_API_KEY_HEADER)
+        .map(HeaderValue::as_bytes);
+    if !is_authorized(state.admin_api_key.as_deref(), provided) {
+        return (StatusCode::UNAUTHORIZED, Json("Invalid API key.")).into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Whether provided API key matches the configured one.
+/// Nothing is authorized if no key is configured, so admin endpoints fail closed.
+fn is_authorized(key: Option<&str>, provided: Option<&[u8]>) -> bool {
+    key.is_some_and(|key| provided == Some(key.as_bytes()))
+}
+
+/// Delete token with all its data request handler.
+/// Responds with number of deleted trades rows, 404 if token is unknown.
+pub(super) async fn delete_token(
+    Path(mint): Path<Mint>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.storage.delete_token(mint.as_str()).await {
+        Ok(trades) => {
+            state.last_prices.remove(mint.as_str());
+            tracing::info!("Deleted token {mint} with {trades} trades rows.");
+            Json(trades).into_response()
+        }
+        Err(e) if e.is::<NotFound>() => {
+            (StatusCode::NOT_FOUND, Json(e.to_string())).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to delete token: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to delete token: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Token retention override.
+#[derive(Deserialize, Debug)]
+pub(super) struct TokenRetention {
+    /// Retention of token data in seconds.
+    retention_secs: u64,
+}
+
+/// Set token retention overriding the global one request handler.
+/// Responds with number of altered cached series, 404 if token is unknown.
+pub(super) async fn set_token_retention(
+    Path(mint): Path<Mint>,
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<TokenRetention>,
+) -> impl IntoResponse {
+    if body.retention_secs == 0 {
+        return (StatusCode::BAD_REQUEST, Json("Retention must be positive.")).into_response();
+    }
+    let retention = Duration::from_secs(body.retention_secs);
+
+    match state
+        .storage
+        .set_token_retention(mint.as_str(), retention)
+        .await
+    {
+        Ok(altered) => {
+            tracing::info!("Set retention of {mint} to {retention:?}, altered {altered} series.");
+            Json(altered).into_response()
+        }
+        Err(e) if e.is::<NotFound>() => {
+            (StatusCode::NOT_FOUND, Json(e.to_string())).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to set token retention: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to set token retention: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get decoded on-chain metadata account of a token request handler.
+/// Responds with 404 if the account doesn't exist.
+pub(super) async fn get_raw_metadata(
+    Path(mint): Path<Mint>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match PumpHandler::query_metadata_account(&state.rpc, mint.to_pubkey()).await {
+        Ok(metadata_acc) => Json(metadata_acc).into_response(),
+        Err(e) if e.is::<NotFound>() => {
+            (StatusCode::NOT_FOUND, Json(e.to_string())).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to query metadata account: {e}.");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(format!("Failed to query metadata account: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Drop cached series of a token request handler.
+/// Responds with number of deleted series.
+pub(super) async fn drop_token_series(
+    Path(mint): Path<Mint>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.storage.drop_token_series(mint.as_str()).await {
+        Ok(deleted) => {
+            tracing::info!("Dropped {deleted} cached series of {mint}.");
+            Json(deleted).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to drop token series: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to drop token series: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Active chart WebSocket connections request handler.
+pub(super) async fn get_connections(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.connections.snapshot())
+}
+
+/// Start metadata backfill request handler.
+/// Backfill runs in background, its result is logged.
+pub(super) async fn backfill_metadata(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let Some(guard) = state.backfill.try_lock() else {
+        return (
+            StatusCode::CONFLICT,
+            Json("Metadata backfill is already running."),
+        );
+    };
+
+    let backfill = state.backfill.clone();
+    tokio::spawn(async move {
+        if let Err(e) = backfill.backfill(guard).await {
+            tracing::warn!("Metadata backfill failed: {e}");
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json("Metadata backfill started."))
+}
+
+/// Start DB maintenance request handler.
+/// Maintenance runs in background, its result is logged.
+pub(super) async fn run_db_maintenance(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let Some(guard) = state.db_maintenance.try_lock() else {
+        return (
+            StatusCode::CONFLICT,
+            Json("Another DB maintenance is running."),
+        );
+    };
+
+    let db_maintenance = state.db_maintenance.clone();
+    tokio::spawn(async move {
+        if let Err(e) = db_maintenance.maintain(guard).await {
+            tracing::warn!("DB maintenance failed: {e}");
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json("DB maintenance started."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_key_is_required() {
+        assert!(is_authorized(Some("secret"), Some(b"secret")));
+        assert!(!is_authorized(Some("secret"), Some(b"wrong")));
+        assert!(!is_authorized(Some("secret"), None));
+    }
+
+    #[test]
+    fn nothing_is_authorized_without_configured_key() {
+        assert!(!is_authorized(None, None));
+        assert!(!is_authorized(None, Some(b"")));
+    }
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    /// Send request to the router, returning response status and body.
+    async fn send(router: &axum::Router, request: Request) -> (StatusCode, String) {
+        use tower::ServiceExt;
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn delete_request(key: Option<&str>) -> Request {
+        let mut request = Request::delete(format!("/admin/tokens/{MINT}"));
+        if let Some(key) = key {
+            request = request.header(ADMIN_API_KEY_HEADER, key);
+        }
+        request.body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn deleted_token_is_gone(pool: sqlx::PgPool) {
+        use crate::api::tests::{app_state, test_config};
+        use crate::db::Db;
+        use crate::model::{Resolution, TradeInfo};
+        use sqlx::types::chrono::DateTime;
+
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let info = TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            sol_amount: 1_000,
+            token_amount: 2_000,
+            is_buy: true,
+            decimals: None,
+        };
+        let buckets = [Resolution::M1, Resolution::H1]
+            .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)));
+        db.insert_trades(&[(buckets.to_vec(), info)]).await.unwrap();
+
+        let mut state = app_state(pool).await;
+        state.admin_api_key = Some(String::from("secret"));
+        let config = test_config(&[("ADMIN_API_KEY", "secret")]);
+        let router = crate::api::router(Arc::new(state), &config);
+
+        let (status, _) = send(&router, delete_request(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = send(&router, delete_request(Some("secret"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "2");
+
+        assert!(db.get_token(MINT).await.unwrap_err().is::<NotFound>());
+        let (status, _) = send(&router, delete_request(Some("secret"))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let tokens = Request::get("/tokens")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let (status, body) = send(&router, tokens).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains(MINT), "{body}");
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn streamed_connections_are_listed(pool: sqlx::PgPool) {
+        use crate::api::tests::{app_state, serve, test_config};
+        use serde_json::Value;
+
+        let mut state = app_state(pool).await;
+        state.admin_api_key = Some(String::from("secret"));
+        let config = test_config(&[("ADMIN_API_KEY", "secret")]);
+        let router = crate::api::router(Arc::new(state), &config);
+        let addr = serve(router.clone()).await;
+        let connections = |key: Option<&'static str>| {
+            let mut request = Request::get("/debug/connections");
+            if let Some(key) = key {
+                request = request.header(ADMIN_API_KEY_HEADER, key);
+            }
+            let request = request.body(axum::body::Body::empty()).unwrap();
+            let router = router.clone();
+            async move {
+                let (status, body) = send(&router, request).await;
+                assert_eq!(status, StatusCode::OK);
+                serde_json::from_str::<Value>(&body).unwrap()
+            }
+        };
+
+        let (status, _) = send(
+            &router,
+            Request::get("/debug/connections")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Wait until connections satisfy the condition, returning them.
+        let listed_until = |condition: fn(&Value) -> bool| async move {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let listed = connections(Some("secret")).await;
+                    if condition(&listed) {
+                        return listed;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("connections are updated in time")
+        };
+
+        let url = format!("ws://{addr}/chart_data_ws/{MINT}/M1?mode=live");
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        // Connection is registered once upgraded.
+        let listed = listed_until(|listed| listed["subscribers"][MINT] == 1).await;
+        assert_eq!(listed["connections"][0]["token"], MINT);
+        assert_eq!(listed["connections"][0]["resolution"], "M1");
+
+        client.close(None).await.unwrap();
+        let listed = listed_until(|listed| listed["connections"] == serde_json::json!([])).await;
+        assert_eq!(listed["subscribers"], serde_json::json!({}));
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn retention_of_unknown_token_is_not_set(pool: sqlx::PgPool) {
+        use crate::api::tests::{app_state, test_config};
+
+        let mut state = app_state(pool).await;
+        state.admin_api_key = Some(String::from("secret"));
+        let config = test_config(&[("ADMIN_API_KEY", "secret")]);
+        let router = crate::api::router(Arc::new(state), &config);
+        let retention_request = |retention_secs: u64| {
+            Request::put(format!("/admin/tokens/{MINT}/retention"))
+                .header(ADMIN_API_KEY_HEADER, "secret")
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(format!(
+                    r#"{{"retention_secs": {retention_secs}}}"#
+                )))
+                .unwrap()
+        };
+
+        let (status, _) = send(&router, retention_request(0)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = send(&router, retention_request(60)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains(MINT), "{body}");
+    }
+}
@@ -0,0 +1,193 @@
+use axum::extract::ws::{Message, WebSocket};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::model::{Mint, Resolution, TradeInfo};
+
+/// Price alert rule sent by a client.
+/// Alert fires when token price moves by `threshold_pct` percents within a resolution window.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    pub token: Mint,
+    pub resolution: Resolution,
+    pub threshold_pct: f64,
+}
+
+/// Price alert sent to a client.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceAlert {
+    pub token: String,
+    pub resolution: Resolution,
+    pub threshold_pct: f64,
+    pub from_price: f64,
+    pub to_price: f64,
+    pub change_pct: f64,
+    /// Trade unix timestamp in milliseconds.
+    pub timestamp: u64,
+}
+
+/// Alert rule with its baseline price.
+struct AlertState {
+    rule: AlertRule,
+    /// Window start and price to compare with.
+    baseline: Option<(DateTime<Utc>, f64)>,
+}
+
+impl AlertState {
+    fn new(rule: AlertRule) -> Self {
+        Self {
+            rule,
+            baseline: None,
+        }
+    }
+
+    /// Update baseline with a new trade price.
+    /// Returns alert if price moved beyond the threshold. Baseline is reset after firing.
+    fn update(&mut self, trade: &TradeInfo, price: f64) -> Option<PriceAlert> {
+        if trade.mint_acc != self.rule.token.as_str() {
+            return None;
+        }
+
+        let window = self.rule.resolution.bucket_start(trade.timestamp);
+        let (baseline_window, baseline_price) = match self.baseline {
+            Some(baseline) if baseline.0 == window => baseline,
+            _ => {
+                self.baseline = Some((window, price));
+                return None;
+            }
+        };
+
+        let change_pct = (price - baseline_price) / baseline_price * 100.0;
+        if !change_pct.is_finite() || change_pct.abs() < self.rule.threshold_pct {
+            return None;
+        }
+
+        self.baseline = Some((baseline_window, price));
+        Some(PriceAlert {
+            token: self.rule.token.to_string(),
+            resolution: self.rule.resolution,
+            threshold_pct: self.rule.threshold_pct,
+            from_price: baseline_price,
+            to_price: price,
+            change_pct,
+            timestamp: trade.timestamp.timestamp_millis() as u64,
+        })
+    }
+}
+
+/// Alerts WebSocket connection handler.
+/// Client sends alert rules, server sends alerts triggered by ingested trades.
+pub async fn handle_websocket(
+    mut socket: WebSocket,
+    mut trades: broadcast::Receiver<TradeInfo>,
+) -> anyhow::Result<()> {
+    let mut alerts: Vec<AlertState> = Vec::new();
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                // Pings are answered by axum.
+                let text = match msg.transpose()? {
+                    None | Some(Message::Close(_)) => return Ok(()),
+                    Some(Message::Text(text)) => text,
+                    Some(_) => continue,
+                };
+
+                match serde_json::from_str::<AlertRule>(&text) {
+                    Ok(rule) => alerts.push(AlertState::new(rule)),
+                    Err(e) => {
+                        let error = format!("Invalid alert rule: {e}");
+                        let json_error = serde_json::to_string(&error)?;
+                        socket.send(Message::Text(json_error.into())).await?;
+                    }
+                }
+            }
+            trade = trades.recv() => {
+                let trade = match trade {
+                    Ok(trade) => trade,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Alerts connection skipped {skipped} trades.");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                let Ok(price) = trade.price() else {
+                    continue;
+                };
+
+                for alert in alerts.iter_mut() {
+                    if let Some(alert) = alert.update(&trade, price) {
+                        let json_alert = serde_json::to_string(&alert)?;
+                        socket.send(Message::Text(json_alert.into())).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    fn rule(threshold_pct: f64) -> AlertState {
+        AlertState::new(AlertRule {
+            token: Mint::try_from(MINT.to_string()).unwrap(),
+            resolution: Resolution::M1,
+            threshold_pct,
+        })
+    }
+
+    fn trade(mint: &str, secs: i64) -> TradeInfo {
+        TradeInfo {
+            mint_acc: mint.to_string(),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount: 1_000,
+            token_amount: 1_000,
+            is_buy: true,
+            decimals: None,
+        }
+    }
+
+    #[test]
+    fn price_jump_fires_alert() {
+        let mut state = rule(20.0);
+        // Minute starts at 1_699_999_980.
+        assert!(state.update(&trade(MINT, 1_700_000_000), 1.0).is_none());
+        assert!(state.update(&trade(MINT, 1_700_000_001), 1.1).is_none());
+
+        let alert = state.update(&trade(MINT, 1_700_000_002), 1.3).unwrap();
+        assert_eq!(alert.token, MINT);
+        assert_eq!(alert.from_price, 1.0);
+        assert_eq!(alert.to_price, 1.3);
+        assert!((alert.change_pct - 30.0).abs() < 1e-9);
+        assert_eq!(alert.timestamp, 1_700_000_002_000);
+
+        // Baseline is reset to the alerted price.
+        assert!(state.update(&trade(MINT, 1_700_000_003), 1.5).is_none());
+        assert!(state.update(&trade(MINT, 1_700_000_004), 1.0).is_some());
+    }
+
+    #[test]
+    fn baseline_is_reset_in_next_window() {
+        let mut state = rule(20.0);
+        assert!(state.update(&trade(MINT, 1_700_000_000), 1.0).is_none());
+        // Next minute starts with a new baseline.
+        assert!(state.update(&trade(MINT, 1_700_000_050), 2.0).is_none());
+        assert!(state.update(&trade(MINT, 1_700_000_051), 2.1).is_none());
+    }
+
+    #[test]
+    fn other_tokens_are_ignored() {
+        let mut state = rule(20.0);
+        let other = "4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R";
+        assert!(state.update(&trade(MINT, 1_700_000_000), 1.0).is_none());
+        assert!(state.update(&trade(other, 1_700_000_001), 10.0).is_none());
+        assert!(state.update(&trade(MINT, 1_700_000_002), 1.0).is_none());
+    }
+}
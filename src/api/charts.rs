@@ -0,0 +1,610 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{AppState, disabled_resolution_response};
+use crate::model::{Candle, CandleOpens, Mint, OpenPolicy, Resolution, TradeOhlcv};
+use crate::parquet_export;
+
+/// Chart path params of WebSocket and candles request handlers.
+#[derive(Deserialize, Debug)]
+pub(super) struct ChartWsPathParams {
+    pub(super) token: Mint,
+    pub(super) resolution: Resolution,
+}
+
+/// Chart data path params of REST request handler.
+#[derive(Deserialize, Debug)]
+pub(super) struct ChartDataPathParams {
+    token: Mint,
+    resolution: ChartDataFile,
+}
+
+/// Resolution path segment of chart data request.
+/// Routes can't have a suffix after a param, so `.parquet` extension is parsed here.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
+struct ChartDataFile {
+    resolution: Resolution,
+    /// Whether Parquet export is requested rather than JSON.
+    parquet: bool,
+}
+
+impl TryFrom<String> for ChartDataFile {
+    type Error = String;
+
+    fn try_from(segment: String) -> Result<Self, Self::Error> {
+        let (resolution, parquet) = match segment.strip_suffix(".parquet") {
+            Some(resolution) => (resolution, true),
+            None => (segment.as_str(), false),
+        };
+        Ok(Self {
+            resolution: resolution.parse()?,
+            parquet,
+        })
+    }
+}
+
+/// Chart data request params.
+#[derive(Deserialize, Debug)]
+struct ChartDataParams {
+    /// Number of the last candles with trades.
+    last: usize,
+    /// Candles open price definition, server default if not set.
+    open: Option<OpenPolicy>,
+}
+
+/// Chart data export request params.
+#[derive(Deserialize, Debug)]
+struct ChartExportParams {
+    /// Window start, unix timestamp in seconds.
+    from: Option<i64>,
+    /// Window end, unix timestamp in seconds.
+    to: Option<i64>,
+}
+
+/// Get chart data request handler.
+/// Responds with the last candles as JSON, or with candles of a window as Parquet file
+/// if resolution has `.parquet` extension.
+pub(super) async fn get_chart_data(
+    Path(path): Path<ChartDataPathParams>,
+    uri: Uri,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let resolution = path.resolution.resolution;
+    if !resolution.is_enabled() {
+        return disabled_resolution_response(resolution);
+    }
+
+    if path.resolution.parquet {
+        match Query::try_from_uri(&uri) {
+            Ok(Query(params)) => export_chart_data(&path.token, resolution, params, &state).await,
+            Err(e) => e.into_response(),
+        }
+    } else {
+        match Query::try_from_uri(&uri) {
+            Ok(Query(params)) => last_chart_data(&path.token, resolution, params, &state).await,
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+/// Respond with the last candles with trades of a token.
+/// Candles are not interpolated, so sparse tokens get `last` candles spanning any time range.
+/// Number of candles is capped by `MAX_HISTORY_POINTS`.
+async fn last_chart_data(
+    token: &Mint,
+    resolution: Resolution,
+    params: ChartDataParams,
+    state: &AppState,
+) -> Response {
+    let n = params.last.min(MAX_HISTORY_POINTS);
+
+    match state
+        .storage
+        .last_n_candles(token.as_str(), resolution, n)
+        .await
+    {
+        Ok(candles) => {
+            let mut opens = CandleOpens::new(params.open.unwrap_or(state.open_policy));
+            let candles: Vec<_> = candles
+                .into_iter()
+                .map(|(datetime, candle)| {
+                    let mut trade = TradeOhlcv {
+                        timestamp: datetime.timestamp_millis() as u64,
+                        candle,
+                    };
+                    opens.apply(&mut trade);
+                    trade
+                })
+                .collect();
+            Json(candles).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to get chart data: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get chart data: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Respond with candles of a token within the history window as Parquet file.
+/// Window is the same as of chart WebSocket history, candles are not interpolated.
+async fn export_chart_data(
+    token: &Mint,
+    resolution: Resolution,
+    params: ChartExportParams,
+    state: &AppState,
+) -> Response {
+    let (from_timestamp, to_timestamp) = history_window(
+        params.from,
+        params.to,
+        None,
+        state.history_points,
+        resolution,
+    );
+
+    let file = state
+        .storage
+        .trades_since(token.as_str(), from_timestamp, resolution)
+        .await
+        .and_then(|mut candles| {
+            candles.retain(|datetime, _| *datetime <= to_timestamp);
+            parquet_export::candles_to_parquet(&candles)
+        });
+    match file {
+        Ok(file) => {
+            let disposition = format!("attachment; filename=\"{token}_{resolution}.parquet\"");
+            let headers = [
+                (
+                    CONTENT_TYPE,
+                    parquet_export::PARQUET_CONTENT_TYPE.to_string(),
+                ),
+                (CONTENT_DISPOSITION, disposition),
+            ];
+            (headers, file).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to export chart data: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to export chart data: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Candles request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct CandlesRangeParams {
+    /// Window start, unix timestamp in seconds. By default window spans `limit` buckets before its end.
+    from: Option<i64>,
+    /// Window end, unix timestamp in seconds, inclusive. Now by default.
+    to: Option<i64>,
+    /// Max number of candles, `DEFAULT_CANDLES_LIMIT` by default, capped by `MAX_HISTORY_POINTS`.
+    limit: Option<usize>,
+    /// Candles open price definition, server default if not set.
+    open: Option<OpenPolicy>,
+}
+
+/// Get candles request handler.
+/// Responds with the earliest `limit` candles with trades starting within the window as JSON,
+/// so long ranges are paged by requesting the next window from the last candle.
+/// Responds with 400 if window is empty or timestamps are out of range.
+pub(super) async fn get_candles(
+    Path(path): Path<ChartWsPathParams>,
+    Query(params): Query<CandlesRangeParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let resolution = path.resolution;
+    if !resolution.is_enabled() {
+        return disabled_resolution_response(resolution);
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_CANDLES_LIMIT)
+        .clamp(1, MAX_HISTORY_POINTS);
+    let to_timestamp = match params.to {
+        Some(to) => match DateTime::from_timestamp(to, 0) {
+            Some(to) => to,
+            None => return bad_timestamp_response("to", to),
+        },
+        None => Utc::now(),
+    };
+    let from_timestamp = match params.from {
+        Some(from) => match DateTime::from_timestamp(from, 0) {
+            Some(from) => resolution.bucket_start(from),
+            None => return bad_timestamp_response("from", from),
+        },
+        None => history_start(to_timestamp, resolution, limit),
+    };
+    if from_timestamp >= to_timestamp {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!(
+                "Window start {from_timestamp} must be before its end {to_timestamp}."
+            )),
+        )
+            .into_response();
+    }
+
+    match state
+        .storage
+        .trades_between(
+            path.token.as_str(),
+            from_timestamp,
+            to_timestamp,
+            resolution,
+            limit,
+        )
+        .await
+    {
+        Ok(candles) => {
+            let mut opens = CandleOpens::new(params.open.unwrap_or(state.open_policy));
+            let candles: Vec<_> = candles
+                .into_iter()
+                .map(|(datetime, candle)| {
+                    let mut trade = TradeOhlcv {
+                        timestamp: datetime.timestamp_millis() as u64,
+                        candle,
+                    };
+                    opens.apply(&mut trade);
+                    trade
+                })
+                .collect();
+            Json(candles).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to get candles: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get candles: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Response to a request with unrepresentable timestamp.
+fn bad_timestamp_response(name: &str, timestamp: i64) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(format!("Timestamp {name}={timestamp} is out of range.")),
+    )
+        .into_response()
+}
+
+/// Max number of history points for a requested window.
+pub(super) const MAX_HISTORY_POINTS: usize = 10_000;
+
+/// Default number of candles of candles request.
+const DEFAULT_CANDLES_LIMIT: usize = 1000;
+
+/// Chart history window for requested unix timestamps in seconds.
+/// Window ends now at the latest and spans `MAX_HISTORY_POINTS` at most.
+/// If start isn't requested, window spans requested number of points including the bucket of its end,
+/// or `history_points` server default, which depends on resolution if not set.
+pub(super) fn history_window(
+    from: Option<i64>,
+    to: Option<i64>,
+    points: Option<usize>,
+    history_points: Option<usize>,
+    resolution: Resolution,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let to_timestamp = to
+        .and_then(|to| DateTime::from_timestamp(to, 0))
+        .map_or_else(Utc::now, |to| to.min(Utc::now()));
+    let max_from_timestamp = history_start(to_timestamp, resolution, MAX_HISTORY_POINTS);
+    let from_timestamp = match from.and_then(|from| DateTime::from_timestamp(from, 0)) {
+        Some(from) => resolution.bucket_start(from.max(max_from_timestamp)),
+        None => {
+            let points = points
+                .or(history_points)
+                .unwrap_or_else(|| resolution.default_history_points())
+                .min(MAX_HISTORY_POINTS);
+            history_start(to_timestamp, resolution, points.saturating_sub(1))
+        }
+    };
+
+    (from_timestamp, to_timestamp)
+}
+
+/// Start of the chart history with given number of points.
+pub(super) fn history_start(
+    to_timestamp: DateTime<Utc>,
+    resolution: Resolution,
+    points: usize,
+) -> DateTime<Utc> {
+    resolution
+        .bucket_start(to_timestamp - Duration::from_secs(points as u64 * resolution.as_seconds()))
+}
+
+// Fill gaps in trade events.
+pub(super) fn interpolate_candles(
+    mut from_timestamp: DateTime<Utc>,
+    to_timestamp: DateTime<Utc>,
+    resolution: Resolution,
+    db_candles: BTreeMap<DateTime<Utc>, Candle>,
+) -> Vec<TradeOhlcv> {
+    let step = Duration::from_secs(resolution.as_seconds());
+    let mut prices = Vec::new();
+    while from_timestamp <= to_timestamp {
+        let Some((db_timestamp, db_candle)) = db_candles
+            .range(..=from_timestamp)
+            .next_back()
+            .map(|(ts, canlde)| (*ts, *canlde))
+        else {
+            from_timestamp += step;
+            continue;
+        };
+
+        let candle = if resolution.bucket_contains(db_timestamp, from_timestamp) {
+            db_candle
+        } else {
+            Candle::flat(db_candle.close)
+        };
+
+        prices.push(TradeOhlcv {
+            timestamp: from_timestamp.timestamp_millis() as u64,
+            candle,
+        });
+
+        from_timestamp += step;
+    }
+
+    prices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(price: f64) -> Candle {
+        Candle {
+            volume: 1.0,
+            trade_count: 1,
+            ..Candle::flat(price)
+        }
+    }
+
+    /// Send history window of given points the way chart WebSocket does.
+    fn backfill(
+        to: DateTime<Utc>,
+        points: usize,
+        candles: &BTreeMap<DateTime<Utc>, Candle>,
+    ) -> Vec<TradeOhlcv> {
+        let (from, to) = history_window(
+            None,
+            Some(to.timestamp()),
+            Some(points),
+            None,
+            Resolution::M1,
+        );
+        interpolate_candles(from, to, Resolution::M1, candles.clone())
+    }
+
+    #[test]
+    fn backfill_sends_requested_points() {
+        let to = DateTime::from_timestamp(1_700_000_030, 0).unwrap();
+        let first = Resolution::M1.bucket_start(to) - Duration::from_secs(24 * 3600);
+        let candles = BTreeMap::from([(first, candle(1.0))]);
+
+        for points in [1, 2, 10, 500] {
+            assert_eq!(backfill(to, points, &candles).len(), points);
+        }
+    }
+
+    #[test]
+    fn backfill_interpolates_gaps() {
+        // Bucket aligned end is included.
+        let to = DateTime::from_timestamp(1_700_000_040, 0).unwrap();
+        let end = Resolution::M1.bucket_start(to);
+        let minute = Duration::from_secs(60);
+        let candles = BTreeMap::from([
+            (end - minute * 20, candle(1.0)),
+            (end - minute * 3, candle(2.0)),
+        ]);
+
+        let history = backfill(to, 10, &candles);
+        assert_eq!(history.len(), 10);
+        assert_eq!(
+            history.last().unwrap().timestamp,
+            end.timestamp_millis() as u64
+        );
+        let traded: Vec<_> = history
+            .iter()
+            .filter(|trade| trade.candle.trade_count > 0)
+            .collect();
+        assert_eq!(traded.len(), 1);
+        assert_eq!(history[0].candle.close, 1.0);
+        assert_eq!(history[9].candle.close, 2.0);
+    }
+
+    #[test]
+    fn backfill_starts_at_first_candle() {
+        let to = DateTime::from_timestamp(1_700_000_030, 0).unwrap();
+        let first = Resolution::M1.bucket_start(to) - Duration::from_secs(4 * 60);
+        let candles = BTreeMap::from([(first, candle(1.0))]);
+
+        assert_eq!(backfill(to, 10, &candles).len(), 5);
+    }
+
+    #[test]
+    fn history_points_default_is_overridden() {
+        let to = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let window = |points, history_points| {
+            let (from, to) = history_window(
+                None,
+                Some(to.timestamp()),
+                points,
+                history_points,
+                Resolution::M1,
+            );
+            (to - from).num_minutes()
+        };
+
+        assert_eq!(window(None, None), 119);
+        assert_eq!(window(None, Some(30)), 29);
+        assert_eq!(window(Some(5), Some(30)), 4);
+        assert_eq!(
+            window(Some(usize::MAX), None),
+            MAX_HISTORY_POINTS as i64 - 1
+        );
+    }
+
+    #[test]
+    fn default_history_start_depends_on_resolution() {
+        // Monday midnight is bucket start of every resolution.
+        let to = DateTime::from_timestamp(1_699_833_600, 0).unwrap();
+        for resolution in Resolution::all() {
+            let (from, _) = history_window(None, Some(to.timestamp()), None, None, resolution);
+            let points = resolution.default_history_points();
+            let span = Duration::from_secs((points as u64 - 1) * resolution.as_seconds());
+            assert_eq!(from, to - span, "{resolution}");
+        }
+    }
+
+    #[test]
+    fn interpolated_timestamps_are_milliseconds() {
+        let from = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let to = from + Duration::from_secs(2);
+        let candles = BTreeMap::from([(from, candle(1.0))]);
+
+        let timestamps: Vec<_> = interpolate_candles(from, to, Resolution::S1, candles)
+            .iter()
+            .map(|trade| trade.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            [1_700_000_000_000, 1_700_000_001_000, 1_700_000_002_000]
+        );
+    }
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    /// Insert token with a trade at each of the minute aligned timestamps.
+    async fn insert_minute_trades(pool: &sqlx::PgPool, timestamps: &[i64]) {
+        use crate::db::Db;
+        use crate::model::TradeInfo;
+
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let trades: Vec<_> = timestamps
+            .iter()
+            .map(|secs| {
+                let timestamp = DateTime::from_timestamp(*secs, 0).unwrap();
+                let info = TradeInfo {
+                    mint_acc: MINT.to_string(),
+                    timestamp,
+                    sol_amount: 1_000,
+                    token_amount: 2_000,
+                    is_buy: true,
+                    decimals: None,
+                };
+                (vec![(Resolution::M1, timestamp)], info)
+            })
+            .collect();
+        db.insert_trades(&trades).await.unwrap();
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn chart_data_window_is_exported_as_parquet(pool: sqlx::PgPool) {
+        use axum::body::Body;
+        use axum::http::Request;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use tower::ServiceExt;
+
+        use crate::api::tests::{test_config, test_state};
+
+        // The last one is after the window.
+        insert_minute_trades(&pool, &[1_700_000_100, 1_700_000_160, 1_700_000_400]).await;
+
+        let router = crate::api::router(test_state(pool).await, &test_config(&[]));
+        let uri = format!("/chart_data/{MINT}/M1.parquet?from=1700000000&to=1700000300");
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[CONTENT_TYPE], parquet_export::PARQUET_CONTENT_TYPE);
+        assert_eq!(
+            headers[CONTENT_DISPOSITION],
+            format!("attachment; filename=\"{MINT}_M1.parquet\"")
+        );
+
+        let file = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 2);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn candles_are_listed_within_window(pool: sqlx::PgPool) {
+        use axum::body::Body;
+        use axum::http::Request;
+        use serde_json::Value;
+        use tower::ServiceExt;
+
+        use crate::api::tests::{test_config, test_state};
+
+        let minute = Resolution::M1.bucket_start(Utc::now()).timestamp();
+        let day_ago = minute - 24 * 3600;
+        insert_minute_trades(&pool, &[day_ago, day_ago + 60, minute - 600, minute - 300]).await;
+        let router = crate::api::router(test_state(pool).await, &test_config(&[]));
+        let get = |query: String| {
+            let request = Request::get(format!("/candles/{MINT}/M1{query}"))
+                .body(Body::empty())
+                .unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+        let timestamps = |candles: &Value| -> Vec<i64> {
+            candles
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|candle| candle["timestamp"].as_i64().unwrap() / 1000)
+                .collect()
+        };
+
+        // Default window spans the last DEFAULT_CANDLES_LIMIT minutes.
+        let (status, candles) = get(String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(timestamps(&candles), [minute - 600, minute - 300]);
+
+        let window = format!("?from={}&to={}", day_ago, day_ago + 3600);
+        let (status, candles) = get(window.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(timestamps(&candles), [day_ago, day_ago + 60]);
+        let (_, candles) = get(format!("{window}&limit=1")).await;
+        assert_eq!(timestamps(&candles), [day_ago]);
+
+        let (status, _) = get(format!("?from={day_ago}&to={day_ago}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(format!("?from={}", i64::MAX)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
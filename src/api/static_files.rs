@@ -0,0 +1,118 @@
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::time::Duration;
+
+/// Max age of fingerprinted static assets, which never change under the same name.
+const IMMUTABLE_ASSET_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Set content type and caching headers of static assets.
+/// WASM gets `application/wasm` as required for streaming compilation,
+/// fingerprinted assets are cached forever, everything else (e.g. `index.html`) is revalidated.
+pub(super) async fn static_headers(req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_string();
+    let mut response = next.run(req).await;
+
+    let cache_control = if response.status().is_success() && is_fingerprinted(&path) {
+        HeaderValue::from_str(&format!(
+            "public, max-age={}, immutable",
+            IMMUTABLE_ASSET_MAX_AGE.as_secs()
+        ))
+        .expect("valid header value")
+    } else {
+        HeaderValue::from_static("no-cache")
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, cache_control);
+    if path.ends_with(".wasm") {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/wasm"));
+    }
+
+    response
+}
+
+/// Whether file name contains content hash, e.g. `front-1a2b3c4d5e6f7a8b_bg.wasm`.
+fn is_fingerprinted(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    file_name
+        .split(['-', '.', '_'])
+        .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::middleware;
+    use tower::ServiceExt;
+    use tower_http::services::ServeDir;
+
+    const WASM: &str = "front-1a2b3c4d5e6f7a8b_bg.wasm";
+
+    #[test]
+    fn hashed_file_names_are_fingerprinted() {
+        assert!(is_fingerprinted(&format!("/assets/{WASM}")));
+        assert!(is_fingerprinted("/assets/index.0123abcd.js"));
+        assert!(!is_fingerprinted("/index.html"));
+        assert!(!is_fingerprinted("/assets/get_data.js"));
+    }
+
+    /// Headers of a static file response, served from a fresh directory with frontend files.
+    async fn static_file_headers(path: &str, accept_encoding: Option<&str>) -> HeaderMap {
+        let dir = std::env::temp_dir().join(format!(
+            "pumpfun_indexer_static_{}_{}_{}",
+            std::process::id(),
+            path.replace(['/', '.'], "_"),
+            accept_encoding.unwrap_or("identity")
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join(WASM), b"\0asm").unwrap();
+        std::fs::write(dir.join(format!("{WASM}.br")), b"compressed").unwrap();
+
+        let router = Router::new()
+            .fallback_service(ServeDir::new(&dir).precompressed_br())
+            .layer(middleware::from_fn(static_headers));
+        let mut request = Request::get(path);
+        if let Some(encoding) = accept_encoding {
+            request = request.header(ACCEPT_ENCODING, encoding);
+        }
+        let response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers().clone()
+    }
+
+    #[tokio::test]
+    async fn wasm_is_served_with_its_content_type() {
+        let headers = static_file_headers(&format!("/{WASM}"), None).await;
+        assert_eq!(headers[CONTENT_TYPE], "application/wasm");
+        assert_eq!(
+            headers[CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+    }
+
+    #[tokio::test]
+    async fn precompressed_wasm_keeps_its_content_type() {
+        let headers = static_file_headers(&format!("/{WASM}"), Some("br")).await;
+        assert_eq!(headers[CONTENT_ENCODING], "br");
+        assert_eq!(headers[CONTENT_TYPE], "application/wasm");
+    }
+
+    #[tokio::test]
+    async fn index_is_revalidated() {
+        let headers = static_file_headers("/index.html", None).await;
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+        assert_eq!(headers[CONTENT_TYPE], "text/html");
+    }
+}
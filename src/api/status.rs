@@ -0,0 +1,354 @@
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum_extra::extract::Host;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::AppState;
+use crate::model::Resolution;
+
+/// Server URLs for clients.
+#[derive(Serialize, Debug)]
+struct ClientConfig {
+    /// Base URL of REST endpoints.
+    http_base_url: String,
+    /// Base URL of WebSocket endpoints.
+    ws_base_url: String,
+}
+
+/// Get server URLs for clients request handler.
+/// URLs are based on `PUBLIC_BASE_URL` or the requested host and TLS mode otherwise.
+pub(super) async fn get_client_config(
+    Host(host): Host,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let http_base_url = match &state.public_base_url {
+        Some(url) => url.clone(),
+        None if state.tls => format!("https://{host}"),
+        None => format!("http://{host}"),
+    };
+    let ws_base_url = match http_base_url.strip_prefix("https://") {
+        Some(rest) => format!("wss://{rest}"),
+        // URL is validated to start with either scheme.
+        None => format!("ws://{}", http_base_url.trim_start_matches("http://")),
+    };
+
+    Json(ClientConfig {
+        http_base_url,
+        ws_base_url,
+    })
+}
+
+/// Version of chart WebSocket messages, bumped on incompatible changes.
+const WS_PROTOCOL_VERSION: u32 = 1;
+
+/// Versions and capabilities of the server.
+#[derive(Serialize, Debug)]
+struct VersionInfo {
+    /// Server crate version.
+    version: &'static str,
+    ws_protocol_version: u32,
+    /// Version of the latest applied DB migration.
+    migration_version: Option<i64>,
+    /// Resolutions candles are served for.
+    resolutions: Vec<Resolution>,
+}
+
+/// Server version request handler.
+pub(super) async fn get_version(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.storage.migration_version().await {
+        Ok(migration_version) => Json(VersionInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            ws_protocol_version: WS_PROTOCOL_VERSION,
+            migration_version,
+            resolutions: Resolution::enabled(),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::info!("Failed to get migration version: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get migration version: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Health status.
+#[derive(Serialize, Debug)]
+struct HealthStatus {
+    /// Whether pumpfun events handler is running.
+    ingesting: bool,
+    /// Whether ingestion is paused by operator.
+    paused: bool,
+}
+
+/// Health check request handler.
+/// Responds with 503 if pumpfun events are not handled anymore.
+pub(super) async fn health(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status = HealthStatus {
+        ingesting: state.ingestion.is_ingesting(),
+        paused: state.ingestion.is_paused(),
+    };
+
+    if status.ingesting {
+        (StatusCode::OK, Json(status))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(status))
+    }
+}
+
+/// Readiness status.
+#[derive(Serialize, Debug)]
+struct ReadyStatus {
+    /// Whether pumpfun events were received since startup.
+    received_events: bool,
+    /// Whether pumpfun events handler is running.
+    ingesting: bool,
+    /// Whether DB is reachable.
+    db: bool,
+    /// Whether Redis is reachable.
+    redis: bool,
+}
+
+impl ReadyStatus {
+    /// Whether traffic may be served.
+    fn is_ready(&self) -> bool {
+        self.received_events && self.ingesting && self.db && self.redis
+    }
+}
+
+/// Max time DB and Redis are waited for by readiness check.
+const READY_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Readiness check request handler.
+/// Responds with 503 until the first pumpfun event is received, so traffic
+/// is held until charts have live data, if events are not handled anymore,
+/// and if DB or Redis is unreachable.
+pub(super) async fn ready(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let (db, redis) = state.storage.ping(READY_PING_TIMEOUT).await;
+    if let Err(e) = &db {
+        tracing::warn!("Readiness check: DB is unreachable: {e}");
+    }
+    if let Err(e) = &redis {
+        tracing::warn!("Readiness check: Redis is unreachable: {e}");
+    }
+
+    let status = ReadyStatus {
+        received_events: state.ingestion.is_ready(),
+        ingesting: state.ingestion.is_ingesting(),
+        db: db.is_ok(),
+        redis: redis.is_ok(),
+    };
+
+    if status.is_ready() {
+        (StatusCode::OK, Json(status))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(status))
+    }
+}
+
+/// Number of sessions listed by sessions request.
+const RECENT_SESSIONS: usize = 100;
+
+/// Recent indexer sessions request handler.
+pub(super) async fn get_sessions(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.sessions.recent(RECENT_SESSIONS).await {
+        Ok(sessions) => Json(sessions).into_response(),
+        Err(e) => {
+            tracing::info!("Failed to get sessions: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get sessions: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Metrics request handler.
+pub(super) async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.metrics.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::routing::get;
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use crate::api::tests::{app_state, test_state};
+    use crate::pump_handler::PumpHandler;
+
+    /// Response status and JSON body of request to the router.
+    async fn send(router: Router, request: Request) -> (StatusCode, Value) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn not_ready_before_first_event(pool: PgPool) {
+        let router = Router::new()
+            .route("/ready", get(ready))
+            .with_state(test_state(pool).await);
+
+        let request = Request::get("/ready").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["received_events"], false);
+        assert_eq!(body["ingesting"], true);
+        assert_eq!(body["db"], true);
+    }
+
+    #[test]
+    fn ready_after_first_event_with_reachable_storage() {
+        let status = |received_events| ReadyStatus {
+            received_events,
+            ingesting: true,
+            db: true,
+            redis: true,
+        };
+        assert!(!status(false).is_ready());
+        assert!(status(true).is_ready());
+    }
+
+    /// Client config served over TLS or not, with base URL if given.
+    async fn client_config(pool: PgPool, tls: bool, public_base_url: Option<&str>) -> Value {
+        let mut state = app_state(pool).await;
+        state.tls = tls;
+        state.public_base_url = public_base_url.map(String::from);
+        let router = Router::new()
+            .route("/config", get(get_client_config))
+            .with_state(Arc::new(state));
+        let request = Request::get("/config")
+            .header("host", "indexer.local:3000")
+            .body(Body::empty())
+            .unwrap();
+        let (status, config) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        config
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn plain_server_advertises_ws(pool: PgPool) {
+        let config = client_config(pool, false, None).await;
+        assert_eq!(config["http_base_url"], "http://indexer.local:3000");
+        assert_eq!(config["ws_base_url"], "ws://indexer.local:3000");
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn tls_server_advertises_wss(pool: PgPool) {
+        let config = client_config(pool, true, None).await;
+        assert_eq!(config["http_base_url"], "https://indexer.local:3000");
+        assert_eq!(config["ws_base_url"], "wss://indexer.local:3000");
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn public_base_url_is_advertised(pool: PgPool) {
+        let config = client_config(pool, false, Some("https://example.com/indexer")).await;
+        assert_eq!(config["http_base_url"], "https://example.com/indexer");
+        assert_eq!(config["ws_base_url"], "wss://example.com/indexer");
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn running_session_is_listed(pool: PgPool) {
+        let router = Router::new()
+            .route("/sessions", get(get_sessions))
+            .with_state(test_state(pool).await);
+
+        let request = Request::get("/sessions").body(Body::empty()).unwrap();
+        let (status, sessions) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0]["started_at"].as_u64().is_some());
+        assert_eq!(sessions[0]["ended_at"], Value::Null);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn applied_migration_version_is_reported(pool: PgPool) {
+        let router = Router::new()
+            .route("/version", get(get_version))
+            .with_state(test_state(pool).await);
+
+        let request = Request::get("/version").body(Body::empty()).unwrap();
+        let (status, version) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let latest = sqlx::migrate!("pg/migrations")
+            .iter()
+            .map(|migration| migration.version)
+            .max();
+        assert_eq!(version["migration_version"].as_i64(), latest);
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["ws_protocol_version"], WS_PROTOCOL_VERSION);
+        assert_eq!(
+            version["resolutions"],
+            serde_json::to_value(Resolution::enabled()).unwrap()
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn health_reflects_ingestion(pool: PgPool) {
+        let state = test_state(pool).await;
+        let router = Router::new()
+            .route("/health", get(health))
+            .with_state(state.clone());
+        let request = || Request::get("/health").body(Body::empty()).unwrap();
+
+        let (status, body) = send(router.clone(), request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ingesting"], true);
+        assert_eq!(body["paused"], false);
+
+        // Event processing task exits.
+        PumpHandler::monitor(tokio::spawn(async {}), &state.ingestion).await;
+        let (status, body) = send(router, request()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ingesting"], false);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn unreachable_dependencies_are_reported(pool: PgPool) {
+        let state = test_state(pool.clone()).await;
+        let router = Router::new().route("/ready", get(ready)).with_state(state);
+        let request = || Request::get("/ready").body(Body::empty()).unwrap();
+
+        // Redis is unreachable in tests.
+        let (status, body) = send(router.clone(), request()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["db"], true);
+        assert_eq!(body["redis"], false);
+
+        pool.close().await;
+        let (status, body) = send(router, request()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["db"], false);
+        assert_eq!(body["redis"], false);
+    }
+
+    #[test]
+    fn not_ready_with_unreachable_storage() {
+        let status = |db, redis| ReadyStatus {
+            received_events: true,
+            ingesting: true,
+            db,
+            redis,
+        };
+        assert!(status(true, true).is_ready());
+        assert!(!status(false, true).is_ready());
+        assert!(!status(true, false).is_ready());
+    }
+}
@@ -0,0 +1,678 @@
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{AGE, CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono_tz::Tz;
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{AppState, disabled_resolution_response};
+use crate::error::NotFound;
+use crate::local_time;
+use crate::model::{Candle, Mint, Resolution, TokenDetails, TradeOhlcv};
+
+/// Get list of tokens request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct TokensParams {
+    /// Attach last candle to every token.
+    #[serde(default)]
+    with_price: bool,
+    /// Keep tokens with at least this volume in the last 24 hours.
+    min_volume_24h: Option<f64>,
+    /// Keep tokens traded since this unix timestamp in seconds.
+    active_since: Option<i64>,
+    /// Stream unfiltered list from DB row by row instead of building it in memory.
+    #[serde(default)]
+    stream: bool,
+    /// Search tokens which name, symbol or mint contains this text, case-insensitive.
+    /// Search responds with a page, see [`TokensPage`].
+    query: Option<String>,
+    /// Max number of tokens of the page, `DEFAULT_TOKENS_PAGE_SIZE` by default,
+    /// capped by `MAX_TOKENS_PAGE_SIZE`.
+    limit: Option<usize>,
+    /// Number of tokens skipped before the page.
+    offset: Option<usize>,
+}
+
+/// Default number of tokens of a page.
+const DEFAULT_TOKENS_PAGE_SIZE: usize = 100;
+
+/// Max number of tokens of a page.
+const MAX_TOKENS_PAGE_SIZE: usize = 1000;
+
+/// Page of tokens list, most recently traded first.
+#[derive(Serialize, Debug)]
+struct TokensPage<T> {
+    tokens: Vec<T>,
+    /// Number of all matching tokens.
+    total: u64,
+}
+
+/// Get list of tokens request handler.
+/// Responds with a page of tokens if search or pagination is requested.
+pub(super) async fn get_tokens(
+    Query(params): Query<TokensParams>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if params.query.is_some() || params.limit.is_some() || params.offset.is_some() {
+        if params.min_volume_24h.is_some() || params.active_since.is_some() || params.stream {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json("Search and pagination can't be combined with filters or streaming."),
+            )
+                .into_response();
+        }
+        return search_tokens(&state, params).await;
+    }
+
+    let active_since = match params.active_since {
+        Some(secs) => match DateTime::from_timestamp(secs, 0) {
+            Some(since) => Some(since),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(format!("Invalid active_since: {secs}.")),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+    let filtered = params.min_volume_24h.is_some() || active_since.is_some();
+    if params.stream && !filtered {
+        return stream_tokens(&state, params.with_price);
+    }
+
+    // Only the full list is cached, filtered ones are rarely repeated.
+    let tokens_result = if filtered {
+        state
+            .storage
+            .get_tokens_filtered(params.min_volume_24h, active_since)
+            .await
+            .map(|tokens| (Arc::new(tokens), None))
+    } else {
+        state
+            .tokens_cache
+            .get(&state.storage)
+            .await
+            .map(|(tokens, age)| (tokens, Some(age)))
+    };
+
+    let (tokens, age) = match tokens_result {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            tracing::info!("Failed to get tokens: {e}.");
+            return Json(format!("Failed to get tokens: {e}.")).into_response();
+        }
+    };
+
+    let mut response = if params.with_price {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|(mint, metadata)| (mint, metadata, state.last_prices.get(mint)))
+            .collect();
+        Json(tokens).into_response()
+    } else {
+        Json(tokens.as_slice()).into_response()
+    };
+
+    if let Some(age) = age {
+        let max_age = state.tokens_cache.ttl().saturating_sub(age);
+        let headers = response.headers_mut();
+        headers.insert(AGE, HeaderValue::from(age.as_secs()));
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_str(&format!("max-age={}", max_age.as_secs()))
+                .expect("valid header value"),
+        );
+    }
+    response
+}
+
+/// Respond with a page of tokens matching search query.
+async fn search_tokens(state: &AppState, params: TokensParams) -> Response {
+    let query = params
+        .query
+        .as_deref()
+        .map(str::trim)
+        .filter(|query| !query.is_empty());
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_TOKENS_PAGE_SIZE)
+        .min(MAX_TOKENS_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0);
+
+    let (tokens, total) = match state.storage.search_tokens(query, limit, offset).await {
+        Ok(page) => page,
+        Err(e) => {
+            tracing::info!("Failed to search tokens: {e}.");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to search tokens: {e}.")),
+            )
+                .into_response();
+        }
+    };
+
+    if params.with_price {
+        let tokens = tokens
+            .into_iter()
+            .map(|(mint, metadata)| {
+                let price = state.last_prices.get(&mint);
+                (mint, metadata, price)
+            })
+            .collect();
+        Json(TokensPage { tokens, total }).into_response()
+    } else {
+        Json(TokensPage { tokens, total }).into_response()
+    }
+}
+
+/// Tokens list streamed from DB as a JSON array.
+/// Response is cut short if DB fails in the middle, so client gets invalid JSON.
+fn stream_tokens(state: &AppState, with_price: bool) -> Response {
+    let last_prices = state.last_prices.clone();
+    let tokens = state
+        .storage
+        .stream_tokens()
+        .enumerate()
+        .map(move |(idx, token)| {
+            let (mint, metadata) = token.inspect_err(|e| {
+                tracing::info!("Failed to stream tokens: {e}.");
+            })?;
+            let mut chunk = if idx == 0 { Vec::new() } else { vec![b','] };
+            if with_price {
+                let price = last_prices.get(&mint);
+                serde_json::to_writer(&mut chunk, &(mint, metadata, price))?;
+            } else {
+                serde_json::to_writer(&mut chunk, &(mint, metadata))?;
+            }
+            anyhow::Ok(chunk)
+        });
+    let body = stream::once(async { anyhow::Ok(b"[".to_vec()) })
+        .chain(tokens)
+        .chain(stream::once(async { anyhow::Ok(b"]".to_vec()) }));
+
+    (
+        [(CONTENT_TYPE, "application/json")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Active tokens request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct ActiveTokensParams {
+    /// Window length in seconds.
+    #[serde(default = "default_active_window")]
+    window: u64,
+}
+
+fn default_active_window() -> u64 {
+    3600
+}
+
+/// Max window of active tokens.
+const MAX_ACTIVE_WINDOW: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Get mints of recently traded tokens request handler.
+pub(super) async fn get_active_tokens(
+    Query(params): Query<ActiveTokensParams>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let since = Utc::now() - Duration::from_secs(params.window).min(MAX_ACTIVE_WINDOW);
+
+    match state.storage.active_mints(since).await {
+        Ok(mints) => Json(mints).into_response(),
+        Err(e) => {
+            tracing::info!("Failed to get active tokens: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get active tokens: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Tokens ranked by buy pressure request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct PressureParams {
+    /// Resolution of candles to sum volumes of.
+    #[serde(default = "default_pressure_resolution")]
+    resolution: Resolution,
+    /// Window length in seconds.
+    #[serde(default = "default_pressure_window")]
+    window: u64,
+    /// Max number of tokens.
+    #[serde(default = "default_pressure_limit")]
+    limit: usize,
+}
+
+fn default_pressure_resolution() -> Resolution {
+    Resolution::M1
+}
+
+fn default_pressure_window() -> u64 {
+    3600
+}
+
+fn default_pressure_limit() -> usize {
+    50
+}
+
+/// Max number of tokens ranked by buy pressure.
+const MAX_PRESSURE_LIMIT: usize = 500;
+
+/// Max window of buy pressure ranking.
+const MAX_PRESSURE_WINDOW: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Get tokens ranked by net buy pressure request handler.
+pub(super) async fn get_tokens_by_pressure(
+    Query(params): Query<PressureParams>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if !params.resolution.is_enabled() {
+        return disabled_resolution_response(params.resolution);
+    }
+    let from = Utc::now() - Duration::from_secs(params.window).min(MAX_PRESSURE_WINDOW);
+    let limit = params.limit.min(MAX_PRESSURE_LIMIT);
+
+    match state
+        .storage
+        .tokens_by_pressure(from, params.resolution, limit)
+        .await
+    {
+        Ok(tokens) => Json(tokens).into_response(),
+        Err(e) => {
+            tracing::info!("Failed to get tokens by pressure: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get tokens by pressure: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get single token request handler.
+/// Responds with 404 if token is unknown and 400 if mint address is invalid.
+pub(super) async fn get_token(
+    Path(mint): Path<Mint>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let metadata = match state.storage.get_token_metadata(mint.as_str()).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.is::<NotFound>() => {
+            return (StatusCode::NOT_FOUND, Json(e.to_string())).into_response();
+        }
+        Err(e) => {
+            tracing::info!("Failed to get token: {e}.");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get token: {e}.")),
+            )
+                .into_response();
+        }
+    };
+
+    // Token may have no trades yet.
+    let last_trade = state
+        .storage
+        .last_trade_sourced(mint.as_str(), Resolution::finest_enabled())
+        .await
+        .inspect_err(|e| tracing::debug!("Failed to read last trade: {e}."))
+        .ok();
+
+    let mut headers = HeaderMap::new();
+    if let Some(last_trade) = &last_trade {
+        headers.insert(
+            "X-Data-Source",
+            HeaderValue::from_static(last_trade.source.as_str()),
+        );
+        if let Some(age) = last_trade.age() {
+            headers.insert("X-Data-Age", HeaderValue::from(age.as_secs()));
+        }
+    }
+
+    let details = TokenDetails {
+        mint: mint.into(),
+        metadata,
+        last_trade: last_trade.map(|trade| {
+            let (datetime, candle) = trade.data;
+            TradeOhlcv {
+                timestamp: datetime.timestamp_millis() as u64,
+                candle,
+            }
+        }),
+    };
+
+    (headers, Json(details)).into_response()
+}
+
+/// Get token candles request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct CandlesParams {
+    /// Comma separated resolutions, e.g. `M1,M5`.
+    resolutions: String,
+    /// Start of the window as UNIX timestamp in seconds.
+    from: i64,
+    /// IANA timezone, e.g. `America/New_York`.
+    /// Daily candles are aligned to local midnight, others stay UTC.
+    tz: Option<Tz>,
+}
+
+/// Candles time range request params.
+#[derive(Deserialize, Debug)]
+pub(super) struct CandleTimeRangeParams {
+    resolution: Resolution,
+}
+
+/// Extent of token candles history.
+#[derive(Serialize, Debug)]
+struct CandleTimeRange {
+    /// Start of the first candle as unix timestamp in milliseconds.
+    first: u64,
+    /// Start of the last candle as unix timestamp in milliseconds.
+    last: u64,
+}
+
+/// Get time range of token candles request handler.
+/// Responds with 404 if the token has no candles.
+pub(super) async fn get_candle_time_range(
+    Path(mint): Path<Mint>,
+    Query(params): Query<CandleTimeRangeParams>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if !params.resolution.is_enabled() {
+        return disabled_resolution_response(params.resolution);
+    }
+
+    match state
+        .storage
+        .candle_time_range(mint.as_str(), params.resolution)
+        .await
+    {
+        Ok(Some((first, last))) => Json(CandleTimeRange {
+            first: first.timestamp_millis() as u64,
+            last: last.timestamp_millis() as u64,
+        })
+        .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(format!("No {} candles of token {mint}.", params.resolution)),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::info!("Failed to get candles time range: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get candles time range: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get token candles for several resolutions request handler.
+/// Responds with candles grouped by resolution.
+pub(super) async fn get_token_candles(
+    Path(mint): Path<Mint>,
+    Query(params): Query<CandlesParams>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let resolutions: Result<Vec<Resolution>, _> =
+        params.resolutions.split(',').map(str::parse).collect();
+    let resolutions = match resolutions {
+        Ok(resolutions) => resolutions,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(e)).into_response(),
+    };
+    if let Some(resolution) = resolutions
+        .iter()
+        .find(|resolution| !resolution.is_enabled())
+    {
+        return disabled_resolution_response(*resolution);
+    }
+    let Some(from) = DateTime::from_timestamp(params.from, 0) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!("Invalid timestamp: {}", params.from)),
+        )
+            .into_response();
+    };
+
+    match token_candles(&state, &mint, from, resolutions, params.tz).await {
+        Ok(trades) => {
+            let candles: HashMap<_, Vec<_>> = trades
+                .into_iter()
+                .map(|(resolution, trades)| {
+                    let candles = trades
+                        .into_iter()
+                        .map(|(datetime, candle)| TradeOhlcv {
+                            timestamp: datetime.timestamp_millis() as u64,
+                            candle,
+                        })
+                        .collect();
+                    (resolution, candles)
+                })
+                .collect();
+
+            let mut headers = HeaderMap::new();
+            if let Some(tz) = params.tz
+                && let Ok(tz) = HeaderValue::from_str(tz.name())
+            {
+                headers.insert("X-Timezone", tz);
+            }
+            (headers, Json(candles)).into_response()
+        }
+        Err(e) => {
+            tracing::info!("Failed to get candles: {e}.");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Failed to get candles: {e}.")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Read token candles for several resolutions.
+/// With timezone, daily candles are aggregated from finer ones to align with local midnight.
+async fn token_candles(
+    state: &AppState,
+    mint: &Mint,
+    from: DateTime<Utc>,
+    mut resolutions: Vec<Resolution>,
+    tz: Option<Tz>,
+) -> anyhow::Result<HashMap<Resolution, BTreeMap<DateTime<Utc>, Candle>>> {
+    let local_days = tz.filter(|_| resolutions.contains(&Resolution::D1));
+    if local_days.is_some() {
+        resolutions.retain(|resolution| *resolution != Resolution::D1);
+    }
+
+    let mut trades = state
+        .storage
+        .trades_since_multi(mint.as_str(), from, &resolutions)
+        .await?;
+
+    if let Some(tz) = local_days {
+        let candles = state
+            .storage
+            .trades_since(
+                mint.as_str(),
+                local_time::local_day_start(from, tz),
+                local_time::local_day_source_resolution()?,
+            )
+            .await?;
+        trades.insert(
+            Resolution::D1,
+            local_time::aggregate_local_days(candles, tz),
+        );
+    }
+
+    Ok(trades)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::api::tests::{test_config, test_state};
+
+    /// Send GET request to the API router, returning response status and JSON body.
+    async fn get_json(state: Arc<AppState>, uri: &str) -> (StatusCode, Value) {
+        let router = crate::api::router(state, &test_config(&[]));
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn large_tokens_list_is_streamed_as_json(pool: sqlx::PgPool) {
+        // Names with characters to escape.
+        sqlx::query(
+            r#"INSERT INTO token (mint, name, symbol)
+            SELECT 'mint' || i, 'Token "' || i || E'"\n', 'T' || i
+            FROM generate_series(1, 10000) AS i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = test_state(pool).await;
+
+        let (status, streamed) = get_json(state.clone(), "/tokens?stream=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let streamed = streamed.as_array().unwrap();
+        assert_eq!(streamed.len(), 10_000);
+        let (_, listed) = get_json(state.clone(), "/tokens").await;
+        let mut streamed = streamed.clone();
+        let mut listed = listed.as_array().unwrap().clone();
+        let mint = |token: &Value| token[0].as_str().unwrap().to_string();
+        streamed.sort_by_key(mint);
+        listed.sort_by_key(mint);
+        assert_eq!(streamed, listed);
+
+        let (_, priced) = get_json(state, "/tokens?stream=true&with_price=true").await;
+        let priced = priced.as_array().unwrap();
+        assert_eq!(priced.len(), 10_000);
+        assert!(
+            priced
+                .iter()
+                .all(|token| token.as_array().unwrap().len() == 3)
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn empty_tokens_list_is_streamed_as_json(pool: sqlx::PgPool) {
+        let (status, streamed) = get_json(test_state(pool).await, "/tokens?stream=true").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(streamed, Value::Array(Vec::new()));
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn candle_time_range_is_reported(pool: sqlx::PgPool) {
+        use crate::db::Db;
+        use crate::model::TradeInfo;
+
+        const MINT: &str = "So11111111111111111111111111111111111111112";
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let trades: Vec<_> = [1_700_000_100, 1_700_000_400]
+            .into_iter()
+            .map(|secs| {
+                let timestamp = DateTime::from_timestamp(secs, 0).unwrap();
+                let info = TradeInfo {
+                    mint_acc: MINT.to_string(),
+                    timestamp,
+                    sol_amount: 1_000,
+                    token_amount: 2_000,
+                    is_buy: true,
+                    decimals: None,
+                };
+                (vec![(Resolution::M1, timestamp)], info)
+            })
+            .collect();
+        db.insert_trades(&trades).await.unwrap();
+        let state = test_state(pool).await;
+
+        let (status, range) = get_json(
+            state.clone(),
+            &format!("/tokens/{MINT}/range?resolution=M1"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            range,
+            serde_json::json!({"first": 1_700_000_100_000u64, "last": 1_700_000_400_000u64})
+        );
+
+        let (status, _) = get_json(state, &format!("/tokens/{MINT}/range?resolution=H1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn completed_tokens_are_flagged(pool: sqlx::PgPool) {
+        use crate::db::Db;
+
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        let (completed, active) = ("CompletedMint", "ActiveMint");
+        for mint in [completed, active] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+        }
+        let completed_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        db.mark_completed(completed, completed_at).await.unwrap();
+
+        let (status, tokens) = get_json(test_state(pool).await, "/tokens").await;
+        assert_eq!(status, StatusCode::OK);
+        let metadata = |mint: &str| {
+            tokens
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|token| token[0] == mint)
+                .unwrap()[1]
+                .clone()
+        };
+        assert_eq!(metadata(completed)["completed_at"], 1_700_000_000_000_u64);
+        assert!(metadata(active).get("completed_at").is_none());
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn tokens_search_responds_with_page(pool: sqlx::PgPool) {
+        sqlx::query(
+            "INSERT INTO token (mint, name, symbol)
+            SELECT 'mint' || i, 'Token ' || i, 'T' || i FROM generate_series(1, 5) AS i",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = test_state(pool).await;
+
+        let (status, page) = get_json(state.clone(), "/tokens?query=token&limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["tokens"].as_array().unwrap().len(), 2);
+        let (_, page) = get_json(state.clone(), "/tokens?query=%20token%203%20").await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["tokens"][0][0], "mint3");
+        let (_, page) = get_json(state.clone(), "/tokens?offset=4").await;
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["tokens"].as_array().unwrap().len(), 1);
+
+        let (status, _) = get_json(state, "/tokens?query=token&stream=true").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,1041 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::response::IntoResponse;
+use futures_util::{Sink, SinkExt};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::charts::{ChartWsPathParams, history_window, interpolate_candles};
+use super::{AppState, alerts, disabled_resolution_response};
+use crate::market_stats::MarketSnapshot;
+use crate::model::{Candle, CandleOpens, Mint, OpenPolicy, Resolution, TokenMetadata, TradeOhlcv};
+
+/// What chart WebSocket streams.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum StreamMode {
+    /// Send history window and close connection.
+    History,
+    /// Send only live updates.
+    Live,
+    /// Send history window, then live updates.
+    #[default]
+    HistoryThenLive,
+}
+
+/// Chart WebSocket messages format.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WsFormat {
+    /// JSON text messages, suitable for browsers.
+    #[default]
+    Json,
+    /// MessagePack binary messages with named fields, more compact for frequent updates.
+    Msgpack,
+}
+
+impl WsFormat {
+    /// Encode candle into a WebSocket message.
+    fn encode(&self, trade: &impl Serialize) -> anyhow::Result<Message> {
+        match self {
+            WsFormat::Json => Ok(Message::Text(serde_json::to_string(trade)?.into())),
+            WsFormat::Msgpack => Ok(Message::Binary(rmp_serde::to_vec_named(trade)?.into())),
+        }
+    }
+}
+
+/// Candle with fields derived from it, sent to clients requested enrichment.
+#[derive(Serialize, Debug)]
+struct EnrichedCandle<'a> {
+    #[serde(flatten)]
+    trade: &'a TradeOhlcv,
+    /// Percent change from open to close.
+    pct_change: f64,
+}
+
+impl<'a> EnrichedCandle<'a> {
+    fn new(trade: &'a TradeOhlcv) -> Self {
+        let candle = &trade.candle;
+        let pct_change = if candle.open > 0.0 {
+            (candle.close - candle.open) / candle.open * 100.0
+        } else {
+            0.0
+        };
+        Self { trade, pct_change }
+    }
+}
+
+/// State of a chart WebSocket subscription.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SubscriptionState {
+    /// Candles of the token will be streamed.
+    Subscribed,
+    /// Subscription is rejected and connection is closed.
+    Error,
+}
+
+/// First message of a chart WebSocket requested ack, so client knows whether
+/// the token exists instead of getting flat candles of an unknown one.
+#[derive(Serialize, Debug)]
+struct SubscriptionAck<'a> {
+    #[serde(rename = "type")]
+    state: SubscriptionState,
+    token: &'a str,
+    resolution: Resolution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Chart query params for a WebSocket request handler.
+#[derive(Deserialize, Debug)]
+pub(super) struct ChartWsQueryParams {
+    #[serde(default)]
+    mode: StreamMode,
+    #[serde(default)]
+    format: WsFormat,
+    /// Send only candles of ended buckets, each one once, instead of repainting the current one.
+    #[serde(default)]
+    closed_only: bool,
+    /// History window start, unix timestamp in seconds.
+    from: Option<i64>,
+    /// History window end, unix timestamp in seconds.
+    to: Option<i64>,
+    /// Number of history points if window start isn't requested, server default if not set.
+    /// Capped by `MAX_WS_HISTORY_POINTS`.
+    points: Option<usize>,
+    /// Candles open price definition, server default if not set.
+    open: Option<OpenPolicy>,
+    /// Add derived fields to candles, they are omitted by default to keep messages small.
+    #[serde(default)]
+    enrich: bool,
+    /// Send subscription ack or error before candles, see [`SubscriptionAck`].
+    #[serde(default)]
+    ack: bool,
+}
+
+impl ChartWsQueryParams {
+    /// Requested number of history points capped by `MAX_WS_HISTORY_POINTS`.
+    fn history_points(&self) -> Option<usize> {
+        self.points.map(|points| points.min(MAX_WS_HISTORY_POINTS))
+    }
+
+    /// Encode candle into a WebSocket message in requested format.
+    fn encode(&self, trade: &TradeOhlcv) -> anyhow::Result<Message> {
+        if self.enrich {
+            self.format.encode(&EnrichedCandle::new(trade))
+        } else {
+            self.format.encode(trade)
+        }
+    }
+}
+
+/// Send subscription ack if the token exists, error otherwise.
+/// Returns whether candles should be streamed.
+async fn ack_subscription(
+    token: &Mint,
+    resolution: Resolution,
+    params: &ChartWsQueryParams,
+    socket: &mut WebSocket,
+    state: &AppState,
+) -> anyhow::Result<bool> {
+    let (subscription_state, error) = match state.storage.token_exists(token.as_str()).await {
+        Ok(true) => (SubscriptionState::Subscribed, None),
+        Ok(false) => (
+            SubscriptionState::Error,
+            Some(format!("Unknown token {token}.")),
+        ),
+        Err(e) => {
+            tracing::info!("Failed to check token existence: {e}.");
+            (
+                SubscriptionState::Error,
+                Some(format!("Failed to check token: {e}.")),
+            )
+        }
+    };
+    let subscribed = error.is_none();
+
+    let ack = SubscriptionAck {
+        state: subscription_state,
+        token: token.as_str(),
+        resolution,
+        error,
+    };
+    send_with_timeout(socket, params.format.encode(&ack)?).await?;
+
+    Ok(subscribed)
+}
+
+/// Upgrade HTTP connection into WebSocket.
+/// Responds with 400 if mint address is invalid.
+pub(super) async fn chart_data_ws(
+    Path(path): Path<ChartWsPathParams>,
+    Query(params): Query<ChartWsQueryParams>,
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    if !path.resolution.is_enabled() {
+        return disabled_resolution_response(path.resolution);
+    }
+
+    ws.on_upgrade(move |socket| async move {
+        let result = handle_websocket(path.token, path.resolution, params, socket, state).await;
+        if let Err(e) = result {
+            tracing::warn!("WS connection failure: {e}.");
+        }
+    })
+    .into_response()
+}
+
+/// Upgrade HTTP connection into price alerts WebSocket.
+pub(super) async fn alerts_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let trades = state.storage.subscribe_trades();
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = alerts::handle_websocket(socket, trades).await {
+            tracing::warn!("Alerts WS connection failure: {e}.");
+        }
+    })
+}
+
+/// New tokens WebSocket request handler.
+pub(super) async fn new_tokens_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let tokens = state.storage.subscribe_tokens();
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = handle_tokens_websocket(socket, tokens).await {
+            tracing::warn!("New tokens WS connection failure: {e}.");
+        }
+    })
+}
+
+/// New tokens WebSocket connection handler.
+/// Sends new tokens and tokens with resolved metadata in the same format as `/tokens` list.
+async fn handle_tokens_websocket(
+    mut socket: WebSocket,
+    mut tokens: broadcast::Receiver<(String, TokenMetadata)>,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                // Client messages are ignored, only disconnect matters.
+                // Pings are answered by axum.
+                if let None | Some(Message::Close(_)) = msg.transpose()? {
+                    return Ok(());
+                }
+            }
+            token = tokens.recv() => {
+                let token = match token {
+                    Ok(token) => token,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("New tokens connection skipped {skipped} tokens.");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                let json_token = serde_json::to_string(&token)?;
+                send_with_timeout(&mut socket, Message::Text(json_token.into())).await?;
+            }
+        }
+    }
+}
+
+/// Market stats WebSocket request handler.
+pub(super) async fn market_stats_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let (latest, snapshots) = state.market_stats.subscribe();
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = handle_market_stats_websocket(socket, latest, snapshots).await {
+            tracing::warn!("Market stats WS connection failure: {e}.");
+        }
+    })
+}
+
+/// Market stats WebSocket connection handler.
+/// Sends the latest stats snapshot right away, then every new one.
+async fn handle_market_stats_websocket(
+    mut socket: WebSocket,
+    latest: Option<MarketSnapshot>,
+    mut snapshots: broadcast::Receiver<MarketSnapshot>,
+) -> anyhow::Result<()> {
+    if let Some(snapshot) = latest {
+        let json_snapshot = serde_json::to_string(&snapshot)?;
+        send_with_timeout(&mut socket, Message::Text(json_snapshot.into())).await?;
+    }
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                // Client messages are ignored, only disconnect matters.
+                // Pings are answered by axum.
+                if let None | Some(Message::Close(_)) = msg.transpose()? {
+                    return Ok(());
+                }
+            }
+            snapshot = snapshots.recv() => {
+                let snapshot = match snapshot {
+                    Ok(snapshot) => snapshot,
+                    // Only the latest snapshot matters.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                let json_snapshot = serde_json::to_string(&snapshot)?;
+                send_with_timeout(&mut socket, Message::Text(json_snapshot.into())).await?;
+            }
+        }
+    }
+}
+
+/// Refresh interval for a WebSocket connection.
+const PRICE_WS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Max number of history points requested by a chart WebSocket client.
+/// Every point is a separate message, so it's lower than REST limit.
+const MAX_WS_HISTORY_POINTS: usize = 1000;
+
+/// Max time to wait for a slow client to accept a WebSocket message.
+const WS_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send message to WebSocket client.
+/// Fails if client doesn't accept it within `WS_SEND_TIMEOUT`, so stuck clients don't hold the task forever.
+async fn send_with_timeout<S>(socket: &mut S, msg: Message) -> anyhow::Result<()>
+where
+    S: Sink<Message, Error = axum::Error> + Unpin,
+{
+    tokio::time::timeout(WS_SEND_TIMEOUT, socket.send(msg))
+        .await
+        .map_err(|_| anyhow::anyhow!("Client didn't accept message in {WS_SEND_TIMEOUT:?}"))??;
+    Ok(())
+}
+
+/// WebSocket connection handler.
+async fn handle_websocket(
+    token: Mint,
+    resolution: Resolution,
+    params: ChartWsQueryParams,
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+) -> anyhow::Result<()> {
+    let _connection = state.connections.register(token.as_str(), resolution);
+
+    if params.ack && !ack_subscription(&token, resolution, &params, &mut socket, &state).await? {
+        send_with_timeout(&mut socket, Message::Close(None)).await?;
+        return Ok(());
+    }
+
+    // Keep hot token candles in memory while streaming.
+    let _hot_subscriber = match params.mode {
+        StreamMode::History => None,
+        _ => Some(
+            state
+                .storage
+                .watch_candles(token.as_str(), resolution)
+                .await,
+        ),
+    };
+
+    // Applied to all sent candles, so the first live one continues history.
+    let mut opens = CandleOpens::new(params.open.unwrap_or(state.open_policy));
+
+    if params.mode != StreamMode::Live {
+        send_history(&token, resolution, &params, &mut opens, &mut socket, &state).await?;
+    }
+
+    if params.mode == StreamMode::History {
+        send_with_timeout(&mut socket, Message::Close(None)).await?;
+        return Ok(());
+    }
+
+    if params.closed_only {
+        return stream_closed_candles(&token, resolution, &params, &mut opens, &mut socket, &state)
+            .await;
+    }
+
+    // Send candle updates as trades arrive and last trade data periodically,
+    // so empty buckets are sent too.
+    let mut candles = state
+        .storage
+        .subscribe_candles(token.to_string(), resolution);
+    let mut refresh = tokio::time::interval(PRICE_WS_REFRESH_INTERVAL);
+    loop {
+        let mut trade = tokio::select! {
+            msg = socket.recv() => {
+                let connected = handle_chart_ws_message(
+                    msg, &token, resolution, &params, &mut opens, &mut socket, &state,
+                )
+                .await?;
+                if !connected {
+                    return Ok(());
+                }
+                continue;
+            }
+            candle = candles.recv() => match candle {
+                Ok(candle) => candle,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = refresh.tick() => match last_candle(&token, resolution, &state).await {
+                Some(candle) => candle,
+                // Nothing to repaint until the token is traded.
+                None => continue,
+            },
+        };
+
+        opens.apply(&mut trade);
+        send_with_timeout(&mut socket, params.encode(&trade)?).await?;
+    }
+}
+
+/// Send candles of ended buckets as their boundaries are crossed.
+/// The bucket open at connection is the first one sent, once it ends.
+async fn stream_closed_candles(
+    token: &Mint,
+    resolution: Resolution,
+    params: &ChartWsQueryParams,
+    opens: &mut CandleOpens,
+    socket: &mut WebSocket,
+    state: &AppState,
+) -> anyhow::Result<()> {
+    let mut open_bucket = resolution.bucket_start(Utc::now());
+    let mut last_close = state
+        .storage
+        .last_trade(token.as_str(), resolution)
+        .await
+        .ok()
+        .map(|(_, candle)| candle.close);
+    let step = Duration::from_secs(resolution.as_seconds());
+    let mut refresh = tokio::time::interval(PRICE_WS_REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let connected =
+                    handle_chart_ws_message(msg, token, resolution, params, opens, socket, state)
+                        .await?;
+                if !connected {
+                    return Ok(());
+                }
+                continue;
+            }
+            _ = refresh.tick() => {}
+        }
+
+        let current_bucket = resolution.bucket_start(Utc::now());
+        if current_bucket <= open_bucket {
+            continue;
+        }
+
+        // Buckets are read again on the next tick if storage fails.
+        let candles = match state
+            .storage
+            .trades_since(token.as_str(), open_bucket, resolution)
+            .await
+        {
+            Ok(candles) => candles,
+            Err(e) => {
+                tracing::info!("Failed to read closed candles: {e}.");
+                continue;
+            }
+        };
+
+        // Several buckets may end between ticks.
+        while open_bucket < current_bucket {
+            let candle = match (candles.get(&open_bucket), last_close) {
+                (Some(candle), _) => Some(*candle),
+                (None, Some(close)) => Some(Candle::flat(close)),
+                // No trades of the token yet.
+                (None, None) => None,
+            };
+            if let Some(candle) = candle {
+                last_close = Some(candle.close);
+                let mut trade = TradeOhlcv {
+                    timestamp: open_bucket.timestamp_millis() as u64,
+                    candle,
+                };
+                opens.apply(&mut trade);
+                send_with_timeout(socket, params.encode(&trade)?).await?;
+            }
+            open_bucket = resolution.bucket_start(open_bucket + step);
+        }
+    }
+}
+
+/// Control message of a chart WebSocket client.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ChartWsControl {
+    /// Send history window again, e.g. when client's buffer is incomplete.
+    Refresh,
+}
+
+/// Handle message of a chart WebSocket client.
+/// History window is sent again on refresh request, unknown messages are ignored.
+/// Pings are answered by axum.
+/// Returns `false` if client disconnected or sent close frame, so streaming stops right away.
+async fn handle_chart_ws_message(
+    msg: Option<Result<Message, axum::Error>>,
+    token: &Mint,
+    resolution: Resolution,
+    params: &ChartWsQueryParams,
+    opens: &mut CandleOpens,
+    socket: &mut WebSocket,
+    state: &AppState,
+) -> anyhow::Result<bool> {
+    let text = match msg.transpose()? {
+        None | Some(Message::Close(_)) => return Ok(false),
+        Some(Message::Text(text)) => text,
+        Some(_) => return Ok(true),
+    };
+
+    match serde_json::from_str::<ChartWsControl>(&text) {
+        Ok(ChartWsControl::Refresh) => {
+            // History precedes already sent candles.
+            opens.reset();
+            send_history(token, resolution, params, opens, socket, state).await?;
+        }
+        Err(e) => tracing::debug!("Unknown chart WS message {text:?}: {e}."),
+    }
+    Ok(true)
+}
+
+/// Candle of the current bucket, empty one if there are no trades in it yet.
+/// Returns `None` if last trade is unknown, rather than a candle with zero price,
+/// which valid trades never have.
+async fn last_candle(token: &Mint, resolution: Resolution, state: &AppState) -> Option<TradeOhlcv> {
+    let current_timestamp = resolution.bucket_start(Utc::now());
+    let (last_db_ts, last_db_candle) =
+        match state.storage.last_trade(token.as_str(), resolution).await {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::info!("Failed to read last price: {e}.");
+                return None;
+            }
+        };
+
+    let candle = if resolution.bucket_contains(last_db_ts, current_timestamp) {
+        last_db_candle
+    } else {
+        Candle::flat(last_db_candle.close)
+    };
+
+    Some(TradeOhlcv {
+        timestamp: current_timestamp.timestamp_millis() as u64,
+        candle,
+    })
+}
+
+/// Send history window to the client.
+/// Window is requested by client or depends on resolution by default.
+/// It starts no earlier than the first candle of the token, so young tokens don't get
+/// history before they existed.
+async fn send_history(
+    token: &Mint,
+    resolution: Resolution,
+    params: &ChartWsQueryParams,
+    opens: &mut CandleOpens,
+    socket: &mut WebSocket,
+    state: &AppState,
+) -> anyhow::Result<()> {
+    let (from_timestamp, to_timestamp) = history_window(
+        params.from,
+        params.to,
+        params.history_points(),
+        state.history_points,
+        resolution,
+    );
+    let from_timestamp = match state
+        .storage
+        .candle_time_range(token.as_str(), resolution)
+        .await
+    {
+        Ok(Some((first, _))) => from_timestamp.max(first),
+        // Never traded, nothing to send.
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            tracing::info!("Failed to read first candle: {e}.");
+            from_timestamp
+        }
+    };
+
+    let db_candles = state
+        .storage
+        .trades_since(token.as_str(), from_timestamp, resolution)
+        .await
+        .inspect_err(|e| tracing::info!("Failed to read prices history: {e}."))
+        .unwrap_or_default();
+
+    let candles = interpolate_candles(from_timestamp, to_timestamp, resolution, db_candles);
+
+    for mut price in candles {
+        opens.apply(&mut price);
+        send_with_timeout(socket, params.encode(&price)?).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::Uri;
+    use axum::routing::get;
+    use futures_util::StreamExt;
+    use sqlx::PgPool;
+    use sqlx::types::chrono::DateTime;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    use crate::api::tests::{serve, test_state};
+    use crate::db::Db;
+    use crate::model::TradeInfo;
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Insert token traded 4 hours ago, at the start of 5 points H1 history window.
+    async fn traded_token(pool: &PgPool) {
+        let timestamp = Utc::now() - Duration::from_secs(4 * 3600);
+        token_traded_at(pool, Resolution::H1, timestamp).await;
+    }
+
+    /// Insert token with a single trade into candle of given resolution.
+    async fn token_traded_at(pool: &PgPool, resolution: Resolution, timestamp: DateTime<Utc>) {
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let info = TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp,
+            sol_amount: 1_000,
+            token_amount: 2_000,
+            is_buy: true,
+            decimals: None,
+        };
+        let bucket = resolution.bucket_start(timestamp);
+        db.insert_trades(&[(vec![(resolution, bucket)], info)])
+            .await
+            .unwrap();
+    }
+
+    /// Connect to H1 chart WebSocket of the token with given query.
+    async fn connect(pool: PgPool, query: &str) -> Client {
+        connect_to(pool, Resolution::H1, query).await
+    }
+
+    /// Connect to chart WebSocket of the token with given resolution and query.
+    async fn connect_to(pool: PgPool, resolution: Resolution, query: &str) -> Client {
+        let router = Router::new()
+            .route("/chart_data_ws/{token}/{resolution}", get(chart_data_ws))
+            .with_state(test_state(pool).await);
+        let addr = serve(router).await;
+        let url = format!("ws://{addr}/chart_data_ws/{MINT}/{resolution}?{query}");
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    /// Next frame sent by server, `None` if connection is closed.
+    async fn next_frame(client: &mut Client) -> Option<ClientMessage> {
+        tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("frame is sent in time")
+            .map(|frame| frame.unwrap())
+    }
+
+    /// Timestamp of the candle sent in the frame.
+    fn candle_timestamp(frame: &ClientMessage) -> u64 {
+        let ClientMessage::Text(text) = frame else {
+            panic!("expected candle, got {frame:?}");
+        };
+        let trade: TradeOhlcv = serde_json::from_str(text).unwrap();
+        trade.timestamp
+    }
+
+    /// Timestamps of the history window of 5 points, which ends at the current bucket.
+    async fn receive_history(client: &mut Client) {
+        let mut timestamps = Vec::new();
+        for _ in 0..5 {
+            timestamps.push(candle_timestamp(&next_frame(client).await.unwrap()));
+        }
+        // Buckets are consecutive, unlike repainted live ones.
+        let hour = 3600 * 1000;
+        let expected: Vec<_> = (0..5).rev().map(|i| current_bucket() - i * hour).collect();
+        assert_eq!(timestamps, expected);
+    }
+
+    fn current_bucket() -> u64 {
+        Resolution::H1.bucket_start(Utc::now()).timestamp_millis() as u64
+    }
+
+    fn params(uri: &'static str) -> ChartWsQueryParams {
+        Query::try_from_uri(&Uri::from_static(uri)).unwrap().0
+    }
+
+    #[test]
+    fn history_points_are_capped() {
+        assert_eq!(params("/").history_points(), None);
+        assert_eq!(params("/?points=500").history_points(), Some(500));
+        assert_eq!(
+            params("/?points=10000").history_points(),
+            Some(MAX_WS_HISTORY_POINTS)
+        );
+    }
+
+    /// Fields of the candle JSON message encoded with given query.
+    fn encoded_fields(uri: &'static str, trade: &TradeOhlcv) -> serde_json::Value {
+        let Message::Text(text) = params(uri).encode(trade).unwrap() else {
+            panic!("JSON candle isn't a text message");
+        };
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn candles_are_enriched_only_on_request() {
+        let trade = TradeOhlcv {
+            timestamp: 1_700_000_040_000,
+            candle: Candle {
+                open: 2.0,
+                close: 2.5,
+                ..Candle::flat(2.0)
+            },
+        };
+
+        let plain = encoded_fields("/", &trade);
+        assert!(plain.get("pct_change").is_none(), "{plain}");
+        let enriched = encoded_fields("/?enrich=true", &trade);
+        assert_eq!(enriched["pct_change"], 25.0);
+        // Candle fields are kept as is.
+        assert_eq!(enriched["timestamp"], plain["timestamp"]);
+        assert_eq!(enriched["candle"], plain["candle"]);
+
+        let unpriced = TradeOhlcv::default();
+        assert_eq!(
+            encoded_fields("/?enrich=true", &unpriced)["pct_change"],
+            0.0
+        );
+    }
+
+    #[test]
+    fn enriched_candle_is_encoded_as_msgpack() {
+        let trade = TradeOhlcv {
+            timestamp: 1_700_000_040_000,
+            candle: Candle {
+                close: 1.0,
+                ..Candle::flat(4.0)
+            },
+        };
+        let Message::Binary(bytes) = params("/?enrich=true&format=msgpack")
+            .encode(&trade)
+            .unwrap()
+        else {
+            panic!("MessagePack candle isn't a binary message");
+        };
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded["pct_change"], -75.0);
+        assert_eq!(decoded["timestamp"], 1_700_000_040_000u64);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn history_mode_closes_after_window(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "mode=history&points=5").await;
+
+        receive_history(&mut client).await;
+        let frame = next_frame(&mut client).await;
+        assert!(
+            matches!(frame, None | Some(ClientMessage::Close(_))),
+            "{frame:?}"
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn young_token_history_starts_at_first_candle(pool: PgPool) {
+        let now = Utc::now();
+        token_traded_at(&pool, Resolution::D1, now - Duration::from_secs(5)).await;
+        // Default D1 window is 100 days long.
+        let mut client = connect_to(pool, Resolution::D1, "mode=history").await;
+
+        let frame = next_frame(&mut client).await.unwrap();
+        let first = Resolution::D1.bucket_start(now - Duration::from_secs(5));
+        assert_eq!(candle_timestamp(&frame), first.timestamp_millis() as u64);
+        // Day may turn over right after the trade, then the current bucket follows.
+        if first != Resolution::D1.bucket_start(Utc::now()) {
+            next_frame(&mut client).await.unwrap();
+        }
+        let frame = next_frame(&mut client).await;
+        assert!(
+            matches!(frame, None | Some(ClientMessage::Close(_))),
+            "{frame:?}"
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn live_mode_skips_history(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "mode=live&points=5").await;
+
+        // Only the current bucket is repainted.
+        for _ in 0..2 {
+            let frame = next_frame(&mut client).await.unwrap();
+            assert_eq!(candle_timestamp(&frame), current_bucket());
+        }
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn history_then_live_mode_streams_after_window(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "points=5").await;
+
+        receive_history(&mut client).await;
+        let frame = next_frame(&mut client).await.unwrap();
+        assert_eq!(candle_timestamp(&frame), current_bucket());
+    }
+
+    /// JSON message sent in the frame.
+    fn json_frame(frame: Option<ClientMessage>) -> serde_json::Value {
+        let Some(ClientMessage::Text(text)) = frame else {
+            panic!("expected JSON message, got {frame:?}");
+        };
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn known_token_subscription_is_acked(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "ack=true&mode=history&points=5").await;
+
+        let ack = json_frame(next_frame(&mut client).await);
+        assert_eq!(
+            ack,
+            serde_json::json!({"type": "subscribed", "token": MINT, "resolution": "H1"})
+        );
+        receive_history(&mut client).await;
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn unknown_token_subscription_is_rejected(pool: PgPool) {
+        let mut client = connect(pool, "ack=true").await;
+
+        let ack = json_frame(next_frame(&mut client).await);
+        assert_eq!(ack["type"], "error");
+        assert_eq!(ack["token"], MINT);
+        assert_eq!(ack["resolution"], "H1");
+        assert_eq!(ack["error"], format!("Unknown token {MINT}."));
+        // No candles follow.
+        let frame = next_frame(&mut client).await;
+        assert!(
+            matches!(frame, None | Some(ClientMessage::Close(_))),
+            "{frame:?}"
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn refresh_request_resends_history(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "points=5").await;
+        receive_history(&mut client).await;
+
+        let refresh = ClientMessage::Text(r#"{"action":"refresh"}"#.into());
+        client.send(refresh).await.unwrap();
+        // Live candles may be repainted before the request is handled.
+        let first = current_bucket() - 4 * 3600 * 1000;
+        loop {
+            let timestamp = candle_timestamp(&next_frame(&mut client).await.unwrap());
+            if timestamp == first {
+                break;
+            }
+            assert_eq!(timestamp, current_bucket());
+        }
+        for i in 1..5 {
+            let timestamp = candle_timestamp(&next_frame(&mut client).await.unwrap());
+            assert_eq!(timestamp, first + i * 3600 * 1000);
+        }
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn unknown_control_message_is_ignored(pool: PgPool) {
+        traded_token(&pool).await;
+        let mut client = connect(pool, "points=5").await;
+        receive_history(&mut client).await;
+
+        let unknown = ClientMessage::Text(r#"{"action":"rewind"}"#.into());
+        client.send(unknown).await.unwrap();
+        // Streaming goes on with live candles only.
+        for _ in 0..2 {
+            let frame = next_frame(&mut client).await.unwrap();
+            assert_eq!(candle_timestamp(&frame), current_bucket());
+        }
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn new_and_resolved_tokens_are_streamed(pool: PgPool) {
+        let state = test_state(pool).await;
+        let storage = state.storage.clone();
+        let router = Router::new()
+            .route("/new_tokens_ws", get(new_tokens_ws))
+            .with_state(state);
+        let addr = serve(router).await;
+        let (mut client, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/new_tokens_ws"))
+                .await
+                .unwrap();
+
+        storage
+            .insert_token_metadata(MINT.to_string(), None)
+            .await
+            .unwrap();
+        let metadata = TokenMetadata {
+            name: String::from("Token"),
+            symbol: String::from("TKN"),
+            uri: String::from("https://example.com/token.json"),
+            completed_at: None,
+            decimals: Some(6),
+        };
+        storage
+            .insert_token_metadata(MINT.to_string(), Some(metadata))
+            .await
+            .unwrap();
+
+        for name in ["unknown", "Token"] {
+            let Some(ClientMessage::Text(text)) = next_frame(&mut client).await else {
+                panic!("expected token");
+            };
+            let (mint, token): (String, TokenMetadata) = serde_json::from_str(&text).unwrap();
+            assert_eq!(mint, MINT);
+            assert_eq!(token.name, name);
+        }
+    }
+
+    /// Client which never accepts messages.
+    struct StalledClient;
+
+    impl Sink<Message> for StalledClient {
+        type Error = axum::Error;
+
+        fn poll_ready(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+
+        fn start_send(self: Pin<&mut Self>, _item: Message) -> Result<(), Self::Error> {
+            unreachable!("Stalled client is never ready")
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_client_is_given_up_after_timeout() {
+        let started = tokio::time::Instant::now();
+        let e = send_with_timeout(&mut StalledClient, Message::Text("{}".into()))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("didn't accept"), "{e}");
+        assert_eq!(started.elapsed(), WS_SEND_TIMEOUT);
+    }
+
+    #[test]
+    fn candle_round_trips_through_msgpack() {
+        let trade = TradeOhlcv {
+            timestamp: 1_700_000_000_000,
+            candle: Candle {
+                open: 0.5,
+                close: 0.25,
+                high: 0.5,
+                low: 0.125,
+                volume: 3_000.0,
+                trade_count: 2,
+                buy_volume: 1_000.0,
+                sell_volume: 2_000.0,
+                vwap: 0.375,
+            },
+        };
+
+        let Message::Binary(bytes) = WsFormat::Msgpack.encode(&trade).unwrap() else {
+            panic!("MessagePack candle isn't a binary message");
+        };
+        let decoded: TradeOhlcv = rmp_serde::from_slice(&bytes).unwrap();
+        let Message::Text(json) = WsFormat::Json.encode(&trade).unwrap() else {
+            panic!("JSON candle isn't a text message");
+        };
+        // Both framings carry the same candle.
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json.as_str());
+        assert_eq!(decoded.timestamp, trade.timestamp);
+        assert_eq!(decoded.candle.low, 0.125);
+        assert_eq!(decoded.candle.trade_count, 2);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn closed_only_candles_are_sent_once_ended(pool: PgPool) {
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let info = TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: Utc::now(),
+            sol_amount: 1_000,
+            token_amount: 2_000,
+            is_buy: true,
+            decimals: None,
+        };
+        let bucket = Resolution::S1.bucket_start(info.timestamp);
+        db.insert_trades(&[(vec![(Resolution::S1, bucket)], info)])
+            .await
+            .unwrap();
+        let mut client = connect_to(pool, Resolution::S1, "mode=live&closed_only=true").await;
+
+        let mut previous = None;
+        for _ in 0..2 {
+            let timestamp = candle_timestamp(&next_frame(&mut client).await.unwrap());
+            // Candle is sent only after its bucket ended.
+            let ended_at = timestamp + 1000;
+            assert!(ended_at <= Utc::now().timestamp_millis() as u64);
+            // Every bucket is sent once, in order.
+            if let Some(previous) = previous {
+                assert_eq!(timestamp, previous + 1000);
+            }
+            previous = Some(timestamp);
+        }
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn unknown_last_trade_has_no_candle(pool: PgPool) {
+        let state = test_state(pool).await;
+        let token = Mint::try_from(MINT.to_string()).unwrap();
+        assert!(last_candle(&token, Resolution::H1, &state).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn untraded_token_gets_no_zero_candle(pool: PgPool) {
+        let mut client = connect(pool, "mode=live").await;
+
+        // Refresh ticks pass without any candle sent.
+        let frame = tokio::time::timeout(PRICE_WS_REFRESH_INTERVAL * 2, client.next()).await;
+        assert!(frame.is_err(), "{frame:?}");
+    }
+}
@@ -0,0 +1,145 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use solana_pubkey::Pubkey;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::metadata_breaker::MetadataBreaker;
+use crate::pump_handler::PumpHandler;
+use crate::rpc_pool::RpcPool;
+use crate::storage::Storage;
+
+/// Delay between metadata queries to respect RPC rate limits.
+const QUERY_DELAY: Duration = Duration::from_millis(200);
+
+/// Max delay between metadata queries after consecutive failures.
+const MAX_QUERY_DELAY: Duration = Duration::from_secs(30);
+
+/// Re-queries metadata of tokens which were stored with fallback values,
+/// e.g. because RPC was unavailable when token was first met.
+#[derive(Clone)]
+pub struct MetadataBackfill {
+    storage: Storage,
+    breaker: MetadataBreaker,
+    rpc: RpcPool,
+    /// Tokens processed per DB query and metadata RPC call.
+    batch_size: usize,
+    running: Arc<Mutex<()>>,
+}
+
+impl MetadataBackfill {
+    /// Create new backfill.
+    pub fn new(
+        storage: Storage,
+        breaker: MetadataBreaker,
+        rpc: RpcPool,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            storage,
+            breaker,
+            rpc,
+            batch_size,
+            running: Default::default(),
+        }
+    }
+
+    /// Run backfill periodically.
+    pub async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let Some(guard) = self.try_lock() else {
+                tracing::info!("Metadata backfill is already running, skipping.");
+                continue;
+            };
+
+            if let Err(e) = self.backfill(guard).await {
+                tracing::warn!("Metadata backfill failed: {e}");
+            }
+        }
+    }
+
+    /// Try to acquire exclusive right to run backfill.
+    /// Returns `None` if backfill is already running.
+    pub fn try_lock(&self) -> Option<OwnedMutexGuard<()>> {
+        self.running.clone().try_lock_owned().ok()
+    }
+
+    /// Query metadata for all unresolved tokens.
+    /// Returns number of resolved tokens.
+    pub async fn backfill(&self, _guard: OwnedMutexGuard<()>) -> anyhow::Result<usize> {
+        let mut resolved = 0;
+        let mut failed = 0;
+        let mut skipped = 0;
+        let mut delay = QUERY_DELAY;
+        let mut last_mint = String::new();
+
+        loop {
+            let mints = self
+                .storage
+                .get_unresolved_tokens(&last_mint, self.batch_size)
+                .await?;
+            let Some(last) = mints.last() else {
+                break;
+            };
+            last_mint = last.clone();
+
+            let mut pubkeys = Vec::with_capacity(mints.len());
+            let mut valid_mints = Vec::with_capacity(mints.len());
+            for mint in mints {
+                if self.breaker.is_open(&mint) {
+                    skipped += 1;
+                    continue;
+                }
+
+                match Pubkey::from_str(&mint) {
+                    Ok(pubkey) => {
+                        pubkeys.push(pubkey);
+                        valid_mints.push(mint);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Bad mint address {mint}: {e}");
+                        failed += 1;
+                    }
+                }
+            }
+
+            if pubkeys.is_empty() {
+                continue;
+            }
+
+            tokio::time::sleep(delay).await;
+
+            match PumpHandler::query_token_metadata_batch(&self.rpc, &pubkeys).await {
+                Ok(metadata) => {
+                    for (mint, metadata) in valid_mints.into_iter().zip(metadata) {
+                        let Some(metadata) = metadata else {
+                            tracing::debug!("Metadata of token {mint} not found");
+                            self.breaker.record_failure(&mint);
+                            failed += 1;
+                            continue;
+                        };
+                        self.breaker.record_success(&mint);
+                        self.storage
+                            .insert_token_metadata(mint, Some(metadata))
+                            .await?;
+                        resolved += 1;
+                    }
+                    delay = QUERY_DELAY;
+                }
+                Err(e) => {
+                    tracing::debug!("Failed to query metadata batch: {e}");
+                    failed += valid_mints.len();
+                    delay = (delay * 2).min(MAX_QUERY_DELAY);
+                }
+            }
+        }
+
+        tracing::info!(
+            "Metadata backfill finished: {resolved} resolved, {failed} failed, {skipped} suspended."
+        );
+        Ok(resolved)
+    }
+}
@@ -1,14 +1,19 @@
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
+use dashmap::DashMap;
 use redis::Client;
 use sqlx::types::chrono::{DateTime, Utc};
 
-use crate::model::{Candle, Resolution, TradeInfo};
+use crate::config::Config;
+use crate::model::{BucketedTrade, Candle, Resolution, TradeInfo};
 
 #[derive(Clone)]
 pub struct Cache {
     redis: Client,
+    /// Retention of tokens overriding `RETENTION_PERIOD`.
+    retentions: Arc<DashMap<String, Duration>>,
 }
 
 const MILLIS_IN_DAY: u64 = 24 * 60 * 60 * 1000;
@@ -18,52 +23,195 @@ pub const RETENTION_PERIOD: Duration = Duration::from_millis(MILLIS_IN_DAY);
 
 impl Cache {
     /// Create new cache instance.
-    pub async fn new(conn_str: &str) -> anyhow::Result<Self> {
-        let redis = redis::Client::open(conn_str)?;
-        let mut connection = redis.get_multiplexed_async_connection().await?;
-
-        let retention = RETENTION_PERIOD.as_millis().to_string();
-        redis::cmd("CONFIG")
-            .arg(&["SET", "ts-retention-policy", &retention])
-            .exec_async(&mut connection)
-            .await?;
+    pub async fn new(config: &Config) -> anyhow::Result<Self> {
+        let cache = Self::open(&config.redis_conn_str)?;
+
+        // Fail early if Redis is unreachable.
+        cache.ping().await?;
+
+        Ok(cache)
+    }
+
+    /// Create cache instance without connecting to Redis.
+    pub fn open(redis_conn_str: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            redis: Client::open(redis_conn_str)?,
+            retentions: Default::default(),
+        })
+    }
 
-        Ok(Self { redis })
+    /// Check Redis is reachable.
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        let mut connection = self.redis.get_multiplexed_async_connection().await?;
+        redis::cmd("PING").exec_async(&mut connection).await?;
+        Ok(())
     }
 
-    /// Insert trade event into cache.
-    pub async fn insert_trade(
+    /// Set retention overrides of tokens, see [`Cache::set_token_retention`].
+    pub fn load_retentions(&self, retentions: impl IntoIterator<Item = (String, Duration)>) {
+        for (mint, retention) in retentions {
+            self.retentions.insert(mint, retention);
+        }
+    }
+
+    /// Set retention of token series, overriding `RETENTION_PERIOD`.
+    /// Existing series are altered, missing ones get it on creation.
+    /// Returns number of altered series.
+    pub async fn set_token_retention(
         &self,
-        timestamps: &[DateTime<Utc>],
-        info: TradeInfo,
-    ) -> anyhow::Result<()> {
+        mint: &str,
+        retention: Duration,
+    ) -> anyhow::Result<usize> {
+        self.retentions.insert(mint.to_string(), retention);
         let mut connection = self.redis.get_multiplexed_async_connection().await?;
 
-        let price = (info.sol_amount as f64) / (info.token_amount as f64);
-        if !price.is_finite() {
-            anyhow::bail!("Bad price: {} / {}", info.sol_amount, info.token_amount);
-        };
+        let names = Self::token_series_names(mint);
+        let mut pipe = redis::pipe();
+        for name in &names {
+            pipe.cmd("EXISTS").arg(name);
+        }
+        let exists: Vec<bool> = pipe.query_async(&mut connection).await?;
 
-        for (timestamp, resolution) in timestamps.iter().zip(Resolution::all().iter()) {
+        let mut pipe = redis::pipe();
+        let mut altered = 0;
+        for (name, _) in names.iter().zip(exists).filter(|(_, exists)| *exists) {
+            pipe.cmd("TS.ALTER")
+                .arg(name)
+                .arg("RETENTION")
+                .arg(retention.as_millis() as u64)
+                .ignore();
+            altered += 1;
+        }
+        if altered > 0 {
+            pipe.exec_async(&mut connection).await?;
+        }
+
+        Ok(altered)
+    }
+
+    /// Retention of token series.
+    fn retention(&self, mint: &str) -> Duration {
+        self.retentions
+            .get(mint)
+            .map_or(RETENTION_PERIOD, |retention| *retention)
+    }
+
+    /// Insert trade events into cache for their candle buckets.
+    /// All series of the trades are updated atomically in a single round trip, in trades order.
+    /// Missing series are created with the token retention, server-wide defaults are not touched,
+    /// so Redis can be shared with other applications.
+    pub async fn insert_trades(&self, trades: &[BucketedTrade]) -> anyhow::Result<()> {
+        let mut connection = self.redis.get_multiplexed_async_connection().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (buckets, info) in trades {
+            self.add_trade(&mut pipe, buckets, info)?;
+        }
+        pipe.exec_async(&mut connection).await?;
+
+        Ok(())
+    }
+
+    /// Add updates of trade series for given candle buckets to the pipeline.
+    fn add_trade(
+        &self,
+        pipe: &mut redis::Pipeline,
+        buckets: &[(Resolution, DateTime<Utc>)],
+        info: &TradeInfo,
+    ) -> anyhow::Result<()> {
+        let price = info.price()?;
+        let retention = self.retention(&info.mint_acc).as_millis() as u64;
+
+        for (resolution, timestamp) in buckets {
             for (mode, policy) in PRICES_POLICIES.iter() {
                 let name = Self::ts_name(&info.mint_acc, *resolution, mode);
                 let timestamp = timestamp.timestamp_millis();
+                let value = series_value(mode, info, price);
 
-                redis::cmd("TS.ADD")
+                pipe.cmd("TS.ADD")
                     .arg(&name)
                     .arg(timestamp)
-                    .arg(price)
+                    .arg(value)
+                    .arg("RETENTION")
+                    .arg(retention)
                     .arg("ON_DUPLICATE")
                     .arg(policy)
-                    .exec_async(&mut connection)
-                    .await?;
+                    .ignore();
             }
         }
 
         Ok(())
     }
 
+    /// Write candles read from DB into token series, after cache lost them.
+    /// Points already in cache are kept, they may be counted by trades inserted since,
+    /// so summed fields are never doubled. Candles out of the token retention are skipped.
+    /// Returns number of written candles.
+    pub async fn backfill(
+        &self,
+        mint: &str,
+        resolution: Resolution,
+        candles: &BTreeMap<DateTime<Utc>, Candle>,
+    ) -> anyhow::Result<usize> {
+        let mut pipe = redis::pipe();
+        let written = self.add_backfill(&mut pipe, mint, resolution, candles);
+        if written == 0 {
+            return Ok(0);
+        }
+
+        let mut connection = self.redis.get_multiplexed_async_connection().await?;
+        pipe.exec_async(&mut connection).await?;
+
+        Ok(written)
+    }
+
+    /// Add commands writing candles within the token retention into series to pipeline.
+    /// Returns number of added candles.
+    fn add_backfill(
+        &self,
+        pipe: &mut redis::Pipeline,
+        mint: &str,
+        resolution: Resolution,
+        candles: &BTreeMap<DateTime<Utc>, Candle>,
+    ) -> usize {
+        let retention = self.retention(mint);
+        let retention_start = Utc::now() - retention;
+        let candles: Vec<_> = candles.range(retention_start..).collect();
+
+        for (datetime, candle) in &candles {
+            for (mode, _policy) in PRICES_POLICIES.iter() {
+                let value = match *mode {
+                    "open" => candle.open,
+                    "high" => candle.high,
+                    "low" => candle.low,
+                    "close" => candle.close,
+                    "volume" => candle.volume,
+                    "trade_count" => candle.trade_count as f64,
+                    "buy_volume" => candle.buy_volume,
+                    "sell_volume" => candle.sell_volume,
+                    "price_volume_sum" => candle.vwap * candle.volume,
+                    _ => unreachable!(),
+                };
+
+                pipe.cmd("TS.ADD")
+                    .arg(Self::ts_name(mint, resolution, mode))
+                    .arg(datetime.timestamp_millis())
+                    .arg(value)
+                    .arg("RETENTION")
+                    .arg(retention.as_millis() as u64)
+                    .arg("ON_DUPLICATE")
+                    .arg("FIRST")
+                    .ignore();
+            }
+        }
+
+        candles.len()
+    }
+
     /// Read last trade event from cache.
+    /// Fails if series are missing or their last points are of different buckets,
+    /// so caller can fall back to DB instead of using a mixed candle.
     pub async fn last_trade(
         &self,
         mint: &str,
@@ -71,8 +219,8 @@ impl Cache {
     ) -> anyhow::Result<(DateTime<Utc>, Candle)> {
         let mut connection = self.redis.get_multiplexed_async_connection().await?;
 
-        let mut values = [0.0; 5];
-        let mut last_timestamp = 0;
+        let mut values = [0.0; PRICES_POLICIES.len()];
+        let mut last_timestamp = None;
         for (idx, (mode, _policy)) in PRICES_POLICIES.iter().enumerate() {
             let name = Self::ts_name(mint, resolution, mode);
 
@@ -81,23 +229,35 @@ impl Cache {
                 .query_async::<(i64, f64)>(&mut connection)
                 .await?;
 
-            last_timestamp = last_timestamp.max(timestamp);
+            if last_timestamp.is_some_and(|last| last != timestamp) {
+                anyhow::bail!("Partial series in cache: {name} ends at {timestamp}");
+            }
+            last_timestamp = Some(timestamp);
             values[idx] = price;
         }
+        let last_timestamp = last_timestamp.unwrap_or_default();
 
-        let datetime = DateTime::from_timestamp_millis(last_timestamp).expect("correct datetime");
+        let datetime = DateTime::from_timestamp_millis(last_timestamp)
+            .ok_or_else(|| anyhow::anyhow!("Bad timestamp in cache: {last_timestamp}"))?;
         let candle = Candle {
             open: values[0],
             high: values[1],
             low: values[2],
             close: values[3],
             volume: values[4],
+            trade_count: values[5] as u64,
+            buy_volume: values[6],
+            sell_volume: values[7],
+            vwap: vwap(values[8], values[4], values[3]),
         };
 
         Ok((datetime, candle))
     }
 
     /// Read trades history from cache.
+    /// Fails if any series is missing or lacks points present in others, so caller can
+    /// fall back to DB instead of getting zero-filled fields. Series without points
+    /// in the range are fine, the token wasn't traded.
     pub async fn trades_since(
         &self,
         mint_acc: &str,
@@ -106,8 +266,7 @@ impl Cache {
     ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
         let mut connection = self.redis.get_multiplexed_async_connection().await?;
 
-        let mut trades = BTreeMap::new();
-
+        let mut series = Vec::with_capacity(PRICES_POLICIES.len());
         for (mode, _policy) in PRICES_POLICIES.iter() {
             let name = Self::ts_name(mint_acc, resolution, mode);
 
@@ -117,36 +276,491 @@ impl Cache {
                 .arg("+")
                 .query_async::<Vec<(i64, f64)>>(&mut connection)
                 .await?;
-
-            for (timestamp, value) in values {
-                let datetime =
-                    DateTime::from_timestamp_millis(timestamp).expect("correct datetime");
-                let trades_entry: &mut Candle = trades.entry(datetime).or_default();
-
-                match *mode {
-                    "open" => trades_entry.open = value,
-                    "high" => trades_entry.high = value,
-                    "low" => trades_entry.low = value,
-                    "close" => trades_entry.close = value,
-                    "volume" => trades_entry.volume = value,
-                    _ => unreachable!(),
-                }
-            }
+            series.push((*mode, values));
         }
 
-        Ok(trades)
+        merge_series(series)
+    }
+
+    /// Delete all time series of the token.
+    /// Returns number of deleted series.
+    pub async fn drop_token_series(&self, mint: &str) -> anyhow::Result<usize> {
+        let mut connection = self.redis.get_multiplexed_async_connection().await?;
+
+        let names = Self::token_series_names(mint);
+        let deleted = redis::cmd("DEL")
+            .arg(&names)
+            .query_async::<usize>(&mut connection)
+            .await?;
+
+        Ok(deleted)
+    }
+
+    /// Names of all time series of the token.
+    fn token_series_names(mint: &str) -> Vec<String> {
+        Resolution::all()
+            .into_iter()
+            .flat_map(|resolution| {
+                PRICES_POLICIES
+                    .iter()
+                    .map(move |(mode, _policy)| Self::ts_name(mint, resolution, mode))
+            })
+            .collect()
     }
 
     /// Name of the time series for given parameters.
+    /// Mint is length-prefixed, so names are unambiguous whatever the mint contains.
     fn ts_name(mint: &str, resolution: Resolution, mode: &str) -> String {
-        format!("trade_{}_{}_{}", mint, resolution, mode)
+        format!("trade:{}:{}:{}:{}", mint.len(), mint, resolution, mode)
     }
 }
 
-const PRICES_POLICIES: [(&str, &str); 5] = [
+/// Value of a trade added to the series of given mode, aggregated by the series policy.
+/// Volumes are token amounts in base units.
+fn series_value(mode: &str, info: &TradeInfo, price: f64) -> f64 {
+    match mode {
+        "trade_count" => 1.0,
+        "volume" => info.token_amount as f64,
+        "buy_volume" if info.is_buy => info.token_amount as f64,
+        "sell_volume" if !info.is_buy => info.token_amount as f64,
+        "buy_volume" | "sell_volume" => 0.0,
+        "price_volume_sum" => info.sol_amount as f64 * 10f64.powi(info.price_exponent()),
+        _ => price,
+    }
+}
+
+/// Volume-weighted average price, close price if there is no volume.
+fn vwap(price_volume_sum: f64, volume: f64, close: f64) -> f64 {
+    if volume > 0.0 {
+        price_volume_sum / volume
+    } else {
+        close
+    }
+}
+
+/// Merge values of every series mode into candles.
+/// Fails if any candle misses a field, e.g. when one series expired or was deleted,
+/// rather than zero filling it, so reads fall back to DB.
+fn merge_series(
+    series: impl IntoIterator<Item = (&'static str, Vec<(i64, f64)>)>,
+) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+    let mut trades = BTreeMap::new();
+    // Number of series each candle got a value from.
+    let mut series_counts: BTreeMap<DateTime<Utc>, usize> = BTreeMap::new();
+
+    for (mode, values) in series {
+        for (timestamp, value) in values {
+            let datetime = DateTime::from_timestamp_millis(timestamp)
+                .ok_or_else(|| anyhow::anyhow!("Bad timestamp in cache: {timestamp}"))?;
+            let trades_entry: &mut Candle = trades.entry(datetime).or_default();
+            *series_counts.entry(datetime).or_default() += 1;
+
+            match mode {
+                "open" => trades_entry.open = value,
+                "high" => trades_entry.high = value,
+                "low" => trades_entry.low = value,
+                "close" => trades_entry.close = value,
+                "volume" => trades_entry.volume = value,
+                "trade_count" => trades_entry.trade_count = value as u64,
+                "buy_volume" => trades_entry.buy_volume = value,
+                "sell_volume" => trades_entry.sell_volume = value,
+                // Replaced with VWAP once volume is read.
+                "price_volume_sum" => trades_entry.vwap = value,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    if let Some((datetime, count)) = series_counts
+        .into_iter()
+        .find(|(_, count)| *count != PRICES_POLICIES.len())
+    {
+        anyhow::bail!(
+            "Partial series in cache: candle at {datetime} has {count} of {} fields",
+            PRICES_POLICIES.len()
+        );
+    }
+    for candle in trades.values_mut() {
+        candle.vwap = vwap(candle.vwap, candle.volume, candle.close);
+    }
+
+    Ok(trades)
+}
+
+const PRICES_POLICIES: [(&str, &str); 9] = [
     ("open", "FIRST"),
     ("high", "MAX"),
     ("low", "MIN"),
     ("close", "LAST"),
     ("volume", "SUM"),
+    ("trade_count", "SUM"),
+    ("buy_volume", "SUM"),
+    ("sell_volume", "SUM"),
+    ("price_volume_sum", "SUM"),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "5xYzTokenMintAddressForCacheTestsPump";
+
+    fn trade(secs: i64, sol_amount: u64, token_amount: u64, is_buy: bool) -> TradeInfo {
+        TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount,
+            token_amount,
+            is_buy,
+            decimals: Some(6),
+        }
+    }
+
+    fn trades() -> Vec<TradeInfo> {
+        vec![
+            trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true),
+            trade(1_700_000_001, 500_000_000, 400_000_000, false),
+            trade(1_700_000_002, 2_000_000_000, 1_500_000_000, true),
+        ]
+    }
+
+    /// Sum of the trades values in series of given mode.
+    fn sum(mode: &str, trades: &[TradeInfo]) -> f64 {
+        trades
+            .iter()
+            .map(|info| series_value(mode, info, info.price().unwrap()))
+            .sum()
+    }
+
+    #[test]
+    fn volume_is_token_amount() {
+        let trades = trades();
+        assert_eq!(sum("volume", &trades), 2_900_000_000.0);
+        assert_eq!(sum("buy_volume", &trades), 2_500_000_000.0);
+        assert_eq!(sum("sell_volume", &trades), 400_000_000.0);
+        assert_eq!(sum("trade_count", &trades), 3.0);
+    }
+
+    #[test]
+    fn price_volume_sum_is_scaled_by_decimals() {
+        let info = trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true);
+        let price_volume = series_value("price_volume_sum", &info, info.price().unwrap());
+        assert!((price_volume - 1_000_000.0).abs() < 1e-6);
+        assert_eq!(
+            vwap(price_volume, 1_000_000_000.0, 0.0),
+            info.price().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn cached_volume_is_read_back() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let cache = Cache::open(&url).unwrap();
+        cache.drop_token_series(MINT).await.unwrap();
+
+        let trades: Vec<_> = trades()
+            .into_iter()
+            .map(|info| {
+                let bucket = Resolution::M1.bucket_start(info.timestamp);
+                (vec![(Resolution::M1, bucket)], info)
+            })
+            .collect();
+        cache.insert_trades(&trades).await.unwrap();
+
+        let from = DateTime::from_timestamp(0, 0).unwrap();
+        let candles = cache.trades_since(MINT, from, Resolution::M1).await;
+        cache.drop_token_series(MINT).await.unwrap();
+        let candles = candles.unwrap();
+        assert_eq!(candles.len(), 1);
+        let candle = candles.values().next().unwrap();
+        assert_eq!(candle.volume, 2_900_000_000.0);
+        assert_eq!(candle.buy_volume, 2_500_000_000.0);
+        assert_eq!(candle.sell_volume, 400_000_000.0);
+        assert_eq!(candle.trade_count, 3);
+    }
+
+    #[test]
+    fn series_names_are_unambiguous() {
+        // Without length prefix both would be `trade:a:b:M1:...`.
+        assert_ne!(
+            Cache::ts_name("a:b", Resolution::M1, "close"),
+            Cache::ts_name("a", Resolution::M1, "b:close")
+        );
+
+        let names = Cache::token_series_names(MINT);
+        assert_eq!(names.len(), Resolution::all().len() * PRICES_POLICIES.len());
+        let unique: std::collections::HashSet<_> = names.iter().collect();
+        assert_eq!(unique.len(), names.len());
+        assert!(names.iter().all(|name| name.contains(MINT)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn token_series_are_dropped() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let cache = Cache::open(&url).unwrap();
+        let trades: Vec<_> = trades()
+            .into_iter()
+            .map(|info| {
+                let bucket = Resolution::M1.bucket_start(info.timestamp);
+                (vec![(Resolution::M1, bucket)], info)
+            })
+            .collect();
+        cache.insert_trades(&trades).await.unwrap();
+
+        assert!(cache.drop_token_series(MINT).await.unwrap() > 0);
+        let mut connection = cache
+            .redis
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        let existing = redis::cmd("EXISTS")
+            .arg(Cache::token_series_names(MINT))
+            .query_async::<usize>(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(existing, 0);
+        assert_eq!(cache.drop_token_series(MINT).await.unwrap(), 0);
+    }
+
+    /// Command arguments as strings.
+    fn cmd_args(cmd: &redis::Cmd) -> Vec<String> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                redis::Arg::Simple(arg) => String::from_utf8_lossy(arg).into_owned(),
+                redis::Arg::Cursor => panic!("trade writes have no cursor"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trade_writes_are_pipelined_with_duplicate_policies() {
+        // Pipeline is built without connecting.
+        let cache = Cache::open("redis://127.0.0.1:1").unwrap();
+        let mut pipe = redis::pipe();
+        for info in trades() {
+            let buckets = [Resolution::M1, Resolution::H1]
+                .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)));
+            cache.add_trade(&mut pipe, &buckets, &info).unwrap();
+        }
+
+        assert_eq!(pipe.len(), 3 * 2 * PRICES_POLICIES.len());
+        let policies = PRICES_POLICIES.iter().cycle();
+        for (cmd, (mode, policy)) in pipe.cmd_iter().zip(policies) {
+            let args = cmd_args(cmd);
+            assert_eq!(args[0], "TS.ADD");
+            assert!(args[1].ends_with(mode), "{args:?}");
+            assert_eq!(args[args.len() - 2..], ["ON_DUPLICATE", *policy]);
+        }
+    }
+
+    /// Values of all series modes with one point per timestamp.
+    fn full_series(timestamps: &[i64]) -> Vec<(&'static str, Vec<(i64, f64)>)> {
+        PRICES_POLICIES
+            .iter()
+            .map(|(mode, _)| (*mode, timestamps.iter().map(|ts| (*ts, 1.0)).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn full_series_are_merged() {
+        let candles = merge_series(full_series(&[60_000, 120_000])).unwrap();
+        assert_eq!(candles.len(), 2);
+        let candle = candles.values().next().unwrap();
+        assert_eq!(candle.volume, 1.0);
+        assert_eq!(candle.trade_count, 1);
+        assert_eq!(candle.vwap, 1.0);
+    }
+
+    #[test]
+    fn empty_series_are_not_partial() {
+        assert!(merge_series(full_series(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn partial_series_are_surfaced() {
+        let mut series = full_series(&[60_000, 120_000]);
+        // Volume lost its latest point.
+        let volume = series
+            .iter_mut()
+            .find(|(mode, _)| *mode == "volume")
+            .unwrap();
+        volume.1.pop();
+
+        let e = merge_series(series).unwrap_err();
+        assert!(e.to_string().contains("8 of 9 fields"), "{e}");
+    }
+
+    #[test]
+    fn series_retention_is_set_per_write() {
+        let cache = Cache::open("redis://127.0.0.1:1").unwrap();
+        let other = "OtherTokenMintAddressForCacheTestsPump";
+        cache.load_retentions([(other.to_string(), Duration::from_secs(60))]);
+        let mut pipe = redis::pipe();
+        for mut info in trades() {
+            let buckets = [(Resolution::M1, Resolution::M1.bucket_start(info.timestamp))];
+            cache.add_trade(&mut pipe, &buckets, &info).unwrap();
+            info.mint_acc = other.to_string();
+            cache.add_trade(&mut pipe, &buckets, &info).unwrap();
+        }
+
+        for cmd in pipe.cmd_iter() {
+            let args = cmd_args(cmd);
+            assert_ne!(args[0], "CONFIG", "{args:?}");
+            let retention = args.iter().position(|arg| arg == "RETENTION").unwrap();
+            let expected = if args[1].contains(other) {
+                60_000
+            } else {
+                MILLIS_IN_DAY
+            };
+            assert_eq!(args[retention + 1], expected.to_string(), "{args:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn created_series_carry_retention() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        // Cache is created the way the server does it.
+        let config = Config::from_vars([
+            (
+                String::from("POSTGRES_CONN_STR"),
+                String::from("postgres://localhost"),
+            ),
+            (String::from("REDIS_CONN_STR"), url),
+        ])
+        .unwrap();
+        let cache = Cache::new(&config).await.unwrap();
+        cache.drop_token_series(MINT).await.unwrap();
+        let mut connection = cache
+            .redis
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        let policy_before: redis::Value = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("ts-retention-policy")
+            .query_async(&mut connection)
+            .await
+            .unwrap();
+
+        let info = trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true);
+        let bucket = Resolution::M1.bucket_start(info.timestamp);
+        cache
+            .insert_trades(&[(vec![(Resolution::M1, bucket)], info)])
+            .await
+            .unwrap();
+
+        let name = Cache::ts_name(MINT, Resolution::M1, "close");
+        let retention = series_retention(&mut connection, &name).await;
+        let policy_after: redis::Value = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("ts-retention-policy")
+            .query_async(&mut connection)
+            .await
+            .unwrap();
+        cache.drop_token_series(MINT).await.unwrap();
+
+        assert_eq!(retention, Some(MILLIS_IN_DAY));
+        assert_eq!(policy_before, policy_after);
+    }
+
+    /// Retention of the series reported by `TS.INFO`, in milliseconds.
+    async fn series_retention(
+        connection: &mut redis::aio::MultiplexedConnection,
+        name: &str,
+    ) -> Option<u64> {
+        let ts_info: Vec<redis::Value> = redis::cmd("TS.INFO")
+            .arg(name)
+            .query_async(connection)
+            .await
+            .unwrap();
+        ts_info
+            .chunks(2)
+            .find(|field| {
+                redis::from_redis_value::<String>(&field[0]).is_ok_and(|key| key == "retentionTime")
+            })
+            .map(|field| redis::from_redis_value::<u64>(&field[1]).unwrap())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn token_retention_alters_existing_series() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let cache = Cache::open(&url).unwrap();
+        cache.drop_token_series(MINT).await.unwrap();
+        let info = trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true);
+        let bucket = Resolution::M1.bucket_start(info.timestamp);
+        cache
+            .insert_trades(&[(vec![(Resolution::M1, bucket)], info)])
+            .await
+            .unwrap();
+
+        let altered = cache
+            .set_token_retention(MINT, Duration::from_secs(7 * 86_400))
+            .await
+            .unwrap();
+        let mut connection = cache
+            .redis
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        let name = Cache::ts_name(MINT, Resolution::M1, "close");
+        let retention = series_retention(&mut connection, &name).await;
+        cache.drop_token_series(MINT).await.unwrap();
+
+        assert_eq!(altered, PRICES_POLICIES.len());
+        assert_eq!(retention, Some(7 * MILLIS_IN_DAY));
+    }
+
+    #[test]
+    fn backfill_keeps_cached_points_within_retention() {
+        let cache = Cache::open("redis://127.0.0.1:1").unwrap();
+        let minute = Resolution::M1.bucket_start(Utc::now());
+        let hour = Duration::from_secs(3600);
+        let candle = Candle {
+            volume: 10.0,
+            trade_count: 2,
+            vwap: 1.5,
+            ..Candle::flat(2.0)
+        };
+        let candles = BTreeMap::from([
+            (minute - hour * 48, candle),
+            (minute - hour, candle),
+            (minute, candle),
+        ]);
+
+        let mut pipe = redis::pipe();
+        assert_eq!(
+            cache.add_backfill(&mut pipe, MINT, Resolution::M1, &candles),
+            2
+        );
+        let commands: Vec<_> = pipe.cmd_iter().map(cmd_args).collect();
+        assert_eq!(commands.len(), 2 * PRICES_POLICIES.len());
+        for args in &commands {
+            // Points counted by live trades since cache went cold are kept.
+            assert_eq!(
+                args[args.len() - 2..],
+                ["ON_DUPLICATE", "FIRST"],
+                "{args:?}"
+            );
+            let timestamp: i64 = args[2].parse().unwrap();
+            assert!(timestamp >= (minute - hour).timestamp_millis(), "{args:?}");
+        }
+        let value = |mode: &str| {
+            let name = Cache::ts_name(MINT, Resolution::M1, mode);
+            commands.iter().find(|args| args[1] == name).unwrap()[3].clone()
+        };
+        assert_eq!(value("trade_count"), "2.0");
+        assert_eq!(value("price_volume_sum"), "15.0");
+
+        // Token retention override keeps older candles.
+        cache.load_retentions([(MINT.to_string(), hour * 72)]);
+        let mut pipe = redis::pipe();
+        assert_eq!(
+            cache.add_backfill(&mut pipe, MINT, Resolution::M1, &candles),
+            3
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::indexer::EventKind;
+use crate::model::{OpenPolicy, Resolution};
+use crate::pump_handler::{MAX_METADATA_BATCH, PauseMode};
+use crate::rpc_pool::DEFAULT_RPC_URL;
+
+/// Default max age of the cached tokens list.
+const DEFAULT_TOKENS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Default duration of DB queries to be logged as slow.
+const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Default window of stored empty candles.
+const DEFAULT_EMPTY_CANDLES_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Default interval between metadata backfill runs.
+const DEFAULT_METADATA_BACKFILL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Default interval between DB maintenance runs.
+const DEFAULT_DB_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Default max number of events handled concurrently.
+const DEFAULT_EVENT_CONCURRENCY: usize = 64;
+
+/// Default max number of trades written in a single batch.
+const DEFAULT_TRADE_BATCH_SIZE: usize = 32;
+
+/// Default max time a trade waits for its batch to fill up.
+const DEFAULT_TRADE_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+/// Max significant digits of prices, doubles don't hold more.
+const MAX_SIGNIFICANT_DIGITS: u8 = 17;
+
+/// Default server listen address.
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:33987";
+
+/// Application configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Postgres connection string.
+    pub postgres_conn_str: String,
+    /// Redis connection string.
+    pub redis_conn_str: String,
+    /// Server listen address.
+    pub listen_addr: String,
+    /// TLS certificate and private key PEM files. Server uses plaintext if not set.
+    pub tls: Option<TlsPaths>,
+    /// Interval between metadata backfill runs.
+    pub metadata_backfill_interval: Duration,
+    /// Tokens resolved per metadata RPC call.
+    pub metadata_batch_size: usize,
+    /// History points override for all resolutions.
+    pub history_points: Option<usize>,
+    /// Interval between DB maintenance runs.
+    pub db_maintenance_interval: Duration,
+    /// Vacuum trades table on DB maintenance.
+    pub db_maintenance_vacuum: bool,
+    /// Compress REST responses.
+    pub http_compression: bool,
+    /// Offset of daily candles start from UTC midnight.
+    pub day_anchor_offset: Duration,
+    /// API key required by admin endpoints. Admin endpoints are open if not set.
+    pub admin_api_key: Option<String>,
+    /// File of trades failed to be inserted into DB.
+    pub retry_queue_path: PathBuf,
+    /// Max number of trades pending DB insertion.
+    pub retry_queue_capacity: usize,
+    /// One of this number of high-frequency ingestion logs is emitted.
+    pub log_sample_rate: NonZeroU64,
+    /// Base URL clients reach the server at, e.g. behind a reverse proxy.
+    pub public_base_url: Option<String>,
+    /// Number of chart subscribers to keep token candles in memory, disabled if not set.
+    pub hot_token_subscribers: Option<usize>,
+    /// Max number of token resolutions kept in memory.
+    pub hot_tokens_max: usize,
+    /// Types of pumpfun events to index.
+    pub index_events: Vec<EventKind>,
+    /// Max age of the cached tokens list.
+    pub tokens_cache_ttl: Duration,
+    /// Solana RPC endpoints in failover order.
+    pub solana_rpc_urls: Vec<String>,
+    /// DB queries taking longer are logged.
+    pub slow_query_threshold: Duration,
+    /// Default definition of chart candles open price.
+    pub open_policy: OpenPolicy,
+    /// Resolutions which empty candles are stored, disabled if empty.
+    pub empty_candles_resolutions: Vec<Resolution>,
+    /// How far back empty candles are stored.
+    pub empty_candles_window: Duration,
+    /// S1 candles are written only for tokens traded or created within it, always if not set.
+    pub s1_activity_window: Option<Duration>,
+    /// Significant digits of prices sent to clients, full precision if not set.
+    pub price_significant_digits: Option<u8>,
+    /// Max number of events handled concurrently.
+    pub event_concurrency: usize,
+    /// Max number of trades written in a single batch, 1 disables batching.
+    pub trade_batch_size: usize,
+    /// Max time a trade waits for its batch to fill up.
+    pub trade_batch_delay: Duration,
+    /// What to do with events while ingestion is paused.
+    pub pause_mode: PauseMode,
+    /// Resolutions which candles are stored.
+    pub enabled_resolutions: Vec<Resolution>,
+}
+
+impl Config {
+    /// Load config from environment variables.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Load config from given variables.
+    /// All missing and invalid variables are reported at once.
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> anyhow::Result<Self> {
+        let mut vars = Vars {
+            vars: vars.into_iter().collect(),
+            errors: Vec::new(),
+        };
+
+        let day_anchor_offset_secs = vars.parse_or("DAY_ANCHOR_OFFSET_SECS", 0u64);
+        if day_anchor_offset_secs >= Resolution::D1.as_seconds() {
+            vars.errors.push(format!(
+                "DAY_ANCHOR_OFFSET_SECS: must be less than a day, got {day_anchor_offset_secs}"
+            ));
+        }
+
+        let tls = match (
+            vars.parse::<PathBuf>("TLS_CERT_PATH"),
+            vars.parse::<PathBuf>("TLS_KEY_PATH"),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsPaths { cert, key }),
+            (None, None) => None,
+            _ => {
+                vars.errors.push(String::from(
+                    "TLS_CERT_PATH, TLS_KEY_PATH: must be set together",
+                ));
+                None
+            }
+        };
+
+        let public_base_url = vars
+            .parse::<String>("PUBLIC_BASE_URL")
+            .map(|url| url.trim_end_matches('/').to_string());
+        if let Some(url) = &public_base_url
+            && !url.starts_with("http://")
+            && !url.starts_with("https://")
+        {
+            vars.errors.push(format!(
+                "PUBLIC_BASE_URL: must start with http:// or https://, got {url:?}"
+            ));
+        }
+
+        let mut solana_rpc_urls = vars.list::<String>("SOLANA_RPC_URLS");
+        if let Some(url) = solana_rpc_urls
+            .iter()
+            .find(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+        {
+            vars.errors.push(format!(
+                "SOLANA_RPC_URLS: must start with http:// or https://, got {url:?}"
+            ));
+        }
+        if solana_rpc_urls.is_empty() {
+            solana_rpc_urls.push(String::from(DEFAULT_RPC_URL));
+        }
+
+        let hot_token_subscribers = vars.parse::<usize>("HOT_TOKEN_SUBSCRIBERS");
+        if hot_token_subscribers == Some(0) {
+            vars.errors
+                .push(String::from("HOT_TOKEN_SUBSCRIBERS: must be positive"));
+        }
+
+        let price_significant_digits = vars.parse::<u8>("PRICE_SIGNIFICANT_DIGITS");
+        if price_significant_digits
+            .is_some_and(|digits| !(1..=MAX_SIGNIFICANT_DIGITS).contains(&digits))
+        {
+            vars.errors.push(format!(
+                "PRICE_SIGNIFICANT_DIGITS: must be from 1 to {MAX_SIGNIFICANT_DIGITS}"
+            ));
+        }
+
+        let event_concurrency = vars.parse_or("EVENT_CONCURRENCY", DEFAULT_EVENT_CONCURRENCY);
+        if event_concurrency == 0 {
+            vars.errors
+                .push(String::from("EVENT_CONCURRENCY: must be positive"));
+        }
+
+        let trade_batch_size = vars.parse_or("TRADE_BATCH_SIZE", DEFAULT_TRADE_BATCH_SIZE);
+        if trade_batch_size == 0 {
+            vars.errors
+                .push(String::from("TRADE_BATCH_SIZE: must be positive"));
+        }
+
+        let index_events = if vars.vars.contains_key("INDEX_EVENTS") {
+            vars.list::<EventKind>("INDEX_EVENTS")
+        } else {
+            EventKind::all().to_vec()
+        };
+        if index_events.is_empty() {
+            vars.errors
+                .push(String::from("INDEX_EVENTS: at least one must be set"));
+        }
+
+        let disabled_resolutions = vars.list::<Resolution>("DISABLED_RESOLUTIONS");
+        let enabled_resolutions: Vec<_> = Resolution::all()
+            .into_iter()
+            .filter(|resolution| !disabled_resolutions.contains(resolution))
+            .collect();
+        if enabled_resolutions.is_empty() {
+            vars.errors.push(String::from(
+                "DISABLED_RESOLUTIONS: at least one must be enabled",
+            ));
+        }
+
+        let empty_candles_resolutions = vars.list::<Resolution>("EMPTY_CANDLES_RESOLUTIONS");
+        if let Some(resolution) = empty_candles_resolutions
+            .iter()
+            .find(|resolution| !enabled_resolutions.contains(resolution))
+        {
+            vars.errors.push(format!(
+                "EMPTY_CANDLES_RESOLUTIONS: resolution {resolution} is disabled"
+            ));
+        }
+
+        let metadata_batch_size = vars.parse_or("METADATA_BATCH_SIZE", MAX_METADATA_BATCH);
+        if !(1..=MAX_METADATA_BATCH).contains(&metadata_batch_size) {
+            vars.errors.push(format!(
+                "METADATA_BATCH_SIZE: must be from 1 to {MAX_METADATA_BATCH}, got {metadata_batch_size}"
+            ));
+        }
+
+        let config = Self {
+            postgres_conn_str: vars.required("POSTGRES_CONN_STR"),
+            redis_conn_str: vars.required("REDIS_CONN_STR"),
+            listen_addr: vars.parse_or("LISTEN_ADDR", DEFAULT_LISTEN_ADDR.to_string()),
+            tls,
+            metadata_backfill_interval: vars.secs_or(
+                "METADATA_BACKFILL_INTERVAL_SECS",
+                DEFAULT_METADATA_BACKFILL_INTERVAL,
+            ),
+            metadata_batch_size,
+            history_points: vars.parse("HISTORY_POINTS"),
+            db_maintenance_interval: vars.secs_or(
+                "DB_MAINTENANCE_INTERVAL_SECS",
+                DEFAULT_DB_MAINTENANCE_INTERVAL,
+            ),
+            db_maintenance_vacuum: vars.parse_or("DB_MAINTENANCE_VACUUM", false),
+            http_compression: vars.parse_or("HTTP_COMPRESSION", true),
+            day_anchor_offset: Duration::from_secs(day_anchor_offset_secs),
+            admin_api_key: vars.parse("ADMIN_API_KEY"),
+            retry_queue_path: vars
+                .parse_or("RETRY_QUEUE_PATH", PathBuf::from("pending_trades.jsonl")),
+            retry_queue_capacity: vars.parse_or("RETRY_QUEUE_CAPACITY", 100_000),
+            log_sample_rate: vars.parse_or("LOG_SAMPLE_RATE", NonZeroU64::MIN),
+            public_base_url,
+            hot_token_subscribers,
+            hot_tokens_max: vars.parse_or("HOT_TOKENS_MAX", 100),
+            index_events,
+            tokens_cache_ttl: vars.secs_or("TOKENS_CACHE_TTL_SECS", DEFAULT_TOKENS_CACHE_TTL),
+            solana_rpc_urls,
+            slow_query_threshold: vars
+                .millis_or("SLOW_QUERY_THRESHOLD_MS", DEFAULT_SLOW_QUERY_THRESHOLD),
+            open_policy: vars.parse_or("CANDLE_OPEN_POLICY", OpenPolicy::FirstTrade),
+            empty_candles_resolutions,
+            empty_candles_window: vars
+                .secs_or("EMPTY_CANDLES_WINDOW_SECS", DEFAULT_EMPTY_CANDLES_WINDOW),
+            s1_activity_window: vars
+                .parse("S1_ACTIVITY_WINDOW_SECS")
+                .map(Duration::from_secs),
+            price_significant_digits,
+            event_concurrency,
+            trade_batch_size,
+            trade_batch_delay: vars.millis_or("TRADE_BATCH_DELAY_MS", DEFAULT_TRADE_BATCH_DELAY),
+            pause_mode: vars.parse_or("PAUSE_MODE", PauseMode::Buffer),
+            enabled_resolutions,
+        };
+
+        if !vars.errors.is_empty() {
+            anyhow::bail!("Invalid config:\n{}", vars.errors.join("\n"));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Paths of TLS certificate and private key PEM files.
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Variables with accumulated parsing errors.
+struct Vars {
+    vars: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl Vars {
+    /// Get required variable.
+    fn required(&mut self, key: &str) -> String {
+        match self.vars.get(key) {
+            Some(value) => value.clone(),
+            None => {
+                self.errors.push(format!("{key}: missing"));
+                String::new()
+            }
+        }
+    }
+
+    /// Parse optional variable.
+    fn parse<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.vars.get(key)?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{key}: {e}, got {value:?}"));
+                None
+            }
+        }
+    }
+
+    /// Parse optional variable with default value.
+    fn parse_or<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(key).unwrap_or(default)
+    }
+
+    /// Parse optional comma separated list, empty if not set.
+    fn list<T>(&mut self, key: &str) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some(value) = self.vars.get(key) else {
+            return Vec::new();
+        };
+
+        let mut items = Vec::new();
+        for item in value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match item.parse() {
+                Ok(item) => items.push(item),
+                Err(e) => self.errors.push(format!("{key}: {e}, got {item:?}")),
+            }
+        }
+        items
+    }
+
+    /// Parse optional duration in seconds with default value.
+    fn secs_or(&mut self, key: &str, default: Duration) -> Duration {
+        self.parse(key).map(Duration::from_secs).unwrap_or(default)
+    }
+
+    /// Parse optional duration in milliseconds.
+    fn millis_or(&mut self, key: &str, default: Duration) -> Duration {
+        self.parse(key)
+            .map(Duration::from_millis)
+            .unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config from required variables and given ones.
+    fn config(vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+        let required = [
+            ("POSTGRES_CONN_STR", "postgres://localhost/pumpfun"),
+            ("REDIS_CONN_STR", "redis://localhost"),
+        ];
+        Config::from_vars(
+            required
+                .iter()
+                .chain(vars)
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        )
+    }
+
+    /// Errors of invalid config.
+    fn errors(vars: impl IntoIterator<Item = (&'static str, &'static str)>) -> String {
+        let vars = vars
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+        Config::from_vars(vars).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_are_used_for_optional_vars() {
+        let config = config(&[]).unwrap();
+        assert_eq!(config.postgres_conn_str, "postgres://localhost/pumpfun");
+        assert_eq!(config.redis_conn_str, "redis://localhost");
+        assert_eq!(config.listen_addr, DEFAULT_LISTEN_ADDR);
+        assert!(config.tls.is_none());
+        assert_eq!(config.history_points, None);
+        assert_eq!(config.event_concurrency, DEFAULT_EVENT_CONCURRENCY);
+        assert_eq!(config.slow_query_threshold, DEFAULT_SLOW_QUERY_THRESHOLD);
+        assert_eq!(config.solana_rpc_urls, [DEFAULT_RPC_URL]);
+        assert_eq!(config.enabled_resolutions, Resolution::all());
+        assert!(config.http_compression);
+    }
+
+    #[test]
+    fn optional_vars_are_parsed() {
+        let config = config(&[
+            ("LISTEN_ADDR", "127.0.0.1:8080"),
+            ("HISTORY_POINTS", "250"),
+            ("SLOW_QUERY_THRESHOLD_MS", "100"),
+            ("TOKENS_CACHE_TTL_SECS", "30"),
+            ("HTTP_COMPRESSION", "false"),
+            ("ADMIN_API_KEY", "secret"),
+        ])
+        .unwrap();
+        assert_eq!(config.listen_addr, "127.0.0.1:8080");
+        assert_eq!(config.history_points, Some(250));
+        assert_eq!(config.slow_query_threshold, Duration::from_millis(100));
+        assert_eq!(config.tokens_cache_ttl, Duration::from_secs(30));
+        assert!(!config.http_compression);
+        assert_eq!(config.admin_api_key.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn all_errors_are_reported_at_once() {
+        let errors = errors([
+            ("HISTORY_POINTS", "many"),
+            ("EVENT_CONCURRENCY", "0"),
+            ("HTTP_COMPRESSION", "yes"),
+        ]);
+        for key in [
+            "POSTGRES_CONN_STR: missing",
+            "REDIS_CONN_STR: missing",
+            "HISTORY_POINTS",
+            "EVENT_CONCURRENCY: must be positive",
+            "HTTP_COMPRESSION",
+        ] {
+            assert!(errors.contains(key), "{key} is not in {errors}");
+        }
+    }
+
+    #[test]
+    fn indexed_events_are_parsed() {
+        assert_eq!(config(&[]).unwrap().index_events, EventKind::all());
+        let indexed = config(&[("INDEX_EVENTS", "create,trade")]).unwrap();
+        assert_eq!(indexed.index_events, [EventKind::Create, EventKind::Trade]);
+
+        let unknown = config(&[("INDEX_EVENTS", "swap")]).unwrap_err().to_string();
+        assert!(unknown.contains("INDEX_EVENTS"), "{unknown}");
+        let empty = config(&[("INDEX_EVENTS", "")]).unwrap_err().to_string();
+        assert!(
+            empty.contains("INDEX_EVENTS: at least one must be set"),
+            "{empty}"
+        );
+    }
+
+    #[test]
+    fn rpc_urls_are_listed_in_order() {
+        let urls = config(&[(
+            "SOLANA_RPC_URLS",
+            "https://rpc.example.com, http://127.0.0.1:8899",
+        )])
+        .unwrap()
+        .solana_rpc_urls;
+        assert_eq!(urls, ["https://rpc.example.com", "http://127.0.0.1:8899"]);
+
+        let e = config(&[("SOLANA_RPC_URLS", "https://rpc.example.com,rpc.example.com")])
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("SOLANA_RPC_URLS: must start with"), "{e}");
+    }
+
+    #[test]
+    fn significant_digits_are_bounded() {
+        assert_eq!(config(&[]).unwrap().price_significant_digits, None);
+        let digits = config(&[("PRICE_SIGNIFICANT_DIGITS", "6")]).unwrap();
+        assert_eq!(digits.price_significant_digits, Some(6));
+
+        for invalid in ["0", "18"] {
+            let e = config(&[("PRICE_SIGNIFICANT_DIGITS", invalid)])
+                .unwrap_err()
+                .to_string();
+            assert!(
+                e.contains("PRICE_SIGNIFICANT_DIGITS: must be from 1 to 17"),
+                "{e}"
+            );
+        }
+    }
+
+    #[test]
+    fn event_concurrency_must_be_positive() {
+        assert_eq!(config(&[]).unwrap().event_concurrency, 64);
+        let bounded = config(&[("EVENT_CONCURRENCY", "8")]).unwrap();
+        assert_eq!(bounded.event_concurrency, 8);
+
+        let e = config(&[("EVENT_CONCURRENCY", "0")])
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("EVENT_CONCURRENCY: must be positive"), "{e}");
+    }
+}
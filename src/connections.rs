@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use sqlx::types::chrono::Utc;
+
+use crate::model::Resolution;
+
+/// Active chart WebSocket connection.
+#[derive(Debug, Clone, Serialize)]
+pub struct Connection {
+    pub token: String,
+    pub resolution: Resolution,
+    /// Connection time as unix timestamp in milliseconds.
+    pub connected_at: u64,
+}
+
+/// Active chart WebSocket connections with subscribers count per token.
+#[derive(Debug, Serialize)]
+pub struct ConnectionsSnapshot {
+    pub connections: Vec<Connection>,
+    pub subscribers: HashMap<String, usize>,
+}
+
+/// Registry of active chart WebSocket connections for debugging load.
+#[derive(Clone, Default)]
+pub struct Connections {
+    next_id: Arc<AtomicU64>,
+    active: Arc<DashMap<u64, Connection>>,
+}
+
+impl Connections {
+    /// Register connection streaming token candles of given resolution.
+    /// Connection is unregistered when the returned guard is dropped,
+    /// so it's removed however the handler exits, even on panic.
+    pub fn register(&self, token: &str, resolution: Resolution) -> ConnectionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection {
+            token: token.to_string(),
+            resolution,
+            connected_at: Utc::now().timestamp_millis() as u64,
+        };
+        self.active.insert(id, connection);
+
+        ConnectionGuard {
+            id,
+            active: self.active.clone(),
+        }
+    }
+
+    /// Active connections, oldest first.
+    pub fn snapshot(&self) -> ConnectionsSnapshot {
+        let mut connections: Vec<_> = self
+            .active
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        connections.sort_by_key(|connection| connection.connected_at);
+
+        let mut subscribers = HashMap::new();
+        for connection in &connections {
+            *subscribers.entry(connection.token.clone()).or_default() += 1;
+        }
+
+        ConnectionsSnapshot {
+            connections,
+            subscribers,
+        }
+    }
+}
+
+/// Registration of an active connection, see [`Connections::register`].
+pub struct ConnectionGuard {
+    id: u64,
+    active: Arc<DashMap<u64, Connection>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_counted_per_token() {
+        let connections = Connections::default();
+        let first = connections.register("first", Resolution::M1);
+        let _second = connections.register("first", Resolution::H1);
+        let _other = connections.register("other", Resolution::M1);
+
+        let snapshot = connections.snapshot();
+        assert_eq!(snapshot.connections.len(), 3);
+        assert_eq!(snapshot.subscribers["first"], 2);
+        assert_eq!(snapshot.subscribers["other"], 1);
+
+        drop(first);
+        let snapshot = connections.snapshot();
+        assert_eq!(snapshot.connections.len(), 2);
+        assert_eq!(snapshot.subscribers["first"], 1);
+    }
+
+    #[test]
+    fn connection_is_unregistered_on_panic() {
+        let connections = Connections::default();
+        let handler = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _connection = connections.register("first", Resolution::M1);
+            panic!("handler failed");
+        }));
+        assert!(handler.is_err());
+
+        let snapshot = connections.snapshot();
+        assert!(snapshot.connections.is_empty());
+        assert!(snapshot.subscribers.is_empty());
+    }
+}
@@ -1,25 +1,96 @@
-use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use sqlx::postgres::PgRow;
+use futures_util::{Stream, StreamExt, stream};
 use sqlx::types::chrono::{NaiveDateTime, Utc};
-use sqlx::{PgPool, Row, migrate::Migrator, types::chrono::DateTime};
+use sqlx::{PgConnection, PgExecutor, PgPool, Row, migrate::Migrator, types::chrono::DateTime};
+use tokio::sync::{Mutex, OwnedMutexGuard, mpsc};
 
-use crate::model::{Candle, Resolution, TokenMetadata, TradeInfo};
+use crate::config::Config;
+use crate::error::NotFound;
+use crate::metrics::Metrics;
+use crate::model::{
+    BucketedTrade, Candle, Resolution, SOL_DECIMALS, Session, TokenMetadata, TokenPressure,
+    TradeInfo,
+};
 
 static MIGRATOR: Migrator = sqlx::migrate!("pg/migrations");
 
+/// Outcome of token metadata insertion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenWrite {
+    /// Token is stored for the first time.
+    Inserted,
+    /// Metadata of a stored token is replaced.
+    Updated,
+    /// Metadata of a stored token is replaced and its decimals are set,
+    /// so its stored candles are rescaled.
+    Rescaled,
+    /// Stored metadata is kept, since it's better than the new one.
+    Kept,
+}
+
+/// Outcome of first trade of a token insertion.
+#[derive(Debug, Clone, Copy)]
+pub struct FirstTradeWrite {
+    /// Outcome of the token insertion.
+    pub token: TokenWrite,
+    /// Decimals of the stored token the trade is priced with.
+    pub decimals: Option<u8>,
+    /// Number of newly created candles.
+    pub new_candles: usize,
+}
+
+/// Number of streamed tokens fetched ahead of the consumer.
+const TOKENS_STREAM_BUFFER: usize = 256;
+
 /// Database instance.
 #[derive(Clone)]
 pub struct Db {
     pool: PgPool,
+    /// Held by heavy maintenance operations so they don't run concurrently.
+    maintenance: Arc<Mutex<()>>,
+    metrics: Arc<Metrics>,
+    /// Queries taking longer are logged.
+    slow_query_threshold: Duration,
 }
 
 impl Db {
     /// Create new database instance.
-    pub async fn new(connection_string: String) -> anyhow::Result<Self> {
-        Ok(Self {
-            pool: PgPool::connect(&connection_string).await?,
-        })
+    pub async fn new(config: &Config, metrics: Arc<Metrics>) -> anyhow::Result<Self> {
+        let pool = PgPool::connect(&config.postgres_conn_str).await?;
+        Ok(Self::from_pool(pool, metrics, config.slow_query_threshold))
+    }
+
+    /// Create database instance on top of connected pool.
+    pub fn from_pool(pool: PgPool, metrics: Arc<Metrics>, slow_query_threshold: Duration) -> Self {
+        Self {
+            pool,
+            maintenance: Default::default(),
+            metrics,
+            slow_query_threshold,
+        }
+    }
+
+    /// Run query, recording its duration and logging it with parameters if it's slow.
+    async fn timed<T>(
+        &self,
+        name: &'static str,
+        params: impl Debug,
+        query: impl Future<Output = T>,
+    ) -> T {
+        let start = Instant::now();
+        let result = query.await;
+        let elapsed = start.elapsed();
+
+        self.metrics.observe_db_query(name, elapsed);
+        if elapsed >= self.slow_query_threshold {
+            tracing::warn!("Slow DB query {name} with {params:?} took {elapsed:?}.");
+        }
+        result
     }
 
     /// Perform migrations.
@@ -28,16 +99,123 @@ impl Db {
         Ok(())
     }
 
+    /// Version of the latest applied migration, if any.
+    pub async fn migration_version(&self) -> anyhow::Result<Option<i64>> {
+        // Migrations table is created by the migrator, so it's not checked at compile time.
+        let query = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool);
+        let version = self.timed("migration_version", (), query).await?;
+
+        Ok(version)
+    }
+
+    /// Try to acquire exclusive right to run maintenance operation.
+    /// Returns `None` if another maintenance operation is running.
+    pub fn try_lock_maintenance(&self) -> Option<OwnedMutexGuard<()>> {
+        self.maintenance.clone().try_lock_owned().ok()
+    }
+
+    /// Update planner statistics of the trades table and optionally vacuum it.
+    /// Requires maintenance lock.
+    pub async fn analyze_trades(
+        &self,
+        _guard: &OwnedMutexGuard<()>,
+        vacuum: bool,
+    ) -> anyhow::Result<()> {
+        // VACUUM can't run inside a transaction, so use simple query protocol.
+        let query = if vacuum {
+            "VACUUM ANALYZE trades"
+        } else {
+            "ANALYZE trades"
+        };
+        let execute = sqlx::raw_sql(query).execute(&self.pool);
+        self.timed("analyze_trades", vacuum, execute).await?;
+        Ok(())
+    }
+
     /// Get tokens list with metadata.
     pub async fn get_tokens(&self) -> Result<Vec<(String, TokenMetadata)>, anyhow::Error> {
-        let rows = sqlx::query("SELECT mint, name, symbol, uri FROM token")
-            .fetch_all(&self.pool)
+        let query = sqlx::query_as!(
+            TokenRow,
+            "SELECT mint, name, symbol, uri, completed_at, decimals FROM token"
+        )
+        .fetch_all(&self.pool);
+        let rows = self.timed("get_tokens", (), query).await?;
+
+        Ok(rows.into_iter().map(TokenRow::into_metadata).collect())
+    }
+
+    /// Stream tokens list with metadata, without loading all rows into memory.
+    /// Rows are fetched by a background task, so the stream doesn't borrow the pool.
+    /// Stream ends after the first error.
+    /// Query is timed until the last row is sent, including waits for the consumer.
+    pub fn stream_tokens(
+        &self,
+    ) -> impl Stream<Item = anyhow::Result<(String, TokenMetadata)>> + Send + 'static {
+        let (tx, rx) = mpsc::channel(TOKENS_STREAM_BUFFER);
+        let db = self.clone();
+        tokio::spawn(async move {
+            let query = async {
+                let mut rows = sqlx::query_as!(
+                    TokenRow,
+                    "SELECT mint, name, symbol, uri, completed_at, decimals FROM token"
+                )
+                .fetch(&db.pool);
+                while let Some(row) = rows.next().await {
+                    let failed = row.is_err();
+                    let token = row.map(TokenRow::into_metadata).map_err(Into::into);
+                    // Stop if consumer is gone.
+                    if tx.send(token).await.is_err() || failed {
+                        break;
+                    }
+                }
+            };
+            db.timed("stream_tokens", (), query).await;
+        });
+
+        stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|token| (token, rx))
+        })
+    }
+
+    /// Get tokens with metadata meeting activity thresholds.
+    /// `min_volume` is a minimum volume of `resolution` candles since `volume_since`,
+    /// `active_since` requires a trade since given timestamp. Unset thresholds are not checked.
+    pub async fn get_tokens_filtered(
+        &self,
+        min_volume: Option<f64>,
+        resolution: Resolution,
+        volume_since: DateTime<Utc>,
+        active_since: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<(String, TokenMetadata)>> {
+        let query = sqlx::query_as!(
+            TokenRow,
+            r#"
+            SELECT mint, name, symbol, uri, completed_at, decimals FROM token
+            WHERE ($1::float8 IS NULL OR mint IN (
+                SELECT mint_acc FROM trades
+                WHERE resol = $2 AND datetime >= $3
+                GROUP BY mint_acc
+                HAVING SUM(volume) >= $1::float8
+            ))
+            AND ($4::timestamp IS NULL OR mint IN (
+                SELECT mint_acc FROM trades WHERE datetime >= $4 AND trade_count > 0
+            ))"#,
+            min_volume,
+            resolution as Resolution,
+            volume_since.naive_utc(),
+            active_since.map(|since| since.naive_utc())
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed(
+                "get_tokens_filtered",
+                (min_volume, resolution, volume_since, active_since),
+                query,
+            )
             .await?;
 
-        Ok(rows
-            .iter()
-            .map(|row| (row.get(0), parse_metadata_row(row, 1)))
-            .collect())
+        Ok(rows.into_iter().map(TokenRow::into_metadata).collect())
     }
 
     /// Read trades history.
@@ -47,199 +225,2190 @@ impl Db {
         timestamp: DateTime<Utc>,
         resolution: Resolution,
     ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
-        let rows = sqlx::query(
-            "
-            SELECT datetime, open_price, close_price, high_price, low_price, volume 
-            FROM trades 
+        let query = sqlx::query_as!(
+            CandleRow,
+            r#"
+            SELECT datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
             WHERE datetime >= $1 AND resol = $2 AND mint_acc = $3
-            ORDER BY datetime",
+            ORDER BY datetime"#,
+            timestamp.naive_utc(),
+            resolution as Resolution,
+            mint_acc
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed("trades_since", (mint_acc, timestamp, resolution), query)
+            .await?;
+
+        let trades = rows.into_iter().map(CandleRow::into_candle).collect();
+
+        Ok(trades)
+    }
+
+    /// Read at most `limit` earliest candles starting within `[from, to]`, all if not limited.
+    pub async fn trades_between(
+        &self,
+        mint_acc: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+        limit: Option<usize>,
+    ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+        let query = sqlx::query_as!(
+            CandleRow,
+            r#"
+            SELECT datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
+            WHERE datetime >= $1 AND datetime <= $2 AND resol = $3 AND mint_acc = $4
+            ORDER BY datetime
+            LIMIT $5"#,
+            from.naive_utc(),
+            to.naive_utc(),
+            resolution as Resolution,
+            mint_acc,
+            limit.map(|limit| limit as i64)
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed("trades_between", (mint_acc, from, to, resolution), query)
+            .await?;
+
+        Ok(rows.into_iter().map(CandleRow::into_candle).collect())
+    }
+
+    /// Read trades history for several resolutions in one query.
+    /// Every requested resolution is present in the result, even if it has no trades.
+    pub async fn trades_since_multi(
+        &self,
+        mint_acc: &str,
+        timestamp: DateTime<Utc>,
+        resolutions: &[Resolution],
+    ) -> anyhow::Result<HashMap<Resolution, BTreeMap<DateTime<Utc>, Candle>>> {
+        let query = sqlx::query!(
+            r#"
+            SELECT
+                resol as "resol: Resolution",
+                datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
+            WHERE datetime >= $1 AND resol = ANY($2) AND mint_acc = $3
+            ORDER BY datetime"#,
+            timestamp.naive_utc(),
+            resolutions as &[Resolution],
+            mint_acc
         )
-        .bind(timestamp)
-        .bind(resolution)
-        .bind(mint_acc)
-        .fetch_all(&self.pool)
-        .await?;
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed(
+                "trades_since_multi",
+                (mint_acc, timestamp, resolutions),
+                query,
+            )
+            .await?;
 
-        let mut trades = BTreeMap::new();
+        let mut trades: HashMap<_, BTreeMap<_, _>> = resolutions
+            .iter()
+            .map(|resolution| (*resolution, BTreeMap::new()))
+            .collect();
         for row in rows {
-            let datetime = row.get::<NaiveDateTime, _>(0).and_utc();
-            let open = row.get::<f64, _>(1);
-            let close = row.get::<f64, _>(2);
-            let high = row.get::<f64, _>(3);
-            let low = row.get::<f64, _>(4);
-            let volume = row.get::<f64, _>(5);
-
-            trades.insert(
-                datetime,
-                Candle {
-                    open,
-                    close,
-                    high,
-                    low,
-                    volume,
-                },
-            );
+            let candle_row = CandleRow {
+                datetime: row.datetime,
+                open_price: row.open_price,
+                close_price: row.close_price,
+                high_price: row.high_price,
+                low_price: row.low_price,
+                volume: row.volume,
+                trade_count: row.trade_count,
+                buy_volume: row.buy_volume,
+                sell_volume: row.sell_volume,
+                vwap: row.vwap,
+            };
+            let (datetime, candle) = candle_row.into_candle();
+            trades
+                .entry(row.resol)
+                .or_default()
+                .insert(datetime, candle);
         }
 
         Ok(trades)
     }
 
+    /// Read the last `n` candles of the token, however far back they span.
+    pub async fn last_n_candles(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+        n: usize,
+    ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+        let query = sqlx::query_as!(
+            CandleRow,
+            r#"
+            SELECT datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
+            WHERE resol = $1 AND mint_acc = $2
+            ORDER BY datetime DESC
+            LIMIT $3"#,
+            resolution as Resolution,
+            mint_acc,
+            n as i64
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed("last_n_candles", (mint_acc, resolution, n), query)
+            .await?;
+
+        // Map orders candles by time.
+        Ok(rows.into_iter().map(CandleRow::into_candle).collect())
+    }
+
+    /// Starts of the first and the last candles of the token,
+    /// `None` if the token was never traded.
+    pub async fn candle_time_range(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+        let query = sqlx::query!(
+            "SELECT MIN(datetime) as first, MAX(datetime) as last FROM trades
+            WHERE resol = $1 AND mint_acc = $2",
+            resolution as Resolution,
+            mint_acc
+        )
+        .fetch_one(&self.pool);
+        let row = self
+            .timed("candle_time_range", (mint_acc, resolution), query)
+            .await?;
+
+        Ok(row
+            .first
+            .zip(row.last)
+            .map(|(first, last)| (first.and_utc(), last.and_utc())))
+    }
+
+    /// Read last candle of every token with given resolution.
+    pub async fn latest_candles(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>, Candle)>> {
+        let query = sqlx::query!(
+            r#"
+            SELECT DISTINCT ON (mint_acc)
+                mint_acc, datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
+            WHERE resol = $1
+            ORDER BY mint_acc, datetime DESC
+            "#,
+            resolution as Resolution
+        )
+        .fetch_all(&self.pool);
+        let rows = self.timed("latest_candles", resolution, query).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let candle_row = CandleRow {
+                    datetime: row.datetime,
+                    open_price: row.open_price,
+                    close_price: row.close_price,
+                    high_price: row.high_price,
+                    low_price: row.low_price,
+                    volume: row.volume,
+                    trade_count: row.trade_count,
+                    buy_volume: row.buy_volume,
+                    sell_volume: row.sell_volume,
+                    vwap: row.vwap,
+                };
+                let (datetime, candle) = candle_row.into_candle();
+                (row.mint_acc, datetime, candle)
+            })
+            .collect())
+    }
+
+    /// Get tokens ranked by net buy pressure since timestamp, highest first.
+    pub async fn tokens_by_pressure(
+        &self,
+        timestamp: DateTime<Utc>,
+        resolution: Resolution,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(String, TokenMetadata, TokenPressure)>> {
+        let query = sqlx::query!(
+            r#"
+            SELECT
+                trades.mint_acc as "mint!",
+                token.name as "name?",
+                token.symbol as "symbol?",
+                token.uri as "uri?",
+                token.completed_at as "completed_at?",
+                token.decimals as "decimals?",
+                SUM(trades.buy_volume)::float8 as "buy_volume!",
+                SUM(trades.sell_volume)::float8 as "sell_volume!"
+            FROM trades
+            LEFT JOIN token ON token.mint = trades.mint_acc
+            WHERE trades.datetime >= $1 AND trades.resol = $2
+            GROUP BY trades.mint_acc, token.name, token.symbol, token.uri, token.completed_at,
+                token.decimals
+            ORDER BY SUM(trades.buy_volume) - SUM(trades.sell_volume) DESC
+            LIMIT $3"#,
+            timestamp.naive_utc(),
+            resolution as Resolution,
+            limit as i64
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed("tokens_by_pressure", (timestamp, resolution, limit), query)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let total_volume = row.buy_volume + row.sell_volume;
+                let pressure = TokenPressure {
+                    buy_volume: row.buy_volume,
+                    sell_volume: row.sell_volume,
+                    net_volume: row.buy_volume - row.sell_volume,
+                    // Candles without volume are neutral.
+                    buy_ratio: if total_volume > 0.0 {
+                        row.buy_volume / total_volume
+                    } else {
+                        0.5
+                    },
+                };
+                let token_row = TokenRow {
+                    mint: row.mint,
+                    name: row.name,
+                    symbol: row.symbol,
+                    uri: row.uri,
+                    completed_at: row.completed_at,
+                    decimals: row.decimals,
+                };
+                let (mint, metadata) = token_row.into_metadata();
+                (mint, metadata, pressure)
+            })
+            .collect())
+    }
+
     /// Read last trade.
     pub async fn last_trade(
         &self,
         mint_acc: &str,
         resolution: Resolution,
     ) -> anyhow::Result<(DateTime<Utc>, Candle)> {
-        let row = sqlx::query(
-            "
-            SELECT datetime, open_price, close_price, high_price, low_price, volume 
-            FROM trades 
+        let query = sqlx::query_as!(
+            CandleRow,
+            r#"
+            SELECT datetime, open_price, close_price, high_price, low_price,
+                volume::float8 as "volume!", trade_count,
+                buy_volume::float8 as "buy_volume!", sell_volume::float8 as "sell_volume!",
+                COALESCE(price_volume_sum / NULLIF(volume, 0), close_price)::float8 as "vwap!"
+            FROM trades
             WHERE resol = $1 AND mint_acc = $2
             ORDER BY datetime DESC
             LIMIT 1
-            ",
+            "#,
+            resolution as Resolution,
+            mint_acc
         )
-        .bind(resolution)
-        .bind(mint_acc)
-        .fetch_one(&self.pool)
-        .await?;
+        .fetch_one(&self.pool);
+        let row = self
+            .timed("last_trade", (mint_acc, resolution), query)
+            .await?;
 
-        let datetime = row.get::<NaiveDateTime, _>(0).and_utc();
-        let open = row.get::<f64, _>(1);
-        let close = row.get::<f64, _>(2);
-        let high = row.get::<f64, _>(3);
-        let low = row.get::<f64, _>(4);
-        let volume = row.get::<f64, _>(5);
+        Ok(row.into_candle())
+    }
 
-        let candle = Candle {
-            open,
-            close,
-            high,
-            low,
-            volume,
-        };
+    /// Insert trades into their candle buckets in a single query, see [`insert_trades`].
+    /// Returns number of newly created candles.
+    pub async fn insert_trades(&self, trades: &[BucketedTrade]) -> anyhow::Result<usize> {
+        let query = insert_trades(&self.pool, trades);
+        self.timed("insert_trades", trades.len(), query).await
+    }
+
+    /// Insert candles without trades with given close prices.
+    /// Existing candles are kept. Returns number of inserted candles.
+    pub async fn insert_empty_candles(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+        candles: &[(DateTime<Utc>, f64)],
+    ) -> anyhow::Result<usize> {
+        let (timestamps, prices): (Vec<_>, Vec<_>) = candles
+            .iter()
+            .map(|(datetime, price)| (datetime.naive_utc(), *price))
+            .unzip();
 
-        Ok((datetime, candle))
+        let query = sqlx::query!(
+            r#"
+            INSERT INTO trades (
+                datetime, mint_acc, resol,
+                open_price, close_price, high_price, low_price,
+                volume, trade_count, buy_volume, sell_volume, price_volume_sum
+            )
+            SELECT datetime, $2, $3, price, price, price, price, 0, 0, 0, 0, 0
+            FROM UNNEST($1::timestamp[], $4::float8[]) AS empty(datetime, price)
+            ON CONFLICT (datetime, mint_acc, resol) DO NOTHING"#,
+            &timestamps,
+            mint_acc,
+            resolution as Resolution,
+            &prices
+        )
+        .execute(&self.pool);
+        let inserted = self
+            .timed("insert_empty_candles", (mint_acc, resolution), query)
+            .await?
+            .rows_affected();
+
+        Ok(inserted as usize)
     }
 
-    /// Insert new trade.
-    pub async fn insert_trade(
+    /// Insert token metadata, unless stored one is better, see [`insert_token`].
+    pub async fn insert_token(
         &self,
-        timestamps: &[DateTime<Utc>],
-        info: TradeInfo,
-    ) -> anyhow::Result<()> {
-        let mint_acc: Vec<_> = (0..timestamps.len())
-            .map(|_| info.mint_acc.clone())
-            .collect();
-        let resol = Resolution::all();
+        mint_acc: String,
+        metadata: Option<TokenMetadata>,
+    ) -> anyhow::Result<TokenWrite> {
+        let params = mint_acc.clone();
+        let transaction = async {
+            // Transaction is rolled back on drop if not committed.
+            let mut tx = self.pool.begin().await?;
+            let (write, _) = insert_token(&mut tx, mint_acc, metadata).await?;
+            tx.commit().await?;
 
-        let price = (info.sol_amount as f64) / (info.token_amount as f64);
-        if !price.is_finite() {
-            anyhow::bail!("Bad price: {} / {}", info.sol_amount, info.token_amount);
+            Ok(write)
         };
+        self.timed("insert_token", params, transaction).await
+    }
 
-        let open_price: Vec<_> = (0..timestamps.len()).map(|_| price).collect();
-        let close_price: Vec<_> = (0..timestamps.len()).map(|_| price).collect();
-        let high_price: Vec<_> = (0..timestamps.len()).map(|_| price).collect();
-        let low_price: Vec<_> = (0..timestamps.len()).map(|_| price).collect();
-        let volume: Vec<_> = (0..timestamps.len())
-            .map(|_| info.token_amount as f64)
-            .collect();
+    /// Insert token metadata with its first trade in a single transaction,
+    /// so neither of them is stored without the other.
+    /// Trade is priced with decimals of the stored token, which may be inserted concurrently
+    /// with other decimals than of the given metadata.
+    pub async fn insert_first_trade_with_metadata(
+        &self,
+        buckets: &[(Resolution, DateTime<Utc>)],
+        mut info: TradeInfo,
+        metadata: Option<TokenMetadata>,
+    ) -> anyhow::Result<FirstTradeWrite> {
+        let params = info.mint_acc.clone();
+        let transaction = async {
+            // Transaction is rolled back on drop if not committed.
+            let mut tx = self.pool.begin().await?;
+            let (token_write, decimals) =
+                insert_token(&mut tx, info.mint_acc.clone(), metadata).await?;
+            info.decimals = decimals;
+            let new_candles = insert_trades(&mut *tx, &[(buckets.to_vec(), info)]).await?;
+            tx.commit().await?;
 
-        sqlx::query(
-            "INSERT INTO trades 
-        (
-            datetime,
+            Ok(FirstTradeWrite {
+                token: token_write,
+                decimals,
+                new_candles,
+            })
+        };
+        self.timed("insert_first_trade_with_metadata", params, transaction)
+            .await
+    }
+
+    /// Delete token with all its trades in a single transaction.
+    /// Returns number of deleted trades rows or `NotFound` error if token is unknown.
+    pub async fn delete_token(&self, mint_acc: &str) -> anyhow::Result<u64> {
+        let transaction = async {
+            // Transaction is rolled back on drop if not committed.
+            let mut tx = self.pool.begin().await?;
+            let trades = sqlx::query!("DELETE FROM trades WHERE mint_acc = $1", mint_acc)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            let tokens = sqlx::query!("DELETE FROM token WHERE mint = $1", mint_acc)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            if tokens == 0 {
+                return Err(NotFound(format!("Token not found for mint: {mint_acc}")).into());
+            }
+            tx.commit().await?;
+
+            Ok(trades)
+        };
+        self.timed("delete_token", mint_acc, transaction).await
+    }
+
+    /// Get mints of tokens with unresolved metadata.
+    /// Mints are ordered, so `after` can be used to iterate over them in batches.
+    pub async fn get_unresolved_tokens(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let query = sqlx::query_scalar!(
+            "SELECT mint FROM token WHERE name IS NULL AND mint > $1 ORDER BY mint LIMIT $2",
+            after,
+            limit as i64
+        )
+        .fetch_all(&self.pool);
+        let mints = self
+            .timed("get_unresolved_tokens", (after, limit), query)
+            .await?;
+
+        Ok(mints)
+    }
+
+    /// Get mints of tokens traded since timestamp.
+    /// Range scan of the primary key, which starts with `datetime`.
+    /// Materialized empty candles are not trades.
+    pub async fn active_mints(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<String>> {
+        let query = sqlx::query_scalar!(
+            "SELECT DISTINCT mint_acc FROM trades WHERE datetime >= $1 AND trade_count > 0",
+            since.naive_utc()
+        )
+        .fetch_all(&self.pool);
+        let mints = self.timed("active_mints", since, query).await?;
+
+        Ok(mints)
+    }
+
+    /// Search tokens by case-insensitive substring of name, symbol or mint, all if not set.
+    /// Page of tokens is ordered by the last trade in the given resolution candles, most recent
+    /// first. Returns the page and total number of matching tokens.
+    pub async fn search_tokens(
+        &self,
+        query: Option<&str>,
+        resolution: Resolution,
+        limit: usize,
+        offset: usize,
+    ) -> anyhow::Result<(Vec<(String, TokenMetadata)>, u64)> {
+        // Query is matched literally.
+        let pattern = query.map(|query| {
+            let escaped = query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{escaped}%")
+        });
+
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!" FROM token
+            WHERE $1::text IS NULL OR name ILIKE $1 OR symbol ILIKE $1 OR mint ILIKE $1"#,
+            pattern
+        )
+        .fetch_one(&self.pool);
+        let total = self.timed("search_tokens_count", query, count).await?;
+
+        let page = sqlx::query_as!(
+            TokenRow,
+            r#"
+            SELECT token.mint, token.name, token.symbol, token.uri, token.completed_at,
+                token.decimals
+            FROM token
+            LEFT JOIN (
+                SELECT mint_acc, MAX(datetime) AS last_traded FROM trades
+                WHERE resol = $2 AND trade_count > 0
+                GROUP BY mint_acc
+            ) activity ON activity.mint_acc = token.mint
+            WHERE $1::text IS NULL OR token.name ILIKE $1 OR token.symbol ILIKE $1
+                OR token.mint ILIKE $1
+            ORDER BY activity.last_traded DESC NULLS LAST, token.mint
+            LIMIT $3 OFFSET $4"#,
+            pattern,
+            resolution as Resolution,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool);
+        let rows = self
+            .timed("search_tokens", (query, limit, offset), page)
+            .await?;
+
+        Ok((
+            rows.into_iter().map(TokenRow::into_metadata).collect(),
+            total as u64,
+        ))
+    }
+
+    /// Get token metadata.
+    /// Returns `NotFound` error if token is unknown.
+    pub async fn get_token(&self, mint_acc: &str) -> anyhow::Result<TokenMetadata> {
+        let query = sqlx::query_as!(
+            TokenRow,
+            "SELECT mint, name, symbol, uri, completed_at, decimals FROM token WHERE mint = $1",
+            mint_acc
+        )
+        .fetch_optional(&self.pool);
+        let row = self.timed("get_token", mint_acc, query).await?;
+
+        match row {
+            Some(row) => Ok(row.into_metadata().1),
+            None => Err(NotFound(format!("Token metadata not found for mint: {mint_acc}")).into()),
+        }
+    }
+
+    /// Mark token bonding curve completed, keeping the first completion time.
+    /// Returns updated metadata, `NotFound` error if token is unknown.
+    pub async fn mark_completed(
+        &self,
+        mint_acc: &str,
+        completed_at: DateTime<Utc>,
+    ) -> anyhow::Result<TokenMetadata> {
+        let query = sqlx::query_as!(
+            TokenRow,
+            "UPDATE token SET completed_at = COALESCE(completed_at, $2)
+            WHERE mint = $1
+            RETURNING mint, name, symbol, uri, completed_at, decimals",
             mint_acc,
-            resol,
-            open_price,
-            close_price,
-            high_price,
-            low_price,
-            volume
-        )
-        SELECT * FROM UNNEST
-        (
-            $1::timestamp[],
-            $2::varchar[],
-            $3::resolution[],
-            $4::decimal[],
-            $5::decimal[],
-            $6::decimal[],
-            $7::decimal[],
-            $8::decimal[]
-        )
-        
-        ON CONFLICT (datetime, mint_acc, resol) DO UPDATE SET
-            open_price = trades.open_price,
-            close_price = EXCLUDED.close_price,
-            high_price = GREATEST(trades.high_price, EXCLUDED.high_price),
-            low_price = LEAST(trades.low_price, EXCLUDED.low_price),
-            volume = trades.volume + EXCLUDED.volume",
-        )
-        .bind(timestamps)
-        .bind(mint_acc)
-        .bind(resol)
-        .bind(open_price)
-        .bind(close_price)
-        .bind(high_price)
-        .bind(low_price)
-        .bind(volume)
-        .execute(&self.pool)
-        .await?;
+            completed_at.naive_utc()
+        )
+        .fetch_optional(&self.pool);
+        let row = self
+            .timed("mark_completed", (mint_acc, completed_at), query)
+            .await?;
 
+        match row {
+            Some(row) => Ok(row.into_metadata().1),
+            None => Err(NotFound(format!("Token not found for mint: {mint_acc}")).into()),
+        }
+    }
+
+    /// Check DB is reachable.
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
         Ok(())
     }
 
-    /// Insert token metadata.
-    pub async fn insert_token(
+    /// Whether token is stored.
+    pub async fn token_exists(&self, mint_acc: &str) -> anyhow::Result<bool> {
+        let query = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM token WHERE mint = $1) as "exists!""#,
+            mint_acc
+        )
+        .fetch_one(&self.pool);
+        let exists = self.timed("token_exists", mint_acc, query).await?;
+
+        Ok(exists)
+    }
+
+    /// Set retention of token data overriding the global one.
+    /// Returns `NotFound` error if token is unknown.
+    pub async fn set_token_retention(
         &self,
-        mint_acc: String,
-        metadata: Option<TokenMetadata>,
+        mint_acc: &str,
+        retention: Duration,
     ) -> anyhow::Result<()> {
-        if let Some(metadata) = metadata {
-            sqlx::query(
-                "INSERT INTO token (mint, name, symbol, uri) VALUES ($1, $2, $3, $4) ON CONFLICT (mint) DO UPDATE 
-        SET name = EXCLUDED.name, symbol = EXCLUDED.symbol, uri = EXCLUDED.uri",
-            )
-            .bind(&mint_acc)
-            .bind(metadata.name)
-            .bind(metadata.symbol)
-            .bind(metadata.uri)
-            .execute(&self.pool)
-            .await?;
-        } else {
-            sqlx::query("INSERT INTO token (mint) VALUES ($1) ON CONFLICT DO NOTHING")
-                .bind(&mint_acc)
-                .execute(&self.pool)
-                .await?;
+        let query = sqlx::query!(
+            "INSERT INTO token_retention (mint, retention_secs)
+            SELECT mint, $2 FROM token WHERE mint = $1
+            ON CONFLICT (mint) DO UPDATE SET retention_secs = EXCLUDED.retention_secs",
+            mint_acc,
+            retention.as_secs() as i64
+        )
+        .execute(&self.pool);
+        let updated = self
+            .timed("set_token_retention", (mint_acc, retention), query)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Err(NotFound(format!("Token not found for mint: {mint_acc}")).into());
         }
 
         Ok(())
     }
 
-    pub async fn get_token(&self, mint_acc: &str) -> anyhow::Result<TokenMetadata> {
-        let row = sqlx::query("SELECT name, symbol, uri FROM token WHERE mint = $1")
-            .bind(mint_acc)
-            .fetch_optional(&self.pool)
+    /// Read retention overrides of all tokens.
+    pub async fn token_retentions(&self) -> anyhow::Result<Vec<(String, Duration)>> {
+        let query =
+            sqlx::query!("SELECT mint, retention_secs FROM token_retention").fetch_all(&self.pool);
+        let rows = self.timed("token_retentions", (), query).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.mint, Duration::from_secs(row.retention_secs as u64)))
+            .collect())
+    }
+
+    /// Record start of an indexer session.
+    /// Returns session id.
+    pub async fn start_session(&self, started_at: DateTime<Utc>) -> anyhow::Result<i64> {
+        let query = sqlx::query_scalar!(
+            "INSERT INTO sessions (started_at) VALUES ($1) RETURNING id",
+            started_at.naive_utc()
+        )
+        .fetch_one(&self.pool);
+        let id = self.timed("start_session", started_at, query).await?;
+
+        Ok(id)
+    }
+
+    /// Update time of the last event handled in the session.
+    pub async fn touch_session(&self, id: i64, last_event_at: DateTime<Utc>) -> anyhow::Result<()> {
+        let query = sqlx::query!(
+            "UPDATE sessions SET last_event_at = $2 WHERE id = $1",
+            id,
+            last_event_at.naive_utc()
+        )
+        .execute(&self.pool);
+        self.timed("touch_session", (id, last_event_at), query)
             .await?;
 
-        match row {
-            Some(row) => Ok(parse_metadata_row(&row, 0)),
-            None => anyhow::bail!("Token metadata not found for mint: {}", mint_acc),
+        Ok(())
+    }
+
+    /// Record end of the session.
+    pub async fn end_session(&self, id: i64, ended_at: DateTime<Utc>) -> anyhow::Result<()> {
+        let query = sqlx::query!(
+            "UPDATE sessions SET ended_at = $2 WHERE id = $1",
+            id,
+            ended_at.naive_utc()
+        )
+        .execute(&self.pool);
+        self.timed("end_session", (id, ended_at), query).await?;
+
+        Ok(())
+    }
+
+    /// Read the latest sessions, newest first.
+    pub async fn recent_sessions(&self, limit: usize) -> anyhow::Result<Vec<Session>> {
+        let query = sqlx::query!(
+            "SELECT id, started_at, ended_at, last_event_at FROM sessions
+            ORDER BY started_at DESC LIMIT $1",
+            limit as i64
+        )
+        .fetch_all(&self.pool);
+        let rows = self.timed("recent_sessions", limit, query).await?;
+
+        let millis = |datetime: NaiveDateTime| datetime.and_utc().timestamp_millis() as u64;
+        Ok(rows
+            .into_iter()
+            .map(|row| Session {
+                id: row.id,
+                started_at: millis(row.started_at),
+                ended_at: row.ended_at.map(millis),
+                last_event_at: row.last_event_at.map(millis),
+            })
+            .collect())
+    }
+}
+
+/// Token table row.
+struct TokenRow {
+    mint: String,
+    name: Option<String>,
+    symbol: Option<String>,
+    uri: Option<String>,
+    completed_at: Option<NaiveDateTime>,
+    decimals: Option<i16>,
+}
+
+impl TokenRow {
+    /// Convert row into mint and metadata, using fallback values for unresolved metadata.
+    fn into_metadata(self) -> (String, TokenMetadata) {
+        let metadata = TokenMetadata {
+            name: self.name.unwrap_or_else(|| String::from("unknown")),
+            symbol: self.symbol.unwrap_or_else(|| String::from("NAN")),
+            uri: self.uri.unwrap_or_else(|| String::from("unknown")),
+            completed_at: self
+                .completed_at
+                .map(|datetime| datetime.and_utc().timestamp_millis() as u64),
+            decimals: self
+                .decimals
+                .and_then(|decimals| u8::try_from(decimals).ok()),
+        };
+        (self.mint, metadata)
+    }
+}
+
+/// Trades table row with a candle.
+struct CandleRow {
+    datetime: NaiveDateTime,
+    open_price: f64,
+    close_price: f64,
+    high_price: f64,
+    low_price: f64,
+    volume: f64,
+    trade_count: i64,
+    buy_volume: f64,
+    sell_volume: f64,
+    vwap: f64,
+}
+
+impl CandleRow {
+    /// Convert row into candle with its timestamp.
+    fn into_candle(self) -> (DateTime<Utc>, Candle) {
+        let candle = Candle {
+            open: self.open_price,
+            close: self.close_price,
+            high: self.high_price,
+            low: self.low_price,
+            volume: self.volume,
+            trade_count: self.trade_count as u64,
+            buy_volume: self.buy_volume,
+            sell_volume: self.sell_volume,
+            vwap: self.vwap,
+        };
+        (self.datetime.and_utc(), candle)
+    }
+}
+
+/// Trades of a candle aggregated for a single upsert row, see [`insert_trades`].
+struct CandleDelta {
+    datetime: NaiveDateTime,
+    mint_acc: String,
+    resolution: Resolution,
+    open_price: f64,
+    open_at: NaiveDateTime,
+    close_price: f64,
+    close_at: NaiveDateTime,
+    high_price: f64,
+    low_price: f64,
+    volume: i64,
+    trade_count: i64,
+    buy_volume: i64,
+    sell_volume: i64,
+    /// SOL amount, scaled by ten to `price_exponent` on insertion into price × volume.
+    price_volume_sum: i64,
+    price_exponent: i32,
+}
+
+impl CandleDelta {
+    /// Candle of a single trade.
+    fn new(
+        datetime: DateTime<Utc>,
+        resolution: Resolution,
+        info: &TradeInfo,
+    ) -> anyhow::Result<Self> {
+        let price = info.price()?;
+        let trade_at = info.timestamp.naive_utc();
+        // Volumes are accumulated exactly in integer base units, see `Candle`.
+        let token_amount = i64::try_from(info.token_amount)?;
+        let (buy_volume, sell_volume) = if info.is_buy {
+            (token_amount, 0)
+        } else {
+            (0, token_amount)
+        };
+
+        Ok(Self {
+            datetime: datetime.naive_utc(),
+            mint_acc: info.mint_acc.clone(),
+            resolution,
+            open_price: price,
+            open_at: trade_at,
+            close_price: price,
+            close_at: trade_at,
+            high_price: price,
+            low_price: price,
+            volume: token_amount,
+            trade_count: 1,
+            buy_volume,
+            sell_volume,
+            price_volume_sum: i64::try_from(info.sol_amount)?,
+            price_exponent: info.price_exponent(),
+        })
+    }
+
+    /// Convert prices into the scale of given exponent.
+    /// Price volume sum is in lamports, so it's scaled on insertion with the rest.
+    fn rescale(&mut self, price_exponent: i32) {
+        let factor = 10f64.powi(price_exponent - self.price_exponent);
+        self.open_price *= factor;
+        self.close_price *= factor;
+        self.high_price *= factor;
+        self.low_price *= factor;
+        self.price_exponent = price_exponent;
+    }
+
+    /// Add candle of a later arrived trade, the same way conflicting rows are merged on upsert.
+    /// Trades of a token priced with other decimals, e.g. read before the stored ones were set,
+    /// are rescaled to the decimals of this candle, so other tokens of the batch are unaffected.
+    fn merge(&mut self, mut other: Self) -> anyhow::Result<()> {
+        if other.price_exponent != self.price_exponent {
+            tracing::warn!(
+                "Decimals of {} differ between trades, rescaling prices by 10^{}.",
+                self.mint_acc,
+                self.price_exponent - other.price_exponent
+            );
+            other.rescale(self.price_exponent);
+        }
+        if other.open_at < self.open_at {
+            self.open_price = other.open_price;
+            self.open_at = other.open_at;
+        }
+        if other.close_at >= self.close_at {
+            self.close_price = other.close_price;
+            self.close_at = other.close_at;
         }
+        self.high_price = self.high_price.max(other.high_price);
+        self.low_price = self.low_price.min(other.low_price);
+        let overflow = || anyhow::anyhow!("Volume overflow of {}", self.mint_acc);
+        self.volume = self.volume.checked_add(other.volume).ok_or_else(overflow)?;
+        self.trade_count += other.trade_count;
+        self.buy_volume = self
+            .buy_volume
+            .checked_add(other.buy_volume)
+            .ok_or_else(overflow)?;
+        self.sell_volume = self
+            .sell_volume
+            .checked_add(other.sell_volume)
+            .ok_or_else(overflow)?;
+        self.price_volume_sum = self
+            .price_volume_sum
+            .checked_add(other.price_volume_sum)
+            .ok_or_else(overflow)?;
+        Ok(())
+    }
+}
+
+/// Insert trades into their candle buckets in a single query using executor.
+/// Returns number of newly created candles.
+///
+/// A row can't be upserted twice by one query, so trades of the same candle are aggregated first.
+/// Trades may arrive out of order, so open and close prices are of the earliest and
+/// the latest trades by time, rather than by arrival. Ties keep arrival order.
+/// Prices are rescaled to decimals of the stored token, which are locked until the end of
+/// transaction, so trades priced before the decimals were set can't mix scales of a candle.
+async fn insert_trades<'e>(
+    executor: impl PgExecutor<'e>,
+    trades: &[BucketedTrade],
+) -> anyhow::Result<usize> {
+    let mut candles: Vec<CandleDelta> = Vec::new();
+    let mut candle_idx: HashMap<_, usize> = HashMap::new();
+    for (buckets, info) in trades {
+        for (resolution, datetime) in buckets {
+            let delta = CandleDelta::new(*datetime, *resolution, info)?;
+            match candle_idx.entry((info.mint_acc.as_str(), *resolution, *datetime)) {
+                Entry::Occupied(idx) => candles[*idx.get()].merge(delta)?,
+                Entry::Vacant(idx) => {
+                    idx.insert(candles.len());
+                    candles.push(delta);
+                }
+            }
+        }
+    }
+
+    fn column<T>(candles: &[CandleDelta], value: impl Fn(&CandleDelta) -> T) -> Vec<T> {
+        candles.iter().map(value).collect()
     }
+
+    let rows = sqlx::query(
+        "INSERT INTO trades 
+    (
+        datetime,
+        mint_acc,
+        resol,
+        open_price,
+        close_price,
+        high_price,
+        low_price,
+        volume,
+        trade_count,
+        buy_volume,
+        sell_volume,
+        price_volume_sum,
+        open_at,
+        close_at
+    )
+    WITH stored AS (
+        SELECT mint, COALESCE(decimals - $16::int4, 0) AS price_exponent
+        FROM token WHERE mint = ANY($2) FOR SHARE
+    )
+    SELECT datetime, mint_acc, resol,
+        open_price * factor, close_price * factor, high_price * factor, low_price * factor,
+        volume, trade_count, buy_volume, sell_volume,
+        price_volume_sum * power(10::numeric, priced.price_exponent), open_at, close_at
+    FROM UNNEST
+    (
+        $1::timestamp[],
+        $2::varchar[],
+        $3::resolution[],
+        $4::float8[],
+        $5::float8[],
+        $6::float8[],
+        $7::float8[],
+        $8::int8[],
+        $9::int8[],
+        $10::int8[],
+        $11::int8[],
+        $12::int8[],
+        $13::timestamp[],
+        $14::timestamp[],
+        $15::int4[]
+    ) AS delta(
+        datetime, mint_acc, resol, open_price, close_price, high_price, low_price,
+        volume, trade_count, buy_volume, sell_volume, price_volume_sum, open_at, close_at,
+        price_exponent
+    )
+    LEFT JOIN stored ON stored.mint = delta.mint_acc,
+    LATERAL (
+        SELECT COALESCE(stored.price_exponent, delta.price_exponent) AS price_exponent,
+            power(
+                10::float8,
+                COALESCE(stored.price_exponent, delta.price_exponent) - delta.price_exponent
+            ) AS factor
+    ) AS priced
+
+    ON CONFLICT (datetime, mint_acc, resol) DO UPDATE SET
+        open_price = CASE WHEN EXCLUDED.open_at < COALESCE(trades.open_at, trades.datetime)
+            THEN EXCLUDED.open_price ELSE trades.open_price END,
+        close_price = CASE WHEN EXCLUDED.close_at >= COALESCE(trades.close_at, trades.datetime)
+            THEN EXCLUDED.close_price ELSE trades.close_price END,
+        open_at = LEAST(COALESCE(trades.open_at, trades.datetime), EXCLUDED.open_at),
+        close_at = GREATEST(COALESCE(trades.close_at, trades.datetime), EXCLUDED.close_at),
+        high_price = GREATEST(trades.high_price, EXCLUDED.high_price),
+        low_price = LEAST(trades.low_price, EXCLUDED.low_price),
+        volume = trades.volume + EXCLUDED.volume,
+        trade_count = trades.trade_count + EXCLUDED.trade_count,
+        buy_volume = trades.buy_volume + EXCLUDED.buy_volume,
+        sell_volume = trades.sell_volume + EXCLUDED.sell_volume,
+        price_volume_sum = trades.price_volume_sum + EXCLUDED.price_volume_sum
+    RETURNING (xmax = 0) AS inserted",
+    )
+    .bind(column(&candles, |candle| candle.datetime))
+    .bind(column(&candles, |candle| candle.mint_acc.clone()))
+    .bind(column(&candles, |candle| candle.resolution))
+    .bind(column(&candles, |candle| candle.open_price))
+    .bind(column(&candles, |candle| candle.close_price))
+    .bind(column(&candles, |candle| candle.high_price))
+    .bind(column(&candles, |candle| candle.low_price))
+    .bind(column(&candles, |candle| candle.volume))
+    .bind(column(&candles, |candle| candle.trade_count))
+    .bind(column(&candles, |candle| candle.buy_volume))
+    .bind(column(&candles, |candle| candle.sell_volume))
+    .bind(column(&candles, |candle| candle.price_volume_sum))
+    .bind(column(&candles, |candle| candle.open_at))
+    .bind(column(&candles, |candle| candle.close_at))
+    .bind(column(&candles, |candle| candle.price_exponent))
+    .bind(SOL_DECIMALS)
+    .fetch_all(executor)
+    .await?;
+
+    // `xmax` is zero only for freshly inserted row versions.
+    Ok(rows
+        .iter()
+        .filter(|row| row.get::<bool, _>("inserted"))
+        .count())
+}
+
+/// Insert token metadata using given executor, which should be a transaction.
+/// Returns decimals of the stored token, which trades of the token are priced with.
+///
+/// Metadata is ranked: unresolved (`NULL` name) < resolved with empty name < resolved.
+/// Stored metadata is replaced only by metadata of the same or higher rank, so concurrent
+/// first sights of a token end with the best metadata whatever order they commit in.
+/// Decimals are set once known and never changed. Stored candles of a token with unknown
+/// decimals are raw base units ratios, so they're rescaled when decimals are set,
+/// keeping one scale of prices of the token.
+async fn insert_token(
+    executor: &mut PgConnection,
+    mint_acc: String,
+    metadata: Option<TokenMetadata>,
+) -> anyhow::Result<(TokenWrite, Option<u8>)> {
+    let (write, decimals) = match metadata {
+        Some(metadata) => {
+            // Nothing is returned on conflict, waiting for a concurrent insertion to commit.
+            let inserted = sqlx::query_scalar!(
+                "INSERT INTO token (mint, name, symbol, uri, decimals) VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT DO NOTHING RETURNING decimals",
+                mint_acc,
+                metadata.name,
+                metadata.symbol,
+                metadata.uri,
+                metadata.decimals.map(i16::from)
+            )
+            .fetch_optional(&mut *executor)
+            .await?;
+            match inserted {
+                Some(decimals) => (TokenWrite::Inserted, decimals),
+                None => update_token(executor, &mint_acc, metadata).await?,
+            }
+        }
+        None => {
+            // Unresolved metadata never replaces stored one.
+            let inserted = sqlx::query_scalar!(
+                "INSERT INTO token (mint) VALUES ($1) ON CONFLICT DO NOTHING RETURNING decimals",
+                mint_acc
+            )
+            .fetch_optional(&mut *executor)
+            .await?;
+            match inserted {
+                Some(decimals) => (TokenWrite::Inserted, decimals),
+                None => {
+                    let decimals =
+                        sqlx::query_scalar!("SELECT decimals FROM token WHERE mint = $1", mint_acc)
+                            .fetch_one(&mut *executor)
+                            .await?;
+                    (TokenWrite::Kept, decimals)
+                }
+            }
+        }
+    };
+
+    Ok((
+        write,
+        decimals.and_then(|decimals: i16| u8::try_from(decimals).ok()),
+    ))
+}
+
+/// Replace metadata of the stored token, unless it's better, see [`insert_token`].
+/// Stored candles are rescaled if decimals of the token are set.
+async fn update_token(
+    executor: &mut PgConnection,
+    mint_acc: &str,
+    metadata: TokenMetadata,
+) -> anyhow::Result<(TokenWrite, Option<i16>)> {
+    // Locked until the end of transaction, so trades are inserted either before
+    // the candles are rescaled or after, with the set decimals.
+    let stored = sqlx::query_scalar!(
+        "SELECT decimals FROM token WHERE mint = $1 FOR UPDATE",
+        mint_acc
+    )
+    .fetch_one(&mut *executor)
+    .await?;
+
+    let updated = sqlx::query_scalar!(
+        "UPDATE token SET name = $2, symbol = $3, uri = $4, decimals = COALESCE(decimals, $5)
+    WHERE mint = $1
+        AND (CASE WHEN $2::varchar IS NULL THEN 0 WHEN $2 = '' THEN 1 ELSE 2 END)
+            >= (CASE WHEN name IS NULL THEN 0 WHEN name = '' THEN 1 ELSE 2 END)
+    RETURNING decimals",
+        mint_acc,
+        metadata.name,
+        metadata.symbol,
+        metadata.uri,
+        metadata.decimals.map(i16::from)
+    )
+    .fetch_optional(&mut *executor)
+    .await?;
+
+    let Some(decimals) = updated else {
+        return Ok((TokenWrite::Kept, stored));
+    };
+    let (None, Some(set)) = (stored, decimals) else {
+        return Ok((TokenWrite::Updated, decimals));
+    };
+
+    let price_exponent = i32::from(set) - SOL_DECIMALS;
+    sqlx::query!(
+        "UPDATE trades SET
+        open_price = open_price * power(10::float8, $2::int4),
+        close_price = close_price * power(10::float8, $2::int4),
+        high_price = high_price * power(10::float8, $2::int4),
+        low_price = low_price * power(10::float8, $2::int4),
+        price_volume_sum = price_volume_sum * power(10::numeric, $2::int4)
+    WHERE mint_acc = $1",
+        mint_acc,
+        price_exponent
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    Ok((TokenWrite::Rescaled, decimals))
 }
 
-fn parse_metadata_row(row: &PgRow, fields_offset: usize) -> TokenMetadata {
-    let name = row
-        .try_get(fields_offset)
-        .unwrap_or_else(|_| String::from("unknown"));
-    let symbol = row
-        .try_get(fields_offset + 1)
-        .unwrap_or_else(|_| String::from("NAN"));
-    let uri = row
-        .try_get(fields_offset + 2)
-        .unwrap_or_else(|_| String::from("unknown"));
-    TokenMetadata { name, symbol, uri }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "5xYzTokenMintAddressForDbTestsPump";
+
+    fn test_db(pool: PgPool) -> Db {
+        Db::from_pool(pool, Default::default(), Duration::from_secs(10))
+    }
+
+    fn trade(secs: i64, sol_amount: u64, token_amount: u64, is_buy: bool) -> TradeInfo {
+        TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount,
+            token_amount,
+            is_buy,
+            decimals: None,
+        }
+    }
+
+    /// Trade bucketed into its M1 candle.
+    fn bucketed(info: TradeInfo) -> BucketedTrade {
+        let bucket = Resolution::M1.bucket_start(info.timestamp);
+        (vec![(Resolution::M1, bucket)], info)
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn bad_trade_fails_only_alone(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let good = bucketed(trade(1_700_000_000, 1_000, 2_000, true));
+        // Zero token amount makes infinite price.
+        let bad = bucketed(trade(1_700_000_100, 1_000, 0, true));
+
+        assert!(
+            db.insert_trades(&[good.clone(), bad.clone()])
+                .await
+                .is_err()
+        );
+        assert_eq!(db.insert_trades(&[good]).await.unwrap(), 1);
+        assert!(db.insert_trades(&[bad]).await.is_err());
+        let from = DateTime::from_timestamp(0, 0).unwrap();
+        let candles = db.trades_since(MINT, from, Resolution::M1).await.unwrap();
+        assert_eq!(candles.len(), 1);
+    }
+
+    fn metadata(decimals: Option<u8>) -> TokenMetadata {
+        TokenMetadata {
+            name: String::from("Token"),
+            symbol: String::from("TKN"),
+            uri: String::from("https://example.com/token.json"),
+            completed_at: None,
+            decimals,
+        }
+    }
+
+    /// Close prices of M1 candles of the test token.
+    async fn closes(db: &Db) -> Vec<f64> {
+        let from = DateTime::from_timestamp(0, 0).unwrap();
+        let candles = db.trades_since(MINT, from, Resolution::M1).await.unwrap();
+        candles.values().map(|candle| candle.close).collect()
+    }
+
+    fn assert_price(price: f64, expected: f64) {
+        assert!(
+            (price - expected).abs() <= expected * 1e-12,
+            "{price} != {expected}"
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn first_trade_is_priced_by_decimals(pool: PgPool) {
+        let db = test_db(pool);
+        // 1 SOL for 1000 whole tokens of 6 decimals.
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true));
+        let info = TradeInfo {
+            decimals: Some(6),
+            ..info
+        };
+
+        let write = db
+            .insert_first_trade_with_metadata(&buckets, info, Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        assert_eq!(write.token, TokenWrite::Inserted);
+        assert_eq!(write.decimals, Some(6));
+        assert_eq!(write.new_candles, 1);
+        assert_eq!(db.get_token(MINT).await.unwrap().decimals, Some(6));
+        assert_price(closes(&db).await[0], 0.001);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn unknown_decimals_keep_raw_ratio(pool: PgPool) {
+        let db = test_db(pool);
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000, 4_000, true));
+
+        let write = db
+            .insert_first_trade_with_metadata(&buckets, info, None)
+            .await
+            .unwrap();
+        assert_eq!(write.decimals, None);
+        assert_eq!(db.get_token(MINT).await.unwrap().decimals, None);
+        assert_price(closes(&db).await[0], 0.25);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn first_trade_is_priced_by_stored_decimals(pool: PgPool) {
+        let db = test_db(pool);
+        // Token is inserted concurrently with other decimals.
+        db.insert_token(MINT.to_string(), Some(metadata(Some(9))))
+            .await
+            .unwrap();
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true));
+        let info = TradeInfo {
+            decimals: Some(6),
+            ..info
+        };
+
+        let write = db
+            .insert_first_trade_with_metadata(&buckets, info, Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        assert_eq!(write.token, TokenWrite::Updated);
+        assert_eq!(write.decimals, Some(9));
+        assert_eq!(db.get_token(MINT).await.unwrap().decimals, Some(9));
+        assert_price(closes(&db).await[0], 1.0);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn stored_candles_are_rescaled_when_decimals_are_set(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true));
+        db.insert_trades(&[(buckets.clone(), info)]).await.unwrap();
+        assert_price(closes(&db).await[0], 1.0);
+
+        let write = db
+            .insert_token(MINT.to_string(), Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        assert_eq!(write, TokenWrite::Rescaled);
+        assert_eq!(db.get_token(MINT).await.unwrap().decimals, Some(6));
+        assert_price(closes(&db).await[0], 0.001);
+
+        // Priced as raw ratio before decimals were set.
+        let later = trade(1_700_000_001, 2_000_000_000, 1_000_000_000, true);
+        db.insert_trades(&[(buckets, later)]).await.unwrap();
+        assert_price(closes(&db).await[0], 0.002);
+
+        let write = db
+            .insert_token(MINT.to_string(), Some(metadata(Some(9))))
+            .await
+            .unwrap();
+        assert_eq!(write, TokenWrite::Updated);
+        db.insert_token(MINT.to_string(), Some(metadata(None)))
+            .await
+            .unwrap();
+        assert_eq!(db.get_token(MINT).await.unwrap().decimals, Some(6));
+        assert_price(closes(&db).await[0], 0.002);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn batch_with_mismatched_decimals_is_rescaled(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        let (buckets, first) = bucketed(trade(1_700_000_000, 1_000_000_000, 1_000_000_000, true));
+        let first = TradeInfo {
+            decimals: Some(6),
+            ..first
+        };
+        // Priced as raw ratio, e.g. read before decimals were stored.
+        let second = trade(1_700_000_001, 2_000_000_000, 1_000_000_000, true);
+
+        db.insert_trades(&[(buckets.clone(), first), (buckets, second)])
+            .await
+            .unwrap();
+        assert_price(closes(&db).await[0], 0.002);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn token_is_found_by_mint(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        db.insert_token(String::from("OtherMint"), None)
+            .await
+            .unwrap();
+
+        let token = db.get_token(MINT).await.unwrap();
+        assert_eq!(token.name, "Token");
+        assert_eq!(token.symbol, "TKN");
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn unknown_token_is_not_found(pool: PgPool) {
+        let db = test_db(pool);
+
+        let e = db.get_token(MINT).await.unwrap_err();
+        assert!(e.is::<NotFound>());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn fallback_token_gets_resolved(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        assert_eq!(db.get_token(MINT).await.unwrap().name, "unknown");
+        assert_eq!(db.get_unresolved_tokens("", 10).await.unwrap(), [MINT]);
+
+        let write = db
+            .insert_token(MINT.to_string(), Some(metadata(None)))
+            .await
+            .unwrap();
+        assert_eq!(write, TokenWrite::Updated);
+        assert_eq!(db.get_token(MINT).await.unwrap().name, "Token");
+        assert!(db.get_unresolved_tokens("", 10).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn unresolved_tokens_are_paged_by_mint(pool: PgPool) {
+        let db = test_db(pool);
+        for mint in ["a", "b", "c"] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+        }
+        db.insert_token(String::from("resolved"), Some(metadata(None)))
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_unresolved_tokens("", 2).await.unwrap(), ["a", "b"]);
+        assert_eq!(db.get_unresolved_tokens("b", 2).await.unwrap(), ["c"]);
+    }
+
+    /// M1 candles of the test token.
+    async fn candles(db: &Db) -> Vec<Candle> {
+        let from = DateTime::from_timestamp(0, 0).unwrap();
+        let candles = db.trades_since(MINT, from, Resolution::M1).await.unwrap();
+        candles.into_values().collect()
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn trade_count_is_incremented(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+
+        // Separate inserts into one bucket.
+        for secs in [1_700_000_000, 1_700_000_010] {
+            db.insert_trades(&[bucketed(trade(secs, 1_000, 2_000, true))])
+                .await
+                .unwrap();
+        }
+        assert_eq!(candles(&db).await[0].trade_count, 2);
+
+        // Batched inserts, aggregated before the upsert.
+        let batch: Vec<_> = (0..3)
+            .map(|i| bucketed(trade(1_700_000_020 + i, 1_000, 2_000, false)))
+            .collect();
+        db.insert_trades(&batch).await.unwrap();
+        assert_eq!(candles(&db).await[0].trade_count, 5);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn failed_first_trade_rolls_back_token(pool: PgPool) {
+        let db = test_db(pool);
+        // Zero token amount makes infinite price, which fails after the token is inserted.
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000, 0, true));
+        assert!(
+            db.insert_first_trade_with_metadata(&buckets, info, Some(metadata(Some(6))))
+                .await
+                .is_err()
+        );
+        assert!(db.get_token(MINT).await.is_err());
+        assert!(candles(&db).await.is_empty());
+
+        let (buckets, info) = bucketed(trade(1_700_000_000, 1_000, 2_000, true));
+        let write = db
+            .insert_first_trade_with_metadata(&buckets, info, Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        assert_eq!(write.token, TokenWrite::Inserted);
+        assert_eq!(db.get_token(MINT).await.unwrap().name, "Token");
+        assert_eq!(candles(&db).await.len(), 1);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn tokens_are_listed_with_metadata(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        db.insert_token(String::from("OtherMint"), None)
+            .await
+            .unwrap();
+
+        let mut tokens = db.get_tokens().await.unwrap();
+        tokens.sort_by(|a, b| a.0.cmp(&b.0));
+        let [(mint, token), (other_mint, other)] = &tokens[..] else {
+            panic!("expected two tokens, got {tokens:?}");
+        };
+        assert_eq!(mint, MINT);
+        assert_eq!(token.name, "Token");
+        assert_eq!(token.symbol, "TKN");
+        assert_eq!(token.uri, "https://example.com/token.json");
+        assert_eq!(token.decimals, Some(6));
+        assert_eq!(token.completed_at, None);
+        // Unresolved metadata gets fallback values.
+        assert_eq!(other_mint, "OtherMint");
+        assert_eq!(other.name, "unknown");
+        assert_eq!(other.symbol, "NAN");
+        assert_eq!(other.decimals, None);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn last_trade_maps_ohlc_columns(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Prices 0.5, 2, 0.25 and 1 in one minute.
+        let trades = [
+            trade(1_700_000_000, 1_000, 2_000, true),
+            trade(1_700_000_001, 4_000, 2_000, true),
+            trade(1_700_000_002, 500, 2_000, false),
+            trade(1_700_000_003, 2_000, 2_000, false),
+        ];
+        let trades: Vec<_> = trades.into_iter().map(bucketed).collect();
+        db.insert_trades(&trades).await.unwrap();
+
+        let (datetime, candle) = db.last_trade(MINT, Resolution::M1).await.unwrap();
+        assert_eq!(
+            datetime,
+            DateTime::from_timestamp(1_699_999_980, 0).unwrap()
+        );
+        assert_price(candle.open, 0.5);
+        assert_price(candle.high, 2.0);
+        assert_price(candle.low, 0.25);
+        assert_price(candle.close, 1.0);
+        assert_eq!(candle.trade_count, 4);
+        assert_eq!(candle.volume, 8_000.0);
+        assert_eq!(candle.buy_volume, 4_000.0);
+        assert_eq!(candle.sell_volume, 4_000.0);
+
+        // The latest candle is read.
+        db.insert_trades(&[bucketed(trade(1_700_000_100, 3_000, 2_000, true))])
+            .await
+            .unwrap();
+        let (datetime, candle) = db.last_trade(MINT, Resolution::M1).await.unwrap();
+        assert_eq!(
+            datetime,
+            DateTime::from_timestamp(1_700_000_100, 0).unwrap()
+        );
+        assert_price(candle.open, 1.5);
+        assert_eq!(candle.trade_count, 1);
+
+        assert!(db.last_trade(MINT, Resolution::H1).await.is_err());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn tiny_prices_survive_round_trip(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // 1 lamport for 1e10 and 1e12 raw tokens.
+        db.insert_trades(&[bucketed(trade(1_700_000_000, 1, 10_000_000_000, true))])
+            .await
+            .unwrap();
+        db.insert_trades(&[bucketed(trade(1_700_000_100, 1, 1_000_000_000_000, true))])
+            .await
+            .unwrap();
+
+        let candles = candles(&db).await;
+        assert_price(candles[0].close, 1e-10);
+        assert_price(candles[0].vwap, 1e-10);
+        assert_price(candles[1].low, 1e-12);
+        assert_price(candles[1].high, 1e-12);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn candles_are_partitioned_by_resolution(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Two minutes of one 5-minute bucket.
+        let trades: Vec<_> = [1_699_999_860, 1_699_999_920]
+            .into_iter()
+            .map(|secs| {
+                let info = trade(secs, 1_000, 2_000, true);
+                let buckets = [Resolution::M1, Resolution::M5]
+                    .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)));
+                (buckets.to_vec(), info)
+            })
+            .collect();
+        db.insert_trades(&trades).await.unwrap();
+
+        let from = DateTime::from_timestamp(0, 0).unwrap();
+        let candles = db
+            .trades_since_multi(
+                MINT,
+                from,
+                &[Resolution::M1, Resolution::M5, Resolution::H1],
+            )
+            .await
+            .unwrap();
+        assert_eq!(candles.len(), 3);
+        assert_eq!(candles[&Resolution::M1].len(), 2);
+        assert!(candles[&Resolution::H1].is_empty());
+        let m5: Vec<_> = candles[&Resolution::M5].iter().collect();
+        let [(datetime, candle)] = m5[..] else {
+            panic!("expected one M5 candle, got {m5:?}");
+        };
+        assert_eq!(
+            *datetime,
+            DateTime::from_timestamp(1_699_999_800, 0).unwrap()
+        );
+        assert_eq!(candle.trade_count, 2);
+
+        // Same shape as single resolution reads.
+        for resolution in [Resolution::M1, Resolution::M5] {
+            let single = db.trades_since(MINT, from, resolution).await.unwrap();
+            let single: Vec<_> = single
+                .iter()
+                .map(|(datetime, c)| (*datetime, c.close))
+                .collect();
+            let multi: Vec<_> = candles[&resolution]
+                .iter()
+                .map(|(datetime, c)| (*datetime, c.close))
+                .collect();
+            assert_eq!(single, multi);
+        }
+    }
+
+    /// Trade of given mint bucketed into its M1 candle.
+    fn mint_trade(mint: &str, secs: i64, token_amount: u64, is_buy: bool) -> BucketedTrade {
+        let mut info = trade(secs, 1_000, token_amount, is_buy);
+        info.mint_acc = mint.to_string();
+        bucketed(info)
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn tokens_are_ranked_by_pressure(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(String::from("Buys"), Some(metadata(Some(6))))
+            .await
+            .unwrap();
+        for mint in ["Mixed", "Sells"] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+        }
+        // Start of the minute of 1_700_000_000.
+        let since = DateTime::from_timestamp(1_699_999_980, 0).unwrap();
+        db.insert_trades(&[
+            mint_trade("Buys", 1_700_000_000, 300, true),
+            mint_trade("Mixed", 1_700_000_000, 200, true),
+            mint_trade("Mixed", 1_700_000_001, 100, false),
+            mint_trade("Sells", 1_700_000_060, 100, false),
+            // Out of the window.
+            mint_trade("Sells", 1_699_999_000, 1_000, true),
+        ])
+        .await
+        .unwrap();
+
+        let ranked = db
+            .tokens_by_pressure(since, Resolution::M1, 10)
+            .await
+            .unwrap();
+        let mints: Vec<_> = ranked.iter().map(|(mint, _, _)| mint.as_str()).collect();
+        assert_eq!(mints, ["Buys", "Mixed", "Sells"]);
+
+        let (_, metadata, buys) = &ranked[0];
+        assert_eq!(metadata.name, "Token");
+        assert_eq!(buys.net_volume, 300.0);
+        assert_eq!(buys.buy_ratio, 1.0);
+        let (_, metadata, mixed) = &ranked[1];
+        assert_eq!(metadata.name, "unknown");
+        assert_eq!(mixed.buy_volume, 200.0);
+        assert_eq!(mixed.sell_volume, 100.0);
+        assert!((mixed.buy_ratio - 2.0 / 3.0).abs() < 1e-12);
+        let (_, _, sells) = &ranked[2];
+        assert_eq!(sells.net_volume, -100.0);
+        assert_eq!(sells.buy_ratio, 0.0);
+
+        let top = db
+            .tokens_by_pressure(since, Resolution::M1, 1)
+            .await
+            .unwrap();
+        assert_eq!(top.len(), 1);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn only_recently_traded_mints_are_active(pool: PgPool) {
+        let db = test_db(pool);
+        let old = "11111111111111111111111111111111";
+        let recent = "So11111111111111111111111111111111111111112";
+        for mint in [old, recent] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+        }
+        let trades = [
+            mint_trade(old, 1_699_990_000, 2_000, true),
+            mint_trade(recent, 1_699_990_000, 2_000, true),
+            mint_trade(recent, 1_700_000_000, 2_000, true),
+        ];
+        db.insert_trades(&trades).await.unwrap();
+
+        let since = DateTime::from_timestamp(1_699_999_980, 0).unwrap();
+        assert_eq!(db.active_mints(since).await.unwrap(), [recent]);
+        let all = DateTime::from_timestamp(0, 0).unwrap();
+        let mut mints = db.active_mints(all).await.unwrap();
+        mints.sort();
+        assert_eq!(mints, [old, recent]);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn resolution_migrations_can_be_rerun(pool: PgPool) {
+        for migration in [
+            include_str!("../pg/migrations/20261016110000_resolution_m30_w1.sql"),
+            include_str!("../pg/migrations/20261016160000_resolution_h4_h12.sql"),
+        ] {
+            sqlx::raw_sql(migration).execute(&pool).await.unwrap();
+        }
+        let db = test_db(pool.clone());
+        db.init().await.unwrap();
+
+        for resolution in Resolution::all() {
+            let stored: Resolution = sqlx::query_scalar("SELECT $1::resolution")
+                .bind(resolution)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(stored, resolution);
+        }
+        // Values are ordered from finest, as variants are.
+        let values: Vec<Resolution> =
+            sqlx::query_scalar("SELECT unnest(enum_range(NULL::resolution))")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(values, Resolution::all());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn token_writes_report_insert_or_update(pool: PgPool) {
+        let db = test_db(pool);
+        let insert = || db.insert_token(MINT.to_string(), Some(metadata(None)));
+        assert_eq!(insert().await.unwrap(), TokenWrite::Inserted);
+        assert_eq!(insert().await.unwrap(), TokenWrite::Updated);
+        // Unresolved metadata doesn't replace resolved one.
+        let write = db.insert_token(MINT.to_string(), None).await.unwrap();
+        assert_eq!(write, TokenWrite::Kept);
+
+        let other = "11111111111111111111111111111111";
+        let write = db.insert_token(other.to_string(), None).await.unwrap();
+        assert_eq!(write, TokenWrite::Inserted);
+        let write = db.insert_token(other.to_string(), None).await.unwrap();
+        assert_eq!(write, TokenWrite::Kept);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn only_new_candles_are_counted(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+
+        let first = bucketed(trade(1_700_000_000, 1_000, 2_000, true));
+        assert_eq!(db.insert_trades(&[first]).await.unwrap(), 1);
+        let same_bucket = bucketed(trade(1_700_000_010, 1_000, 2_000, true));
+        assert_eq!(db.insert_trades(&[same_bucket]).await.unwrap(), 0);
+        let next_bucket = bucketed(trade(1_700_000_100, 1_000, 2_000, true));
+        assert_eq!(db.insert_trades(&[next_bucket]).await.unwrap(), 1);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn huge_volumes_are_accumulated_exactly(pool: PgPool) {
+        let db = test_db(pool.clone());
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Odd amount above 2^53 isn't representable as a double.
+        let amount = (1 << 53) + 1;
+
+        // Merged in a batch, then added on upsert.
+        let batch = [
+            bucketed(trade(1_700_000_000, 1_000, amount, true)),
+            bucketed(trade(1_700_000_001, 1_000, amount, false)),
+        ];
+        db.insert_trades(&batch).await.unwrap();
+        db.insert_trades(&[bucketed(trade(1_700_000_002, 1_000, amount, true))])
+            .await
+            .unwrap();
+
+        let (volume, buy_volume, sell_volume): (String, String, String) = sqlx::query_as(
+            "SELECT volume::text, buy_volume::text, sell_volume::text FROM trades
+            WHERE mint_acc = $1 AND resol = 'M1'",
+        )
+        .bind(MINT)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(volume, (3 * amount).to_string());
+        assert_eq!(buy_volume, (2 * amount).to_string());
+        assert_eq!(sell_volume, amount.to_string());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn tokens_are_filtered_by_activity(pool: PgPool) {
+        let db = test_db(pool);
+        let [dead, old, active, whale] = [
+            "11111111111111111111111111111111",
+            "22222222222222222222222222222222",
+            "33333333333333333333333333333333",
+            "44444444444444444444444444444444",
+        ];
+        for mint in [dead, old, active, whale] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+        }
+        let trades = [
+            mint_trade(old, 1_699_990_000, 10_000, true),
+            mint_trade(active, 1_700_000_000, 1_000, true),
+            mint_trade(whale, 1_700_000_000, 4_000, true),
+            mint_trade(whale, 1_700_000_100, 4_000, false),
+        ];
+        db.insert_trades(&trades).await.unwrap();
+
+        let since = DateTime::from_timestamp(1_699_999_980, 0).unwrap();
+        let db = &db;
+        let filtered = |min_volume, active_since| async move {
+            let tokens = db
+                .get_tokens_filtered(min_volume, Resolution::M1, since, active_since)
+                .await
+                .unwrap();
+            let mut mints: Vec<_> = tokens.into_iter().map(|(mint, _)| mint).collect();
+            mints.sort();
+            mints
+        };
+        assert_eq!(filtered(None, None).await, [dead, old, active, whale]);
+        assert_eq!(filtered(Some(5_000.0), None).await, [whale]);
+        assert_eq!(filtered(None, Some(since)).await, [active, whale]);
+        assert_eq!(filtered(Some(1_000.0), Some(since)).await, [active, whale]);
+        let later = DateTime::from_timestamp(1_700_000_100, 0).unwrap();
+        assert_eq!(filtered(None, Some(later)).await, [whale]);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn vwap_is_weighted_by_volume(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Prices 0.5, 3 and 1 with volumes 2000, 1000 and 500.
+        let batch = [
+            bucketed(trade(1_700_000_000, 1_000, 2_000, true)),
+            bucketed(trade(1_700_000_001, 3_000, 1_000, false)),
+        ];
+        db.insert_trades(&batch).await.unwrap();
+        db.insert_trades(&[bucketed(trade(1_700_000_002, 500, 500, true))])
+            .await
+            .unwrap();
+
+        let expected = (0.5 * 2_000.0 + 3.0 * 1_000.0 + 500.0) / 3_500.0;
+        let (_, candle) = db.last_trade(MINT, Resolution::M1).await.unwrap();
+        assert_price(candle.vwap, expected);
+        assert_price(candles(&db).await[0].vwap, expected);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn last_candles_span_sparse_history(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Trades days apart.
+        let day = 24 * 3600;
+        let trades: Vec<_> = (0..5)
+            .map(|i| {
+                bucketed(trade(
+                    1_700_000_000 + i * day,
+                    1_000 * (i as u64 + 1),
+                    1_000,
+                    true,
+                ))
+            })
+            .collect();
+        db.insert_trades(&trades).await.unwrap();
+
+        let candles = db.last_n_candles(MINT, Resolution::M1, 3).await.unwrap();
+        let timestamps: Vec<_> = candles
+            .keys()
+            .map(|datetime| datetime.timestamp())
+            .collect();
+        assert_eq!(
+            timestamps,
+            (2..5).map(|i| 1_699_999_980 + i * day).collect::<Vec<_>>()
+        );
+        let closes: Vec<_> = candles.values().map(|candle| candle.close).collect();
+        assert_eq!(closes, [3.0, 4.0, 5.0]);
+
+        // All candles if there are fewer.
+        let candles = db.last_n_candles(MINT, Resolution::M1, 10).await.unwrap();
+        assert_eq!(candles.len(), 5);
+        assert!(
+            db.last_n_candles(MINT, Resolution::H1, 3)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    /// Layer recording messages of emitted warnings.
+    struct Warnings(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Warnings {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Message<'a>(&'a mut String);
+
+            impl tracing::field::Visit for Message<'_> {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+                    if field.name() == "message" {
+                        *self.0 = format!("{value:?}");
+                    }
+                }
+            }
+
+            if *event.metadata().level() == tracing::Level::WARN {
+                let mut message = String::new();
+                event.record(&mut Message(&mut message));
+                self.0.lock().unwrap().push(message);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_query_is_logged_with_params() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Warnings(warnings.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        // Pool is never connected to, queries are mocked.
+        let pool = PgPool::connect_lazy("postgres://127.0.0.1:1/pumpfun").unwrap();
+        let metrics = Arc::new(Metrics::default());
+        let db = Db::from_pool(pool, metrics.clone(), Duration::from_millis(20));
+
+        db.timed("fast_query", (MINT, 1), async {}).await;
+        assert!(warnings.lock().unwrap().is_empty());
+
+        let slow = tokio::time::sleep(Duration::from_millis(30));
+        db.timed("slow_query", (MINT, Resolution::M1), slow).await;
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with("Slow DB query slow_query"),
+            "{warnings:?}"
+        );
+        assert!(warnings[0].contains(MINT), "{warnings:?}");
+        assert!(warnings[0].contains("M1"), "{warnings:?}");
+
+        let rendered = metrics.render();
+        for query in ["fast_query", "slow_query"] {
+            let count = format!("db_query_duration_seconds_count{{query=\"{query}\"}} 1");
+            assert!(rendered.contains(&count), "{rendered}");
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn candle_time_range_spans_first_to_last(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        assert_eq!(
+            db.candle_time_range(MINT, Resolution::M1).await.unwrap(),
+            None
+        );
+
+        let trades: Vec<_> = [1_700_000_100, 1_699_990_000, 1_700_003_000]
+            .into_iter()
+            .map(|secs| bucketed(trade(secs, 1_000, 2_000, true)))
+            .collect();
+        db.insert_trades(&trades).await.unwrap();
+
+        let minute = |secs| Resolution::M1.bucket_start(DateTime::from_timestamp(secs, 0).unwrap());
+        assert_eq!(
+            db.candle_time_range(MINT, Resolution::M1).await.unwrap(),
+            Some((minute(1_699_990_000), minute(1_700_003_000)))
+        );
+        assert_eq!(
+            db.candle_time_range(MINT, Resolution::H1).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            db.candle_time_range("unknown", Resolution::M1)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn resolved_metadata_wins_concurrent_inserts(pool: PgPool) {
+        let db = test_db(pool);
+        let fallback = TokenMetadata {
+            name: String::new(),
+            symbol: String::new(),
+            uri: String::new(),
+            ..metadata(None)
+        };
+        let mints: Vec<_> = (0..20).map(|i| format!("{MINT}{i}")).collect();
+
+        // Every mint is first seen with all kinds of metadata at once, in varying order.
+        let inserts = mints.iter().enumerate().flat_map(|(i, mint)| {
+            let mut writes = vec![
+                db.insert_token(mint.clone(), Some(metadata(None))),
+                db.insert_token(mint.clone(), Some(fallback.clone())),
+                db.insert_token(mint.clone(), None),
+            ];
+            writes.rotate_left(i % 3);
+            writes
+        });
+        let writes = futures_util::future::try_join_all(inserts).await.unwrap();
+        let inserted = writes
+            .iter()
+            .filter(|write| **write == TokenWrite::Inserted)
+            .count();
+        assert_eq!(inserted, mints.len());
+
+        let tokens = db.get_tokens().await.unwrap();
+        assert_eq!(tokens.len(), mints.len());
+        for (mint, token) in tokens {
+            assert_eq!(token.name, "Token", "{mint}");
+            assert_eq!(token.symbol, "TKN", "{mint}");
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn token_retention_is_overridden(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        assert!(db.token_retentions().await.unwrap().is_empty());
+
+        db.set_token_retention(MINT, Duration::from_secs(60))
+            .await
+            .unwrap();
+        db.set_token_retention(MINT, Duration::from_secs(3_600))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.token_retentions().await.unwrap(),
+            [(MINT.to_string(), Duration::from_secs(3_600))]
+        );
+
+        let e = db
+            .set_token_retention("UnknownMint", Duration::from_secs(60))
+            .await
+            .unwrap_err();
+        assert!(e.is::<NotFound>(), "{e}");
+        assert_eq!(db.token_retentions().await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn migration_version_is_latest_applied(pool: PgPool) {
+        let db = test_db(pool);
+        let latest = MIGRATOR.iter().map(|migration| migration.version).max();
+        assert_eq!(db.migration_version().await.unwrap(), latest);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn multi_hour_candles_are_stored(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let info = trade(1_700_000_000, 1_000, 2_000, true);
+        let buckets: Vec<_> = [Resolution::M30, Resolution::H4, Resolution::H12]
+            .into_iter()
+            .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)))
+            .collect();
+        db.insert_trades(&[(buckets.clone(), info)]).await.unwrap();
+
+        for (resolution, bucket) in buckets {
+            let (datetime, candle) = db.last_trade(MINT, resolution).await.unwrap();
+            assert_eq!(datetime, bucket, "{resolution}");
+            assert_eq!(candle.trade_count, 1);
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn token_is_fetched_by_mint(pool: PgPool) {
+        let db = test_db(pool);
+        let other = "OtherTokenMintAddressForDbTestsPump";
+        for (mint, name) in [(MINT, "Token"), (other, "Other")] {
+            let metadata = TokenMetadata {
+                name: name.to_string(),
+                symbol: name.to_uppercase(),
+                uri: format!("https://example.com/{name}.json"),
+                completed_at: None,
+                decimals: Some(6),
+            };
+            db.insert_token(mint.to_string(), Some(metadata))
+                .await
+                .unwrap();
+        }
+
+        let token = db.get_token(other).await.unwrap();
+        assert_eq!(token.name, "Other");
+        assert_eq!(token.symbol, "OTHER");
+        assert_eq!(token.uri, "https://example.com/Other.json");
+        assert_eq!(db.get_token(MINT).await.unwrap().name, "Token");
+        let e = db.get_token("UnknownMint").await.unwrap_err();
+        assert!(e.is::<NotFound>(), "{e}");
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn first_completion_is_kept(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        assert_eq!(db.get_token(MINT).await.unwrap().completed_at, None);
+
+        let completed_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let metadata = db.mark_completed(MINT, completed_at).await.unwrap();
+        assert_eq!(metadata.completed_at, Some(1_700_000_000_000));
+        // Repeated event doesn't move completion time.
+        let later = completed_at + Duration::from_secs(60);
+        let metadata = db.mark_completed(MINT, later).await.unwrap();
+        assert_eq!(metadata.completed_at, Some(1_700_000_000_000));
+        assert_eq!(
+            db.get_token(MINT).await.unwrap().completed_at,
+            Some(1_700_000_000_000)
+        );
+
+        let e = db.mark_completed("UnknownMint", later).await.unwrap_err();
+        assert!(e.is::<NotFound>(), "{e}");
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn out_of_order_trades_keep_chronological_ohlc(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Prices 0.5, 2, 0.25 and 1 in chronological order within a minute.
+        let minute_trades = |minute: i64| {
+            [(1_000, 0), (4_000, 10), (500, 20), (2_000, 30)]
+                .map(|(sol_amount, secs)| bucketed(trade(minute + secs, sol_amount, 2_000, true)))
+        };
+        let (first, second, third) = (1_700_000_100, 1_700_000_160, 1_700_000_220);
+
+        // Separate inserts in reverse order.
+        for trade in minute_trades(first).into_iter().rev() {
+            db.insert_trades(&[trade]).await.unwrap();
+        }
+        // Single reversed batch.
+        let mut batch = minute_trades(second);
+        batch.reverse();
+        db.insert_trades(&batch).await.unwrap();
+        // Separate inserts shuffled, close arrives before open.
+        let [open, high, low, close] = minute_trades(third);
+        for trade in [close, low, open, high] {
+            db.insert_trades(&[trade]).await.unwrap();
+        }
+
+        let candles = candles(&db).await;
+        assert_eq!(candles.len(), 3);
+        for candle in candles {
+            assert_price(candle.open, 0.5);
+            assert_price(candle.high, 2.0);
+            assert_price(candle.low, 0.25);
+            assert_price(candle.close, 1.0);
+            assert_eq!(candle.trade_count, 4);
+        }
+    }
+
+    /// Insert tokens with given mint, name and symbol.
+    async fn insert_named_tokens(db: &Db, tokens: &[(&str, &str, &str)]) {
+        for (mint, name, symbol) in tokens {
+            let metadata = TokenMetadata {
+                name: name.to_string(),
+                symbol: symbol.to_string(),
+                uri: String::from("https://example.com/token.json"),
+                completed_at: None,
+                decimals: None,
+            };
+            db.insert_token(mint.to_string(), Some(metadata))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn tokens_are_searched_by_name_symbol_and_mint(pool: PgPool) {
+        let db = test_db(pool);
+        insert_named_tokens(
+            &db,
+            &[
+                ("MintA", "Doge Moon", "DMOON"),
+                ("MintB", "Cat", "MOONCAT"),
+                ("MintMoonC", "Frog", "FRG"),
+                ("MintD", "100% Pump_", "PCT"),
+                ("MintE", "Other", "OTH"),
+            ],
+        )
+        .await;
+        let search = |query: Option<&'static str>| {
+            let db = db.clone();
+            async move {
+                let (tokens, total) = db
+                    .search_tokens(query, Resolution::M1, 100, 0)
+                    .await
+                    .unwrap();
+                let mut mints: Vec<_> = tokens.into_iter().map(|(mint, _)| mint).collect();
+                mints.sort();
+                assert_eq!(total, mints.len() as u64);
+                mints
+            }
+        };
+
+        // Case-insensitive match of any of the fields.
+        assert_eq!(search(Some("moon")).await, ["MintA", "MintB", "MintMoonC"]);
+        assert_eq!(search(Some("oth")).await, ["MintE"]);
+        // Wildcards are matched literally.
+        assert_eq!(search(Some("0%")).await, ["MintD"]);
+        assert_eq!(search(Some("p_")).await, ["MintD"]);
+        assert_eq!(search(Some("%")).await, ["MintD"]);
+        assert!(search(Some("missing")).await.is_empty());
+        assert_eq!(search(None).await.len(), 5);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn searched_tokens_are_paged_by_activity(pool: PgPool) {
+        let db = test_db(pool);
+        let mints = ["Mint0", "Mint1", "Mint2", "Mint3", "Mint4"];
+        insert_named_tokens(&db, &mints.map(|mint| (mint, "Token", "TKN"))).await;
+        // Mint3 is traded last, then Mint1, others are not traded.
+        db.insert_trades(&[
+            mint_trade("Mint1", 1_700_000_000, 2_000, true),
+            mint_trade("Mint3", 1_700_000_100, 2_000, true),
+        ])
+        .await
+        .unwrap();
+        let page = |limit, offset| {
+            let db = db.clone();
+            async move {
+                let (tokens, total) = db
+                    .search_tokens(Some("token"), Resolution::M1, limit, offset)
+                    .await
+                    .unwrap();
+                assert_eq!(total, 5);
+                tokens.into_iter().map(|(mint, _)| mint).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(page(2, 0).await, ["Mint3", "Mint1"]);
+        assert_eq!(page(2, 2).await, ["Mint0", "Mint2"]);
+        // Last page is partial, pages past the end are empty.
+        assert_eq!(page(2, 4).await, ["Mint4"]);
+        assert!(page(2, 5).await.is_empty());
+        assert!(page(0, 0).await.is_empty());
+        assert_eq!(page(10, 0).await.len(), 5);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn buy_and_sell_volumes_accumulate_separately(pool: PgPool) {
+        let db = test_db(pool);
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+
+        // Separate inserts into one bucket.
+        for (secs, token_amount, is_buy) in [
+            (1_700_000_100, 2_000, true),
+            (1_700_000_101, 3_000, false),
+            (1_700_000_102, 4_000, true),
+        ] {
+            db.insert_trades(&[bucketed(trade(secs, 1_000, token_amount, is_buy))])
+                .await
+                .unwrap();
+        }
+        let candle = candles(&db).await[0];
+        assert_eq!(candle.buy_volume, 6_000.0);
+        assert_eq!(candle.sell_volume, 3_000.0);
+        assert_eq!(candle.volume, 9_000.0);
+
+        // Mixed batch adds to both.
+        db.insert_trades(&[
+            bucketed(trade(1_700_000_103, 1_000, 500, false)),
+            bucketed(trade(1_700_000_104, 1_000, 1_500, true)),
+        ])
+        .await
+        .unwrap();
+        let candle = candles(&db).await[0];
+        assert_eq!(candle.buy_volume, 7_500.0);
+        assert_eq!(candle.sell_volume, 3_500.0);
+        assert_eq!(candle.volume, 11_000.0);
+        assert_eq!(candle.trade_count, 5);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn streamed_tokens_are_timed(pool: PgPool) {
+        let metrics = Arc::new(Metrics::default());
+        let db = Db::from_pool(pool, metrics.clone(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        db.insert_token(String::from("OtherMint"), None)
+            .await
+            .unwrap();
+
+        let tokens: Vec<_> = db.stream_tokens().collect().await;
+        assert_eq!(tokens.len(), 2);
+        // Query is observed before the stream ends.
+        let count = "db_query_duration_seconds_count{query=\"stream_tokens\"} 1";
+        assert!(metrics.render().contains(count), "{}", metrics.render());
+    }
 }
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::db::Db;
+use crate::model::{Candle, Resolution};
+
+/// Interval between empty candles materialization runs.
+const MATERIALIZE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Persists candles of buckets without trades, so the trades table is gap-free
+/// for readers not going through the server.
+///
+/// Only tokens traded within the window are processed, and only buckets since their first
+/// candle in the window are filled. Empty candles carry the previous close forward and have
+/// no volume and trades. Current bucket is never filled, but a late trade of a filled bucket
+/// is added to the empty candle, keeping its open price.
+#[derive(Clone)]
+pub struct EmptyCandles {
+    db: Db,
+    resolutions: Vec<Resolution>,
+    window: Duration,
+}
+
+impl EmptyCandles {
+    /// Create new materialization of given resolutions candles within the window.
+    pub fn new(db: Db, resolutions: Vec<Resolution>, window: Duration) -> Self {
+        Self {
+            db,
+            resolutions,
+            window,
+        }
+    }
+
+    /// Run materialization periodically.
+    pub async fn run(self) {
+        loop {
+            tokio::time::sleep(MATERIALIZE_INTERVAL).await;
+
+            match self.materialize().await {
+                Ok(0) => {}
+                Ok(inserted) => tracing::info!("Materialized {inserted} empty candles."),
+                Err(e) => tracing::warn!("Empty candles materialization failed: {e}"),
+            }
+        }
+    }
+
+    /// Insert empty candles of active tokens.
+    /// Returns number of inserted candles.
+    pub async fn materialize(&self) -> anyhow::Result<usize> {
+        let now = Utc::now();
+        let since = now - self.window;
+
+        let mut inserted = 0;
+        for mint in self.db.active_mints(since).await? {
+            let candles = self
+                .db
+                .trades_since_multi(&mint, since, &self.resolutions)
+                .await?;
+            for (resolution, candles) in candles {
+                let gaps = carry_forward_gaps(resolution, &candles, resolution.bucket_start(now));
+                if !gaps.is_empty() {
+                    inserted += self
+                        .db
+                        .insert_empty_candles(&mint, resolution, &gaps)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(inserted)
+    }
+}
+
+/// Buckets without candles from the first candle until `until` (exclusive),
+/// with close price of the preceding candle.
+fn carry_forward_gaps(
+    resolution: Resolution,
+    candles: &BTreeMap<DateTime<Utc>, Candle>,
+    until: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, f64)> {
+    let mut gaps = Vec::new();
+    let Some((first, _)) = candles.first_key_value() else {
+        return gaps;
+    };
+
+    let step = Duration::from_secs(resolution.as_seconds());
+    let mut bucket = *first;
+    let mut close = 0.0;
+    while bucket < until {
+        match candles.get(&bucket) {
+            Some(candle) => close = candle.close,
+            None => gaps.push((bucket, close)),
+        }
+        bucket = resolution.bucket_start(bucket + step);
+    }
+
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    use crate::model::TradeInfo;
+
+    const MINT: &str = "5xYzTokenMintAddressForEmptyCandlesPump";
+
+    fn minute(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn gaps_carry_previous_close_until_current_bucket() {
+        // Minute aligned.
+        let start = 1_700_000_100;
+        let candles = BTreeMap::from([
+            (minute(start), Candle::flat(1.0)),
+            (minute(start + 180), Candle::flat(2.0)),
+        ]);
+
+        let gaps = carry_forward_gaps(Resolution::M1, &candles, minute(start + 300));
+        assert_eq!(
+            gaps,
+            [
+                (minute(start + 60), 1.0),
+                (minute(start + 120), 1.0),
+                (minute(start + 240), 2.0),
+            ]
+        );
+        assert!(carry_forward_gaps(Resolution::M1, &BTreeMap::new(), minute(start)).is_empty());
+    }
+
+    fn trade(timestamp: DateTime<Utc>, sol_amount: u64) -> TradeInfo {
+        TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp,
+            sol_amount,
+            token_amount: 1_000,
+            is_buy: true,
+            decimals: None,
+        }
+    }
+
+    async fn insert_trade(db: &Db, info: TradeInfo) {
+        let bucket = Resolution::M1.bucket_start(info.timestamp);
+        db.insert_trades(&[(vec![(Resolution::M1, bucket)], info)])
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn gaps_of_active_tokens_are_filled(pool: PgPool) {
+        let db = Db::from_pool(pool, Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        let now = Utc::now();
+        let first = Resolution::M1.bucket_start(now - Duration::from_secs(10 * 60));
+        let second = first + Duration::from_secs(4 * 60);
+        insert_trade(&db, trade(first, 1_000)).await;
+        insert_trade(&db, trade(second, 2_000)).await;
+
+        let empty = EmptyCandles::new(db.clone(), vec![Resolution::M1], Duration::from_secs(3600));
+        let inserted = empty.materialize().await.unwrap();
+        let candles = db.trades_since(MINT, first, Resolution::M1).await.unwrap();
+        assert_eq!(inserted, candles.len() - 2);
+        // Up to the bucket current at materialization.
+        assert!(inserted >= 8, "{inserted}");
+
+        let step = Duration::from_secs(60);
+        for (i, (bucket, candle)) in candles.iter().enumerate() {
+            assert_eq!(*bucket, first + step * i as u32);
+            if *bucket == first || *bucket == second {
+                assert_eq!(candle.trade_count, 1);
+                continue;
+            }
+            let close = if *bucket < second { 1.0 } else { 2.0 };
+            assert_eq!((candle.open, candle.close), (close, close));
+            assert_eq!((candle.volume, candle.trade_count), (0.0, 0));
+        }
+
+        // Filled buckets are kept.
+        let rerun = empty.materialize().await.unwrap();
+        assert!(rerun <= 1, "{rerun}");
+
+        // Late trade is added to the empty candle, which keeps its open price.
+        let late = first + step * 2;
+        insert_trade(&db, trade(late + Duration::from_secs(30), 3_000)).await;
+        let candles = db.trades_since(MINT, late, Resolution::M1).await.unwrap();
+        let candle = candles[&late];
+        assert_eq!(candle.open, 1.0);
+        assert_eq!(candle.close, 3.0);
+        assert_eq!(candle.trade_count, 1);
+        assert_eq!(candle.volume, 1_000.0);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn inactive_tokens_are_not_filled(pool: PgPool) {
+        let db = Db::from_pool(pool, Default::default(), Duration::from_secs(10));
+        db.insert_token(MINT.to_string(), None).await.unwrap();
+        // Traded before the window only.
+        let old = Resolution::M1.bucket_start(Utc::now() - Duration::from_secs(3 * 3600));
+        insert_trade(&db, trade(old, 1_000)).await;
+
+        let empty = EmptyCandles::new(db.clone(), vec![Resolution::M1], Duration::from_secs(3600));
+        assert_eq!(empty.materialize().await.unwrap(), 0);
+        let candles = db.trades_since(MINT, old, Resolution::M1).await.unwrap();
+        assert_eq!(candles.len(), 1);
+    }
+}
@@ -0,0 +1,15 @@
+use std::fmt;
+
+/// Requested entity doesn't exist.
+/// Can be extracted from `anyhow::Error` with `downcast_ref` to distinguish
+/// missing data from storage failures.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::model::{Candle, Resolution, TradeInfo, TradeOhlcv};
+
+/// Number of the latest candles kept in memory per hot token and resolution.
+pub const HOT_CANDLES: usize = 1000;
+
+/// In-memory tier of the latest candles of the most watched tokens.
+///
+/// Token is promoted when the number of its chart subscribers with the same resolution
+/// reaches the threshold and demoted when it drops below. Promoted candles are kept up
+/// to date by ingested trades, so reads don't touch cache or DB. Late trades of already
+/// passed buckets are ignored, like in other in-memory projections.
+#[derive(Clone)]
+pub struct HotCandles {
+    /// Number of subscribers to promote a token, tier is disabled if not set.
+    threshold: Option<usize>,
+    /// Max number of promoted token resolutions, least recently read are evicted.
+    max_buffers: usize,
+    subscribers: Arc<DashMap<(String, Resolution), usize>>,
+    buffers: Arc<DashMap<String, HashMap<Resolution, HotBuffer>>>,
+}
+
+/// Latest candles of a token with one resolution.
+struct HotBuffer {
+    candles: VecDeque<TradeOhlcv>,
+    /// All candles since this timestamp are in the buffer.
+    covered_since: DateTime<Utc>,
+    last_read: Instant,
+}
+
+/// Registered chart subscriber of a token.
+/// Token is demoted on drop if subscribers drop below the threshold.
+pub struct HotSubscriber {
+    hot: HotCandles,
+    key: (String, Resolution),
+}
+
+impl Drop for HotSubscriber {
+    fn drop(&mut self) {
+        let count = {
+            let Some(mut count) = self.hot.subscribers.get_mut(&self.key) else {
+                return;
+            };
+            *count -= 1;
+            *count
+        };
+        if count == 0 {
+            self.hot
+                .subscribers
+                .remove_if(&self.key, |_, count| *count == 0);
+        }
+
+        if self
+            .hot
+            .threshold
+            .is_some_and(|threshold| count < threshold)
+        {
+            let (mint, resolution) = &self.key;
+            self.hot.demote(mint, *resolution);
+        }
+    }
+}
+
+impl HotCandles {
+    /// Create new tier.
+    pub fn new(threshold: Option<usize>, max_buffers: usize) -> Self {
+        Self {
+            threshold,
+            max_buffers,
+            subscribers: Default::default(),
+            buffers: Default::default(),
+        }
+    }
+
+    /// Register chart subscriber of the token.
+    /// Returns subscriber guard and whether the token should be promoted now.
+    pub fn subscribe(&self, mint: &str, resolution: Resolution) -> (HotSubscriber, bool) {
+        let key = (mint.to_string(), resolution);
+        let count = {
+            let mut count = self.subscribers.entry(key.clone()).or_default();
+            *count += 1;
+            *count
+        };
+        let promote = self.threshold.is_some_and(|threshold| count >= threshold)
+            && !self.is_promoted(mint, resolution);
+
+        let subscriber = HotSubscriber {
+            hot: self.clone(),
+            key,
+        };
+        (subscriber, promote)
+    }
+
+    /// Start of the window to load on promotion.
+    pub fn load_start(resolution: Resolution) -> DateTime<Utc> {
+        let window = Duration::from_secs(HOT_CANDLES as u64 * resolution.as_seconds());
+        resolution.bucket_start(Utc::now() - window)
+    }
+
+    /// Promote token with candles loaded since `since`.
+    /// Skipped if subscribers dropped below the threshold while loading.
+    pub fn promote(
+        &self,
+        mint: &str,
+        resolution: Resolution,
+        since: DateTime<Utc>,
+        candles: BTreeMap<DateTime<Utc>, Candle>,
+    ) {
+        let key = (mint.to_string(), resolution);
+        let count = self.subscribers.get(&key).map_or(0, |count| *count);
+        if self.threshold.is_none_or(|threshold| count < threshold) {
+            return;
+        }
+
+        let mut candles: VecDeque<_> = candles
+            .into_iter()
+            .map(|(datetime, candle)| TradeOhlcv {
+                timestamp: datetime.timestamp_millis() as u64,
+                candle,
+            })
+            .collect();
+        let mut covered_since = since;
+        while candles.len() > HOT_CANDLES {
+            if let Some(oldest) = candles.pop_front() {
+                covered_since = next_bucket(resolution, oldest.timestamp);
+            }
+        }
+
+        if self.buffers_count() >= self.max_buffers {
+            self.evict_least_recently_read();
+        }
+        self.buffers.entry(mint.to_string()).or_default().insert(
+            resolution,
+            HotBuffer {
+                candles,
+                covered_since,
+                last_read: Instant::now(),
+            },
+        );
+        tracing::info!("Token {mint} candles with resolution {resolution} promoted to memory.");
+    }
+
+    /// Candles of a promoted token since timestamp.
+    /// Returns `None` if token is not promoted or the window is not covered by memory.
+    pub fn candles_since(
+        &self,
+        mint: &str,
+        from_timestamp: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Option<BTreeMap<DateTime<Utc>, Candle>> {
+        let mut buffers = self.buffers.get_mut(mint)?;
+        let buffer = buffers.get_mut(&resolution)?;
+        if from_timestamp < buffer.covered_since {
+            return None;
+        }
+        buffer.last_read = Instant::now();
+
+        let from_millis = from_timestamp.timestamp_millis() as u64;
+        buffer
+            .candles
+            .iter()
+            .filter(|trade| trade.timestamp >= from_millis)
+            .map(|trade| Some((datetime(trade.timestamp)?, trade.candle)))
+            .collect()
+    }
+
+    /// Last candle of a promoted token.
+    /// Returns `None` if token is not promoted or has no candles.
+    pub fn last_candle(
+        &self,
+        mint: &str,
+        resolution: Resolution,
+    ) -> Option<(DateTime<Utc>, Candle)> {
+        let mut buffers = self.buffers.get_mut(mint)?;
+        let buffer = buffers.get_mut(&resolution)?;
+        buffer.last_read = Instant::now();
+
+        let last = buffer.candles.back()?;
+        Some((datetime(last.timestamp)?, last.candle))
+    }
+
+    /// Update promoted candles with ingested trades.
+    pub async fn run(self, mut trades: broadcast::Receiver<TradeInfo>) {
+        loop {
+            match trades.recv().await {
+                Ok(trade) => self.update(&trade),
+                Err(RecvError::Lagged(skipped)) => {
+                    // Promoted candles missed trades, so they are reloaded on next promotion.
+                    tracing::warn!("Hot candles skipped {skipped} trades, demoting all.");
+                    self.buffers.clear();
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        tracing::error!("Hot candles updates stopped");
+    }
+
+    /// Apply trade to promoted candles of the token.
+    fn update(&self, trade: &TradeInfo) {
+        let Some(mut buffers) = self.buffers.get_mut(&trade.mint_acc) else {
+            return;
+        };
+        let Ok(price) = trade.price() else {
+            return;
+        };
+
+        for (resolution, buffer) in buffers.iter_mut() {
+            let timestamp = resolution.bucket_start(trade.timestamp).timestamp_millis() as u64;
+            match buffer.candles.back_mut() {
+                Some(last) if last.timestamp >= timestamp => {
+                    last.apply_trade(timestamp, price, trade.token_amount as f64, trade.is_buy);
+                }
+                _ => {
+                    let mut candle = TradeOhlcv::default();
+                    candle.apply_trade(timestamp, price, trade.token_amount as f64, trade.is_buy);
+                    buffer.candles.push_back(candle);
+                    if buffer.candles.len() > HOT_CANDLES
+                        && let Some(oldest) = buffer.candles.pop_front()
+                    {
+                        buffer.covered_since = next_bucket(*resolution, oldest.timestamp);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether candles of the token with given resolution are in memory.
+    fn is_promoted(&self, mint: &str, resolution: Resolution) -> bool {
+        self.buffers
+            .get(mint)
+            .is_some_and(|buffers| buffers.contains_key(&resolution))
+    }
+
+    /// Remove candles of the token with given resolution from memory.
+    fn demote(&self, mint: &str, resolution: Resolution) {
+        if let Some(mut buffers) = self.buffers.get_mut(mint)
+            && buffers.remove(&resolution).is_some()
+        {
+            tracing::info!("Token {mint} candles with resolution {resolution} demoted.");
+        }
+        self.buffers
+            .remove_if(mint, |_, buffers| buffers.is_empty());
+    }
+
+    /// Remove candles of the token with all resolutions from memory,
+    /// e.g. when its stored candles are changed. Reads fall back to storage until promoted again.
+    pub fn demote_token(&self, mint: &str) {
+        if self.buffers.remove(mint).is_some() {
+            tracing::info!("Token {mint} candles demoted.");
+        }
+    }
+
+    /// Number of promoted token resolutions.
+    fn buffers_count(&self) -> usize {
+        self.buffers.iter().map(|buffers| buffers.len()).sum()
+    }
+
+    /// Demote the least recently read token resolution.
+    fn evict_least_recently_read(&self) {
+        let oldest = self
+            .buffers
+            .iter()
+            .flat_map(|buffers| {
+                let mint = buffers.key().clone();
+                buffers
+                    .iter()
+                    .map(|(resolution, buffer)| (buffer.last_read, mint.clone(), *resolution))
+                    .collect::<Vec<_>>()
+            })
+            .min_by_key(|(last_read, _, _)| *last_read);
+
+        if let Some((_, mint, resolution)) = oldest {
+            self.demote(&mint, resolution);
+        }
+    }
+}
+
+/// Datetime of a candle timestamp in milliseconds.
+fn datetime(timestamp: u64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(timestamp as i64)
+}
+
+/// Start of the bucket following the one starting at timestamp in milliseconds.
+fn next_bucket(resolution: Resolution, timestamp: u64) -> DateTime<Utc> {
+    let start = datetime(timestamp).unwrap_or_default();
+    resolution.bucket_start(start + Duration::from_secs(resolution.as_seconds()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn trade(mint: &str, secs: i64, sol_amount: u64) -> TradeInfo {
+        TradeInfo {
+            mint_acc: mint.to_string(),
+            timestamp: at(secs),
+            sol_amount,
+            token_amount: 1_000,
+            is_buy: true,
+            decimals: None,
+        }
+    }
+
+    /// Promote the token with a single M1 candle at 1_699_999_980 since 1_699_999_800.
+    fn promoted(hot: &HotCandles, mint: &str) -> HotSubscriber {
+        let (subscriber, promote) = hot.subscribe(mint, Resolution::M1);
+        assert!(promote);
+        let candles = BTreeMap::from([(at(1_699_999_980), Candle::flat(1.0))]);
+        hot.promote(mint, Resolution::M1, at(1_699_999_800), candles);
+        subscriber
+    }
+
+    #[test]
+    fn token_is_promoted_at_threshold_and_demoted_below() {
+        let hot = HotCandles::new(Some(2), 10);
+        let (first, promote) = hot.subscribe(MINT, Resolution::M1);
+        assert!(!promote);
+        let second = promoted(&hot, MINT);
+        assert!(hot.is_promoted(MINT, Resolution::M1));
+        // Already promoted token isn't promoted again.
+        let (third, promote) = hot.subscribe(MINT, Resolution::M1);
+        assert!(!promote);
+
+        drop(third);
+        assert!(hot.is_promoted(MINT, Resolution::M1));
+        drop(second);
+        assert!(!hot.is_promoted(MINT, Resolution::M1));
+        drop(first);
+        assert!(hot.subscribers.is_empty());
+    }
+
+    #[test]
+    fn trades_update_promoted_candles() {
+        let hot = HotCandles::new(Some(1), 10);
+        let _subscriber = promoted(&hot, MINT);
+
+        // The last candle is updated, the next bucket is started.
+        hot.update(&trade(MINT, 1_700_000_000, 2_000));
+        hot.update(&trade(MINT, 1_700_000_100, 3_000));
+        // Other tokens are ignored.
+        hot.update(&trade(
+            "11111111111111111111111111111111",
+            1_700_000_100,
+            5_000,
+        ));
+
+        let candles = hot
+            .candles_since(MINT, at(1_699_999_800), Resolution::M1)
+            .unwrap();
+        let candles: Vec<_> = candles.into_values().collect();
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].open, 1.0);
+        assert_eq!(candles[0].close, 2.0);
+        assert_eq!(candles[1].close, 3.0);
+        let (datetime, last) = hot.last_candle(MINT, Resolution::M1).unwrap();
+        assert_eq!(datetime, at(1_700_000_100));
+        assert_eq!(last.close, 3.0);
+    }
+
+    #[test]
+    fn uncovered_window_is_not_served() {
+        let hot = HotCandles::new(Some(1), 10);
+        let _subscriber = promoted(&hot, MINT);
+
+        assert!(
+            hot.candles_since(MINT, at(1_699_999_800), Resolution::M1)
+                .is_some()
+        );
+        assert!(
+            hot.candles_since(MINT, at(1_699_999_740), Resolution::M1)
+                .is_none()
+        );
+        assert!(
+            hot.candles_since(MINT, at(1_699_999_800), Resolution::M5)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn least_recently_read_token_is_evicted() {
+        let hot = HotCandles::new(Some(1), 2);
+        let [first, second, third] = [
+            "11111111111111111111111111111111",
+            "22222222222222222222222222222222",
+            "33333333333333333333333333333333",
+        ];
+        let _first = promoted(&hot, first);
+        let _second = promoted(&hot, second);
+        hot.last_candle(first, Resolution::M1).unwrap();
+
+        let _third = promoted(&hot, third);
+        assert_eq!(hot.buffers_count(), 2);
+        assert!(hot.is_promoted(first, Resolution::M1));
+        assert!(!hot.is_promoted(second, Resolution::M1));
+        assert!(hot.is_promoted(third, Resolution::M1));
+    }
+
+    #[test]
+    fn token_is_demoted_with_all_resolutions() {
+        let hot = HotCandles::new(Some(1), 10);
+        let _subscriber = promoted(&hot, MINT);
+        let (_other, promote) = hot.subscribe(MINT, Resolution::M5);
+        assert!(promote);
+        hot.promote(MINT, Resolution::M5, at(1_699_999_800), BTreeMap::new());
+
+        hot.demote_token(MINT);
+        assert!(!hot.is_promoted(MINT, Resolution::M1));
+        assert!(!hot.is_promoted(MINT, Resolution::M5));
+        // Still subscribed token is promoted again by the next subscriber.
+        let (_again, promote) = hot.subscribe(MINT, Resolution::M1);
+        assert!(promote);
+    }
+}
@@ -1,15 +1,73 @@
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use pumpfun::PumpFun;
-use pumpfun::common::stream::Subscription;
+use pumpfun::common::stream::{PumpFunEvent, Subscription};
 use pumpfun::common::types::{Cluster, PriorityFee};
 use solana_commitment_config::CommitmentConfig;
 use solana_keypair::Keypair;
 use tokio::sync::mpsc::Sender;
 
+use crate::log_sampling::INGESTION_TARGET;
+use crate::metrics::Metrics;
 use crate::model::IndexedPumpfunEvent;
 
+/// Type of pumpfun events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Create,
+    Trade,
+    Complete,
+    SetParams,
+}
+
+impl EventKind {
+    /// All known event types.
+    pub fn all() -> [EventKind; 4] {
+        [
+            EventKind::Create,
+            EventKind::Trade,
+            EventKind::Complete,
+            EventKind::SetParams,
+        ]
+    }
+
+    /// Type of the event, `None` for event types unknown to the indexer.
+    pub fn of(event: &PumpFunEvent) -> Option<Self> {
+        match event {
+            PumpFunEvent::Create(_) => Some(EventKind::Create),
+            PumpFunEvent::Trade(_) => Some(EventKind::Trade),
+            PumpFunEvent::Complete(_) => Some(EventKind::Complete),
+            PumpFunEvent::SetParams(_) => Some(EventKind::SetParams),
+            // Events not decoded by the client crate.
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(EventKind::Create),
+            "trade" => Ok(EventKind::Trade),
+            "complete" => Ok(EventKind::Complete),
+            "set_params" => Ok(EventKind::SetParams),
+            _ => Err(String::from(
+                "expected `create`, `trade`, `complete` or `set_params`",
+            )),
+        }
+    }
+}
+
+/// Whether event is of one of given types.
+fn is_indexed(event: &PumpFunEvent, events: &[EventKind]) -> bool {
+    EventKind::of(event).is_some_and(|kind| events.contains(&kind))
+}
+
 /// Pumpfun event indexer.
 pub struct Indexer {
     client: PumpFun,
@@ -27,11 +85,14 @@ impl Indexer {
     }
 
     /// Subscribe to events.
-    /// Captured events will be sent to the given sender.
+    /// Captured events of given types will be sent to the given sender, others are dropped
+    /// right away. See [`forward_event`] for events the sender can't take.
     /// Returns subscription. It will stop event capture task on drop.
     pub async fn subscribe(
         &self,
         pumpfun_ops_sender: Sender<IndexedPumpfunEvent>,
+        events: Vec<EventKind>,
+        metrics: Arc<Metrics>,
     ) -> anyhow::Result<Subscription> {
         let index = AtomicU64::new(0);
         let subscription = self
@@ -40,7 +101,7 @@ impl Indexer {
                 None,
                 Some(CommitmentConfig::confirmed()),
                 move |_, mb_event, mb_error, _| {
-                    tracing::trace!("Received event: {mb_event:?}");
+                    tracing::trace!(target: INGESTION_TARGET, "Received event: {mb_event:?}");
 
                     if let Some(err) = mb_error {
                         let error_str = err.to_string();
@@ -53,15 +114,14 @@ impl Indexer {
                     }
 
                     if let Some(event) = mb_event {
+                        if !is_indexed(&event, &events) {
+                            return;
+                        }
+
                         let idx = index.fetch_add(1, Ordering::Relaxed);
                         let idx_event = IndexedPumpfunEvent { _index: idx, event };
 
-                        let sender_clone = pumpfun_ops_sender.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = sender_clone.send(idx_event).await {
-                                tracing::warn!("Failed to send Pumpfun event: {e}");
-                            }
-                        });
+                        forward_event(&pumpfun_ops_sender, idx_event, &metrics);
                     }
                 },
             )
@@ -71,3 +131,81 @@ impl Indexer {
         Ok(subscription)
     }
 }
+
+/// Send captured event to the handler without waiting, so events keep their order
+/// and pending ones are bounded by the channel capacity.
+/// Event is dropped and counted if the channel is full, e.g. while ingestion is paused,
+/// or closed on shutdown.
+pub fn forward_event(
+    sender: &Sender<IndexedPumpfunEvent>,
+    event: IndexedPumpfunEvent,
+    metrics: &Metrics,
+) {
+    if let Err(e) = sender.try_send(event) {
+        metrics
+            .channel_dropped_events
+            .fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(target: INGESTION_TARGET, "Dropped pumpfun event: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pumpfun::common::stream::CompleteEvent;
+    use solana_pubkey::Pubkey;
+
+    fn completion() -> PumpFunEvent {
+        PumpFunEvent::Complete(CompleteEvent {
+            user: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            bonding_curve: Pubkey::new_unique(),
+            timestamp: 1_700_000_000,
+        })
+    }
+
+    #[test]
+    fn event_kinds_are_parsed() {
+        let kinds: Vec<EventKind> = ["create", "trade", "complete", "set_params"]
+            .into_iter()
+            .map(|kind| kind.parse().unwrap())
+            .collect();
+        assert_eq!(kinds, EventKind::all());
+        assert!("swap".parse::<EventKind>().is_err());
+        assert!("Trade".parse::<EventKind>().is_err());
+    }
+
+    #[test]
+    fn filtered_out_events_are_dropped() {
+        let event = completion();
+        assert_eq!(EventKind::of(&event), Some(EventKind::Complete));
+        assert!(!is_indexed(&event, &[EventKind::Trade]));
+        assert!(!is_indexed(&event, &[]));
+        assert!(is_indexed(&event, &[EventKind::Trade, EventKind::Complete]));
+        assert!(is_indexed(&event, &EventKind::all()));
+    }
+
+    #[test]
+    fn events_are_forwarded_in_order_until_channel_is_full() {
+        let metrics = Metrics::default();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(2);
+        let forward = |index| {
+            let event = IndexedPumpfunEvent {
+                _index: index,
+                event: completion(),
+            };
+            forward_event(&sender, event, &metrics);
+        };
+        let dropped = || metrics.channel_dropped_events.load(Ordering::Relaxed);
+
+        (0..3).for_each(forward);
+        assert_eq!(receiver.try_recv().unwrap()._index, 0);
+        assert_eq!(receiver.try_recv().unwrap()._index, 1);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(dropped(), 1);
+
+        receiver.close();
+        forward(3);
+        assert_eq!(dropped(), 2);
+    }
+}
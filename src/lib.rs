@@ -0,0 +1,36 @@
+//! Pump.fun tokens indexer core.
+//!
+//! Events of the pump.fun program are captured by [`indexer::Indexer`], handled by
+//! [`pump_handler::PumpHandler`] and aggregated into candles of every enabled
+//! [`model::Resolution`] in [`storage::Storage`], which unifies Postgres ([`db::Db`])
+//! and Redis time series ([`cache::Cache`]). The server binary wires them together
+//! and serves REST and WebSocket APIs.
+
+pub mod api;
+pub mod backfill;
+pub mod cache;
+pub mod config;
+pub mod connections;
+pub mod db;
+pub mod empty_candles;
+pub mod error;
+pub mod hot_candles;
+pub mod indexer;
+pub mod local_time;
+pub mod log_sampling;
+pub mod maintenance;
+pub mod market_stats;
+pub mod metadata_breaker;
+pub mod metrics;
+pub mod model;
+pub mod parquet_export;
+pub mod prices;
+pub mod pump_handler;
+pub mod retry_queue;
+pub mod rpc_pool;
+pub mod selftest;
+pub mod sessions;
+pub mod storage;
+pub mod token_activity;
+pub mod tokens_cache;
+pub mod trade_batcher;
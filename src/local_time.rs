@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono_tz::Tz;
+use sqlx::types::chrono::{DateTime, NaiveTime, TimeZone, Utc};
+
+use crate::model::{Candle, Resolution};
+
+/// Resolution of candles aggregated into local days: the coarsest enabled one up to M15.
+/// Every real timezone offset is a multiple of 15 minutes, so such buckets never cross local midnight.
+pub fn local_day_source_resolution() -> anyhow::Result<Resolution> {
+    [
+        Resolution::M15,
+        Resolution::M5,
+        Resolution::M1,
+        Resolution::S1,
+    ]
+    .into_iter()
+    .find(Resolution::is_enabled)
+    .ok_or_else(|| anyhow::anyhow!("Local days require one of S1, M1, M5, M15 resolutions"))
+}
+
+/// Start of the local day in timezone containing timestamp.
+/// If local midnight doesn't exist because of DST transition, the day starts at the first existing local time.
+pub fn local_day_start(timestamp: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+    let mut local_start = timestamp
+        .with_timezone(&tz)
+        .date_naive()
+        .and_time(NaiveTime::MIN);
+    // DST gaps are at most a couple of hours, so this ends quickly.
+    loop {
+        if let Some(start) = tz.from_local_datetime(&local_start).earliest() {
+            return start.with_timezone(&Utc);
+        }
+        local_start += Duration::from_secs(Resolution::M15.as_seconds());
+    }
+}
+
+/// Aggregate candles into local days of timezone.
+/// Candles must not cross local midnight, see `local_day_source_resolution`.
+/// Resulting candles are keyed by the UTC timestamp of the local day start,
+/// so days are 23 or 25 hours long on DST transitions.
+pub fn aggregate_local_days(
+    candles: BTreeMap<DateTime<Utc>, Candle>,
+    tz: Tz,
+) -> BTreeMap<DateTime<Utc>, Candle> {
+    let mut days: BTreeMap<DateTime<Utc>, Candle> = BTreeMap::new();
+
+    // Candles are ordered by time, so the first candle of a day sets its open.
+    for (timestamp, candle) in candles {
+        let day_start = local_day_start(timestamp, tz);
+        match days.get_mut(&day_start) {
+            Some(day) => {
+                let price_volume_sum = day.vwap * day.volume + candle.vwap * candle.volume;
+                day.close = candle.close;
+                day.high = day.high.max(candle.high);
+                day.low = day.low.min(candle.low);
+                day.volume += candle.volume;
+                day.trade_count += candle.trade_count;
+                day.buy_volume += candle.buy_volume;
+                day.sell_volume += candle.sell_volume;
+                if day.volume > 0.0 {
+                    day.vwap = price_volume_sum / day.volume;
+                }
+            }
+            None => {
+                days.insert(day_start, candle);
+            }
+        }
+    }
+
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn candle(price: f64, volume: f64) -> Candle {
+        Candle {
+            open: price,
+            close: price,
+            high: price,
+            low: price,
+            volume,
+            trade_count: 1,
+            buy_volume: volume,
+            sell_volume: 0.0,
+            vwap: price,
+        }
+    }
+
+    #[test]
+    fn local_days_follow_dst_transitions() {
+        let tz = chrono_tz::America::New_York;
+        // Spring forward on 2024-03-10, the day is 23 hours long.
+        let spring = local_day_start(datetime("2024-03-10T12:00:00Z"), tz);
+        assert_eq!(spring, datetime("2024-03-10T05:00:00Z"));
+        let next = local_day_start(datetime("2024-03-11T12:00:00Z"), tz);
+        assert_eq!(next, datetime("2024-03-11T04:00:00Z"));
+        assert_eq!((next - spring).num_hours(), 23);
+
+        // Fall back on 2024-11-03, the day is 25 hours long.
+        let fall = local_day_start(datetime("2024-11-03T12:00:00Z"), tz);
+        assert_eq!(fall, datetime("2024-11-03T04:00:00Z"));
+        let next = local_day_start(datetime("2024-11-04T12:00:00Z"), tz);
+        assert_eq!((next - fall).num_hours(), 25);
+    }
+
+    #[test]
+    fn missing_local_midnight_starts_day_at_first_local_time() {
+        // Clocks jump from 00:00 to 01:00 in Chile on 2024-09-08.
+        let tz = chrono_tz::America::Santiago;
+        let start = local_day_start(datetime("2024-09-08T12:00:00Z"), tz);
+        assert_eq!(start, datetime("2024-09-08T04:00:00Z"));
+        assert_eq!(
+            start.with_timezone(&tz).time(),
+            NaiveTime::from_hms_opt(1, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn candles_are_aggregated_into_local_days() {
+        let tz = chrono_tz::America::New_York;
+        let candles = BTreeMap::from([
+            // Last candle of 2024-03-09 local day.
+            (datetime("2024-03-10T04:45:00Z"), candle(1.0, 10.0)),
+            // 23 hours long 2024-03-10 local day.
+            (datetime("2024-03-10T05:00:00Z"), candle(2.0, 10.0)),
+            (datetime("2024-03-10T15:00:00Z"), candle(4.0, 30.0)),
+            (datetime("2024-03-11T03:45:00Z"), candle(3.0, 10.0)),
+            // 2024-03-11 local day.
+            (datetime("2024-03-11T04:00:00Z"), candle(5.0, 10.0)),
+        ]);
+
+        let days = aggregate_local_days(candles, tz);
+        let starts: Vec<_> = days.keys().copied().collect();
+        assert_eq!(
+            starts,
+            [
+                datetime("2024-03-09T05:00:00Z"),
+                datetime("2024-03-10T05:00:00Z"),
+                datetime("2024-03-11T04:00:00Z"),
+            ]
+        );
+
+        let day = days[&datetime("2024-03-10T05:00:00Z")];
+        assert_eq!(day.open, 2.0);
+        assert_eq!(day.close, 3.0);
+        assert_eq!(day.high, 4.0);
+        assert_eq!(day.low, 2.0);
+        assert_eq!(day.volume, 50.0);
+        assert_eq!(day.trade_count, 3);
+        assert_eq!(day.vwap, (2.0 * 10.0 + 4.0 * 30.0 + 3.0 * 10.0) / 50.0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{Event, Level, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+
+/// Target of high-frequency ingestion logs, which are sampled.
+pub const INGESTION_TARGET: &str = concat!(env!("CARGO_CRATE_NAME"), "::ingestion");
+
+/// One of this number of ingestion logs is emitted.
+static SAMPLE_RATE: AtomicU64 = AtomicU64::new(1);
+
+/// Number of ingestion logs seen by the sampler.
+static SEEN: AtomicU64 = AtomicU64::new(0);
+
+/// Set sampling rate of ingestion logs.
+pub fn set_sample_rate(rate: NonZeroU64) {
+    SAMPLE_RATE.store(rate.get(), Ordering::Relaxed);
+}
+
+/// Log filter emitting one of `SAMPLE_RATE` ingestion events below `WARN`.
+/// Warnings, errors, spans and other events are not sampled.
+pub struct IngestionSampler;
+
+impl<S> Filter<S> for IngestionSampler {
+    fn enabled(&self, _metadata: &Metadata<'_>, _ctx: &Context<'_, S>) -> bool {
+        true
+    }
+
+    /// Sampling is decided per event, which is built only if enabled by other filters,
+    /// so events dropped by the env filter don't shift the sampling and dropped ones
+    /// are never formatted.
+    fn event_enabled(&self, event: &Event<'_>, _ctx: &Context<'_, S>) -> bool {
+        let metadata = event.metadata();
+        if metadata.target() != INGESTION_TARGET || *metadata.level() <= Level::WARN {
+            return true;
+        }
+
+        let rate = SAMPLE_RATE.load(Ordering::Relaxed);
+        SEEN.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use tracing::Subscriber;
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Layer counting emitted events.
+    struct EventCounter(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for EventCounter {
+        fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn only_frequent_ingestion_logs_are_sampled() {
+        // Only this test logs through the sampler, so its sampling starts from zero.
+        set_sample_rate(NonZeroU64::new(100).unwrap());
+        let emitted = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry()
+            .with(EventCounter(emitted.clone()).with_filter(IngestionSampler));
+
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..1000 {
+                tracing::debug!(target: INGESTION_TARGET, "Received event");
+            }
+            for _ in 0..10 {
+                tracing::warn!(target: INGESTION_TARGET, "Failed to handle event");
+            }
+            tracing::debug!("Other event");
+        });
+
+        assert_eq!(emitted.load(Ordering::Relaxed), 1000 / 100 + 10 + 1);
+    }
+}
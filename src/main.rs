@@ -1,39 +1,35 @@
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::{Path, State};
-use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::{Json, Router};
-use db::Db;
-use serde::Deserialize;
-use sqlx::types::chrono::{DateTime, Utc};
-use std::collections::BTreeMap;
+use anyhow::Context;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tower_http::cors::{Any, CorsLayer};
-use tower_http::services::ServeDir;
-use tower_http::trace::DefaultMakeSpan;
-use tower_http::trace::TraceLayer;
+use tracing_subscriber::Layer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::cache::Cache;
-use crate::indexer::Indexer;
-use crate::model::{Candle, Resolution, TradeOhlcv};
-use crate::pump_handler::PumpHandler;
-use crate::storage::Storage;
-
-mod cache;
-mod db;
-mod indexer;
-mod model;
-mod pump_handler;
-mod storage;
-
-/// State shared between app clients.
-struct AppState {
-    storage: Storage,
-    _indexer: Indexer,
-}
+use pumpfun_indexer::api::{self, AppState};
+use pumpfun_indexer::backfill::MetadataBackfill;
+use pumpfun_indexer::cache::Cache;
+use pumpfun_indexer::config::Config;
+use pumpfun_indexer::connections::Connections;
+use pumpfun_indexer::db::Db;
+use pumpfun_indexer::empty_candles::EmptyCandles;
+use pumpfun_indexer::hot_candles::HotCandles;
+use pumpfun_indexer::indexer::Indexer;
+use pumpfun_indexer::log_sampling::{self, IngestionSampler};
+use pumpfun_indexer::maintenance::DbMaintenance;
+use pumpfun_indexer::market_stats::MarketStats;
+use pumpfun_indexer::metadata_breaker::MetadataBreaker;
+use pumpfun_indexer::metrics::Metrics;
+use pumpfun_indexer::model::{Candle, Resolution};
+use pumpfun_indexer::prices::LastPrices;
+use pumpfun_indexer::pump_handler::{IngestionControl, PumpHandler};
+use pumpfun_indexer::retry_queue::{RETRY_INTERVAL, RetryQueue};
+use pumpfun_indexer::rpc_pool::RpcPool;
+use pumpfun_indexer::selftest;
+use pumpfun_indexer::sessions::Sessions;
+use pumpfun_indexer::storage::Storage;
+use pumpfun_indexer::token_activity::TokenActivity;
+use pumpfun_indexer::tokens_cache::TokensCache;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -45,7 +41,7 @@ async fn main() -> anyhow::Result<()> {
                 format!("{}=debug,tower_http=debug", env!("CARGO_CRATE_NAME")).into()
             }),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_filter(IngestionSampler))
         .init();
 
     if let Err(e) = dotenv::dotenv() {
@@ -54,24 +50,107 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Tracing initialized.");
 
-    // Init db connection.
-    let conn_str = std::env::var("POSTGRES_CONN_STR")?;
-    let db = Db::new(conn_str).await?;
-    if let Err(e) = db.init().await {
-        tracing::info!("Failed to apply migrations: {e}");
-    } else {
-        tracing::info!("Migrations applied.");
+    let config = Config::from_env()?;
+    tracing::info!("Config loaded.");
+    log_sampling::set_sample_rate(config.log_sample_rate);
+
+    // Fail fast on bad certificate before connecting anything.
+    let tls = match &config.tls {
+        Some(paths) => {
+            let tls = api::load_tls(paths).await?;
+            tracing::info!("TLS initialized.");
+            Some(tls)
+        }
+        None => None,
     };
+
+    // Must be set before any candle is aligned.
+    Resolution::set_day_anchor_offset(config.day_anchor_offset);
+    Resolution::set_enabled(&config.enabled_resolutions);
+    Candle::set_display_significant_digits(config.price_significant_digits);
+
+    let metrics = Arc::new(Metrics::default());
+
+    // Init db connection.
+    let db = Db::new(&config, metrics.clone()).await?;
+    // Serving with partially applied migrations would hide schema errors, e.g. missing enum values.
+    db.init().await.context("Failed to apply migrations")?;
+    tracing::info!("Migrations applied.");
     tracing::info!("Db initialized.");
 
+    // Start DB maintenance.
+    let db_maintenance = DbMaintenance::new(db.clone(), config.db_maintenance_vacuum);
+    tokio::spawn(db_maintenance.clone().run(config.db_maintenance_interval));
+    tracing::info!("DB maintenance initialized.");
+
+    if !config.empty_candles_resolutions.is_empty() {
+        let empty_candles = EmptyCandles::new(
+            db.clone(),
+            config.empty_candles_resolutions.clone(),
+            config.empty_candles_window,
+        );
+        tokio::spawn(empty_candles.run());
+        tracing::info!("Empty candles materialization initialized.");
+    }
+
     // Init redis connection.
-    let conn_str = std::env::var("REDIS_CONN_STR")?;
-    let cache = Cache::new(&conn_str).await?;
+    let cache = Cache::new(&config).await?;
     tracing::info!("Cache initialized.");
 
-    let storage = Storage::new(db, cache).await;
+    if std::env::args().any(|arg| arg == "--selftest") {
+        return selftest::run(db, cache, metrics).await;
+    }
+
+    let sessions = Sessions::start(db.clone())
+        .await
+        .context("Failed to record session start")?;
+
+    let retry_queue = RetryQueue::open(
+        config.retry_queue_path.clone(),
+        config.retry_queue_capacity,
+        metrics.clone(),
+    )
+    .await
+    .context("Failed to open retry queue")?;
+    let hot_candles = HotCandles::new(config.hot_token_subscribers, config.hot_tokens_max);
+    let token_activity = TokenActivity::new(config.s1_activity_window);
+    tokio::spawn(token_activity.clone().run());
+    let storage = Storage::new(
+        db,
+        cache,
+        metrics.clone(),
+        retry_queue,
+        hot_candles.clone(),
+        token_activity,
+    )
+    .await
+    .with_batching(config.trade_batch_size, config.trade_batch_delay);
+    tokio::spawn(hot_candles.run(storage.subscribe_trades()));
+    match storage.load_token_retentions().await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Loaded {count} token retention overrides."),
+        Err(e) => tracing::warn!("Failed to load token retention overrides: {e}"),
+    }
     tracing::info!("Storage initialized.");
 
+    // Replay trades failed to be inserted before restart, so they precede new ones.
+    match storage.replay_pending_trades().await {
+        Ok(replayed) => tracing::info!("Replayed {replayed} pending trades."),
+        Err(e) => tracing::warn!("Failed to replay pending trades: {e:#}"),
+    }
+    tokio::spawn(storage.clone().run_retries(RETRY_INTERVAL));
+
+    // Load last prices before ingestion starts, so no trade is missed.
+    let last_prices = LastPrices::load(&storage)
+        .await
+        .inspect_err(|e| tracing::warn!("Failed to load last prices: {e}"))
+        .unwrap_or_default();
+    tokio::spawn(last_prices.clone().run(storage.subscribe_trades()));
+    tracing::info!("Last prices initialized.");
+
+    let tokens_cache = TokensCache::new(config.tokens_cache_ttl);
+    tokio::spawn(tokens_cache.clone().run(storage.subscribe_tokens()));
+
     // Channel to push events from pumpfun to PumpHandler.
     let (tx, rx) = mpsc::channel(1024);
 
@@ -79,187 +158,153 @@ async fn main() -> anyhow::Result<()> {
     let indexer = Indexer::new()?;
     tracing::info!("Indexer initialized.");
 
-    let _subscription = indexer.subscribe(tx).await?;
-    tokio::spawn(PumpHandler::run(storage.clone(), rx));
+    let ingestion = IngestionControl::new(config.pause_mode, config.event_concurrency);
+    tokio::spawn(sessions.clone().run(ingestion.clone()));
+
+    let subscription = indexer
+        .subscribe(tx, config.index_events.clone(), metrics.clone())
+        .await?;
+    let metadata_breaker = MetadataBreaker::new(metrics.clone());
+    let rpc = RpcPool::new(&config.solana_rpc_urls);
+    let market_stats = MarketStats::default();
+    tokio::spawn(market_stats.clone().run(storage.clone()));
+    let pump_handler = tokio::spawn(PumpHandler::run(
+        storage.clone(),
+        metrics.clone(),
+        metadata_breaker.clone(),
+        rpc.clone(),
+        market_stats.clone(),
+        ingestion.clone(),
+        rx,
+    ));
     tracing::info!("PumpHandler initialized.");
 
+    // Start metadata backfill.
+    let backfill = MetadataBackfill::new(
+        storage.clone(),
+        metadata_breaker,
+        rpc.clone(),
+        config.metadata_batch_size,
+    );
+    tokio::spawn(backfill.clone().run(config.metadata_backfill_interval));
+    tracing::info!("Metadata backfill initialized.");
+
     let state = Arc::new(AppState {
         storage,
-        _indexer: indexer,
+        backfill,
+        db_maintenance,
+        last_prices,
+        tokens_cache,
+        rpc,
+        market_stats,
+        connections: Connections::default(),
+        sessions: sessions.clone(),
+        metrics,
+        history_points: config.history_points,
+        open_policy: config.open_policy,
+        admin_api_key: config.admin_api_key.clone(),
+        public_base_url: config.public_base_url.clone(),
+        tls: tls.is_some(),
+        ingestion: ingestion.clone(),
+        indexer,
     });
 
-    // CORS are not required for test task.
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    let serve_dir = ServeDir::new("assets");
-
-    let router = Router::new()
-        .route("/chart_data_ws/{token}/{resolution}", get(chart_data_ws))
-        .route("/tokens", get(get_tokens))
-        .nest_service("/assets", serve_dir.clone())
-        .fallback_service(serve_dir)
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::default().include_headers(true)),
-        )
-        .layer(cors)
-        .with_state(state);
-
-    let addr = "0.0.0.0:33987";
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .expect("failed to init TCP listener");
-
-    tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, router.layer(TraceLayer::new_for_http())).await?;
-
-    Ok(())
-}
-
-/// Get list of tokens request handler.
-async fn get_tokens(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let tokens_result = state.storage.get_tokens().await;
+    // Report unhealthy state if events are not handled anymore.
+    let monitored_state = state.clone();
+    tokio::spawn(async move {
+        PumpHandler::monitor(pump_handler, &monitored_state.ingestion).await;
+    });
 
-    match tokens_result {
-        Ok(tokens) => Json(tokens).into_response(),
-        Err(e) => {
-            tracing::info!("Failed to get tokens: {e}.");
-            Json(format!("Failed to get tokens: {e}.")).into_response()
+    let router = api::router(state, &config);
+    match tls {
+        Some(tls) => {
+            let addr: SocketAddr = config.listen_addr.parse()?;
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown_signal().await;
+                    handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+                }
+            });
+
+            tracing::info!("listening on {addr} with TLS");
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(router.into_make_service())
+                .await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(&config.listen_addr)
+                .await
+                .expect("failed to init TCP listener");
+
+            tracing::info!("listening on {}", listener.local_addr().unwrap());
+            axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown_signal())
+                .await?;
         }
     }
-}
 
-/// Chart params for a WebSocket request handler.
-#[derive(Deserialize, Debug)]
-struct ChartWsPathParams {
-    token: String,
-    resolution: Resolution,
-}
-
-/// Upgrade HTTP connection into WebSocket.
-async fn chart_data_ws(
-    Path(path): Path<ChartWsPathParams>,
-    ws: WebSocketUpgrade,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async move {
-        let result = handle_websocket(path.token, path.resolution, socket, state).await;
-        if let Err(e) = result {
-            tracing::warn!("WS connection failure: {e}.");
-        }
-    })
-}
+    tracing::info!("Server stopped, shutting down.");
 
-/// History point for a chart.
-const POINTS_PER_CHART: usize = 100;
-
-/// Refresh interval for a WebSocket connection.
-const PRICE_WS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
-
-/// WebSocket connection handler.
-async fn handle_websocket(
-    token: String,
-    resolution: Resolution,
-    mut socket: WebSocket,
-    state: Arc<AppState>,
-) -> anyhow::Result<()> {
-    // Get history data at the start.
-    let to_timestamp = Utc::now();
-    let step = Duration::from_secs(resolution.as_seconds());
-    let from_timestamp = resolution.align_datetime(
-        to_timestamp - Duration::from_secs(POINTS_PER_CHART as u64 * resolution.as_seconds()),
+    // Stop capture, then handle events already received.
+    // Trades are written as they are handled, so only events being handled are to be flushed.
+    // Failed DB writes are already persisted in the retry queue.
+    drop(subscription);
+    let deadline = Instant::now() + SHUTDOWN_DRAIN_TIMEOUT;
+    if !ingestion.stop(SHUTDOWN_DRAIN_TIMEOUT).await {
+        tracing::warn!("Pumpfun handler didn't stop in {SHUTDOWN_DRAIN_TIMEOUT:?}.");
+    }
+    let in_flight = ingestion.in_flight();
+    let remaining = ingestion
+        .drain(deadline.saturating_duration_since(Instant::now()))
+        .await;
+    tracing::info!(
+        "Flushed {} in-flight events, {remaining} not finished in {SHUTDOWN_DRAIN_TIMEOUT:?}.",
+        in_flight.saturating_sub(remaining)
     );
 
-    let db_candles = state
-        .storage
-        .trades_since(&token, from_timestamp, resolution)
-        .await
-        .inspect_err(|e| tracing::info!("Failed to read prices history: {e}."))
-        .unwrap_or_default();
-
-    let candles = interpolate_candles(from_timestamp, to_timestamp, step, db_candles);
-
-    for price in candles {
-        let json_price = sqlx::types::Json::from(price).encode_to_string()?;
-        socket.send(Message::Text(json_price.into())).await?;
+    if let Err(e) = sessions.end(&ingestion).await {
+        tracing::warn!("Failed to record session end: {e}");
     }
 
-    // Send last trade data to the client periodically.
-    loop {
-        tokio::time::sleep(PRICE_WS_REFRESH_INTERVAL).await;
+    Ok(())
+}
 
-        let current_timestamp = resolution.align_datetime(Utc::now());
-        let (last_db_ts, last_db_candle) = match state.storage.last_trade(&token, resolution).await
-        {
-            Ok(p) => p,
-            Err(e) => {
-                tracing::info!("Failed to read last price: {e}.");
-                Default::default()
-            }
-        };
-
-        let candle = if current_timestamp >= last_db_ts && current_timestamp < last_db_ts + step {
-            last_db_candle
-        } else {
-            Candle {
-                open: last_db_candle.close,
-                close: last_db_candle.close,
-                high: last_db_candle.close,
-                low: last_db_candle.close,
-                volume: 0.0,
-            }
-        };
+/// Time in-flight requests are given to complete on TLS server shutdown.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
 
-        let trade = TradeOhlcv {
-            timestamp: current_timestamp.timestamp_millis() as u64 / 1000,
-            candle,
-        };
+/// Max time received events are waited to be handled for on shutdown.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
-        let json_trade = sqlx::types::Json::from(trade).encode_to_string()?;
-        socket.send(Message::Text(json_trade.into())).await?;
-    }
-}
+/// Wait for Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
 
-// Fill gaps in trade events.
-fn interpolate_candles(
-    mut from_timestamp: DateTime<Utc>,
-    to_timestamp: DateTime<Utc>,
-    step: Duration,
-    db_candles: BTreeMap<DateTime<Utc>, Candle>,
-) -> Vec<TradeOhlcv> {
-    let mut prices = Vec::new();
-    while from_timestamp <= to_timestamp {
-        let Some((db_timestamp, db_candle)) = db_candles
-            .range(..=from_timestamp)
-            .next_back()
-            .map(|(ts, canlde)| (*ts, *canlde))
-        else {
-            from_timestamp += step;
-            continue;
-        };
-
-        let candle = if from_timestamp >= db_timestamp && from_timestamp < db_timestamp + step {
-            db_candle
-        } else {
-            Candle {
-                open: db_candle.close,
-                close: db_candle.close,
-                high: db_candle.close,
-                low: db_candle.close,
-                volume: 0.0,
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
-        };
-
-        prices.push(TradeOhlcv {
-            timestamp: from_timestamp.timestamp_millis() as u64 / 1000,
-            candle,
-        });
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-        from_timestamp += step;
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Ctrl+C received."),
+        _ = terminate => tracing::info!("SIGTERM received."),
     }
-
-    prices
 }
@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::OwnedMutexGuard;
+
+use crate::db::Db;
+
+/// Periodic DB maintenance.
+/// Keeps planner statistics of the high-churn trades table up to date.
+#[derive(Clone)]
+pub struct DbMaintenance {
+    db: Db,
+    vacuum: bool,
+}
+
+impl DbMaintenance {
+    /// Create new maintenance.
+    /// If `vacuum` is set, trades table is vacuumed in addition to analyze.
+    pub fn new(db: Db, vacuum: bool) -> Self {
+        Self { db, vacuum }
+    }
+
+    /// Run maintenance periodically.
+    pub async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let Some(guard) = self.db.try_lock_maintenance() else {
+                tracing::info!("Another DB maintenance is running, skipping.");
+                continue;
+            };
+
+            if let Err(e) = self.maintain(guard).await {
+                tracing::warn!("DB maintenance failed: {e}");
+            }
+        }
+    }
+
+    /// Try to acquire exclusive right to run maintenance.
+    /// Returns `None` if another maintenance is running.
+    pub fn try_lock(&self) -> Option<OwnedMutexGuard<()>> {
+        self.db.try_lock_maintenance()
+    }
+
+    /// Analyze (and optionally vacuum) trades table.
+    pub async fn maintain(&self, guard: OwnedMutexGuard<()>) -> anyhow::Result<()> {
+        let start = Instant::now();
+        self.db.analyze_trades(&guard, self.vacuum).await?;
+        tracing::info!(
+            "DB maintenance finished in {:?} (vacuum: {}).",
+            start.elapsed(),
+            self.vacuum
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::PgPool;
+
+    fn test_maintenance(pool: PgPool, vacuum: bool) -> DbMaintenance {
+        let db = Db::from_pool(pool, Default::default(), Duration::from_secs(10));
+        DbMaintenance::new(db, vacuum)
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn maintenance_runs_exclusively(pool: PgPool) {
+        let maintenance = test_maintenance(pool, false);
+        let guard = maintenance.try_lock().unwrap();
+        // Clones share the lock with DB operations.
+        assert!(maintenance.clone().try_lock().is_none());
+        assert!(maintenance.db.try_lock_maintenance().is_none());
+
+        maintenance.maintain(guard).await.unwrap();
+        assert!(maintenance.try_lock().is_some());
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn trades_are_vacuumed(pool: PgPool) {
+        let maintenance = test_maintenance(pool, true);
+        let guard = maintenance.try_lock().unwrap();
+        maintenance.maintain(guard).await.unwrap();
+    }
+}
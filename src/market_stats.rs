@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::types::chrono::Utc;
+use tokio::sync::broadcast;
+
+use crate::storage::Storage;
+
+/// Period market stats are computed over.
+pub const MARKET_STATS_PERIOD: Duration = Duration::from_secs(60);
+
+/// Number of stats snapshots kept for slow subscribers.
+const STATS_CHANNEL_CAPACITY: usize = 16;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+/// Market-wide activity over the last period.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MarketSnapshot {
+    /// End of the period as unix timestamp in milliseconds.
+    pub timestamp: u64,
+    /// Created tokens.
+    pub creates: u64,
+    /// Trades of all tokens.
+    pub trades: u64,
+    /// Total volume of trades in SOL.
+    pub sol_volume: f64,
+    /// Number of tokens traded, read from DB.
+    pub active_tokens: usize,
+}
+
+/// Market-wide activity counters, periodically rolled up into snapshots
+/// broadcast to subscribers.
+#[derive(Clone)]
+pub struct MarketStats {
+    creates: Arc<AtomicU64>,
+    trades: Arc<AtomicU64>,
+    lamports: Arc<AtomicU64>,
+    latest: Arc<Mutex<Option<MarketSnapshot>>>,
+    snapshots: broadcast::Sender<MarketSnapshot>,
+}
+
+impl Default for MarketStats {
+    fn default() -> Self {
+        let (snapshots, _) = broadcast::channel(STATS_CHANNEL_CAPACITY);
+        Self {
+            creates: Default::default(),
+            trades: Default::default(),
+            lamports: Default::default(),
+            latest: Default::default(),
+            snapshots,
+        }
+    }
+}
+
+impl MarketStats {
+    /// Count created token.
+    pub fn record_create(&self) {
+        self.creates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count trade with its SOL amount in lamports.
+    pub fn record_trade(&self, lamports: u64) {
+        self.trades.fetch_add(1, Ordering::Relaxed);
+        self.lamports.fetch_add(lamports, Ordering::Relaxed);
+    }
+
+    /// Subscribe to stats snapshots.
+    /// Returns the latest snapshot, if any, so subscribers don't wait for the next period.
+    pub fn subscribe(&self) -> (Option<MarketSnapshot>, broadcast::Receiver<MarketSnapshot>) {
+        let receiver = self.snapshots.subscribe();
+        let latest = *self.latest.lock().unwrap_or_else(PoisonError::into_inner);
+        (latest, receiver)
+    }
+
+    /// Roll up counters into a snapshot every period.
+    /// Runs apart from ingestion, which only bumps counters.
+    pub async fn run(self, storage: Storage) {
+        let mut interval = tokio::time::interval(MARKET_STATS_PERIOD);
+        // The first tick completes immediately, counters are empty yet.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.roll_up(&storage).await;
+        }
+    }
+
+    /// Take counters into a snapshot and broadcast it.
+    async fn roll_up(&self, storage: &Storage) -> MarketSnapshot {
+        let now = Utc::now();
+        let creates = self.creates.swap(0, Ordering::Relaxed);
+        let trades = self.trades.swap(0, Ordering::Relaxed);
+        let lamports = self.lamports.swap(0, Ordering::Relaxed);
+        let active_tokens = match storage.active_mints(now - MARKET_STATS_PERIOD).await {
+            Ok(mints) => mints.len(),
+            Err(e) => {
+                tracing::info!("Failed to read active tokens: {e}.");
+                0
+            }
+        };
+
+        let snapshot = MarketSnapshot {
+            timestamp: now.timestamp_millis() as u64,
+            creates,
+            trades,
+            sol_volume: lamports as f64 / LAMPORTS_PER_SOL,
+            active_tokens,
+        };
+        *self.latest.lock().unwrap_or_else(PoisonError::into_inner) = Some(snapshot);
+        // No subscribers is fine.
+        let _ = self.snapshots.send(snapshot);
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    use crate::db::Db;
+    use crate::hot_candles::HotCandles;
+    use crate::model::{Resolution, TradeInfo};
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn stats_reflect_recorded_events(pool: PgPool) {
+        let db = Db::from_pool(pool.clone(), Default::default(), Duration::from_secs(10));
+        let storage =
+            Storage::for_tests(pool, "redis://127.0.0.1:1", HotCandles::new(None, 0)).await;
+        for mint in ["first", "second"] {
+            db.insert_token(mint.to_string(), None).await.unwrap();
+            let info = TradeInfo {
+                mint_acc: mint.to_string(),
+                timestamp: Utc::now(),
+                sol_amount: 1_000,
+                token_amount: 2_000,
+                is_buy: true,
+                decimals: None,
+            };
+            let bucket = Resolution::M1.bucket_start(info.timestamp);
+            db.insert_trades(&[(vec![(Resolution::M1, bucket)], info)])
+                .await
+                .unwrap();
+        }
+
+        let stats = MarketStats::default();
+        let (latest, mut snapshots) = stats.subscribe();
+        assert!(latest.is_none());
+        stats.record_create();
+        stats.record_trade(1_500_000_000);
+        stats.record_trade(500_000_000);
+        stats.record_trade(250_000_000);
+
+        let snapshot = stats.roll_up(&storage).await;
+        assert_eq!(snapshot.creates, 1);
+        assert_eq!(snapshot.trades, 3);
+        assert_eq!(snapshot.sol_volume, 2.25);
+        assert_eq!(snapshot.active_tokens, 2);
+        let received = snapshots.recv().await.unwrap();
+        assert_eq!(received.timestamp, snapshot.timestamp);
+        assert_eq!(stats.subscribe().0.unwrap().trades, 3);
+
+        // Counters start over every period.
+        let snapshot = stats.roll_up(&storage).await;
+        assert_eq!((snapshot.creates, snapshot.trades), (0, 0));
+        assert_eq!(snapshot.sol_volume, 0.0);
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use solana_pubkey::Pubkey;
+
+use crate::metrics::Metrics;
+use crate::model::TokenMetadata;
+use crate::pump_handler::PumpHandler;
+use crate::rpc_pool::RpcPool;
+
+/// Consecutive metadata fetch failures of a token after which fetches are suspended.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Time metadata fetches of a token are suspended for.
+const COOLDOWN: Duration = Duration::from_secs(3600);
+
+/// Min interval between evictions of expired failure states.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failures of a token metadata fetches.
+#[derive(Debug)]
+struct FailureState {
+    consecutive: u32,
+    open_until: Option<Instant>,
+    /// State is evicted after this time, unless the token fails again:
+    /// cooldown after the last failure, or after the end of suspension.
+    expires_at: Instant,
+}
+
+/// Metadata fetch shared by concurrent queries of the same token.
+type SharedFetch = Shared<BoxFuture<'static, Option<TokenMetadata>>>;
+
+/// Per-token circuit breaker of metadata fetches.
+/// Some tokens never have resolvable metadata, so retrying them just wastes RPC.
+/// Concurrent queries of a token share a single fetch, e.g. on a burst of trades of a new token.
+#[derive(Clone)]
+pub struct MetadataBreaker {
+    failures: Arc<DashMap<String, FailureState>>,
+    /// Time of the next eviction of expired failure states.
+    next_eviction: Arc<Mutex<Instant>>,
+    /// Fetches in flight by token. Entry is removed once its fetch completes.
+    in_flight: Arc<DashMap<String, SharedFetch>>,
+    metrics: Arc<Metrics>,
+}
+
+impl MetadataBreaker {
+    /// Create new breaker with all tokens allowed.
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            failures: Default::default(),
+            next_eviction: Arc::new(Mutex::new(Instant::now() + EVICTION_INTERVAL)),
+            in_flight: Default::default(),
+            metrics,
+        }
+    }
+
+    /// Whether metadata fetches of the token are suspended.
+    pub fn is_open(&self, mint: &str) -> bool {
+        self.failures
+            .get(mint)
+            .and_then(|state| state.open_until)
+            .is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    /// Record successful fetch, so the token is allowed again.
+    pub fn record_success(&self, mint: &str) {
+        self.failures.remove(mint);
+    }
+
+    /// Record failed fetch. Suspends fetches of the token after `FAILURE_THRESHOLD` failures in a row.
+    pub fn record_failure(&self, mint: &str) {
+        self.evict_expired();
+
+        let now = Instant::now();
+        let mut state = self
+            .failures
+            .entry(mint.to_string())
+            .or_insert(FailureState {
+                consecutive: 0,
+                open_until: None,
+                expires_at: now,
+            });
+        state.consecutive += 1;
+        state.expires_at = now + COOLDOWN;
+        if state.consecutive >= FAILURE_THRESHOLD {
+            // One more try is allowed after cooldown, next failure suspends again.
+            state.consecutive = FAILURE_THRESHOLD - 1;
+            state.open_until = Some(now + COOLDOWN);
+            state.expires_at = now + 2 * COOLDOWN;
+            self.metrics
+                .metadata_breaker_opened
+                .fetch_add(1, Ordering::Relaxed);
+            tracing::info!("Metadata fetches of {mint} suspended for {COOLDOWN:?}.");
+        }
+    }
+
+    /// Remove failure states of tokens which haven't failed for a while, so tokens failing
+    /// once aren't kept forever. Runs at most once per `EVICTION_INTERVAL`.
+    fn evict_expired(&self) {
+        let now = Instant::now();
+        {
+            let mut next_eviction = self.next_eviction.lock().unwrap();
+            if now < *next_eviction {
+                return;
+            }
+            *next_eviction = now + EVICTION_INTERVAL;
+        }
+        self.failures.retain(|_, state| now < state.expires_at);
+    }
+
+    /// Query token metadata unless fetches of the token are suspended.
+    /// Joins the fetch of the token already in flight, if any.
+    pub async fn query(&self, rpc: &RpcPool, mint: Pubkey) -> Option<TokenMetadata> {
+        let mint_str = mint.to_string();
+        if self.is_open(&mint_str) {
+            return None;
+        }
+
+        let fetch = match self.in_flight.entry(mint_str.clone()) {
+            Entry::Occupied(entry) => {
+                self.metrics
+                    .metadata_fetches_joined
+                    .fetch_add(1, Ordering::Relaxed);
+                entry.get().clone()
+            }
+            Entry::Vacant(entry) => {
+                let breaker = self.clone();
+                let rpc = rpc.clone();
+                let fetch = async move {
+                    let metadata = breaker.fetch(&rpc, mint).await;
+                    // Later queries, e.g. metadata refresh, start a new fetch.
+                    breaker.in_flight.remove(&mint.to_string());
+                    metadata
+                }
+                .boxed()
+                .shared();
+                entry.insert(fetch).clone()
+            }
+        };
+        fetch.await
+    }
+
+    /// Fetch token metadata and record the outcome.
+    async fn fetch(&self, rpc: &RpcPool, mint: Pubkey) -> Option<TokenMetadata> {
+        let mint_str = mint.to_string();
+        match PumpHandler::query_token_metadata(rpc, mint).await {
+            Ok(metadata) => {
+                self.record_success(&mint_str);
+                Some(metadata)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to query token metadata: {e}");
+                self.record_failure(&mint_str);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use serde_json::{Value, json};
+    use std::sync::atomic::AtomicUsize;
+
+    /// Mock RPC endpoint without any accounts, counting calls.
+    /// Responses are delayed, so concurrent queries overlap.
+    async fn empty_rpc(calls: Arc<AtomicUsize>) -> RpcPool {
+        let handler = move |Json(request): Json<Value>| async move {
+            calls.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let accounts = vec![Value::Null; request["params"][0].as_array().unwrap().len()];
+            Json(json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": {"context": {"slot": 1}, "value": accounts},
+            }))
+        };
+        let router = axum::Router::new().route("/", axum::routing::post(handler));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        RpcPool::new(&[format!("http://{addr}")])
+    }
+
+    #[tokio::test]
+    async fn failing_token_is_not_fetched_until_cooldown() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let rpc = empty_rpc(calls.clone()).await;
+        let metrics = Arc::new(Metrics::default());
+        let breaker = MetadataBreaker::new(metrics.clone());
+        let mint = Pubkey::new_unique();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(breaker.query(&rpc, mint).await.is_none());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), FAILURE_THRESHOLD as usize);
+        assert!(breaker.is_open(&mint.to_string()));
+        assert_eq!(metrics.metadata_breaker_opened.load(Ordering::Relaxed), 1);
+
+        // Suspended token is not fetched.
+        assert!(breaker.query(&rpc, mint).await.is_none());
+        assert_eq!(calls.load(Ordering::Relaxed), FAILURE_THRESHOLD as usize);
+
+        // One more try is made after cooldown, and its failure suspends again.
+        breaker
+            .failures
+            .get_mut(&mint.to_string())
+            .unwrap()
+            .open_until = Some(Instant::now());
+        assert!(!breaker.is_open(&mint.to_string()));
+        assert!(breaker.query(&rpc, mint).await.is_none());
+        assert_eq!(
+            calls.load(Ordering::Relaxed),
+            FAILURE_THRESHOLD as usize + 1
+        );
+        assert!(breaker.is_open(&mint.to_string()));
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = MetadataBreaker::new(Default::default());
+        let mint = "So11111111111111111111111111111111111111112";
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            breaker.record_failure(mint);
+        }
+        breaker.record_success(mint);
+        breaker.record_failure(mint);
+        assert!(!breaker.is_open(mint));
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_share_fetch() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let rpc = empty_rpc(calls.clone()).await;
+        let metrics = Arc::new(Metrics::default());
+        let breaker = MetadataBreaker::new(metrics.clone());
+        let mint = Pubkey::new_unique();
+
+        let (first, second) = tokio::join!(breaker.query(&rpc, mint), breaker.query(&rpc, mint));
+        assert!(first.is_none() && second.is_none());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.metadata_fetches_joined.load(Ordering::Relaxed), 1);
+        assert!(breaker.in_flight.is_empty());
+    }
+
+    #[test]
+    fn expired_failures_are_evicted() {
+        let breaker = MetadataBreaker::new(Default::default());
+        let (stale, suspended, fresh) = ("stale", "suspended", "fresh");
+        breaker.record_failure(stale);
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_failure(suspended);
+        }
+        let now = Instant::now();
+        breaker.failures.get_mut(stale).unwrap().expires_at = now;
+        // Suspension has ended, but the one more try is still tracked.
+        breaker.failures.get_mut(suspended).unwrap().open_until = Some(now);
+
+        // Eviction isn't due yet.
+        breaker.record_failure(fresh);
+        assert!(breaker.failures.contains_key(stale));
+
+        *breaker.next_eviction.lock().unwrap() = now;
+        breaker.record_failure(fresh);
+        assert!(!breaker.failures.contains_key(stale));
+        assert!(breaker.failures.contains_key(suspended));
+        assert_eq!(breaker.failures.get(fresh).unwrap().consecutive, 2);
+    }
+}
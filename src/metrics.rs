@@ -0,0 +1,202 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use dashmap::DashMap;
+
+/// Upper bounds of DB query duration histogram buckets in seconds.
+const QUERY_DURATION_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Application metrics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Events skipped because of invalid timestamp.
+    pub invalid_timestamp_events: AtomicU64,
+    /// Events dropped while ingestion was paused.
+    pub paused_dropped_events: AtomicU64,
+    /// Events dropped because the events channel was full or closed.
+    pub channel_dropped_events: AtomicU64,
+    /// Storage reads served by in-memory candles of hot tokens.
+    pub memory_reads: AtomicU64,
+    /// Storage reads served by cache.
+    pub cache_reads: AtomicU64,
+    /// Storage reads served by DB.
+    pub db_reads: AtomicU64,
+    /// Candles written back into cache after it missed them.
+    pub cache_backfilled_candles: AtomicU64,
+    /// Tokens stored for the first time.
+    pub new_tokens: AtomicU64,
+    /// Candles created by ingested trades.
+    pub new_candles: AtomicU64,
+    /// Times metadata fetches of a token were suspended after repeated failures.
+    pub metadata_breaker_opened: AtomicU64,
+    /// Metadata queries which joined a fetch of the same token already in flight.
+    pub metadata_fetches_joined: AtomicU64,
+    /// Ingested trades failed to be inserted into DB.
+    pub failed_trade_inserts: AtomicU64,
+    /// Trades queued for retry after failed DB insertion.
+    pub retry_queued_trades: AtomicU64,
+    /// Trades dropped because retry queue was full.
+    pub retry_dropped_trades: AtomicU64,
+    /// Durations of DB queries by query name.
+    db_query_durations: DashMap<&'static str, Histogram>,
+}
+
+/// Histogram of durations.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative number of observations in every bucket of `QUERY_DURATION_BUCKETS`.
+    buckets: [AtomicU64; QUERY_DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Record duration of a DB query.
+    pub fn observe_db_query(&self, query: &'static str, duration: Duration) {
+        let histogram = self.db_query_durations.entry(query).or_default();
+        let secs = duration.as_secs_f64();
+        for (bucket, le) in histogram.buckets.iter().zip(QUERY_DURATION_BUCKETS) {
+            if secs <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        histogram.count.fetch_add(1, Ordering::Relaxed);
+        histogram
+            .sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Render metrics in Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "invalid_timestamp_events",
+            "Events skipped because of invalid timestamp.",
+            &self.invalid_timestamp_events,
+        );
+        write_counter(
+            &mut out,
+            "paused_dropped_events",
+            "Events dropped while ingestion was paused.",
+            &self.paused_dropped_events,
+        );
+        write_counter(
+            &mut out,
+            "channel_dropped_events",
+            "Events dropped because the events channel was full or closed.",
+            &self.channel_dropped_events,
+        );
+        write_counter(
+            &mut out,
+            "storage_memory_reads",
+            "Storage reads served by in-memory candles of hot tokens.",
+            &self.memory_reads,
+        );
+        write_counter(
+            &mut out,
+            "storage_cache_reads",
+            "Storage reads served by cache.",
+            &self.cache_reads,
+        );
+        write_counter(
+            &mut out,
+            "storage_db_reads",
+            "Storage reads served by DB.",
+            &self.db_reads,
+        );
+        write_counter(
+            &mut out,
+            "cache_backfilled_candles",
+            "Candles written back into cache after it missed them.",
+            &self.cache_backfilled_candles,
+        );
+        write_counter(
+            &mut out,
+            "new_tokens",
+            "Tokens stored for the first time.",
+            &self.new_tokens,
+        );
+        write_counter(
+            &mut out,
+            "new_candles",
+            "Candles created by ingested trades.",
+            &self.new_candles,
+        );
+        write_counter(
+            &mut out,
+            "metadata_breaker_opened",
+            "Times metadata fetches of a token were suspended after repeated failures.",
+            &self.metadata_breaker_opened,
+        );
+        write_counter(
+            &mut out,
+            "metadata_fetches_joined",
+            "Metadata queries which joined a fetch of the same token already in flight.",
+            &self.metadata_fetches_joined,
+        );
+        write_counter(
+            &mut out,
+            "failed_trade_inserts",
+            "Ingested trades failed to be inserted into DB.",
+            &self.failed_trade_inserts,
+        );
+        write_counter(
+            &mut out,
+            "retry_queued_trades",
+            "Trades queued for retry after failed DB insertion.",
+            &self.retry_queued_trades,
+        );
+        write_counter(
+            &mut out,
+            "retry_dropped_trades",
+            "Trades dropped because retry queue was full.",
+            &self.retry_dropped_trades,
+        );
+        self.write_db_query_durations(&mut out);
+        out
+    }
+
+    /// Write DB query durations histogram in Prometheus text format.
+    fn write_db_query_durations(&self, out: &mut String) {
+        let name = "db_query_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Durations of DB queries.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        let mut queries: Vec<_> = self
+            .db_query_durations
+            .iter()
+            .map(|entry| *entry.key())
+            .collect();
+        queries.sort_unstable();
+        for query in queries {
+            let Some(histogram) = self.db_query_durations.get(query) else {
+                continue;
+            };
+            for (bucket, le) in histogram.buckets.iter().zip(QUERY_DURATION_BUCKETS) {
+                let value = bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{query=\"{query}\",le=\"{le}\"}} {value}"
+                );
+            }
+            let count = histogram.count.load(Ordering::Relaxed);
+            let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{query=\"{query}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(out, "{name}_sum{{query=\"{query}\"}} {sum}");
+            let _ = writeln!(out, "{name}_count{{query=\"{query}\"}} {count}");
+        }
+    }
+}
+
+/// Write counter in Prometheus text format.
+fn write_counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let value = value.load(Ordering::Relaxed);
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {value}");
+}
@@ -1,11 +1,24 @@
 use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, AtomicU8, AtomicU16, Ordering};
+use std::time::Duration;
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use pumpfun::common::stream::PumpFunEvent;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use solana_pubkey::Pubkey;
 use sqlx::types::chrono::{DateTime, Utc};
 
-/// Candle with open, close, high, low and volume.
+/// Candle with open, close, high, low, volume and number of trades.
+///
+/// Prices are kept as doubles everywhere (`FLOAT8` in DB, doubles in Redis time series),
+/// so ~15 significant digits are preserved at any magnitude, including tiny SOL prices.
+/// Conversions through fixed-point types must be avoided, since they round small values.
+///
+/// Volumes are in token base units. A single trade is always exact as a double: the whole
+/// pump.fun supply is 10^15 base units, below 2^53. Sums may exceed 2^53, so DB accumulates
+/// them exactly as `NUMERIC` and converts to doubles only on read, while cache and in-memory
+/// candles accumulate doubles and may be off by a few base units for huge volumes.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Candle {
     pub open: f64,
@@ -13,18 +26,167 @@ pub struct Candle {
     pub high: f64,
     pub low: f64,
     pub volume: f64,
+    pub trade_count: u64,
+    /// Volume of buy trades.
+    pub buy_volume: f64,
+    /// Volume of sell trades.
+    pub sell_volume: f64,
+    /// Volume-weighted average price, close price if there is no volume
+    /// or it's unknown for candles stored before it was tracked.
+    pub vwap: f64,
+}
+
+impl Candle {
+    /// Candle of a bucket without trades, keeping the previous close price.
+    pub fn flat(price: f64) -> Self {
+        Self {
+            open: price,
+            close: price,
+            high: price,
+            low: price,
+            vwap: price,
+            ..Default::default()
+        }
+    }
+
+    /// Set significant digits prices are rounded to when candles are sent to clients,
+    /// full precision if not set. Stored prices are never rounded.
+    pub fn set_display_significant_digits(digits: Option<u8>) {
+        DISPLAY_SIGNIFICANT_DIGITS.store(digits.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Candle with prices rounded for clients, see [`Candle::set_display_significant_digits`].
+    /// Volumes are kept as is.
+    pub fn for_display(self) -> Self {
+        self.rounded(DISPLAY_SIGNIFICANT_DIGITS.load(Ordering::Relaxed))
+    }
+
+    /// Candle with prices rounded to significant digits, as is for 0 digits.
+    fn rounded(self, digits: u8) -> Self {
+        if digits == 0 {
+            return self;
+        }
+
+        Self {
+            open: round_significant(self.open, digits),
+            close: round_significant(self.close, digits),
+            high: round_significant(self.high, digits),
+            low: round_significant(self.low, digits),
+            vwap: round_significant(self.vwap, digits),
+            ..self
+        }
+    }
+
+    /// Candle with given open price, extending high and low to include it.
+    pub fn with_open(self, open: f64) -> Self {
+        Self {
+            open,
+            high: self.high.max(open),
+            low: self.low.min(open),
+            ..self
+        }
+    }
+}
+
+/// Significant digits of prices sent to clients, 0 for full precision.
+static DISPLAY_SIGNIFICANT_DIGITS: AtomicU8 = AtomicU8::new(0);
+
+/// Round value to significant digits, so tiny and huge prices keep their magnitude
+/// unlike with fixed decimals.
+fn round_significant(value: f64, digits: u8) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    // Decimal scientific notation rounds exactly, scaling by powers of ten may overflow.
+    format!("{:.*e}", usize::from(digits - 1), value)
+        .parse()
+        .unwrap_or(value)
+}
+
+/// Definition of a candle open price.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenPolicy {
+    /// Price of the first trade in the bucket.
+    #[default]
+    FirstTrade,
+    /// Close of the previous candle, so the chart has no gaps between candles.
+    PreviousClose,
+}
+
+impl FromStr for OpenPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first_trade" => Ok(OpenPolicy::FirstTrade),
+            "previous_close" => Ok(OpenPolicy::PreviousClose),
+            _ => Err(String::from("expected `first_trade` or `previous_close`")),
+        }
+    }
+}
+
+/// Applies open policy to time-ordered candles, where the last one may be repeated with updates.
+#[derive(Debug)]
+pub struct CandleOpens {
+    policy: OpenPolicy,
+    /// Timestamp and close of the latest candle.
+    last: Option<(u64, f64)>,
+    /// Close of the candle preceding the latest one.
+    previous_close: Option<f64>,
+}
+
+impl CandleOpens {
+    /// Create new policy applier.
+    pub fn new(policy: OpenPolicy) -> Self {
+        Self {
+            policy,
+            last: None,
+            previous_close: None,
+        }
+    }
+
+    /// Forget applied candles, so older ones can be applied again.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.previous_close = None;
+    }
+
+    /// Apply policy to the next candle. Candles older than the latest one are left as is.
+    pub fn apply(&mut self, trade: &mut TradeOhlcv) {
+        match self.last {
+            Some((timestamp, _)) if trade.timestamp < timestamp => return,
+            Some((timestamp, close)) if trade.timestamp > timestamp => {
+                self.previous_close = Some(close);
+            }
+            _ => {}
+        }
+        self.last = Some((trade.timestamp, trade.candle.close));
+
+        if self.policy == OpenPolicy::PreviousClose
+            && let Some(previous_close) = self.previous_close
+        {
+            trade.candle = trade.candle.with_open(previous_close);
+        }
+    }
 }
 
 /// Trade events time resolution.
-#[derive(Debug, Clone, Copy, sqlx::Type, Serialize, Deserialize)]
+/// Parsed leniently, see [`Resolution::from_str`], serialized as variant name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type, Serialize, Deserialize)]
 #[sqlx(type_name = "resolution")]
+#[serde(try_from = "String")]
 pub enum Resolution {
     S1,
     M1,
     M5,
     M15,
+    M30,
     H1,
+    H4,
+    H12,
     D1,
+    W1,
 }
 
 impl fmt::Display for Resolution {
@@ -34,13 +196,70 @@ impl fmt::Display for Resolution {
             Resolution::M1 => write!(f, "M1"),
             Resolution::M5 => write!(f, "M5"),
             Resolution::M15 => write!(f, "M15"),
+            Resolution::M30 => write!(f, "M30"),
             Resolution::H1 => write!(f, "H1"),
+            Resolution::H4 => write!(f, "H4"),
+            Resolution::H12 => write!(f, "H12"),
             Resolution::D1 => write!(f, "D1"),
+            Resolution::W1 => write!(f, "W1"),
         }
     }
 }
 
+impl FromStr for Resolution {
+    type Err = String;
+
+    /// Parse case-insensitive variant name, e.g. `m1`, trading alias, e.g. `1m` or `1min`,
+    /// or number of seconds, e.g. `60`. Surrounding whitespace is ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lowercase = s.to_ascii_lowercase();
+        Resolution::all()
+            .into_iter()
+            .find(|resolution| {
+                lowercase == resolution.to_string().to_ascii_lowercase()
+                    || resolution.aliases().contains(&lowercase.as_str())
+                    || lowercase == resolution.as_seconds().to_string()
+            })
+            .ok_or_else(|| {
+                let names: Vec<_> = Resolution::all()
+                    .iter()
+                    .map(Resolution::to_string)
+                    .collect();
+                format!(
+                    "Unknown resolution {s:?}, expected one of {} in any case, \
+                    an alias like 1m or 5min, or seconds like 60",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl Resolution {
+    /// Common trading aliases of the resolution in lowercase.
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Resolution::S1 => &["1s", "1sec"],
+            Resolution::M1 => &["1m", "1min"],
+            Resolution::M5 => &["5m", "5min"],
+            Resolution::M15 => &["15m", "15min"],
+            Resolution::M30 => &["30m", "30min"],
+            Resolution::H1 => &["1h", "60m", "60min"],
+            Resolution::H4 => &["4h", "240m", "240min"],
+            Resolution::H12 => &["12h"],
+            Resolution::D1 => &["1d"],
+            Resolution::W1 => &["1w"],
+        }
+    }
+
     /// Convert resolution to seconds.
     pub fn as_seconds(&self) -> u64 {
         match self {
@@ -48,8 +267,12 @@ impl Resolution {
             Resolution::M1 => 60,
             Resolution::M5 => 300,
             Resolution::M15 => 900,
+            Resolution::M30 => 1800,
             Resolution::H1 => 3600,
+            Resolution::H4 => 14400,
+            Resolution::H12 => 43200,
             Resolution::D1 => 86400,
+            Resolution::W1 => 604800,
         }
     }
 
@@ -58,46 +281,343 @@ impl Resolution {
         self.as_seconds() * 1000
     }
 
+    /// Default number of history points for a chart.
+    /// Chosen to cover a sensible wall-clock window for each resolution:
+    /// S1 - 5 minutes, M1 - 2 hours, M5 - 12 hours, M15 - 2 days, M30 - 2 days, H1 - 1 week,
+    /// H4 - 30 days, H12 - 60 days, D1 - 90 days, W1 - 1 year.
+    pub fn default_history_points(&self) -> usize {
+        match self {
+            Resolution::S1 => 300,
+            Resolution::M1 => 120,
+            Resolution::M5 => 144,
+            Resolution::M15 => 192,
+            Resolution::M30 => 96,
+            Resolution::H1 => 168,
+            Resolution::H4 => 180,
+            Resolution::H12 => 120,
+            Resolution::D1 => 90,
+            Resolution::W1 => 52,
+        }
+    }
+
     /// All available resolutions.
-    pub fn all() -> [Resolution; 6] {
+    pub fn all() -> [Resolution; 10] {
         [
             Resolution::S1,
             Resolution::M1,
             Resolution::M5,
             Resolution::M15,
+            Resolution::M30,
             Resolution::H1,
+            Resolution::H4,
+            Resolution::H12,
             Resolution::D1,
+            Resolution::W1,
         ]
     }
 
-    /// Align timestamp to the closest resolution step.
-    pub fn align_datetime(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
-        let ts_millis = timestamp.timestamp_millis() as u64 / self.as_millis() * self.as_millis();
-        DateTime::from_timestamp_millis(ts_millis as i64).expect("correct datetime")
+    /// Set resolutions which candles are stored. Others are neither written nor served.
+    /// At least one resolution must be enabled.
+    pub fn set_enabled(resolutions: &[Resolution]) {
+        let mask = resolutions
+            .iter()
+            .fold(0, |mask, resolution| mask | resolution.enabled_bit());
+        ENABLED_RESOLUTIONS.store(mask, Ordering::Relaxed);
+    }
+
+    /// Enabled resolutions, finest first.
+    pub fn enabled() -> Vec<Resolution> {
+        Self::all()
+            .into_iter()
+            .filter(Resolution::is_enabled)
+            .collect()
+    }
+
+    /// Finest enabled resolution.
+    pub fn finest_enabled() -> Resolution {
+        // Config guarantees at least one enabled resolution.
+        Self::enabled().first().copied().unwrap_or(Resolution::S1)
+    }
+
+    /// Whether candles of the resolution are stored.
+    pub fn is_enabled(&self) -> bool {
+        ENABLED_RESOLUTIONS.load(Ordering::Relaxed) & self.enabled_bit() != 0
+    }
+
+    /// Bit of the resolution in enabled resolutions mask.
+    fn enabled_bit(&self) -> u16 {
+        1 << (*self as u16)
+    }
+
+    /// Set offset of multi-hour candles start from UTC midnight, e.g. to align daily candles
+    /// to a local market day. Must be set before any timestamp is aligned,
+    /// otherwise stored candles will have inconsistent boundaries.
+    pub fn set_day_anchor_offset(offset: Duration) {
+        DAY_ANCHOR_OFFSET_MILLIS.store(offset.as_millis() as i64, Ordering::Relaxed);
+    }
+
+    /// Start of the bucket containing timestamp, i.e. timestamp aligned to the resolution step.
+    /// Multi-hour resolutions are aligned with the day anchor offset, weeks start on Monday.
+    pub fn bucket_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        self.anchored_bucket_start(timestamp, DAY_ANCHOR_OFFSET_MILLIS.load(Ordering::Relaxed))
+    }
+
+    /// Start of the bucket containing timestamp with given day anchor offset in milliseconds.
+    fn anchored_bucket_start(
+        &self,
+        timestamp: DateTime<Utc>,
+        day_anchor_offset: i64,
+    ) -> DateTime<Utc> {
+        let step = self.as_millis() as i64;
+        let mut offset = if self.as_seconds() > 3600 {
+            day_anchor_offset
+        } else {
+            0
+        };
+        if *self == Resolution::W1 {
+            offset += WEEK_START_OFFSET_MILLIS;
+        }
+        let ts_millis = (timestamp.timestamp_millis() - offset).div_euclid(step) * step + offset;
+        // Aligned timestamp is at most one step earlier, so it's in range for any sane input.
+        DateTime::from_timestamp_millis(ts_millis).unwrap_or(timestamp)
+    }
+
+    /// End of the bucket containing timestamp (exclusive).
+    pub fn bucket_end(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        self.bucket_start(timestamp) + Duration::from_secs(self.as_seconds())
+    }
+
+    /// Whether `timestamp` belongs to the same bucket as `bucket_timestamp`.
+    pub fn bucket_contains(
+        &self,
+        bucket_timestamp: DateTime<Utc>,
+        timestamp: DateTime<Utc>,
+    ) -> bool {
+        timestamp >= self.bucket_start(bucket_timestamp)
+            && timestamp < self.bucket_end(bucket_timestamp)
+    }
+}
+
+/// Mask of enabled resolutions, all are enabled by default.
+static ENABLED_RESOLUTIONS: AtomicU16 = AtomicU16::new(u16::MAX);
+
+/// Offset of Monday from UNIX epoch, which is Thursday.
+const WEEK_START_OFFSET_MILLIS: i64 = 4 * 24 * 3600 * 1000;
+
+/// Offset of multi-hour candles start from UTC midnight in milliseconds.
+static DAY_ANCHOR_OFFSET_MILLIS: AtomicI64 = AtomicI64::new(0);
+
+/// Earliest accepted event timestamp in seconds (2024-01-01, before pump.fun launch).
+pub const MIN_EVENT_TIMESTAMP: i64 = 1_704_067_200;
+
+/// Max accepted difference between event timestamp and current time in the future.
+pub const MAX_EVENT_CLOCK_SKEW: Duration = Duration::from_secs(600);
+
+/// Convert event timestamp in seconds to datetime.
+/// Returns `None` if timestamp is before pump.fun existed or too far in the future.
+pub fn event_datetime(timestamp: i64) -> Option<DateTime<Utc>> {
+    let max_timestamp = (Utc::now() + MAX_EVENT_CLOCK_SKEW).timestamp();
+    if !(MIN_EVENT_TIMESTAMP..=max_timestamp).contains(&timestamp) {
+        return None;
+    }
+
+    DateTime::from_timestamp(timestamp, 0)
+}
+
+/// Token mint address validated to be a correct Solana pubkey.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Mint(String);
+
+impl Mint {
+    /// Mint address as string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Mint address as pubkey.
+    pub fn to_pubkey(&self) -> Pubkey {
+        Pubkey::from_str(&self.0).expect("mint is validated on construction")
     }
 }
 
+impl TryFrom<String> for Mint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Pubkey::from_str(&value).map_err(|e| format!("Invalid mint address {value:?}: {e}"))?;
+        Ok(Self(value))
+    }
+}
+
+impl From<Mint> for String {
+    fn from(value: Mint) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for Mint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Decimals of SOL amounts in lamports.
+pub const SOL_DECIMALS: i32 = 9;
+
 /// Trade event info.
 #[derive(Debug, Clone)]
 pub struct TradeInfo {
     pub mint_acc: String,
+    pub timestamp: DateTime<Utc>,
     pub sol_amount: u64,
     pub token_amount: u64,
+    pub is_buy: bool,
+    /// Token decimals, `None` if unknown.
+    pub decimals: Option<u8>,
+}
+
+impl TradeInfo {
+    /// Token price in SOL per whole token: `(sol_amount / 10^9) / (token_amount / 10^decimals)`.
+    /// Ratio of raw amounts if token decimals are unknown.
+    pub fn price(&self) -> anyhow::Result<f64> {
+        let price = (self.sol_amount as f64) / (self.token_amount as f64)
+            * 10f64.powi(self.price_exponent());
+        if !price.is_finite() {
+            anyhow::bail!("Bad price: {} / {}", self.sol_amount, self.token_amount);
+        };
+        Ok(price)
+    }
+
+    /// Exponent of ten converting the SOL amount into price × token amount.
+    pub fn price_exponent(&self) -> i32 {
+        self.decimals
+            .map_or(0, |decimals| i32::from(decimals) - SOL_DECIMALS)
+    }
+}
+
+/// Indexer run, timestamps are unix milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: i64,
+    pub started_at: u64,
+    /// Not set for the running session and ones ended without graceful shutdown.
+    pub ended_at: Option<u64>,
+    /// Time of the last handled event, updated periodically.
+    pub last_event_at: Option<u64>,
 }
 
 /// Price data with timestamp.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct TradeOhlcv {
+    /// Candle start as unix timestamp in milliseconds.
     pub timestamp: u64,
+    #[serde(serialize_with = "serialize_display_candle")]
     pub candle: Candle,
 }
 
+/// Serialize candle for clients with prices rounded, see [`Candle::for_display`].
+fn serialize_display_candle<S: Serializer>(
+    candle: &Candle,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    candle.for_display().serialize(serializer)
+}
+
+impl TradeOhlcv {
+    /// Apply trade with bucket timestamp in milliseconds to the candle.
+    /// Trade of a newer bucket starts a new candle, trade of an older one is ignored.
+    pub fn apply_trade(&mut self, timestamp: u64, price: f64, volume: f64, is_buy: bool) {
+        let (buy_volume, sell_volume) = if is_buy { (volume, 0.0) } else { (0.0, volume) };
+        if timestamp < self.timestamp {
+            // Late trade of a closed candle.
+            return;
+        }
+
+        if timestamp > self.timestamp {
+            *self = TradeOhlcv {
+                timestamp,
+                candle: Candle {
+                    open: price,
+                    close: price,
+                    high: price,
+                    low: price,
+                    volume,
+                    trade_count: 1,
+                    buy_volume,
+                    sell_volume,
+                    vwap: price,
+                },
+            };
+            return;
+        }
+
+        let price_volume_sum = self.candle.vwap * self.candle.volume + price * volume;
+
+        self.candle.close = price;
+        self.candle.high = self.candle.high.max(price);
+        self.candle.low = self.candle.low.min(price);
+        self.candle.volume += volume;
+        self.candle.trade_count += 1;
+        self.candle.buy_volume += buy_volume;
+        self.candle.sell_volume += sell_volume;
+        if self.candle.volume > 0.0 {
+            self.candle.vwap = price_volume_sum / self.candle.volume;
+        }
+    }
+}
+
+/// Trade with the candle buckets it's inserted into.
+pub type BucketedTrade = (Vec<(Resolution, DateTime<Utc>)>, TradeInfo);
+
 /// Token metadata.
-#[derive(BorshSerialize, BorshDeserialize, Debug, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Serialize, Deserialize)]
 pub struct TokenMetadata {
     pub name: String,
     pub symbol: String,
     pub uri: String,
+    /// Time bonding curve was completed and token migrated to AMM, as unix timestamp
+    /// in milliseconds. Not a part of on-chain metadata.
+    #[borsh(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+    /// Token decimals from its mint account, `None` if unknown.
+    /// Not a part of on-chain metadata.
+    #[borsh(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+}
+
+impl TokenMetadata {
+    /// Placeholder metadata for a token which metadata is not resolved yet.
+    pub fn unresolved() -> Self {
+        Self {
+            name: String::from("unknown"),
+            symbol: String::from("NAN"),
+            uri: String::from("unknown"),
+            completed_at: None,
+            decimals: None,
+        }
+    }
+}
+
+/// Directional trading volume of a token over a window.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TokenPressure {
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+    /// Net buy pressure: `buy_volume - sell_volume`.
+    pub net_volume: f64,
+    /// Share of buys in total volume, from 0 (only sells) to 1 (only buys).
+    pub buy_ratio: f64,
+}
+
+/// Token metadata with its current state.
+#[derive(Debug, Serialize)]
+pub struct TokenDetails {
+    pub mint: String,
+    pub metadata: TokenMetadata,
+    pub last_trade: Option<TradeOhlcv>,
 }
 
 /// Indexed pumpfun event.
@@ -107,3 +627,440 @@ pub struct IndexedPumpfunEvent {
     pub _index: u64,
     pub event: PumpFunEvent,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(sol_amount: u64, token_amount: u64, decimals: Option<u8>) -> TradeInfo {
+        TradeInfo {
+            mint_acc: String::from("mint"),
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            sol_amount,
+            token_amount,
+            is_buy: true,
+            decimals,
+        }
+    }
+
+    #[test]
+    fn price_is_scaled_by_decimals() {
+        // 1 SOL for 1000 whole tokens of 6 decimals.
+        let info = trade(1_000_000_000, 1_000_000_000, Some(6));
+        assert_eq!(info.price_exponent(), -3);
+        assert!((info.price().unwrap() - 0.001).abs() < 1e-15);
+
+        let info = trade(1_000_000_000, 1_000_000_000, Some(9));
+        assert_eq!(info.price().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn price_is_raw_ratio_with_unknown_decimals() {
+        let info = trade(1_000_000_000, 4_000_000_000, None);
+        assert_eq!(info.price_exponent(), 0);
+        assert_eq!(info.price().unwrap(), 0.25);
+    }
+
+    #[test]
+    fn zero_token_amount_has_no_price() {
+        assert!(trade(1, 0, Some(6)).price().is_err());
+    }
+
+    #[test]
+    fn out_of_range_event_timestamps_are_rejected() {
+        assert_eq!(event_datetime(-1), None);
+        assert_eq!(event_datetime(0), None);
+        assert_eq!(event_datetime(MIN_EVENT_TIMESTAMP - 1), None);
+        let far_future = Utc::now() + MAX_EVENT_CLOCK_SKEW + Duration::from_secs(60);
+        assert_eq!(event_datetime(far_future.timestamp()), None);
+        assert_eq!(event_datetime(i64::MAX), None);
+    }
+
+    #[test]
+    fn sane_event_timestamps_are_accepted() {
+        let datetime = event_datetime(MIN_EVENT_TIMESTAMP).unwrap();
+        assert_eq!(datetime.timestamp(), MIN_EVENT_TIMESTAMP);
+        let now = Utc::now().timestamp();
+        assert_eq!(event_datetime(now).unwrap().timestamp(), now);
+    }
+
+    #[test]
+    fn valid_mint_is_accepted() {
+        let address = "So11111111111111111111111111111111111111112";
+        let mint = Mint::try_from(address.to_string()).unwrap();
+        assert_eq!(mint.as_str(), address);
+        assert_eq!(mint.to_pubkey().to_string(), address);
+        let mint: Mint = serde_json::from_str(&format!("{address:?}")).unwrap();
+        assert_eq!(mint.to_string(), address);
+    }
+
+    #[test]
+    fn invalid_mints_are_rejected() {
+        let invalid = [
+            "",
+            "mint",
+            // Not base58.
+            "So1111111111111111111111111111111111111111O",
+            "So11111111111111111111111111111111111111112; DROP TABLE token",
+            // Too long for 32 bytes.
+            "So11111111111111111111111111111111111111112So111111111111111111111",
+        ];
+        for address in invalid {
+            assert!(Mint::try_from(address.to_string()).is_err(), "{address}");
+            assert!(serde_json::from_str::<Mint>(&format!("{address:?}")).is_err());
+        }
+    }
+
+    fn datetime(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn daily_candles_shift_with_day_anchor() {
+        let eight_hours = 8 * 3600 * 1000;
+        let morning = datetime("2024-03-05T07:00:00Z");
+        let evening = datetime("2024-03-05T20:00:00Z");
+
+        // UTC midnight by default.
+        for timestamp in [morning, evening] {
+            assert_eq!(
+                Resolution::D1.anchored_bucket_start(timestamp, 0),
+                datetime("2024-03-05T00:00:00Z")
+            );
+        }
+
+        // Days start at 08:00 UTC, so the morning belongs to the previous day.
+        assert_eq!(
+            Resolution::D1.anchored_bucket_start(morning, eight_hours),
+            datetime("2024-03-04T08:00:00Z")
+        );
+        assert_eq!(
+            Resolution::D1.anchored_bucket_start(evening, eight_hours),
+            datetime("2024-03-05T08:00:00Z")
+        );
+        assert_eq!(
+            Resolution::H12.anchored_bucket_start(evening, eight_hours),
+            datetime("2024-03-05T20:00:00Z")
+        );
+    }
+
+    #[test]
+    fn sub_daily_candles_ignore_day_anchor() {
+        let timestamp = datetime("2024-03-05T07:30:00Z");
+        let eight_hours = 8 * 3600 * 1000;
+        assert_eq!(
+            Resolution::H1.anchored_bucket_start(timestamp, eight_hours),
+            datetime("2024-03-05T07:00:00Z")
+        );
+        assert_eq!(
+            Resolution::M15.anchored_bucket_start(timestamp, eight_hours),
+            timestamp
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 2024-03-06 is Wednesday.
+        let timestamp = datetime("2024-03-06T12:00:00Z");
+        assert_eq!(
+            Resolution::W1.anchored_bucket_start(timestamp, 0),
+            datetime("2024-03-04T00:00:00Z")
+        );
+        assert_eq!(
+            Resolution::W1.anchored_bucket_start(timestamp, 8 * 3600 * 1000),
+            datetime("2024-03-04T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn bucket_boundaries_are_exclusive_at_end() {
+        let start = datetime("2024-03-05T10:05:00Z");
+        let end = datetime("2024-03-05T10:10:00Z");
+        let milli = Duration::from_millis(1);
+        let resolution = Resolution::M5;
+
+        assert_eq!(resolution.bucket_start(start), start);
+        assert_eq!(resolution.bucket_end(start), end);
+        assert_eq!(resolution.bucket_start(end - milli), start);
+        assert_eq!(resolution.bucket_end(end - milli), end);
+        assert_eq!(resolution.bucket_start(end), end);
+        assert_eq!(
+            resolution.bucket_start(start - milli),
+            start - Duration::from_secs(300)
+        );
+
+        assert!(resolution.bucket_contains(start, start));
+        assert!(resolution.bucket_contains(start, end - milli));
+        assert!(!resolution.bucket_contains(start, end));
+        assert!(!resolution.bucket_contains(start, start - milli));
+        // Any timestamp of the bucket identifies it.
+        assert!(resolution.bucket_contains(end - milli, start));
+    }
+
+    #[test]
+    fn applied_trades_split_volume_by_direction() {
+        let mut trade = TradeOhlcv::default();
+        trade.apply_trade(60_000, 1.0, 100.0, true);
+        trade.apply_trade(60_000, 2.0, 300.0, false);
+        trade.apply_trade(60_000, 1.5, 50.0, true);
+        assert_eq!(trade.candle.volume, 450.0);
+        assert_eq!(trade.candle.buy_volume, 150.0);
+        assert_eq!(trade.candle.sell_volume, 300.0);
+
+        // Trade of the next bucket starts volumes over.
+        trade.apply_trade(120_000, 1.0, 10.0, false);
+        assert_eq!(trade.candle.buy_volume, 0.0);
+        assert_eq!(trade.candle.sell_volume, 10.0);
+    }
+
+    #[test]
+    fn millisecond_timestamp_survives_round_trip() {
+        let datetime = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let trade = TradeOhlcv {
+            timestamp: datetime.timestamp_millis() as u64,
+            candle: Candle::flat(1.0),
+        };
+
+        let json = serde_json::to_string(&trade).unwrap();
+        assert!(json.contains("\"timestamp\":1700000000123"), "{json}");
+        let received: TradeOhlcv = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            DateTime::from_timestamp_millis(received.timestamp as i64),
+            Some(datetime)
+        );
+    }
+
+    #[test]
+    fn applied_trades_update_vwap() {
+        let mut trade = TradeOhlcv::default();
+        trade.apply_trade(60_000, 0.5, 2_000.0, true);
+        assert_eq!(trade.candle.vwap, 0.5);
+        trade.apply_trade(60_000, 3.0, 1_000.0, false);
+        trade.apply_trade(60_000, 1.0, 500.0, true);
+        let expected = (0.5 * 2_000.0 + 3.0 * 1_000.0 + 500.0) / 3_500.0;
+        assert!((trade.candle.vwap - expected).abs() < 1e-12);
+    }
+
+    /// Candles of consecutive minutes with a gap, flat candle fills it.
+    fn gapped_series() -> Vec<TradeOhlcv> {
+        let candle = |open: f64, close: f64| Candle {
+            open,
+            close,
+            high: open.max(close),
+            low: open.min(close),
+            trade_count: 1,
+            ..Default::default()
+        };
+        [candle(1.0, 2.0), Candle::flat(2.0), candle(3.0, 2.5)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, candle)| TradeOhlcv {
+                timestamp: i as u64 * 60_000,
+                candle,
+            })
+            .collect()
+    }
+
+    fn opens(policy: OpenPolicy, series: &mut [TradeOhlcv]) -> Vec<f64> {
+        let mut opens = CandleOpens::new(policy);
+        series
+            .iter_mut()
+            .map(|trade| {
+                opens.apply(trade);
+                trade.candle.open
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_trade_opens_keep_gaps() {
+        let mut series = gapped_series();
+        assert_eq!(opens(OpenPolicy::FirstTrade, &mut series), [1.0, 2.0, 3.0]);
+        assert_eq!(series[2].candle.low, 2.5);
+    }
+
+    #[test]
+    fn previous_close_opens_close_gaps() {
+        let mut series = gapped_series();
+        assert_eq!(
+            opens(OpenPolicy::PreviousClose, &mut series),
+            [1.0, 2.0, 2.0]
+        );
+        // Range is extended to the new open.
+        assert_eq!(series[2].candle.high, 3.0);
+        assert_eq!(series[2].candle.low, 2.0);
+    }
+
+    #[test]
+    fn repainted_candle_keeps_previous_close_open() {
+        let mut opens = CandleOpens::new(OpenPolicy::PreviousClose);
+        let mut series = gapped_series();
+        for trade in &mut series {
+            opens.apply(trade);
+        }
+
+        // Latest candle is repainted with a new trade, older ones are not touched.
+        let mut repainted = series[2];
+        repainted.candle.open = 3.0;
+        repainted.candle.close = 4.0;
+        opens.apply(&mut repainted);
+        assert_eq!(repainted.candle.open, 2.0);
+        let mut older = gapped_series()[1];
+        older.candle.close = 5.0;
+        opens.apply(&mut older);
+        assert_eq!(older.candle.open, 2.0);
+
+        // Next candle opens at the repainted close.
+        let mut next = TradeOhlcv {
+            timestamp: 180_000,
+            candle: Candle::flat(1.0),
+        };
+        opens.apply(&mut next);
+        assert_eq!(next.candle.open, 4.0);
+
+        opens.reset();
+        let mut first = gapped_series()[0];
+        opens.apply(&mut first);
+        assert_eq!(first.candle.open, 1.0);
+    }
+
+    #[test]
+    fn open_policies_are_parsed() {
+        assert_eq!(OpenPolicy::default(), OpenPolicy::FirstTrade);
+        assert_eq!("first_trade".parse(), Ok(OpenPolicy::FirstTrade));
+        assert_eq!("previous_close".parse(), Ok(OpenPolicy::PreviousClose));
+        assert!("close".parse::<OpenPolicy>().is_err());
+    }
+
+    #[test]
+    fn prices_are_rounded_to_significant_digits() {
+        assert_eq!(
+            round_significant(0.000_000_123_456_789, 6),
+            0.000_000_123_457
+        );
+        assert_eq!(round_significant(123_456_789.0, 6), 123_457_000.0);
+        assert_eq!(round_significant(-0.012_345_65, 3), -0.0123);
+        assert_eq!(round_significant(1e-300 / 3.0, 2), 3.3e-301);
+        assert_eq!(round_significant(1.234_56e300, 3), 1.23e300);
+        assert_eq!(round_significant(0.0, 6), 0.0);
+        assert!(round_significant(f64::NAN, 6).is_nan());
+    }
+
+    #[test]
+    fn only_candle_prices_are_rounded() {
+        let candle = Candle {
+            open: 2.718_281_828e-8,
+            close: 3.141_592_653e-8,
+            high: 3.141_592_653e-8,
+            low: 1.414_213_562e-8,
+            volume: 123_456_789.0,
+            trade_count: 3,
+            buy_volume: 100_000_001.0,
+            sell_volume: 23_456_788.0,
+            vwap: 2.236_067_977e-8,
+        };
+
+        let rounded = candle.rounded(6);
+        assert_eq!(rounded.open, 2.718_28e-8);
+        assert_eq!(rounded.close, 3.141_59e-8);
+        assert_eq!(rounded.high, 3.141_59e-8);
+        assert_eq!(rounded.low, 1.414_21e-8);
+        assert_eq!(rounded.vwap, 2.236_07e-8);
+        assert_eq!(rounded.volume, candle.volume);
+        assert_eq!(rounded.buy_volume, candle.buy_volume);
+        assert_eq!(rounded.sell_volume, candle.sell_volume);
+        assert_eq!(rounded.trade_count, candle.trade_count);
+
+        // Full precision without configured digits.
+        assert_eq!(candle.rounded(0).close, candle.close);
+    }
+
+    #[test]
+    fn resolutions_are_parsed_leniently() {
+        let parsed = [
+            ("M1", Resolution::M1),
+            ("m1", Resolution::M1),
+            ("1m", Resolution::M1),
+            ("1MIN", Resolution::M1),
+            ("60", Resolution::M1),
+            (" 5m\n", Resolution::M5),
+            ("1s", Resolution::S1),
+            ("15m", Resolution::M15),
+            ("1h", Resolution::H1),
+            ("3600", Resolution::H1),
+            ("4h", Resolution::H4),
+            ("12h", Resolution::H12),
+            ("1d", Resolution::D1),
+            ("w1", Resolution::W1),
+        ];
+        for (s, resolution) in parsed {
+            assert_eq!(s.parse::<Resolution>(), Ok(resolution), "{s:?}");
+        }
+        // Path params, query params and config lists are deserialized the same way.
+        let resolution: Resolution = serde_json::from_str(r#""1h""#).unwrap();
+        assert_eq!(resolution, Resolution::H1);
+        assert_eq!(serde_json::to_string(&resolution).unwrap(), r#""H1""#);
+    }
+
+    #[test]
+    fn unknown_resolution_lists_accepted_forms() {
+        for s in ["", "2m", "M 1", "61", "1y"] {
+            let e = s.parse::<Resolution>().unwrap_err();
+            assert!(
+                e.starts_with(&format!("Unknown resolution {:?}", s.trim())),
+                "{e}"
+            );
+            assert!(e.contains("S1, M1, M5"), "{e}");
+        }
+        assert!(serde_json::from_str::<Resolution>(r#""2m""#).is_err());
+    }
+
+    #[test]
+    fn multi_hour_candles_are_aligned() {
+        let timestamp = datetime("2024-03-05T15:47:12Z");
+        let aligned = [
+            (Resolution::M30, "2024-03-05T15:30:00Z"),
+            (Resolution::H4, "2024-03-05T12:00:00Z"),
+            (Resolution::H12, "2024-03-05T12:00:00Z"),
+        ];
+        for (resolution, start) in aligned {
+            assert_eq!(
+                resolution.anchored_bucket_start(timestamp, 0),
+                datetime(start),
+                "{resolution}"
+            );
+        }
+
+        // Days start at 02:00 UTC, so do 4-hour and 12-hour candles.
+        let two_hours = 2 * 3600 * 1000;
+        assert_eq!(
+            Resolution::H4.anchored_bucket_start(timestamp, two_hours),
+            datetime("2024-03-05T14:00:00Z")
+        );
+        assert_eq!(
+            Resolution::H12.anchored_bucket_start(timestamp, two_hours),
+            datetime("2024-03-05T14:00:00Z")
+        );
+        assert_eq!(
+            Resolution::H12.anchored_bucket_start(datetime("2024-03-05T01:00:00Z"), two_hours),
+            datetime("2024-03-04T14:00:00Z")
+        );
+    }
+
+    #[test]
+    fn weeks_do_not_drift() {
+        // Wednesday, the week started in the previous year.
+        assert_eq!(
+            Resolution::W1.anchored_bucket_start(datetime("2025-01-01T00:00:00Z"), 0),
+            datetime("2024-12-30T00:00:00Z")
+        );
+        // Sunday just before the next week.
+        assert_eq!(
+            Resolution::W1.anchored_bucket_start(datetime("2030-06-16T23:59:59Z"), 0),
+            datetime("2030-06-10T00:00:00Z")
+        );
+        // Monday starts its own week.
+        let monday = datetime("2030-06-17T00:00:00Z");
+        assert_eq!(Resolution::W1.anchored_bucket_start(monday, 0), monday);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::model::Candle;
+
+/// Content type of Parquet files.
+pub const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+
+/// Encode candles into a Parquet file with columns timestamp (UTC milliseconds),
+/// open, high, low, close and volume.
+/// Candles are written as a single row group, number of them is capped by callers.
+pub fn candles_to_parquet(candles: &BTreeMap<DateTime<Utc>, Candle>) -> anyhow::Result<Vec<u8>> {
+    let price_field = |name| Field::new(name, DataType::Float64, false);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        price_field("open"),
+        price_field("high"),
+        price_field("low"),
+        price_field("close"),
+        price_field("volume"),
+    ]));
+
+    let timestamps: TimestampMillisecondArray = candles
+        .keys()
+        .map(|datetime| Some(datetime.timestamp_millis()))
+        .collect();
+    let column = |field: fn(&Candle) -> f64| -> ArrayRef {
+        Arc::new(candles.values().map(field).collect::<Float64Array>())
+    };
+    let columns = vec![
+        Arc::new(timestamps.with_timezone("UTC")) as ArrayRef,
+        column(|candle| candle.open),
+        column(|candle| candle.high),
+        column(|candle| candle.low),
+        column(|candle| candle.close),
+        column(|candle| candle.volume),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut file = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut file, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, TimestampMillisecondType};
+    use axum::body::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn candle(open: f64, close: f64, volume: f64) -> Candle {
+        Candle {
+            open,
+            close,
+            high: open.max(close) * 1.5,
+            low: open.min(close) / 2.0,
+            volume,
+            trade_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn candles_are_read_back_from_parquet() {
+        // Tiny prices keep full precision.
+        let candles = BTreeMap::from([
+            (
+                DateTime::from_timestamp(1_700_000_040, 0).unwrap(),
+                candle(2.5e-8, 3.125e-8, 1_000.0),
+            ),
+            (
+                DateTime::from_timestamp(1_700_000_100, 0).unwrap(),
+                candle(3.125e-8, 1.0e-9, 2.0e15),
+            ),
+        ]);
+        let file = candles_to_parquet(&candles).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), candles.len());
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            ["timestamp", "open", "high", "low", "close", "volume"]
+        );
+
+        let timestamps = batch.column(0).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(timestamps.timezone(), Some("UTC"));
+        let column = |idx: usize| batch.column(idx).as_primitive::<Float64Type>().clone();
+        for (row, (datetime, candle)) in candles.iter().enumerate() {
+            assert_eq!(timestamps.value(row), datetime.timestamp_millis());
+            assert_eq!(column(1).value(row), candle.open);
+            assert_eq!(column(2).value(row), candle.high);
+            assert_eq!(column(3).value(row), candle.low);
+            assert_eq!(column(4).value(row), candle.close);
+            assert_eq!(column(5).value(row), candle.volume);
+        }
+        assert!((1..6).all(|idx| column(idx).null_count() == 0));
+    }
+
+    #[test]
+    fn no_candles_make_empty_file() {
+        let file = candles_to_parquet(&BTreeMap::new()).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 0);
+    }
+}
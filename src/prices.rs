@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::model::{Candle, Resolution, TradeInfo, TradeOhlcv};
+use crate::storage::Storage;
+
+/// In-memory projection of the last candle of every token.
+/// Candles have the finest enabled resolution.
+/// Kept up to date by ingested trades, so reads don't touch storage.
+#[derive(Clone, Default)]
+pub struct LastPrices {
+    prices: Arc<DashMap<String, TradeOhlcv>>,
+}
+
+impl LastPrices {
+    /// Load last candles from storage.
+    pub async fn load(storage: &Storage) -> anyhow::Result<Self> {
+        let prices = storage
+            .latest_candles(Resolution::finest_enabled())
+            .await?
+            .into_iter()
+            .map(|(mint, datetime, candle)| {
+                let trade = TradeOhlcv {
+                    timestamp: datetime.timestamp_millis() as u64,
+                    candle,
+                };
+                (mint, trade)
+            })
+            .collect();
+
+        Ok(Self {
+            prices: Arc::new(prices),
+        })
+    }
+
+    /// Update last candles with ingested trades.
+    pub async fn run(self, mut trades: broadcast::Receiver<TradeInfo>) {
+        loop {
+            match trades.recv().await {
+                Ok(trade) => self.update(&trade),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Last prices skipped {skipped} trades.");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        tracing::error!("Last prices updates stopped");
+    }
+
+    /// Last candle of the token.
+    pub fn get(&self, mint: &str) -> Option<TradeOhlcv> {
+        self.prices.get(mint).map(|price| *price)
+    }
+
+    /// Forget last candle of the token.
+    pub fn remove(&self, mint: &str) {
+        self.prices.remove(mint);
+    }
+
+    /// Apply trade to the last candle of the token.
+    fn update(&self, trade: &TradeInfo) {
+        let Ok(price) = trade.price() else {
+            return;
+        };
+        let timestamp = Resolution::finest_enabled()
+            .bucket_start(trade.timestamp)
+            .timestamp_millis() as u64;
+        let volume = trade.token_amount as f64;
+
+        let mut entry = self
+            .prices
+            .entry(trade.mint_acc.clone())
+            .or_insert(TradeOhlcv {
+                timestamp,
+                candle: Candle {
+                    open: price,
+                    close: price,
+                    high: price,
+                    low: price,
+                    volume: 0.0,
+                    trade_count: 0,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    vwap: price,
+                },
+            });
+
+        entry
+            .value_mut()
+            .apply_trade(timestamp, price, volume, trade.is_buy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::types::chrono::DateTime;
+    use std::time::Duration;
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    fn trade(secs: i64, sol_amount: u64, is_buy: bool) -> TradeInfo {
+        TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount,
+            token_amount: 1_000,
+            is_buy,
+            decimals: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn projection_is_updated_by_trades() {
+        let prices = LastPrices::default();
+        let (sender, receiver) = broadcast::channel(16);
+        let task = tokio::spawn(prices.clone().run(receiver));
+
+        sender.send(trade(1_700_000_000, 1_000, true)).unwrap();
+        sender.send(trade(1_700_000_000, 3_000, false)).unwrap();
+        // Late trade of a passed bucket is ignored.
+        sender.send(trade(1_600_000_000, 9_000, true)).unwrap();
+        drop(sender);
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let last = prices.get(MINT).unwrap();
+        let bucket = Resolution::finest_enabled()
+            .bucket_start(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        assert_eq!(last.timestamp, bucket.timestamp_millis() as u64);
+        assert_eq!(last.candle.open, 1.0);
+        assert_eq!(last.candle.close, 3.0);
+        assert_eq!(last.candle.high, 3.0);
+        assert_eq!(last.candle.trade_count, 2);
+        assert_eq!(last.candle.buy_volume, 1_000.0);
+        assert_eq!(last.candle.sell_volume, 1_000.0);
+
+        prices.remove(MINT);
+        assert!(prices.get(MINT).is_none());
+    }
+
+    #[test]
+    fn newer_bucket_replaces_last_candle() {
+        let prices = LastPrices::default();
+        prices.update(&trade(1_700_000_000, 1_000, true));
+        prices.update(&trade(1_700_100_000, 2_000, true));
+
+        let last = prices.get(MINT).unwrap();
+        let bucket = Resolution::finest_enabled()
+            .bucket_start(DateTime::from_timestamp(1_700_100_000, 0).unwrap());
+        assert_eq!(last.timestamp, bucket.timestamp_millis() as u64);
+        assert_eq!(last.candle.open, 2.0);
+        assert_eq!(last.candle.trade_count, 1);
+    }
+}
@@ -1,51 +1,298 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use pumpfun::PumpFun;
-use pumpfun::common::stream::{CreateEvent, PumpFunEvent, TradeEvent};
+use pumpfun::common::stream::{CompleteEvent, CreateEvent, PumpFunEvent, TradeEvent};
+use serde::{Serialize, Serializer};
 use solana_account_decoder_client_types::UiAccountEncoding;
 use solana_commitment_config::CommitmentConfig;
 use solana_pubkey::Pubkey;
 use solana_rpc_client_types::config::RpcAccountInfoConfig;
-use sqlx::types::chrono::DateTime;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::{Semaphore, watch};
+use tokio::task::JoinHandle;
 
-use crate::model::{IndexedPumpfunEvent, Resolution, TokenMetadata, TradeInfo};
+use crate::error::NotFound;
+use crate::market_stats::MarketStats;
+use crate::metadata_breaker::MetadataBreaker;
+use crate::metrics::Metrics;
+use crate::model::{IndexedPumpfunEvent, TokenMetadata, TradeInfo, event_datetime};
+use crate::rpc_pool::RpcPool;
 use crate::storage::Storage;
 
+/// What to do with events while ingestion is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Stop reading events, so they are buffered in the channel.
+    /// Events arriving while the channel is full are dropped, see [`crate::indexer::forward_event`].
+    Buffer,
+    /// Read and drop events.
+    Drop,
+}
+
+impl FromStr for PauseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffer" => Ok(PauseMode::Buffer),
+            "drop" => Ok(PauseMode::Drop),
+            _ => Err(String::from("expected `buffer` or `drop`")),
+        }
+    }
+}
+
+/// Runtime control of events ingestion.
+#[derive(Debug, Clone)]
+pub struct IngestionControl {
+    paused: Arc<watch::Sender<bool>>,
+    mode: PauseMode,
+    /// Time the last event was received from subscription as unix milliseconds, 0 if none.
+    last_event: Arc<AtomicI64>,
+    /// Number of events being handled.
+    in_flight: Arc<watch::Sender<usize>>,
+    /// Permits of events handled concurrently.
+    permits: Arc<Semaphore>,
+    /// Lifecycle of the event processing task, see [`IngestionControl::stop`].
+    run_state: Arc<watch::Sender<RunState>>,
+}
+
+/// Lifecycle of the event processing task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    /// Stop is requested, events already received are being handled.
+    Stopping,
+    Stopped,
+}
+
+/// Event being handled, see [`IngestionControl::drain`].
+struct InFlightEvent(Arc<watch::Sender<usize>>);
+
+impl Drop for InFlightEvent {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+impl IngestionControl {
+    /// Create new control with running ingestion, handling up to `concurrency` events at once.
+    pub fn new(mode: PauseMode, concurrency: usize) -> Self {
+        Self {
+            paused: Arc::new(watch::Sender::new(false)),
+            mode,
+            last_event: Arc::new(AtomicI64::new(0)),
+            in_flight: Arc::new(watch::Sender::new(0)),
+            permits: Arc::new(Semaphore::new(concurrency)),
+            run_state: Arc::new(watch::Sender::new(RunState::Running)),
+        }
+    }
+
+    /// Whether event processing task is running, including while it's stopping.
+    pub fn is_ingesting(&self) -> bool {
+        *self.run_state.borrow() != RunState::Stopped
+    }
+
+    /// Whether the first event was received, i.e. subscription is delivering events.
+    pub fn is_ready(&self) -> bool {
+        self.last_event.load(Ordering::Relaxed) != 0
+    }
+
+    /// Time the last event was received, if any.
+    pub fn last_event_at(&self) -> Option<DateTime<Utc>> {
+        match self.last_event.load(Ordering::Relaxed) {
+            0 => None,
+            millis => DateTime::from_timestamp_millis(millis),
+        }
+    }
+
+    /// Pause or resume ingestion.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.send_replace(paused);
+    }
+
+    /// Whether ingestion is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Number of events being handled.
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.borrow()
+    }
+
+    /// Wait up to timeout for events being handled to be written.
+    /// Ingestion should be paused first, so no new events are started.
+    /// Returns number of events still being handled.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let mut in_flight = self.in_flight.subscribe();
+        // Sender is never dropped while control exists.
+        let _ =
+            tokio::time::timeout(timeout, in_flight.wait_for(|in_flight| *in_flight == 0)).await;
+        self.in_flight()
+    }
+
+    /// Stop event processing task and wait up to timeout for it to exit.
+    /// Channel is closed, so subscription can't send more events, and events already in it
+    /// are started, unless ingestion is paused in drop mode.
+    /// Returns whether the task exited in time.
+    pub async fn stop(&self, timeout: Duration) -> bool {
+        self.run_state.send_if_modified(|state| {
+            let running = *state == RunState::Running;
+            if running {
+                *state = RunState::Stopping;
+            }
+            running
+        });
+        let mut run_state = self.run_state.subscribe();
+        // Sender is never dropped while control exists.
+        tokio::time::timeout(
+            timeout,
+            run_state.wait_for(|state| *state == RunState::Stopped),
+        )
+        .await
+        .is_ok()
+    }
+
+    /// Count event as being handled until the returned guard is dropped.
+    fn start_event(&self) -> InFlightEvent {
+        self.in_flight.send_modify(|in_flight| *in_flight += 1);
+        InFlightEvent(self.in_flight.clone())
+    }
+}
+
 /// Pumpfun event processor.
 #[derive(Debug)]
 pub struct PumpHandler;
 
 impl PumpHandler {
     /// Run event processing task.
-    pub async fn run(storage: Storage, mut pumpfun_ops_sender: Receiver<IndexedPumpfunEvent>) {
-        while let Some(event) = pumpfun_ops_sender.recv().await {
+    pub async fn run(
+        storage: Storage,
+        metrics: Arc<Metrics>,
+        breaker: MetadataBreaker,
+        rpc: RpcPool,
+        market: MarketStats,
+        control: IngestionControl,
+        mut pumpfun_ops_sender: Receiver<IndexedPumpfunEvent>,
+    ) {
+        let mut paused = control.paused.subscribe();
+        let mut run_state = control.run_state.subscribe();
+        let mut closed = false;
+        loop {
+            let event = tokio::select! {
+                event = pumpfun_ops_sender.recv() => event,
+                _ = run_state.wait_for(|state| *state == RunState::Stopping), if !closed => {
+                    // Received events are still handled, then channel yields none.
+                    pumpfun_ops_sender.close();
+                    closed = true;
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+
+            let now = Utc::now().timestamp_millis();
+            if control.last_event.swap(now, Ordering::Relaxed) == 0 {
+                tracing::info!("First pumpfun event received, ready.");
+            }
+
+            if control.is_paused() {
+                match control.mode {
+                    PauseMode::Drop => {
+                        metrics
+                            .paused_dropped_events
+                            .fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    PauseMode::Buffer => {
+                        // Buffered events are handled on stop as well.
+                        // Senders are never dropped while control exists.
+                        tokio::select! {
+                            _ = paused.wait_for(|paused| !paused) => {}
+                            _ = run_state.wait_for(|state| *state != RunState::Running) => {}
+                        }
+                    }
+                }
+            }
+
             let storage = storage.clone();
+            let metrics = metrics.clone();
+            let breaker = breaker.clone();
+            let rpc = rpc.clone();
+            let market = market.clone();
+            // Events wait in the channel while all permits are taken, so bursts don't
+            // exhaust DB and RPC connections. Semaphore is never closed.
+            let Ok(permit) = control.permits.clone().acquire_owned().await else {
+                break;
+            };
+            let in_flight = control.start_event();
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_event(event, &storage).await {
+                let _in_flight = in_flight;
+                let _permit = permit;
+                let result =
+                    Self::handle_event(event, &storage, &metrics, &breaker, &rpc, &market).await;
+                if let Err(e) = result {
                     tracing::warn!("Failed to handle event: {e}");
                 }
             });
         }
 
-        tracing::error!("Pumpfun handler exited");
+        if closed {
+            tracing::info!("Pumpfun handler stopped");
+        } else {
+            tracing::error!("Pumpfun handler exited");
+        }
+        control.run_state.send_replace(RunState::Stopped);
+    }
+
+    /// Wait for event processing task to exit and mark it stopped, even if it panicked,
+    /// so health checks report events are not handled anymore.
+    pub async fn monitor(handler: JoinHandle<()>, control: &IngestionControl) {
+        if let Err(e) = handler.await {
+            tracing::error!("PumpHandler task failed: {e}");
+        }
+        control.run_state.send_replace(RunState::Stopped);
     }
 
     /// Handle pumpfun event.
     /// If token first met in trade/create event, it will be inserted into db with metadata.
-    async fn handle_event(idx_event: IndexedPumpfunEvent, storage: &Storage) -> anyhow::Result<()> {
+    async fn handle_event(
+        idx_event: IndexedPumpfunEvent,
+        storage: &Storage,
+        metrics: &Metrics,
+        breaker: &MetadataBreaker,
+        rpc: &RpcPool,
+        market: &MarketStats,
+    ) -> anyhow::Result<()> {
         match idx_event.event {
-            PumpFunEvent::Create(create) => Self::handle_create(storage, create).await,
-            PumpFunEvent::Trade(trade) => Self::handle_trade(storage, trade).await,
+            PumpFunEvent::Create(create) => {
+                market.record_create();
+                Self::handle_create(storage, breaker, rpc, create).await
+            }
+            PumpFunEvent::Trade(trade) => {
+                market.record_trade(trade.sol_amount);
+                Self::handle_trade(storage, metrics, breaker, rpc, trade).await
+            }
+            PumpFunEvent::Complete(complete) => {
+                Self::handle_complete(storage, metrics, complete).await
+            }
             _ => Ok(()),
         }
     }
 
     /// Handle create event.
-    async fn handle_create(storage: &Storage, create: CreateEvent) -> anyhow::Result<()> {
-        let metadata = Self::query_token_metadata(create.mint)
-            .await
-            .inspect_err(|e| tracing::warn!("Failed to query token metadata: {e}"))
-            .ok();
+    async fn handle_create(
+        storage: &Storage,
+        breaker: &MetadataBreaker,
+        rpc: &RpcPool,
+        create: CreateEvent,
+    ) -> anyhow::Result<()> {
+        let metadata = breaker.query(rpc, create.mint).await;
 
         storage
             .insert_token_metadata(create.mint.to_string(), metadata)
@@ -54,75 +301,808 @@ impl PumpHandler {
         Ok(())
     }
 
+    /// Handle bonding curve complete event. Token isn't traded on the curve anymore,
+    /// so its candles stop with the completing trade.
+    /// Events of unknown tokens and with invalid timestamp are skipped.
+    async fn handle_complete(
+        storage: &Storage,
+        metrics: &Metrics,
+        complete: CompleteEvent,
+    ) -> anyhow::Result<()> {
+        let Some(datetime) = event_datetime(complete.timestamp) else {
+            tracing::warn!(
+                "Skipping completion of {} with invalid timestamp: {}",
+                complete.mint,
+                complete.timestamp
+            );
+            metrics
+                .invalid_timestamp_events
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        };
+
+        match storage
+            .mark_completed(complete.mint.to_string(), datetime)
+            .await
+        {
+            Ok(()) => {
+                tracing::info!("Token {} completed bonding curve.", complete.mint);
+                Ok(())
+            }
+            Err(e) if e.is::<NotFound>() => {
+                tracing::debug!("Skipping completion of unknown token {}", complete.mint);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Handle trade event.
-    async fn handle_trade(storage: &Storage, trade: TradeEvent) -> anyhow::Result<()> {
-        let times = Resolution::all()
-            .iter()
-            .map(|res| {
-                // bind time to resolution
-                let timestamp_millis = trade.timestamp as u64 / res.as_seconds() * res.as_millis();
-                DateTime::from_timestamp_millis(timestamp_millis as _).expect("correct datetime")
-            })
-            .collect::<Vec<_>>();
+    /// Events with invalid timestamp are skipped.
+    /// Fails if the trade wasn't inserted into DB, it's counted by `failed_trade_inserts`.
+    async fn handle_trade(
+        storage: &Storage,
+        metrics: &Metrics,
+        breaker: &MetadataBreaker,
+        rpc: &RpcPool,
+        trade: TradeEvent,
+    ) -> anyhow::Result<()> {
+        let Some(datetime) = event_datetime(trade.timestamp) else {
+            tracing::warn!(
+                "Skipping trade of {} with invalid timestamp: {}",
+                trade.mint,
+                trade.timestamp
+            );
+            metrics
+                .invalid_timestamp_events
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        };
+
+        // bind time to resolution, S1 only for active tokens
+        let times = storage.trade_buckets(&trade.mint.to_string(), datetime);
 
-        let trade_info = TradeInfo {
+        let mut trade_info = TradeInfo {
             mint_acc: trade.mint.to_string(),
+            timestamp: datetime,
             sol_amount: trade.sol_amount,
             token_amount: trade.token_amount,
+            is_buy: trade.is_buy,
+            decimals: None,
         };
 
-        if storage
-            .get_token_metadata(&trade_info.mint_acc)
-            .await
-            .is_err()
-        {
-            let metadata = Self::query_token_metadata(trade.mint)
-                .await
-                .inspect_err(|e| tracing::warn!("Failed to query token metadata: {e}"))
-                .ok();
+        // Prices are scaled by decimals of the stored token.
+        let result = match storage.get_token_metadata(&trade_info.mint_acc).await {
+            Ok(metadata) => {
+                trade_info.decimals = metadata.decimals;
+                storage.insert_trade(&times, trade_info).await
+            }
+            Err(_) => {
+                let metadata = breaker.query(rpc, trade.mint).await;
+                // Replaced by decimals of the stored token if it's inserted concurrently.
+                trade_info.decimals = metadata.as_ref().and_then(|metadata| metadata.decimals);
+
+                storage
+                    .insert_first_trade_with_metadata(&times, trade_info, metadata)
+                    .await
+            }
+        };
 
-            storage
-                .insert_token_metadata(trade_info.mint_acc.clone(), metadata)
-                .await?;
+        if result.is_err() {
+            metrics.failed_trade_inserts.fetch_add(1, Ordering::Relaxed);
         }
+        result
+    }
 
-        storage.insert_trade(&times, trade_info).await?;
+    /// Query token metadata with decimals from its mint account, in a single RPC call.
+    /// Returns `NotFound` error if metadata account doesn't exist.
+    /// Decimals are `None` if mint account is missing or malformed.
+    pub async fn query_token_metadata(
+        rpc: &RpcPool,
+        mint: Pubkey,
+    ) -> anyhow::Result<TokenMetadata> {
+        let metadata_pda = PumpFun::get_metadata_pda(&mint);
+        let accounts = [metadata_pda, mint];
+        let resp = rpc
+            .call(|client| {
+                let accounts = &accounts;
+                async move {
+                    client
+                        .get_multiple_accounts_with_config(
+                            accounts,
+                            Self::metadata_account_config(),
+                        )
+                        .await
+                }
+            })
+            .await?;
+        let mut accounts = resp.value.into_iter();
+        let (metadata_acc, mint_acc) = (accounts.next().flatten(), accounts.next().flatten());
 
-        Ok(())
+        let Some(metadata_acc) = metadata_acc else {
+            return Err(NotFound(format!(
+                "Metadata account {metadata_pda} of token {mint} not found"
+            ))
+            .into());
+        };
+        let mut metadata = Self::decode_metadata(&metadata_acc.data)?;
+        metadata.decimals = Self::mint_decimals(&mint, mint_acc.as_ref().map(|acc| &acc.data[..]));
+        Ok(metadata)
     }
 
-    /// Query token metadata.
-    async fn query_token_metadata(mint: Pubkey) -> anyhow::Result<TokenMetadata> {
+    /// Query decoded metadata account of the token.
+    /// Returns `NotFound` error if the account doesn't exist.
+    pub async fn query_metadata_account(
+        rpc: &RpcPool,
+        mint: Pubkey,
+    ) -> anyhow::Result<MetadataAccount> {
         let metadata_pda = PumpFun::get_metadata_pda(&mint);
-        let resp =
-            solana_rpc_client::rpc_client::RpcClient::new("https://api.mainnet-beta.solana.com")
-                .get_account_with_config(
-                    &metadata_pda,
-                    RpcAccountInfoConfig {
-                        encoding: Some(UiAccountEncoding::Base64),
-                        commitment: Some(CommitmentConfig::confirmed()),
-                        ..Default::default()
-                    },
-                )?;
+        let resp = rpc
+            .call(|client| async move {
+                client
+                    .get_account_with_config(&metadata_pda, Self::metadata_account_config())
+                    .await
+            })
+            .await?;
         let Some(acc) = resp.value else {
-            anyhow::bail!("Account value with token metadata not found");
+            return Err(NotFound(format!(
+                "Metadata account {metadata_pda} of token {mint} not found"
+            ))
+            .into());
         };
-        let mut metadata_acc = MetadataAccount::deserialize(&mut acc.data.as_ref())?;
+
+        Self::decode_metadata_account(&acc.data)
+    }
+
+    /// Query metadata of up to `MAX_METADATA_BATCH` tokens with decimals from their mint accounts,
+    /// in a single RPC call.
+    /// Returns metadata in the order of mints, `None` if token has no metadata account.
+    pub async fn query_token_metadata_batch(
+        rpc: &RpcPool,
+        mints: &[Pubkey],
+    ) -> anyhow::Result<Vec<Option<TokenMetadata>>> {
+        if mints.len() > MAX_METADATA_BATCH {
+            anyhow::bail!(
+                "Too many mints in metadata batch: {} > {MAX_METADATA_BATCH}",
+                mints.len()
+            );
+        }
+
+        // Metadata account of each token is followed by its mint account.
+        let accounts: Vec<_> = mints
+            .iter()
+            .flat_map(|mint| [PumpFun::get_metadata_pda(mint), *mint])
+            .collect();
+        let resp = rpc
+            .call(|client| {
+                let accounts = &accounts;
+                async move {
+                    client
+                        .get_multiple_accounts_with_config(
+                            accounts,
+                            Self::metadata_account_config(),
+                        )
+                        .await
+                }
+            })
+            .await?;
+
+        let mut accounts = resp.value.into_iter();
+        Ok(mints
+            .iter()
+            .map(|mint| {
+                let (metadata_acc, mint_acc) =
+                    (accounts.next().flatten(), accounts.next().flatten());
+                let mut metadata = Self::decode_metadata(&metadata_acc?.data)
+                    .inspect_err(|e| tracing::warn!("Bad metadata account of {mint}: {e}"))
+                    .ok()?;
+                metadata.decimals =
+                    Self::mint_decimals(mint, mint_acc.as_ref().map(|acc| &acc.data[..]));
+                Some(metadata)
+            })
+            .collect())
+    }
+
+    /// Decimals of the token from its mint account data.
+    /// `None` if mint account is missing or malformed, then prices are raw amounts ratio.
+    fn mint_decimals(mint: &Pubkey, data: Option<&[u8]>) -> Option<u8> {
+        match data.map(Self::decode_mint_decimals) {
+            Some(Ok(decimals)) => Some(decimals),
+            Some(Err(e)) => {
+                tracing::warn!("Bad mint account of {mint}, prices are raw amounts ratio: {e}");
+                None
+            }
+            None => {
+                tracing::warn!("Mint account of {mint} not found, prices are raw amounts ratio");
+                None
+            }
+        }
+    }
+
+    /// Config of metadata account queries.
+    fn metadata_account_config() -> RpcAccountInfoConfig {
+        RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            ..Default::default()
+        }
+    }
+
+    /// Decode token metadata from metadata account data.
+    fn decode_metadata(data: &[u8]) -> anyhow::Result<TokenMetadata> {
+        Self::decode_metadata_account(data).map(|metadata_acc| metadata_acc.data)
+    }
+
+    /// Decode decimals from SPL token mint account data.
+    fn decode_mint_decimals(data: &[u8]) -> anyhow::Result<u8> {
+        if data.len() < MINT_ACCOUNT_LEN {
+            anyhow::bail!(
+                "Mint account is {} bytes, expected {MINT_ACCOUNT_LEN}",
+                data.len()
+            );
+        }
+        Ok(data[MINT_DECIMALS_OFFSET])
+    }
+
+    /// Decode metadata account data.
+    /// Null padding of metadata strings is trimmed.
+    fn decode_metadata_account(mut data: &[u8]) -> anyhow::Result<MetadataAccount> {
+        let mut metadata_acc = MetadataAccount::deserialize(&mut data)?;
 
         metadata_acc.data.name = metadata_acc.data.name.trim_end_matches("\0").to_string();
         metadata_acc.data.symbol = metadata_acc.data.symbol.trim_end_matches("\0").to_string();
         metadata_acc.data.uri = metadata_acc.data.uri.trim_end_matches("\0").to_string();
 
-        Ok(metadata_acc.data)
+        Ok(metadata_acc)
     }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+/// Max number of accounts in a single `getMultipleAccounts` RPC call.
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Max number of tokens in a metadata batch, each with metadata and mint accounts.
+pub const MAX_METADATA_BATCH: usize = MAX_MULTIPLE_ACCOUNTS / 2;
+
+/// Min length of SPL token mint account data, Token-2022 extensions follow it.
+const MINT_ACCOUNT_LEN: usize = 82;
+
+/// Offset of decimals in SPL token mint account data,
+/// after mint authority option and supply.
+const MINT_DECIMALS_OFFSET: usize = 44;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Serialize)]
 pub struct MetadataAccount {
     pub key: u8,
+    #[serde(serialize_with = "serialize_pubkey")]
     pub update_authority: Pubkey,
+    #[serde(serialize_with = "serialize_pubkey")]
     pub mint: Pubkey,
     pub data: TokenMetadata,
     pub primary_sale_happened: bool,
     pub is_mutable: bool,
 }
+
+/// Serialize pubkey as base58 string.
+fn serialize_pubkey<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mint_decimals_are_decoded_at_offset() {
+        let mut data = vec![0; MINT_ACCOUNT_LEN];
+        data[MINT_DECIMALS_OFFSET] = 6;
+        assert_eq!(PumpHandler::decode_mint_decimals(&data).unwrap(), 6);
+
+        // Token-2022 mints have extensions after the base layout.
+        data.resize(MINT_ACCOUNT_LEN + 100, 0xff);
+        assert_eq!(PumpHandler::decode_mint_decimals(&data).unwrap(), 6);
+    }
+
+    #[test]
+    fn short_mint_account_is_rejected() {
+        assert!(PumpHandler::decode_mint_decimals(&[]).is_err());
+        assert!(PumpHandler::decode_mint_decimals(&[6; MINT_ACCOUNT_LEN - 1]).is_err());
+    }
+
+    /// Storage over unreachable DB and Redis, enough for events which are not handled.
+    async fn offline_storage() -> Storage {
+        let pool = sqlx::PgPool::connect_lazy("postgres://127.0.0.1:1/none").unwrap();
+        let hot = crate::hot_candles::HotCandles::new(None, 0);
+        Storage::for_tests(pool, "redis://127.0.0.1:1", hot).await
+    }
+
+    /// Spawn event processing task of the control, returns sender of its events.
+    async fn spawn_handler(
+        control: &IngestionControl,
+        metrics: Arc<Metrics>,
+    ) -> (
+        tokio::sync::mpsc::Sender<IndexedPumpfunEvent>,
+        JoinHandle<()>,
+    ) {
+        spawn_storage_handler(offline_storage().await, RpcPool::new(&[]), control, metrics)
+    }
+
+    /// Spawn event processing task of the control writing into given storage.
+    fn spawn_storage_handler(
+        storage: Storage,
+        rpc: RpcPool,
+        control: &IngestionControl,
+        metrics: Arc<Metrics>,
+    ) -> (
+        tokio::sync::mpsc::Sender<IndexedPumpfunEvent>,
+        JoinHandle<()>,
+    ) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let handler = tokio::spawn(PumpHandler::run(
+            storage,
+            metrics.clone(),
+            MetadataBreaker::new(metrics),
+            rpc,
+            MarketStats::default(),
+            control.clone(),
+            receiver,
+        ));
+        (sender, handler)
+    }
+
+    #[tokio::test]
+    async fn closed_channel_stops_ingestion() {
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let (sender, handler) = spawn_handler(&control, Default::default()).await;
+        let monitor = tokio::spawn({
+            let control = control.clone();
+            async move { PumpHandler::monitor(handler, &control).await }
+        });
+
+        tokio::task::yield_now().await;
+        assert!(control.is_ingesting());
+
+        drop(sender);
+        tokio::time::timeout(Duration::from_secs(5), monitor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!control.is_ingesting());
+    }
+
+    #[tokio::test]
+    async fn panicked_handler_stops_ingestion() {
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let handler = tokio::spawn(async { panic!("handler failed") });
+
+        PumpHandler::monitor(handler, &control).await;
+        assert!(!control.is_ingesting());
+    }
+
+    /// Completion with invalid timestamp, which is handled without storage.
+    fn skipped_completion() -> IndexedPumpfunEvent {
+        IndexedPumpfunEvent {
+            _index: 0,
+            event: PumpFunEvent::Complete(CompleteEvent {
+                user: Pubkey::new_unique(),
+                mint: Pubkey::new_unique(),
+                bonding_curve: Pubkey::new_unique(),
+                timestamp: 0,
+            }),
+        }
+    }
+
+    /// Wait up to 5 seconds for condition to hold.
+    async fn eventually(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition holds in time");
+    }
+
+    #[tokio::test]
+    async fn paused_events_are_dropped() {
+        let control = IngestionControl::new(PauseMode::Drop, 4);
+        let metrics = Arc::new(Metrics::default());
+        let (sender, _handler) = spawn_handler(&control, metrics.clone()).await;
+        let handled = || metrics.invalid_timestamp_events.load(Ordering::Relaxed);
+        let dropped = || metrics.paused_dropped_events.load(Ordering::Relaxed);
+
+        control.set_paused(true);
+        assert!(control.is_paused());
+        sender.send(skipped_completion()).await.unwrap();
+        sender.send(skipped_completion()).await.unwrap();
+        eventually(|| dropped() == 2).await;
+        assert_eq!(handled(), 0);
+
+        control.set_paused(false);
+        sender.send(skipped_completion()).await.unwrap();
+        eventually(|| handled() == 1).await;
+        assert_eq!(dropped(), 2);
+    }
+
+    #[tokio::test]
+    async fn paused_events_are_buffered() {
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let metrics = Arc::new(Metrics::default());
+        let (sender, _handler) = spawn_handler(&control, metrics.clone()).await;
+        let handled = || metrics.invalid_timestamp_events.load(Ordering::Relaxed);
+
+        control.set_paused(true);
+        sender.send(skipped_completion()).await.unwrap();
+        sender.send(skipped_completion()).await.unwrap();
+        eventually(|| control.last_event_at().is_some()).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handled(), 0);
+
+        control.set_paused(false);
+        eventually(|| handled() == 2).await;
+        assert_eq!(metrics.paused_dropped_events.load(Ordering::Relaxed), 0);
+    }
+
+    /// Metadata account data of a token with given name.
+    fn metadata_account_data(name: &str) -> Vec<u8> {
+        let account = MetadataAccount {
+            key: 4,
+            update_authority: Pubkey::default(),
+            mint: Pubkey::default(),
+            data: TokenMetadata {
+                name: format!("{name}\0\0\0"),
+                symbol: String::from("TEST"),
+                uri: String::from("https://example.com/token.json"),
+                completed_at: None,
+                decimals: None,
+            },
+            primary_sale_happened: false,
+            is_mutable: true,
+        };
+        borsh::to_vec(&account).unwrap()
+    }
+
+    /// Mock RPC endpoint counting calls.
+    /// Account requested by `getAccountInfo` exists. Accounts of `getMultipleAccounts` are
+    /// metadata and mint account pairs of tokens: metadata of every other token exists,
+    /// mint accounts with 6 decimals exist for all tokens but the first.
+    async fn mock_rpc(calls: Arc<std::sync::atomic::AtomicUsize>) -> String {
+        use axum::Json;
+        use base64::Engine;
+        use serde_json::{Value, json};
+
+        let account = |data: Vec<u8>| {
+            json!({
+                "data": [base64::engine::general_purpose::STANDARD.encode(data), "base64"],
+                "executable": false,
+                "lamports": 1_000_000,
+                "owner": Pubkey::default().to_string(),
+                "rentEpoch": 0,
+                "space": 0,
+            })
+        };
+        let handler = move |Json(request): Json<Value>| async move {
+            calls.fetch_add(1, Ordering::Relaxed);
+            let value = match request["method"].as_str().unwrap() {
+                "getAccountInfo" => account(metadata_account_data("Token 0")),
+                "getMultipleAccounts" => (0..request["params"][0].as_array().unwrap().len())
+                    .map(|i| match (i / 2, i % 2) {
+                        (token, 0) if token % 2 == 0 => {
+                            account(metadata_account_data(&format!("Token {token}")))
+                        }
+                        (token, 1) if token > 0 => {
+                            let mut data = vec![0; MINT_ACCOUNT_LEN];
+                            data[MINT_DECIMALS_OFFSET] = 6;
+                            account(data)
+                        }
+                        _ => Value::Null,
+                    })
+                    .collect(),
+                method => panic!("Unexpected RPC method {method}"),
+            };
+            Json(json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": {"context": {"slot": 1}, "value": value},
+            }))
+        };
+        let router = axum::Router::new().route("/", axum::routing::post(handler));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn metadata_batch_is_queried_in_one_call() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rpc = RpcPool::new(&[mock_rpc(calls.clone()).await]);
+        let mints: Vec<_> = (0..10).map(|_| Pubkey::new_unique()).collect();
+
+        let metadata = PumpHandler::query_token_metadata_batch(&rpc, &mints)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(metadata.len(), mints.len());
+        for (i, metadata) in metadata.iter().enumerate() {
+            match metadata {
+                Some(metadata) => {
+                    assert_eq!(i % 2, 0);
+                    assert_eq!(metadata.name, format!("Token {i}"));
+                    // Mint account of the first token is missing.
+                    assert_eq!(metadata.decimals, (i > 0).then_some(6));
+                }
+                None => assert_eq!(i % 2, 1),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_metadata_batch_is_rejected() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rpc = RpcPool::new(&[mock_rpc(calls.clone()).await]);
+        let mints = vec![Pubkey::new_unique(); MAX_METADATA_BATCH + 1];
+
+        assert!(
+            PumpHandler::query_token_metadata_batch(&rpc, &mints)
+                .await
+                .is_err()
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn metadata_account_is_decoded() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rpc = RpcPool::new(&[mock_rpc(calls).await]);
+
+        let account = PumpHandler::query_metadata_account(&rpc, Pubkey::new_unique())
+            .await
+            .unwrap();
+        assert_eq!(account.key, 4);
+        assert!(account.is_mutable);
+        assert!(!account.primary_sale_happened);
+        // Null padding is trimmed.
+        assert_eq!(account.data.name, "Token 0");
+        assert_eq!(account.data.symbol, "TEST");
+        assert_eq!(account.data.uri, "https://example.com/token.json");
+
+        // Keys are shown as base58 strings.
+        let json = serde_json::to_value(&account).unwrap();
+        assert_eq!(json["mint"], Pubkey::default().to_string());
+        assert_eq!(json["update_authority"], Pubkey::default().to_string());
+        assert_eq!(json["data"]["name"], "Token 0");
+    }
+
+    #[tokio::test]
+    async fn token_metadata_is_parsed_from_rpc_response() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rpc = RpcPool::new(&[mock_rpc(calls.clone()).await]);
+
+        let metadata = PumpHandler::query_token_metadata(&rpc, Pubkey::new_unique())
+            .await
+            .unwrap();
+        // Metadata and mint accounts are read in one call.
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(metadata.name, "Token 0");
+        assert_eq!(metadata.symbol, "TEST");
+        assert_eq!(metadata.uri, "https://example.com/token.json");
+        // Mint account is missing, so prices are raw amounts ratio.
+        assert_eq!(metadata.decimals, None);
+    }
+
+    #[tokio::test]
+    async fn first_event_makes_ingestion_ready() {
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let (sender, _handler) = spawn_handler(&control, Default::default()).await;
+        assert!(!control.is_ready());
+        assert_eq!(control.last_event_at(), None);
+
+        sender.send(skipped_completion()).await.unwrap();
+        eventually(|| control.is_ready()).await;
+        assert!(control.last_event_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn metadata_is_queried_from_next_endpoint_on_failure() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        // Nothing listens on the first endpoint.
+        let rpc = RpcPool::new(&[
+            String::from("http://127.0.0.1:1"),
+            mock_rpc(calls.clone()).await,
+        ]);
+
+        let account = PumpHandler::query_metadata_account(&rpc, Pubkey::new_unique())
+            .await
+            .unwrap();
+        assert_eq!(account.data.name, "Token 0");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    /// Storage over test DB with a known token, so its trades are handled without RPC.
+    async fn storage_with_token(pool: sqlx::PgPool, mint: Pubkey) -> Storage {
+        let hot = crate::hot_candles::HotCandles::new(None, 0);
+        let storage = Storage::for_tests(pool, "redis://127.0.0.1:1", hot).await;
+        let metadata = TokenMetadata {
+            name: String::from("Token"),
+            symbol: String::from("TKN"),
+            uri: String::from("https://example.com/token.json"),
+            completed_at: None,
+            decimals: Some(6),
+        };
+        storage
+            .insert_token_metadata(mint.to_string(), Some(metadata))
+            .await
+            .unwrap();
+        storage
+    }
+
+    fn trade_event(mint: Pubkey, timestamp: i64) -> IndexedPumpfunEvent {
+        IndexedPumpfunEvent {
+            _index: 0,
+            event: PumpFunEvent::Trade(TradeEvent {
+                mint,
+                sol_amount: 1_000_000,
+                token_amount: 2_000_000,
+                is_buy: true,
+                user: Pubkey::new_unique(),
+                timestamp,
+                virtual_sol_reserves: 0,
+                virtual_token_reserves: 0,
+                real_sol_reserves: 0,
+                real_token_reserves: 0,
+            }),
+        }
+    }
+
+    /// Number of trades in the M1 candle of the token at given time.
+    async fn minute_trades(storage: &Storage, mint: Pubkey, timestamp: i64) -> u64 {
+        let datetime = DateTime::from_timestamp(timestamp, 0).unwrap();
+        let minute = crate::model::Resolution::M1.bucket_start(datetime);
+        let candles = storage
+            .trades_since(&mint.to_string(), minute, crate::model::Resolution::M1)
+            .await
+            .unwrap();
+        candles.get(&minute).map_or(0, |candle| candle.trade_count)
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn buffered_trades_are_flushed_on_stop(pool: sqlx::PgPool) {
+        let mint = Pubkey::new_unique();
+        // Batch is written long after the events are received.
+        let storage = storage_with_token(pool, mint)
+            .await
+            .with_batching(100, Duration::from_millis(500));
+        let control = IngestionControl::new(PauseMode::Buffer, 32);
+        let (sender, _handler) = spawn_storage_handler(
+            storage.clone(),
+            RpcPool::new(&[]),
+            &control,
+            Default::default(),
+        );
+        let timestamp = Utc::now().timestamp();
+        for _ in 0..20 {
+            sender.send(trade_event(mint, timestamp)).await.unwrap();
+        }
+
+        // Shutdown sequence.
+        assert!(control.stop(Duration::from_secs(5)).await);
+        assert!(control.in_flight() > 0);
+        assert_eq!(control.drain(Duration::from_secs(5)).await, 0);
+        assert_eq!(minute_trades(&storage, mint, timestamp).await, 20);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn in_flight_events_are_bounded(pool: sqlx::PgPool) {
+        let mint = Pubkey::new_unique();
+        // Handlers wait for the batch write, so permits are held for a while.
+        let storage = storage_with_token(pool, mint)
+            .await
+            .with_batching(100, Duration::from_millis(200));
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let mut in_flight = control.in_flight.subscribe();
+        let max_in_flight = tokio::spawn(async move {
+            let mut max = 0;
+            while in_flight.changed().await.is_ok() {
+                max = max.max(*in_flight.borrow_and_update());
+            }
+            max
+        });
+        let (sender, _handler) = spawn_storage_handler(
+            storage.clone(),
+            RpcPool::new(&[]),
+            &control,
+            Default::default(),
+        );
+        let timestamp = Utc::now().timestamp();
+        for _ in 0..20 {
+            sender.send(trade_event(mint, timestamp)).await.unwrap();
+        }
+
+        assert!(control.stop(Duration::from_secs(10)).await);
+        assert_eq!(control.drain(Duration::from_secs(10)).await, 0);
+        assert_eq!(minute_trades(&storage, mint, timestamp).await, 20);
+        drop(control);
+        assert_eq!(max_in_flight.await.unwrap(), 4);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn metadata_is_queried_only_for_new_tokens(pool: sqlx::PgPool) {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rpc = RpcPool::new(&[mock_rpc(calls.clone()).await]);
+        let known = Pubkey::new_unique();
+        let storage = storage_with_token(pool, known).await;
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let (sender, _handler) =
+            spawn_storage_handler(storage.clone(), rpc, &control, Default::default());
+        let timestamp = Utc::now().timestamp();
+
+        let new = Pubkey::new_unique();
+        for _ in 0..3 {
+            sender.send(trade_event(known, timestamp)).await.unwrap();
+        }
+        sender.send(trade_event(new, timestamp)).await.unwrap();
+        assert!(control.stop(Duration::from_secs(5)).await);
+        assert_eq!(control.drain(Duration::from_secs(5)).await, 0);
+
+        // Only the new token metadata is queried.
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(minute_trades(&storage, known, timestamp).await, 3);
+        let metadata = storage.get_token_metadata(&new.to_string()).await.unwrap();
+        assert_eq!(metadata.name, "Token 0");
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn failed_trade_inserts_are_counted(pool: sqlx::PgPool) {
+        let mint = Pubkey::new_unique();
+        let storage = storage_with_token(pool.clone(), mint).await;
+        let metrics = Arc::new(Metrics::default());
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let (sender, _handler) =
+            spawn_storage_handler(storage, RpcPool::new(&[]), &control, metrics.clone());
+        let timestamp = Utc::now().timestamp();
+
+        sender.send(trade_event(mint, timestamp)).await.unwrap();
+        assert!(control.stop(Duration::from_secs(5)).await);
+        assert_eq!(control.drain(Duration::from_secs(5)).await, 0);
+        assert_eq!(metrics.failed_trade_inserts.load(Ordering::Relaxed), 0);
+
+        // DB is unreachable.
+        pool.close().await;
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let storage = Storage::for_tests(
+            pool,
+            "redis://127.0.0.1:1",
+            crate::hot_candles::HotCandles::new(None, 0),
+        )
+        .await;
+        let (sender, _handler) =
+            spawn_storage_handler(storage, RpcPool::new(&[]), &control, metrics.clone());
+        sender.send(trade_event(mint, timestamp)).await.unwrap();
+        assert!(control.stop(Duration::from_secs(5)).await);
+        assert_eq!(control.drain(Duration::from_secs(5)).await, 0);
+        assert_eq!(metrics.failed_trade_inserts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn paused_events_are_bounded_by_channel() {
+        let control = IngestionControl::new(PauseMode::Buffer, 4);
+        let metrics = Arc::new(Metrics::default());
+        let (sender, _handler) = spawn_handler(&control, metrics.clone()).await;
+        let handled = || metrics.invalid_timestamp_events.load(Ordering::Relaxed);
+        let dropped = || metrics.channel_dropped_events.load(Ordering::Relaxed);
+
+        control.set_paused(true);
+        for _ in 0..100 {
+            crate::indexer::forward_event(&sender, skipped_completion(), &metrics);
+            tokio::task::yield_now().await;
+        }
+        // Handler holds at most one event while paused, the rest wait in the full channel.
+        assert_eq!(sender.capacity(), 0);
+        let pending = 100 - dropped();
+        assert!(pending <= sender.max_capacity() as u64 + 1);
+        assert_eq!(handled(), 0);
+
+        control.set_paused(false);
+        eventually(|| handled() == pending).await;
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::DateTime;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::metrics::Metrics;
+use crate::model::TradeInfo;
+
+/// Interval of pending trades replay.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Durable queue of trades failed to be inserted into DB.
+///
+/// Trades are appended to a local JSON lines file, so they survive restarts,
+/// and replayed in order once DB is available again.
+#[derive(Clone)]
+pub struct RetryQueue {
+    path: PathBuf,
+    capacity: usize,
+    metrics: Arc<Metrics>,
+    /// Number of pending trades. Guards the file.
+    pending: Arc<Mutex<usize>>,
+}
+
+/// Trade as stored in the queue file.
+/// Candle timestamps are not stored, but derived from enabled resolutions on replay,
+/// so they stay consistent if resolutions are changed between restarts.
+#[derive(Clone, Serialize, Deserialize)]
+struct PendingTrade {
+    mint_acc: String,
+    timestamp_millis: i64,
+    sol_amount: u64,
+    token_amount: u64,
+    is_buy: bool,
+    /// Missing in trades queued before decimals were tracked.
+    #[serde(default)]
+    decimals: Option<u8>,
+}
+
+impl From<&TradeInfo> for PendingTrade {
+    fn from(info: &TradeInfo) -> Self {
+        Self {
+            mint_acc: info.mint_acc.clone(),
+            timestamp_millis: info.timestamp.timestamp_millis(),
+            sol_amount: info.sol_amount,
+            token_amount: info.token_amount,
+            is_buy: info.is_buy,
+            decimals: info.decimals,
+        }
+    }
+}
+
+impl TryFrom<PendingTrade> for TradeInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(trade: PendingTrade) -> Result<Self, Self::Error> {
+        let timestamp = DateTime::from_timestamp_millis(trade.timestamp_millis)
+            .ok_or_else(|| anyhow::anyhow!("Bad timestamp: {}", trade.timestamp_millis))?;
+        Ok(Self {
+            mint_acc: trade.mint_acc,
+            timestamp,
+            sol_amount: trade.sol_amount,
+            token_amount: trade.token_amount,
+            is_buy: trade.is_buy,
+            decimals: trade.decimals,
+        })
+    }
+}
+
+impl RetryQueue {
+    /// Open queue file. Missing file means there are no pending trades.
+    pub async fn open(
+        path: PathBuf,
+        capacity: usize,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Self> {
+        let trades = read_pending(&path).await?;
+        if !trades.is_empty() {
+            tracing::info!("{} trades are pending DB insertion.", trades.len());
+        }
+
+        Ok(Self {
+            path,
+            capacity,
+            metrics,
+            pending: Arc::new(Mutex::new(trades.len())),
+        })
+    }
+
+    /// Append trade to the queue.
+    /// Trade is dropped if the queue is full.
+    pub async fn push(&self, info: &TradeInfo) -> anyhow::Result<()> {
+        let mut pending = self.pending.lock().await;
+        if *pending >= self.capacity {
+            self.metrics
+                .retry_dropped_trades
+                .fetch_add(1, Ordering::Relaxed);
+            anyhow::bail!("Retry queue is full, dropping trade of {}", info.mint_acc);
+        }
+
+        let mut line = serde_json::to_string(&PendingTrade::from(info))?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+
+        *pending += 1;
+        self.metrics
+            .retry_queued_trades
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Replay pending trades in order with `insert`.
+    /// Stops at the first failed insertion and keeps the rest for later.
+    /// Returns number of replayed trades.
+    pub async fn drain<F, Fut>(&self, mut insert: F) -> anyhow::Result<usize>
+    where
+        F: FnMut(TradeInfo) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let mut pending = self.pending.lock().await;
+        if *pending == 0 {
+            return Ok(0);
+        }
+
+        let trades = read_pending(&self.path).await?;
+        let mut replayed = 0;
+        let mut error = None;
+        for trade in &trades {
+            let info = match TradeInfo::try_from(trade.clone()) {
+                Ok(info) => info,
+                Err(e) => {
+                    tracing::error!("Dropping malformed pending trade: {e}");
+                    replayed += 1;
+                    continue;
+                }
+            };
+            if let Err(e) = insert(info).await {
+                error = Some(e);
+                break;
+            }
+            replayed += 1;
+        }
+
+        // Rewrite remaining trades atomically, so nothing is lost on crash.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path).await?;
+        for trade in &trades[replayed..] {
+            let mut line = serde_json::to_string(trade)?;
+            line.push('\n');
+            tmp.write_all(line.as_bytes()).await?;
+        }
+        tmp.sync_all().await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        *pending = trades.len() - replayed;
+
+        match error {
+            Some(e) => Err(e.context(format!("{} trades are still pending", *pending))),
+            None => Ok(replayed),
+        }
+    }
+}
+
+/// Read pending trades from queue file.
+/// Missing file means no pending trades, torn last line of an interrupted append is skipped.
+async fn read_pending(path: &Path) -> anyhow::Result<Vec<PendingTrade>> {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut trades = Vec::new();
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str(&line) {
+            Ok(trade) => trades.push(trade),
+            Err(e) => tracing::error!("Skipping malformed pending trade {line:?}: {e}"),
+        }
+    }
+
+    Ok(trades)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh queue file path unique to the test.
+    fn queue_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "pumpfun_indexer_retry_queue_{}_{name}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn trade(secs: i64) -> TradeInfo {
+        TradeInfo {
+            mint_acc: String::from("So11111111111111111111111111111111111111112"),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount: 1_000,
+            token_amount: 2_000,
+            is_buy: true,
+            decimals: Some(6),
+        }
+    }
+
+    #[tokio::test]
+    async fn trades_are_not_lost_while_db_is_down() {
+        let path = queue_path("downtime");
+        let queue = RetryQueue::open(path.clone(), 10, Default::default())
+            .await
+            .unwrap();
+        for secs in [1, 2, 3] {
+            queue.push(&trade(secs)).await.unwrap();
+        }
+
+        // DB fails after the first replayed trade.
+        let mut inserted = Vec::new();
+        let e = queue
+            .drain(|info| {
+                let result = if inserted.is_empty() {
+                    inserted.push(info.timestamp.timestamp());
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("DB is down"))
+                };
+                async move { result }
+            })
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("2 trades are still pending"), "{e}");
+
+        // Pending trades survive restart and are replayed in order once DB recovers.
+        let queue = RetryQueue::open(path.clone(), 10, Default::default())
+            .await
+            .unwrap();
+        let replayed = queue
+            .drain(|info| {
+                inserted.push(info.timestamp.timestamp());
+                assert_eq!(info.decimals, Some(6));
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, 2);
+        assert_eq!(inserted, [1, 2, 3]);
+        assert_eq!(queue.drain(|_| async { Ok(()) }).await.unwrap(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn trades_are_dropped_when_full() {
+        let path = queue_path("full");
+        let metrics = Arc::new(Metrics::default());
+        let queue = RetryQueue::open(path.clone(), 2, metrics.clone())
+            .await
+            .unwrap();
+        queue.push(&trade(1)).await.unwrap();
+        queue.push(&trade(2)).await.unwrap();
+        assert!(queue.push(&trade(3)).await.is_err());
+        assert_eq!(metrics.retry_queued_trades.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.retry_dropped_trades.load(Ordering::Relaxed), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torn_last_line_is_skipped() {
+        let path = queue_path("torn");
+        let queue = RetryQueue::open(path.clone(), 10, Default::default())
+            .await
+            .unwrap();
+        queue.push(&trade(1)).await.unwrap();
+        // Append interrupted by crash.
+        let mut file = OpenOptions::new().append(true).open(&path).await.unwrap();
+        file.write_all(b"{\"mint_acc\":\"So1").await.unwrap();
+
+        assert_eq!(read_pending(&path).await.unwrap().len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+
+/// Default Solana RPC endpoint.
+pub const DEFAULT_RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// Consecutive failures of an endpoint after which it's skipped.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Time an unhealthy endpoint is skipped for.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Solana RPC endpoint with its health.
+struct Endpoint {
+    url: String,
+    client: Arc<RpcClient>,
+    health: Mutex<Health>,
+}
+
+/// Failures of an endpoint.
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+/// Solana RPC endpoints with failover.
+/// Public endpoints rate-limit aggressively, so requests fall back to the next endpoint
+/// on failure, and endpoints failing repeatedly are skipped for a cooldown.
+#[derive(Clone)]
+pub struct RpcPool {
+    endpoints: Arc<Vec<Endpoint>>,
+}
+
+impl RpcPool {
+    /// Create new pool with endpoints tried in the given order.
+    pub fn new(urls: &[String]) -> Self {
+        let endpoints = urls
+            .iter()
+            .map(|url| Endpoint {
+                url: url.clone(),
+                client: Arc::new(RpcClient::new(url.clone())),
+                health: Default::default(),
+            })
+            .collect();
+
+        Self {
+            endpoints: Arc::new(endpoints),
+        }
+    }
+
+    /// Run request against healthy endpoints in order until one succeeds.
+    /// Unhealthy endpoints are tried too if all of them are unhealthy.
+    /// Returns the last error if all endpoints fail.
+    pub async fn call<T, E, F>(&self, request: impl Fn(Arc<RpcClient>) -> F) -> anyhow::Result<T>
+    where
+        F: Future<Output = Result<T, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let mut endpoints: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_healthy())
+            .collect();
+        if endpoints.is_empty() {
+            endpoints = self.endpoints.iter().collect();
+        }
+
+        let mut last_error = anyhow::anyhow!("No RPC endpoints configured");
+        for endpoint in endpoints {
+            match request(endpoint.client.clone()).await {
+                Ok(result) => {
+                    endpoint.record_success();
+                    return Ok(result);
+                }
+                Err(e) => {
+                    let e = e.into();
+                    tracing::warn!("RPC request to {} failed: {e}", endpoint.url);
+                    endpoint.record_failure();
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+impl Endpoint {
+    /// Lock endpoint health. Health stays consistent even if a holder panicked.
+    fn health(&self) -> MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether endpoint isn't in cooldown.
+    fn is_healthy(&self) -> bool {
+        self.health()
+            .unhealthy_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Record successful request, so the endpoint is healthy again.
+    fn record_success(&self) {
+        *self.health() = Health::default();
+    }
+
+    /// Record failed request. Endpoint is skipped for `COOLDOWN` after `FAILURE_THRESHOLD`
+    /// failures in a row.
+    fn record_failure(&self) {
+        let mut health = self.health();
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            // One more try is allowed after cooldown, next failure skips it again.
+            health.consecutive_failures = FAILURE_THRESHOLD - 1;
+            health.unhealthy_until = Some(Instant::now() + COOLDOWN);
+            tracing::warn!(
+                "RPC endpoint {} is unhealthy, skipping it for {COOLDOWN:?}.",
+                self.url
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> RpcPool {
+        RpcPool::new(&[
+            String::from("http://127.0.0.1:1"),
+            String::from("http://127.0.0.1:2"),
+        ])
+    }
+
+    /// Request failing on the first endpoint, returning index of the endpoint it ran on.
+    async fn call(pool: &RpcPool, tried: &Mutex<Vec<usize>>) -> anyhow::Result<usize> {
+        pool.call(|client| async move {
+            let idx = pool
+                .endpoints
+                .iter()
+                .position(|endpoint| Arc::ptr_eq(&endpoint.client, &client))
+                .unwrap();
+            tried.lock().unwrap().push(idx);
+            if idx == 0 {
+                Err(anyhow::anyhow!("Too many requests"))
+            } else {
+                Ok(idx)
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn failed_endpoint_falls_back_to_next() {
+        let pool = pool();
+        let tried = Mutex::new(Vec::new());
+
+        assert_eq!(call(&pool, &tried).await.unwrap(), 1);
+        assert_eq!(*tried.lock().unwrap(), [0, 1]);
+        assert!(pool.endpoints[0].is_healthy());
+        assert_eq!(pool.endpoints[0].health().consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn repeatedly_failing_endpoint_is_skipped() {
+        let pool = pool();
+        let tried = Mutex::new(Vec::new());
+        for _ in 0..FAILURE_THRESHOLD {
+            call(&pool, &tried).await.unwrap();
+        }
+        assert!(!pool.endpoints[0].is_healthy());
+
+        tried.lock().unwrap().clear();
+        assert_eq!(call(&pool, &tried).await.unwrap(), 1);
+        assert_eq!(*tried.lock().unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_endpoints_are_tried_if_all_are() {
+        let pool = pool();
+        for endpoint in pool.endpoints.iter() {
+            endpoint.health().unhealthy_until = Some(Instant::now() + COOLDOWN);
+        }
+        let tried = Mutex::new(Vec::new());
+
+        assert_eq!(call(&pool, &tried).await.unwrap(), 1);
+        assert_eq!(*tried.lock().unwrap(), [0, 1]);
+        // Success makes the endpoint healthy again.
+        assert!(pool.endpoints[1].is_healthy());
+        assert!(!pool.endpoints[0].is_healthy());
+    }
+
+    #[tokio::test]
+    async fn last_error_is_returned_if_all_fail() {
+        let pool = RpcPool::new(&[String::from("http://127.0.0.1:1")]);
+        let tried = Mutex::new(Vec::new());
+        let e = call(&pool, &tried).await.unwrap_err();
+        assert_eq!(e.to_string(), "Too many requests");
+
+        let e = RpcPool::new(&[]).call(|_| async { anyhow::Ok(()) }).await;
+        assert!(e.unwrap_err().to_string().contains("No RPC endpoints"));
+    }
+}
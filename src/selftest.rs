@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::cache::Cache;
+use crate::db::Db;
+use crate::hot_candles::HotCandles;
+use crate::metrics::Metrics;
+use crate::model::{Candle, Resolution, TradeInfo};
+use crate::retry_queue::RetryQueue;
+use crate::storage::Storage;
+use crate::token_activity::TokenActivity;
+
+/// Prefix of the synthetic token mint, never a valid base58 address.
+const SELFTEST_MINT_PREFIX: &str = "selftest-";
+
+/// Max relative difference of compared values, DB and cache round differently.
+const TOLERANCE: f64 = 1e-9;
+
+/// Validate the storage stack with a write/read round trip of a synthetic trade.
+///
+/// The trade is inserted through [`Storage::insert_trade`], read back from DB and cache
+/// of every enabled resolution, and from storage via `trades_since` and `last_trade`.
+/// The synthetic token is deleted afterwards. Failed DB writes go to a temporary retry
+/// queue, so the synthetic trade is never replayed by the indexer.
+/// Every check is logged, returns error if any of them fails.
+pub async fn run(db: Db, cache: Cache, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    let retry_path = std::env::temp_dir().join("pumpfun_indexer_selftest.jsonl");
+    let retry = RetryQueue::open(retry_path.clone(), 1, metrics.clone()).await?;
+    let storage = Storage::new(
+        db.clone(),
+        cache.clone(),
+        metrics,
+        retry,
+        HotCandles::new(None, 0),
+        TokenActivity::new(None),
+    )
+    .await;
+
+    let now = Utc::now();
+    let trade = TradeInfo {
+        mint_acc: format!("{SELFTEST_MINT_PREFIX}{}", now.timestamp_millis()),
+        timestamp: now,
+        sol_amount: 1_000_000_000,
+        token_amount: 35_000_000_000_000,
+        is_buy: true,
+        // Decimals of pumpfun tokens, so prices are scaled as for them.
+        decimals: Some(6),
+    };
+    let mint = trade.mint_acc.clone();
+
+    let result = round_trip(&storage, &db, &cache, trade).await;
+
+    if let Err(e) = storage.delete_token(&mint).await {
+        tracing::warn!("Self-test: failed to delete synthetic token {mint}: {e}");
+    }
+    if let Err(e) = tokio::fs::remove_file(&retry_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("Self-test: failed to remove {}: {e}", retry_path.display());
+    }
+
+    let failed = result?;
+    if failed > 0 {
+        anyhow::bail!("Self-test failed: {failed} checks failed");
+    }
+    tracing::info!("Self-test passed.");
+    Ok(())
+}
+
+/// Insert trade and check it's read back consistently.
+/// Returns number of failed checks.
+async fn round_trip(
+    storage: &Storage,
+    db: &Db,
+    cache: &Cache,
+    trade: TradeInfo,
+) -> anyhow::Result<usize> {
+    let mint = trade.mint_acc.clone();
+    let expected = Candle {
+        open: trade.price()?,
+        close: trade.price()?,
+        high: trade.price()?,
+        low: trade.price()?,
+        volume: trade.token_amount as f64,
+        trade_count: 1,
+        buy_volume: trade.token_amount as f64,
+        sell_volume: 0.0,
+        vwap: trade.price()?,
+    };
+    let buckets: Vec<_> = Resolution::enabled()
+        .into_iter()
+        .map(|resolution| (resolution, resolution.bucket_start(trade.timestamp)))
+        .collect();
+
+    storage.insert_token_metadata(mint.clone(), None).await?;
+    storage.insert_trade(&buckets, trade).await?;
+
+    let mut failed = 0;
+    let mut check = |name: String, result: anyhow::Result<()>| match result {
+        Ok(()) => tracing::info!("Self-test: {name}: PASS"),
+        Err(e) => {
+            tracing::error!("Self-test: {name}: FAIL: {e}");
+            failed += 1;
+        }
+    };
+
+    for (resolution, bucket) in &buckets {
+        let (resolution, bucket) = (*resolution, *bucket);
+        let db_candle = db
+            .trades_since(&mint, bucket, resolution)
+            .await
+            .and_then(|candles| single_candle(candles.into_iter().collect(), bucket));
+        let cache_candle = cache
+            .trades_since(&mint, bucket, resolution)
+            .await
+            .and_then(|candles| single_candle(candles.into_iter().collect(), bucket));
+
+        check(
+            format!("{resolution} DB candle"),
+            match &db_candle {
+                Ok(candle) => compare(candle, &expected),
+                Err(e) => Err(anyhow::anyhow!("{e:#}")),
+            },
+        );
+        check(
+            format!("{resolution} cache candle"),
+            match &cache_candle {
+                Ok(candle) => compare(candle, &expected),
+                Err(e) => Err(anyhow::anyhow!("{e:#}")),
+            },
+        );
+        if let (Ok(db_candle), Ok(cache_candle)) = (&db_candle, &cache_candle) {
+            check(
+                format!("{resolution} DB and cache agree"),
+                compare(cache_candle, db_candle),
+            );
+        }
+    }
+
+    // Enabled resolutions are finest first.
+    let (finest, finest_bucket) = buckets[0];
+    check(
+        String::from("storage trades_since"),
+        storage
+            .trades_since(&mint, finest_bucket, finest)
+            .await
+            .and_then(|candles| single_candle(candles.into_iter().collect(), finest_bucket))
+            .and_then(|candle| compare(&candle, &expected)),
+    );
+    check(
+        String::from("storage last_trade"),
+        storage
+            .last_trade(&mint, finest)
+            .await
+            .and_then(|last| single_candle(vec![last], finest_bucket))
+            .and_then(|candle| compare(&candle, &expected)),
+    );
+
+    Ok(failed)
+}
+
+/// The only candle, which must start at the bucket.
+fn single_candle(
+    candles: Vec<(DateTime<Utc>, Candle)>,
+    bucket: DateTime<Utc>,
+) -> anyhow::Result<Candle> {
+    match candles.as_slice() {
+        [(datetime, candle)] if *datetime == bucket => Ok(*candle),
+        [(datetime, _)] => anyhow::bail!("Candle starts at {datetime}, expected {bucket}"),
+        candles => anyhow::bail!("Expected 1 candle, got {}", candles.len()),
+    }
+}
+
+/// Check candles are equal within tolerance.
+fn compare(actual: &Candle, expected: &Candle) -> anyhow::Result<()> {
+    let fields = [
+        ("open", actual.open, expected.open),
+        ("close", actual.close, expected.close),
+        ("high", actual.high, expected.high),
+        ("low", actual.low, expected.low),
+        ("volume", actual.volume, expected.volume),
+        ("buy_volume", actual.buy_volume, expected.buy_volume),
+        ("sell_volume", actual.sell_volume, expected.sell_volume),
+        ("vwap", actual.vwap, expected.vwap),
+    ];
+    for (name, actual, expected) in fields {
+        if (actual - expected).abs() > TOLERANCE * expected.abs() {
+            anyhow::bail!("{name} is {actual}, expected {expected}");
+        }
+    }
+    if actual.trade_count != expected.trade_count {
+        anyhow::bail!(
+            "trade_count is {}, expected {}",
+            actual.trade_count,
+            expected.trade_count
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn candle(close: f64) -> Candle {
+        Candle {
+            open: close,
+            close,
+            high: close,
+            low: close,
+            volume: 1_000.0,
+            trade_count: 1,
+            buy_volume: 1_000.0,
+            sell_volume: 0.0,
+            vwap: close,
+        }
+    }
+
+    #[test]
+    fn candles_are_compared_within_tolerance() {
+        let expected = candle(2.0);
+        assert!(compare(&candle(2.0 + 1e-12), &expected).is_ok());
+
+        let e = compare(&candle(2.1), &expected).unwrap_err();
+        assert!(e.to_string().starts_with("open is 2.1"), "{e}");
+        let counted = Candle {
+            trade_count: 2,
+            ..expected
+        };
+        let e = compare(&counted, &expected).unwrap_err();
+        assert_eq!(e.to_string(), "trade_count is 2, expected 1");
+    }
+
+    #[test]
+    fn single_candle_must_start_at_bucket() {
+        let bucket = DateTime::from_timestamp(1_700_000_100, 0).unwrap();
+        let later = DateTime::from_timestamp(1_700_000_160, 0).unwrap();
+        assert!(single_candle(vec![(bucket, candle(1.0))], bucket).is_ok());
+        assert!(single_candle(vec![(later, candle(1.0))], bucket).is_err());
+        assert!(single_candle(vec![], bucket).is_err());
+        let e =
+            single_candle(vec![(bucket, candle(1.0)), (later, candle(1.0))], bucket).unwrap_err();
+        assert_eq!(e.to_string(), "Expected 1 candle, got 2");
+    }
+
+    fn test_db(pool: sqlx::PgPool) -> Db {
+        Db::from_pool(pool, Default::default(), Duration::from_secs(10))
+    }
+
+    /// Count of synthetic tokens left in DB.
+    async fn synthetic_tokens(pool: &sqlx::PgPool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM token WHERE mint LIKE 'selftest-%'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn unreachable_cache_fails_selftest(pool: sqlx::PgPool) {
+        let cache = Cache::open("redis://127.0.0.1:1").unwrap();
+        let result = run(test_db(pool.clone()), cache, Default::default()).await;
+        assert!(result.is_err());
+        assert_eq!(synthetic_tokens(&pool).await, 0);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn selftest_passes(pool: sqlx::PgPool) {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let cache = Cache::open(&url).unwrap();
+        run(test_db(pool.clone()), cache, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(synthetic_tokens(&pool).await, 0);
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use sqlx::types::chrono::Utc;
+
+use crate::db::Db;
+use crate::model::Session;
+use crate::pump_handler::IngestionControl;
+
+/// Interval between updates of the session last event time.
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Current indexer run recorded in DB, so data gaps can be correlated with downtime.
+#[derive(Clone)]
+pub struct Sessions {
+    db: Db,
+    id: i64,
+}
+
+impl Sessions {
+    /// Record start of a new session.
+    pub async fn start(db: Db) -> anyhow::Result<Self> {
+        let id = db.start_session(Utc::now()).await?;
+        Ok(Self { db, id })
+    }
+
+    /// Update the session last event time from ingestion periodically.
+    pub async fn run(self, control: IngestionControl) {
+        let mut interval = tokio::time::interval(TOUCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.touch(&control).await {
+                tracing::warn!("Failed to update session {}: {e}", self.id);
+            }
+        }
+    }
+
+    /// Record end of the session with its last event time, on graceful shutdown.
+    pub async fn end(&self, control: &IngestionControl) -> anyhow::Result<()> {
+        self.touch(control).await?;
+        self.db.end_session(self.id, Utc::now()).await
+    }
+
+    /// Read the latest sessions, newest first.
+    pub async fn recent(&self, limit: usize) -> anyhow::Result<Vec<Session>> {
+        self.db.recent_sessions(limit).await
+    }
+
+    /// Store last event time, if any event was received.
+    async fn touch(&self, control: &IngestionControl) -> anyhow::Result<()> {
+        match control.last_event_at() {
+            Some(last_event_at) => self.db.touch_session(self.id, last_event_at).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    use crate::pump_handler::PauseMode;
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn session_is_recorded_from_start_to_shutdown(pool: PgPool) {
+        let db = Db::from_pool(pool, Default::default(), Duration::from_secs(10));
+        let control = IngestionControl::new(PauseMode::Buffer, 1);
+
+        // Previous run ended without graceful shutdown.
+        let crashed = Sessions::start(db.clone()).await.unwrap();
+        let last_event_at = Utc::now();
+        db.touch_session(crashed.id, last_event_at).await.unwrap();
+        let session = Sessions::start(db.clone()).await.unwrap();
+
+        let sessions = session.recent(10).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, session.id);
+        assert_eq!(sessions[0].ended_at, None);
+        assert_eq!(sessions[1].id, crashed.id);
+        assert_eq!(sessions[1].ended_at, None);
+        assert_eq!(
+            sessions[1].last_event_at,
+            Some(last_event_at.timestamp_millis() as u64)
+        );
+
+        session.end(&control).await.unwrap();
+        let sessions = session.recent(1).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        let ended = &sessions[0];
+        assert_eq!(ended.id, session.id);
+        assert!(
+            ended
+                .ended_at
+                .is_some_and(|ended_at| ended_at >= ended.started_at)
+        );
+        // No events were received.
+        assert_eq!(ended.last_event_at, None);
+    }
+}
@@ -1,22 +1,222 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+use futures_util::Stream;
 use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
 
 use crate::cache::{self, Cache};
-use crate::db::Db;
-use crate::model::{Candle, Resolution, TokenMetadata, TradeInfo};
+use crate::db::{Db, TokenWrite};
+use crate::hot_candles::{HotCandles, HotSubscriber};
+use crate::metrics::Metrics;
+use crate::model::{
+    BucketedTrade, Candle, Resolution, TokenMetadata, TokenPressure, TradeInfo, TradeOhlcv,
+};
+use crate::retry_queue::RetryQueue;
+use crate::token_activity::TokenActivity;
+use crate::trade_batcher::TradeBatcher;
 
 /// Storage layer to unify work with DB and cache.
 #[derive(Clone)]
 pub struct Storage {
     db: Db,
     cache: Cache,
+    metrics: Arc<Metrics>,
+    retry: RetryQueue,
+    hot: HotCandles,
+    activity: TokenActivity,
+    /// Batcher of trade inserts, trades are inserted one by one if not set.
+    batcher: Option<TradeBatcher>,
+    trades: broadcast::Sender<TradeInfo>,
+    tokens: broadcast::Sender<(String, TokenMetadata)>,
+}
+
+/// Capacity of the ingested trades channel.
+/// Subscribers lagging behind more than this number of trades skip the oldest ones.
+const TRADES_CHANNEL_CAPACITY: usize = 4096;
+
+/// Capacity of a candle updates subscription channel.
+const CANDLES_CHANNEL_CAPACITY: usize = 256;
+
+/// Capacity of the new and updated tokens channel.
+const TOKENS_CHANNEL_CAPACITY: usize = 1024;
+
+/// Origin of the data read from storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSource {
+    Memory,
+    Cache,
+    Db,
+}
+
+impl DataSource {
+    /// Source name for headers and logs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataSource::Memory => "memory",
+            DataSource::Cache => "cache",
+            DataSource::Db => "db",
+        }
+    }
+}
+
+/// Data read from storage with its origin and freshness.
+#[derive(Debug)]
+pub struct Sourced<T> {
+    pub data: T,
+    pub source: DataSource,
+    /// Timestamp of the newest data point, if any.
+    pub newest: Option<DateTime<Utc>>,
+}
+
+impl<T> Sourced<T> {
+    /// Age of the newest data point.
+    pub fn age(&self) -> Option<Duration> {
+        self.newest
+            .and_then(|newest| (Utc::now() - newest).to_std().ok())
+    }
 }
 
 impl Storage {
     /// Create new storage.
-    pub async fn new(db: Db, cache: Cache) -> Self {
-        Self { db, cache }
+    pub async fn new(
+        db: Db,
+        cache: Cache,
+        metrics: Arc<Metrics>,
+        retry: RetryQueue,
+        hot: HotCandles,
+        activity: TokenActivity,
+    ) -> Self {
+        let (trades, _) = broadcast::channel(TRADES_CHANNEL_CAPACITY);
+        let (tokens, _) = broadcast::channel(TOKENS_CHANNEL_CAPACITY);
+        Self {
+            db,
+            cache,
+            metrics,
+            retry,
+            hot,
+            activity,
+            batcher: None,
+            trades,
+            tokens,
+        }
+    }
+
+    /// Insert trades in batches of up to `max_size` trades, see [`TradeBatcher`].
+    /// Batch of a single trade means no batching.
+    pub fn with_batching(mut self, max_size: usize, max_delay: Duration) -> Self {
+        if max_size > 1 {
+            // Batcher writes through storage without batcher, so there's no cycle.
+            self.batcher = Some(TradeBatcher::start(self.clone(), max_size, max_delay));
+        }
+        self
+    }
+
+    /// Subscribe to trades inserted into storage.
+    pub fn subscribe_trades(&self) -> broadcast::Receiver<TradeInfo> {
+        self.trades.subscribe()
+    }
+
+    /// Subscribe to candle updates of the token with given resolution.
+    ///
+    /// Updated candle is sent after every ingested trade of the token. The first update
+    /// is read from storage, so it covers trades made before subscription; later ones are
+    /// folded from ingested trades. Updates are produced while the receiver is alive and
+    /// stop shortly after it's dropped. A receiver lagging behind more than
+    /// `CANDLES_CHANNEL_CAPACITY` updates skips the oldest ones and gets `RecvError::Lagged`.
+    pub fn subscribe_candles(
+        &self,
+        mint_acc: String,
+        resolution: Resolution,
+    ) -> broadcast::Receiver<TradeOhlcv> {
+        let (sender, receiver) = broadcast::channel(CANDLES_CHANNEL_CAPACITY);
+        let trades = self.subscribe_trades();
+        tokio::spawn(
+            self.clone()
+                .forward_candles(mint_acc, resolution, trades, sender),
+        );
+        receiver
+    }
+
+    /// Fold ingested trades of the token into candles and send them to subscribers.
+    async fn forward_candles(
+        self,
+        mint_acc: String,
+        resolution: Resolution,
+        mut trades: broadcast::Receiver<TradeInfo>,
+        candles: broadcast::Sender<TradeOhlcv>,
+    ) {
+        let mut last: Option<TradeOhlcv> = None;
+        loop {
+            let trade = match trades.recv().await {
+                Ok(trade) => trade,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Candles subscription skipped {skipped} trades.");
+                    // Folded candle missed trades, so reread it.
+                    last = None;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            // All receivers are dropped.
+            if candles.receiver_count() == 0 {
+                return;
+            }
+
+            if trade.mint_acc != mint_acc {
+                continue;
+            }
+
+            let candle = match &mut last {
+                Some(candle) => {
+                    let Ok(price) = trade.price() else {
+                        continue;
+                    };
+                    let timestamp =
+                        resolution.bucket_start(trade.timestamp).timestamp_millis() as u64;
+                    candle.apply_trade(timestamp, price, trade.token_amount as f64, trade.is_buy);
+                    *candle
+                }
+                None => {
+                    // Trade is broadcast after insertion, so stored candle includes it.
+                    let Ok((datetime, candle)) = self.last_trade(&mint_acc, resolution).await
+                    else {
+                        continue;
+                    };
+                    *last.insert(TradeOhlcv {
+                        timestamp: datetime.timestamp_millis() as u64,
+                        candle,
+                    })
+                }
+            };
+
+            // Receivers may be dropped meanwhile.
+            if candles.send(candle).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Register chart subscriber of the token, promoting the token to memory
+    /// if it's watched enough. Token may be demoted once the guard is dropped.
+    pub async fn watch_candles(&self, mint_acc: &str, resolution: Resolution) -> HotSubscriber {
+        let (subscriber, promote) = self.hot.subscribe(mint_acc, resolution);
+        if promote {
+            let since = HotCandles::load_start(resolution);
+            match self.trades_since(mint_acc, since, resolution).await {
+                Ok(candles) => self.hot.promote(mint_acc, resolution, since, candles),
+                Err(e) => tracing::warn!("Failed to load candles of hot token {mint_acc}: {e}"),
+            }
+        }
+        subscriber
+    }
+
+    /// Subscribe to new tokens and tokens with newly resolved metadata.
+    pub fn subscribe_tokens(&self) -> broadcast::Receiver<(String, TokenMetadata)> {
+        self.tokens.subscribe()
     }
 
     /// Get tokens list with metadata.
@@ -24,6 +224,50 @@ impl Storage {
         self.db.get_tokens().await
     }
 
+    /// Stream tokens list with metadata, without loading it into memory.
+    pub fn stream_tokens(
+        &self,
+    ) -> impl Stream<Item = anyhow::Result<(String, TokenMetadata)>> + Send + 'static {
+        self.db.stream_tokens()
+    }
+
+    /// Get tokens with metadata meeting activity thresholds.
+    /// `min_volume_24h` is checked against hourly candles, or the finest enabled ones
+    /// if hourly are disabled.
+    pub async fn get_tokens_filtered(
+        &self,
+        min_volume_24h: Option<f64>,
+        active_since: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<(String, TokenMetadata)>> {
+        let resolution = if Resolution::H1.is_enabled() {
+            Resolution::H1
+        } else {
+            Resolution::finest_enabled()
+        };
+        let volume_since = resolution.bucket_start(Utc::now() - Duration::from_secs(24 * 3600));
+        self.db
+            .get_tokens_filtered(min_volume_24h, resolution, volume_since, active_since)
+            .await
+    }
+
+    /// Search tokens by name, symbol or mint, most recently traded first.
+    /// Returns the page and total number of matching tokens.
+    pub async fn search_tokens(
+        &self,
+        query: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> anyhow::Result<(Vec<(String, TokenMetadata)>, u64)> {
+        // Coarsest candles have the fewest rows to find the last trades in.
+        let resolution = Resolution::enabled()
+            .last()
+            .copied()
+            .unwrap_or(Resolution::finest_enabled());
+        self.db
+            .search_tokens(query, resolution, limit, offset)
+            .await
+    }
+
     /// Read trades history.
     pub async fn trades_since(
         &self,
@@ -31,23 +275,166 @@ impl Storage {
         from_timestamp: DateTime<Utc>,
         resolution: Resolution,
     ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+        self.trades_since_sourced(mint_acc, from_timestamp, resolution)
+            .await
+            .map(|trades| trades.data)
+    }
+
+    /// Read trades history and report where it was read from.
+    pub async fn trades_since_sourced(
+        &self,
+        mint_acc: &str,
+        from_timestamp: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> anyhow::Result<Sourced<BTreeMap<DateTime<Utc>, Candle>>> {
+        if let Some(candles) = self.hot.candles_since(mint_acc, from_timestamp, resolution) {
+            self.metrics.memory_reads.fetch_add(1, Ordering::Relaxed);
+            return Ok(Sourced {
+                newest: candles.keys().next_back().copied(),
+                data: candles,
+                source: DataSource::Memory,
+            });
+        }
+
+        // Older part of the range is out of cache retention, it's read from DB
+        // and merged with the newer cached part.
         let cache_start = Utc::now() - cache::RETENTION_PERIOD;
-        if from_timestamp > cache_start {
-            match self
-                .cache
-                .trades_since(mint_acc, from_timestamp, resolution)
-                .await
-            {
-                Ok(cached) => return Ok(cached),
-                Err(e) => {
-                    tracing::error!("Failed to read trades from cache: {e}");
+        match self
+            .cache
+            .trades_since(mint_acc, from_timestamp.max(cache_start), resolution)
+            .await
+        {
+            Ok(cached) => {
+                self.metrics.cache_reads.fetch_add(1, Ordering::Relaxed);
+                let data = if from_timestamp < cache_start {
+                    let mut trades = self
+                        .db
+                        .trades_between(mint_acc, from_timestamp, cache_start, resolution, None)
+                        .await?;
+                    self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+                    // Cache takes precedence at the boundary.
+                    trades.extend(cached);
+                    trades
+                } else {
+                    cached
+                };
+                return Ok(Sourced {
+                    newest: data.keys().next_back().copied(),
+                    data,
+                    source: DataSource::Cache,
+                });
+            }
+            Err(e) => {
+                tracing::error!("Failed to read trades from cache: {e}");
+            }
+        };
+
+        let trades = self
+            .db
+            .trades_since(mint_acc, from_timestamp, resolution)
+            .await?;
+        self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+        if trades
+            .keys()
+            .next_back()
+            .is_some_and(|newest| *newest >= cache_start)
+        {
+            self.spawn_backfill(mint_acc, resolution, trades.clone());
+        }
+        Ok(Sourced {
+            newest: trades.keys().next_back().copied(),
+            data: trades,
+            source: DataSource::Db,
+        })
+    }
+
+    /// Write candles read from DB back into cache in background,
+    /// so following reads of the range are served by cache again.
+    fn spawn_backfill(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+        candles: BTreeMap<DateTime<Utc>, Candle>,
+    ) {
+        let cache = self.cache.clone();
+        let metrics = self.metrics.clone();
+        let mint_acc = mint_acc.to_string();
+        tokio::spawn(async move {
+            match cache.backfill(&mint_acc, resolution, &candles).await {
+                Ok(written) => {
+                    metrics
+                        .cache_backfilled_candles
+                        .fetch_add(written as u64, Ordering::Relaxed);
                 }
-            };
+                Err(e) => tracing::warn!("Failed to backfill cache of {mint_acc}: {e}"),
+            }
+        });
+    }
+
+    /// Read at most `limit` earliest candles starting within `[from, to]`.
+    /// Recent windows are read like [`Storage::trades_since`], older ones are bounded by DB query,
+    /// so long ranges aren't loaded whole.
+    pub async fn trades_between(
+        &self,
+        mint_acc: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+        limit: usize,
+    ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+        let cache_start = Utc::now() - cache::RETENTION_PERIOD;
+        if from > cache_start {
+            let candles = self.trades_since(mint_acc, from, resolution).await?;
+            return Ok(candles
+                .into_iter()
+                .take_while(|(datetime, _)| *datetime <= to)
+                .take(limit)
+                .collect());
         }
 
-        self.db
-            .trades_since(mint_acc, from_timestamp, resolution)
-            .await
+        let trades = self
+            .db
+            .trades_between(mint_acc, from, to, resolution, Some(limit))
+            .await?;
+        self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+        Ok(trades)
+    }
+
+    /// Read trades history for several resolutions from DB in one round trip.
+    pub async fn trades_since_multi(
+        &self,
+        mint_acc: &str,
+        from_timestamp: DateTime<Utc>,
+        resolutions: &[Resolution],
+    ) -> anyhow::Result<HashMap<Resolution, BTreeMap<DateTime<Utc>, Candle>>> {
+        let trades = self
+            .db
+            .trades_since_multi(mint_acc, from_timestamp, resolutions)
+            .await?;
+        self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+        Ok(trades)
+    }
+
+    /// Read the last `n` candles of the token from DB, however far back they span.
+    pub async fn last_n_candles(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+        n: usize,
+    ) -> anyhow::Result<BTreeMap<DateTime<Utc>, Candle>> {
+        let candles = self.db.last_n_candles(mint_acc, resolution, n).await?;
+        self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+        Ok(candles)
+    }
+
+    /// Starts of the first and the last candles of the token,
+    /// `None` if the token was never traded.
+    pub async fn candle_time_range(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+        self.db.candle_time_range(mint_acc, resolution).await
     }
 
     /// Read last trade of the token with given resolution.
@@ -57,48 +444,795 @@ impl Storage {
         mint_acc: &str,
         resolution: Resolution,
     ) -> anyhow::Result<(DateTime<Utc>, Candle)> {
+        self.last_trade_sourced(mint_acc, resolution)
+            .await
+            .map(|trade| trade.data)
+    }
+
+    /// Read last trade of the token and report where it was read from.
+    pub async fn last_trade_sourced(
+        &self,
+        mint_acc: &str,
+        resolution: Resolution,
+    ) -> anyhow::Result<Sourced<(DateTime<Utc>, Candle)>> {
+        if let Some(trade) = self.hot.last_candle(mint_acc, resolution) {
+            self.metrics.memory_reads.fetch_add(1, Ordering::Relaxed);
+            return Ok(Sourced {
+                newest: Some(trade.0),
+                data: trade,
+                source: DataSource::Memory,
+            });
+        }
+
         match self.cache.last_trade(mint_acc, resolution).await {
-            Ok(candle) => return Ok(candle),
+            Ok(trade) => {
+                self.metrics.cache_reads.fetch_add(1, Ordering::Relaxed);
+                return Ok(Sourced {
+                    newest: Some(trade.0),
+                    data: trade,
+                    source: DataSource::Cache,
+                });
+            }
             Err(e) => tracing::error!("Failed to read last trade from cache: {e}"),
         };
 
-        self.db.last_trade(mint_acc, resolution).await
+        let trade = self.db.last_trade(mint_acc, resolution).await?;
+        self.metrics.db_reads.fetch_add(1, Ordering::Relaxed);
+        Ok(Sourced {
+            newest: Some(trade.0),
+            data: trade,
+            source: DataSource::Db,
+        })
+    }
+
+    /// Read last candle of every token with given resolution.
+    pub async fn latest_candles(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>, Candle)>> {
+        self.db.latest_candles(resolution).await
+    }
+
+    /// Candle buckets of a trade at given time, see [`TokenActivity`].
+    pub fn trade_buckets(
+        &self,
+        mint_acc: &str,
+        datetime: DateTime<Utc>,
+    ) -> Vec<(Resolution, DateTime<Utc>)> {
+        self.activity.trade_buckets(mint_acc, datetime)
     }
 
-    /// Insert new trade.
-    /// Try to insert into cache and DB.
+    /// Insert new trade into given candle buckets.
+    /// Try to insert into cache and DB. Returns once the trade is written,
+    /// with batching enabled that is when its batch is.
+    /// Fails if DB insertion fails, cache failures are only logged.
     pub async fn insert_trade(
         &self,
-        timestamps: &[DateTime<Utc>],
+        buckets: &[(Resolution, DateTime<Utc>)],
         info: TradeInfo,
     ) -> anyhow::Result<()> {
+        let trade = (buckets.to_vec(), info);
+        match &self.batcher {
+            Some(batcher) => batcher.insert(trade).await,
+            None => self
+                .insert_trades(vec![trade])
+                .await
+                .pop()
+                .expect("result of every trade"),
+        }
+    }
+
+    /// Insert trades into their candle buckets with a single round trip to cache and DB each.
+    /// If DB insertion of the batch fails because of its trades, they are inserted one at a time,
+    /// so a bad trade doesn't fail the others. Failed trades are queued for retry.
+    /// Returns result of every trade in order.
+    /// Cache failures are only logged, DB is the authoritative store.
+    pub async fn insert_trades(&self, trades: Vec<BucketedTrade>) -> Vec<anyhow::Result<()>> {
         let (cache_result, db_result) = tokio::join!(
-            self.cache.insert_trade(timestamps, info.clone()),
-            self.db.insert_trade(timestamps, info)
+            self.cache.insert_trades(&trades),
+            self.db.insert_trades(&trades)
         );
 
         if let Err(e) = cache_result {
+            tracing::error!("Failed to insert {} trades into cache: {e}", trades.len());
+        }
+
+        let db_results = match db_result {
+            Ok(new_candles) => {
+                self.metrics
+                    .new_candles
+                    .fetch_add(new_candles as u64, Ordering::Relaxed);
+                trades.iter().map(|_| Ok(())).collect()
+            }
+            Err(e) if trades.len() > 1 && is_trade_specific(&e) => {
+                tracing::warn!(
+                    "Failed to insert {} trades into db, inserting one at a time: {e}",
+                    trades.len()
+                );
+                let mut results = Vec::with_capacity(trades.len());
+                for trade in &trades {
+                    let result = self.db.insert_trades(std::slice::from_ref(trade)).await;
+                    match &result {
+                        Ok(new_candles) => {
+                            self.metrics
+                                .new_candles
+                                .fetch_add(*new_candles as u64, Ordering::Relaxed);
+                        }
+                        Err(e) => tracing::error!(
+                            "Failed to insert trade of {} into db: {e}",
+                            trade.1.mint_acc
+                        ),
+                    }
+                    results.push(result.map(|_| ()));
+                }
+                results
+            }
+            Err(e) => {
+                tracing::error!("Failed to insert {} trades into db: {e}", trades.len());
+                // Trades are failed by DB rather than by themselves, so every one gets the error.
+                let e = format!("{e:#}");
+                trades.iter().map(|_| Err(anyhow::anyhow!("{e}"))).collect()
+            }
+        };
+
+        let mut results = Vec::with_capacity(trades.len());
+        for ((_, info), result) in trades.iter().zip(db_results) {
+            results.push(match result {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    let queued = if self.queue_retry(info).await {
+                        "queued"
+                    } else {
+                        "not queued"
+                    };
+                    Err(e.context(format!(
+                        "Failed to insert trade into db, {queued} for retry"
+                    )))
+                }
+            });
+        }
+
+        // Trades are in cache, so subscribers get them even if DB insertion failed.
+        for (_, info) in trades {
+            notify(&self.trades, info);
+        }
+
+        results
+    }
+
+    /// Insert first trade of a new token with its metadata into given candle buckets.
+    /// Token and trade are inserted into DB atomically.
+    /// Fails if DB insertion fails, cache failures are only logged.
+    pub async fn insert_first_trade_with_metadata(
+        &self,
+        buckets: &[(Resolution, DateTime<Utc>)],
+        info: TradeInfo,
+        metadata: Option<TokenMetadata>,
+    ) -> anyhow::Result<()> {
+        let token = (
+            info.mint_acc.clone(),
+            metadata.clone().unwrap_or_else(TokenMetadata::unresolved),
+        );
+        // Cache is written after DB, so the trade is priced with decimals of the stored token.
+        let db_result = self
+            .db
+            .insert_first_trade_with_metadata(buckets, info.clone(), metadata)
+            .await;
+
+        let (info, result) = match db_result {
+            Ok(write) => {
+                self.metrics
+                    .new_candles
+                    .fetch_add(write.new_candles as u64, Ordering::Relaxed);
+                // Token may be inserted concurrently by its create event.
+                match write.token {
+                    TokenWrite::Inserted => self.announce_new_token(token),
+                    TokenWrite::Rescaled => self.forget_candles(&info.mint_acc).await,
+                    TokenWrite::Updated | TokenWrite::Kept => {}
+                }
+                if write.decimals != info.decimals {
+                    tracing::warn!(
+                        "Decimals of {} are {:?}, not {:?} as fetched, trade is priced with stored ones.",
+                        info.mint_acc,
+                        write.decimals,
+                        info.decimals
+                    );
+                }
+                let info = TradeInfo {
+                    decimals: write.decimals,
+                    ..info
+                };
+                (info, Ok(()))
+            }
+            Err(e) => {
+                tracing::error!("Failed to insert first trade into db: {e}");
+                let context = if self.queue_retry(&info).await {
+                    "Failed to insert first trade into db, queued for retry"
+                } else {
+                    "Failed to insert first trade into db, not queued for retry"
+                };
+                (info, Err(e.context(context)))
+            }
+        };
+
+        let trade = [(buckets.to_vec(), info.clone())];
+        if let Err(e) = self.cache.insert_trades(&trade).await {
             tracing::error!("Failed to insert trade into cache: {e}");
         }
 
-        if let Err(e) = db_result {
-            tracing::error!("Failed to insert trade into db: {e}");
+        notify(&self.trades, info);
+
+        result
+    }
+
+    /// Queue trade failed to be inserted into DB for retry.
+    /// Returns whether it was queued.
+    async fn queue_retry(&self, info: &TradeInfo) -> bool {
+        match self.retry.push(info).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::error!("Failed to queue trade for retry: {e}");
+                false
+            }
         }
+    }
 
+    /// Replay trades failed to be inserted into DB.
+    /// Returns number of replayed trades.
+    pub async fn replay_pending_trades(&self) -> anyhow::Result<usize> {
+        self.retry.drain(|info| self.replay_trade(info)).await
+    }
+
+    /// Periodically replay trades failed to be inserted into DB.
+    pub async fn run_retries(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            match self.replay_pending_trades().await {
+                Ok(0) => {}
+                Ok(replayed) => tracing::info!("Replayed {replayed} pending trades into DB."),
+                Err(e) => tracing::warn!("Failed to replay pending trades: {e:#}"),
+            }
+        }
+    }
+
+    /// Insert pending trade into DB.
+    /// Cache and trades subscribers got the trade when it was ingested.
+    /// Candles of all enabled resolutions are written, regardless of token activity.
+    /// Token may be missing if its first trade failed, so it's inserted without metadata
+    /// to be resolved by backfill.
+    async fn replay_trade(&self, info: TradeInfo) -> anyhow::Result<()> {
+        let buckets: Vec<_> = Resolution::enabled()
+            .into_iter()
+            .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)))
+            .collect();
+        let mint_acc = info.mint_acc.clone();
+        let write = self
+            .db
+            .insert_first_trade_with_metadata(&buckets, info, None)
+            .await?;
+
+        self.metrics
+            .new_candles
+            .fetch_add(write.new_candles as u64, Ordering::Relaxed);
+        if write.token == TokenWrite::Inserted {
+            self.announce_new_token((mint_acc, TokenMetadata::unresolved()));
+        }
         Ok(())
     }
 
+    /// Get mints of tokens traded since timestamp.
+    pub async fn active_mints(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<String>> {
+        self.db.active_mints(since).await
+    }
+
+    /// Get tokens ranked by net buy pressure since timestamp, highest first.
+    pub async fn tokens_by_pressure(
+        &self,
+        from_timestamp: DateTime<Utc>,
+        resolution: Resolution,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(String, TokenMetadata, TokenPressure)>> {
+        self.db
+            .tokens_by_pressure(from_timestamp, resolution, limit)
+            .await
+    }
+
+    /// Delete token with all its trades from DB and cache.
+    /// Returns number of deleted DB trades rows.
+    pub async fn delete_token(&self, mint_acc: &str) -> anyhow::Result<u64> {
+        let trades = self.db.delete_token(mint_acc).await?;
+        // Cached series expire anyway, so failure to drop them is not fatal.
+        if let Err(e) = self.cache.drop_token_series(mint_acc).await {
+            tracing::error!("Failed to drop series of deleted token {mint_acc}: {e}");
+        }
+        Ok(trades)
+    }
+
+    /// Drop candles of the token held in cache and memory, after its stored ones are rescaled.
+    /// Cache is refilled from DB on reads.
+    async fn forget_candles(&self, mint_acc: &str) {
+        self.hot.demote_token(mint_acc);
+        if let Err(e) = self.cache.drop_token_series(mint_acc).await {
+            tracing::error!("Failed to drop series of rescaled token {mint_acc}: {e}");
+        }
+    }
+
+    /// Delete all cached series of the token.
+    pub async fn drop_token_series(&self, mint_acc: &str) -> anyhow::Result<usize> {
+        self.cache.drop_token_series(mint_acc).await
+    }
+
+    /// Load token retention overrides from DB into cache.
+    /// Returns number of overrides.
+    pub async fn load_token_retentions(&self) -> anyhow::Result<usize> {
+        let retentions = self.db.token_retentions().await?;
+        let count = retentions.len();
+        self.cache.load_retentions(retentions);
+        Ok(count)
+    }
+
+    /// Set retention of token data overriding the global one.
+    /// Returns number of altered cached series or `NotFound` error if token is unknown.
+    pub async fn set_token_retention(
+        &self,
+        mint_acc: &str,
+        retention: Duration,
+    ) -> anyhow::Result<usize> {
+        self.db.set_token_retention(mint_acc, retention).await?;
+        self.cache.set_token_retention(mint_acc, retention).await
+    }
+
     /// Get token metadata.
     pub async fn get_token_metadata(&self, mint_acc: &str) -> anyhow::Result<TokenMetadata> {
         self.db.get_token(mint_acc).await
     }
 
+    /// Version of the latest applied DB migration, if any.
+    pub async fn migration_version(&self) -> anyhow::Result<Option<i64>> {
+        self.db.migration_version().await
+    }
+
+    /// Whether token is stored.
+    pub async fn token_exists(&self, mint_acc: &str) -> anyhow::Result<bool> {
+        self.db.token_exists(mint_acc).await
+    }
+
+    /// Get mints of tokens with unresolved metadata, ordered by mint.
+    pub async fn get_unresolved_tokens(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        self.db.get_unresolved_tokens(after, limit).await
+    }
+
     /// Insert token metadata.
+    /// New tokens and resolved metadata are announced to tokens subscribers.
     pub async fn insert_token_metadata(
         &self,
         mint_acc: String,
         metadata: Option<TokenMetadata>,
     ) -> anyhow::Result<()> {
-        self.db.insert_token(mint_acc, metadata).await
+        let write = self
+            .db
+            .insert_token(mint_acc.clone(), metadata.clone())
+            .await?;
+
+        match (write, metadata) {
+            (TokenWrite::Inserted, metadata) => {
+                self.activity.record_create(&mint_acc);
+                let metadata = metadata.unwrap_or_else(TokenMetadata::unresolved);
+                self.announce_new_token((mint_acc, metadata));
+            }
+            (TokenWrite::Updated, Some(metadata)) => {
+                notify(&self.tokens, (mint_acc, metadata));
+            }
+            (TokenWrite::Rescaled, Some(metadata)) => {
+                self.forget_candles(&mint_acc).await;
+                notify(&self.tokens, (mint_acc, metadata));
+            }
+            // Subscribers already have better metadata.
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Check DB and cache are reachable within timeout, returns results of both checks.
+    pub async fn ping(&self, timeout: Duration) -> (anyhow::Result<()>, anyhow::Result<()>) {
+        tokio::join!(
+            check_within(timeout, self.db.ping()),
+            check_within(timeout, self.cache.ping())
+        )
+    }
+
+    /// Mark token bonding curve completed and announce it to tokens subscribers.
+    /// Returns `NotFound` error if token is unknown.
+    pub async fn mark_completed(
+        &self,
+        mint_acc: String,
+        completed_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let metadata = self.db.mark_completed(&mint_acc, completed_at).await?;
+        notify(&self.tokens, (mint_acc, metadata));
+        Ok(())
+    }
+
+    /// Count newly stored token and announce it to tokens subscribers.
+    fn announce_new_token(&self, token: (String, TokenMetadata)) {
+        self.metrics.new_tokens.fetch_add(1, Ordering::Relaxed);
+        notify(&self.tokens, token);
+    }
+}
+
+/// Broadcast value to subscribers.
+fn notify<T>(sender: &broadcast::Sender<T>, value: T) {
+    // No subscribers is not an error.
+    let _ = sender.send(value);
+}
+
+/// Run check, failing it if it takes longer than timeout.
+async fn check_within(
+    timeout: Duration,
+    check: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out after {timeout:?}")))
+}
+
+/// Whether DB insertion failure may be caused by the inserted trades,
+/// rather than by DB being unreachable, so inserting them one by one can succeed.
+fn is_trade_specific(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(_)) | None => true,
+        Some(_) => false,
+    }
+}
+
+#[cfg(test)]
+impl Storage {
+    /// Storage over given DB pool and Redis URL with a retry queue in a fresh temporary file.
+    pub(crate) async fn for_tests(pool: sqlx::PgPool, redis_url: &str, hot: HotCandles) -> Self {
+        static QUEUES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let metrics = Arc::new(Metrics::default());
+        let db = Db::from_pool(pool, metrics.clone(), Duration::from_secs(10));
+        let cache = Cache::open(redis_url).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "pumpfun_indexer_retry_{}_{}.jsonl",
+            std::process::id(),
+            QUEUES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let retry = RetryQueue::open(path, 100, metrics.clone()).await.unwrap();
+        Self::new(db, cache, metrics, retry, hot, TokenActivity::new(None)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreachable_db_is_not_trade_specific() {
+        assert!(!is_trade_specific(&sqlx::Error::PoolTimedOut.into()));
+        let e = anyhow::Error::from(sqlx::Error::PoolClosed).context("Failed to insert");
+        assert!(!is_trade_specific(&e));
+    }
+
+    #[test]
+    fn bad_trade_is_trade_specific() {
+        assert!(is_trade_specific(&anyhow::anyhow!("Bad price: 1 / 0")));
+    }
+
+    const MINT: &str = "5xYzTokenMintAddressForStorageTestsPump";
+
+    /// Port nothing listens on, so cache operations fail.
+    const UNREACHABLE_REDIS: &str = "redis://127.0.0.1:1";
+
+    fn trade(secs: i64) -> TradeInfo {
+        TradeInfo {
+            mint_acc: MINT.to_string(),
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            sol_amount: 1_000,
+            token_amount: 2_000,
+            is_buy: true,
+            decimals: None,
+        }
+    }
+
+    /// Trade of the current minute bucketed into its M1 candle.
+    fn recent_trade() -> BucketedTrade {
+        let info = trade(Utc::now().timestamp());
+        let bucket = Resolution::M1.bucket_start(info.timestamp);
+        (vec![(Resolution::M1, bucket)], info)
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn source_flips_to_db_without_cache(pool: sqlx::PgPool) {
+        let hot = HotCandles::new(Some(1), 10);
+        let storage = Storage::for_tests(pool, UNREACHABLE_REDIS, hot.clone()).await;
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+        storage.db.insert_trades(&[recent_trade()]).await.unwrap();
+
+        let since = HotCandles::load_start(Resolution::M1);
+        let trades = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(trades.source, DataSource::Db);
+        assert_eq!(trades.data.len(), 1);
+        let last = storage
+            .last_trade_sourced(MINT, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(last.source, DataSource::Db);
+        assert_eq!(Some(last.data.0), trades.newest);
+
+        // Promoted token is served by memory, bypassing both cache and DB.
+        let (_subscriber, promote) = hot.subscribe(MINT, Resolution::M1);
+        assert!(promote);
+        hot.promote(MINT, Resolution::M1, since, trades.data);
+        let trades = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(trades.source, DataSource::Memory);
+        assert_eq!(trades.data.len(), 1);
+        let last = storage
+            .last_trade_sourced(MINT, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(last.source, DataSource::Memory);
+    }
+
+    async fn next_candle(candles: &mut broadcast::Receiver<TradeOhlcv>) -> TradeOhlcv {
+        tokio::time::timeout(Duration::from_secs(5), candles.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn inserted_trades_are_received_as_candles(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(pool, UNREACHABLE_REDIS, HotCandles::new(None, 0)).await;
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+        let mut candles = storage.subscribe_candles(MINT.to_string(), Resolution::M1);
+
+        let (buckets, info) = recent_trade();
+        let bucket = buckets[0].1.timestamp_millis() as u64;
+        storage.insert_trade(&buckets, info.clone()).await.unwrap();
+        let first = next_candle(&mut candles).await;
+        assert_eq!(first.timestamp, bucket);
+        assert_eq!(first.candle.close, info.price().unwrap());
+
+        // Sell of twice the tokens halves the price.
+        let sell = TradeInfo {
+            token_amount: 4_000,
+            is_buy: false,
+            ..info
+        };
+        storage.insert_trade(&buckets, sell.clone()).await.unwrap();
+        let second = next_candle(&mut candles).await;
+        assert_eq!(second.timestamp, bucket);
+        assert_eq!(second.candle.close, sell.price().unwrap());
+        assert_eq!(second.candle.open, first.candle.open);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn promoted_token_is_read_without_io(pool: sqlx::PgPool) {
+        let hot = HotCandles::new(Some(1), 10);
+        let storage = Storage::for_tests(pool.clone(), UNREACHABLE_REDIS, hot.clone()).await;
+        let (_subscriber, promote) = hot.subscribe(MINT, Resolution::M1);
+        assert!(promote);
+        let (buckets, info) = recent_trade();
+        let since = HotCandles::load_start(Resolution::M1);
+        let candles = BTreeMap::from([(buckets[0].1, Candle::flat(info.price().unwrap()))]);
+        hot.promote(MINT, Resolution::M1, since, candles);
+
+        // Neither cache nor DB can be read.
+        pool.close().await;
+        let trades = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(trades.source, DataSource::Memory);
+        assert_eq!(trades.data.len(), 1);
+        let last = storage
+            .last_trade_sourced(MINT, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(last.source, DataSource::Memory);
+        assert_eq!(last.data.0, buckets[0].1);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn cold_cache_is_backfilled_on_read(pool: sqlx::PgPool) {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let storage = Storage::for_tests(pool, &url, HotCandles::new(None, 0)).await;
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+        // Written to DB only, as if cache lost it.
+        storage.db.insert_trades(&[recent_trade()]).await.unwrap();
+
+        let since = Utc::now() - Duration::from_secs(3600);
+        let cold = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(cold.source, DataSource::Db);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while storage
+                .metrics
+                .cache_backfilled_candles
+                .load(Ordering::Relaxed)
+                == 0
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cache is backfilled in time");
+
+        let warm = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        assert_eq!(warm.source, DataSource::Cache);
+        let summary = |candles: &BTreeMap<DateTime<Utc>, Candle>| -> Vec<_> {
+            candles
+                .iter()
+                .map(|(datetime, candle)| (*datetime, candle.close, candle.trade_count))
+                .collect()
+        };
+        assert_eq!(summary(&warm.data), summary(&cold.data));
+    }
+
+    /// Trade of two days ago, out of cache retention, and of the current minute,
+    /// bucketed into their M1 candles.
+    fn trades_across_retention() -> [BucketedTrade; 2] {
+        let old = trade(Utc::now().timestamp() - 2 * 86_400);
+        let old_bucket = Resolution::M1.bucket_start(old.timestamp);
+        [(vec![(Resolution::M1, old_bucket)], old), recent_trade()]
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn range_across_retention_is_read_from_db_without_cache(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(pool, UNREACHABLE_REDIS, HotCandles::new(None, 0)).await;
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+        let trades = trades_across_retention();
+        storage.db.insert_trades(&trades).await.unwrap();
+
+        let since = Utc::now() - Duration::from_secs(3 * 86_400);
+        let candles = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        assert_eq!(candles.source, DataSource::Db);
+        let buckets: Vec<_> = candles.data.keys().copied().collect();
+        assert_eq!(buckets, trades.map(|(buckets, _)| buckets[0].1));
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn range_across_retention_merges_db_and_cache(pool: sqlx::PgPool) {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let storage = Storage::for_tests(pool, &url, HotCandles::new(None, 0)).await;
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+        let [old, recent] = trades_across_retention();
+        storage
+            .db
+            .insert_trades(std::slice::from_ref(&old))
+            .await
+            .unwrap();
+        let (buckets, info) = recent.clone();
+        storage.insert_trade(&buckets, info).await.unwrap();
+        // Cache only trade, so cached candle differs from the DB one.
+        storage
+            .cache
+            .insert_trades(std::slice::from_ref(&recent))
+            .await
+            .unwrap();
+
+        let since = Utc::now() - Duration::from_secs(3 * 86_400);
+        let candles = storage
+            .trades_since_sourced(MINT, since, Resolution::M1)
+            .await
+            .unwrap();
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        assert_eq!(candles.source, DataSource::Cache);
+        let candles: Vec<_> = candles
+            .data
+            .into_iter()
+            .map(|(datetime, candle)| (datetime, candle.trade_count))
+            .collect();
+        // Cached candle takes precedence over the DB one.
+        assert_eq!(candles, [(old.0[0].1, 1), (recent.0[0].1, 2)]);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn cache_failure_is_tolerated(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(pool, UNREACHABLE_REDIS, HotCandles::new(None, 0)).await;
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+
+        let (buckets, info) = recent_trade();
+        storage.insert_trade(&buckets, info).await.unwrap();
+        let (_, candle) = storage.db.last_trade(MINT, Resolution::M1).await.unwrap();
+        assert_eq!(candle.trade_count, 1);
+        assert_eq!(
+            storage.metrics.retry_queued_trades.load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn db_failure_is_returned(pool: sqlx::PgPool) {
+        let storage =
+            Storage::for_tests(pool.clone(), UNREACHABLE_REDIS, HotCandles::new(None, 0)).await;
+        pool.close().await;
+
+        let (buckets, info) = recent_trade();
+        let e = storage.insert_trade(&buckets, info).await.unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("Failed to insert trade into db, queued for retry"),
+            "{e}"
+        );
+        assert_eq!(
+            storage.metrics.retry_queued_trades.load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    #[ignore = "needs Redis with TimeSeries module at REDIS_URL"]
+    async fn trade_is_inserted_into_db_and_cache(pool: sqlx::PgPool) {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is set");
+        let storage = Storage::for_tests(pool, &url, HotCandles::new(None, 0)).await;
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        storage
+            .db
+            .insert_token(MINT.to_string(), None)
+            .await
+            .unwrap();
+
+        let (buckets, info) = recent_trade();
+        storage.insert_trade(&buckets, info).await.unwrap();
+        let (_, db_candle) = storage.db.last_trade(MINT, Resolution::M1).await.unwrap();
+        let cached = storage.cache.last_trade(MINT, Resolution::M1).await;
+        storage.cache.drop_token_series(MINT).await.unwrap();
+        let (_, cached_candle) = cached.unwrap();
+        assert_eq!(db_candle.trade_count, 1);
+        assert_eq!(cached_candle.trade_count, 1);
+        assert_eq!(cached_candle.close, db_candle.close);
     }
 }
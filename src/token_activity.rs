@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::model::Resolution;
+
+/// Candle buckets written for trades depending on token activity.
+///
+/// S1 candles are the most expensive to write and rarely needed for the long tail of dead
+/// tokens, so with an activity window they're written only for tokens traded or created
+/// within the window before the trade. Coarser resolutions are always written.
+/// Activity is tracked in memory, so after restart S1 resumes from the second trade.
+#[derive(Clone)]
+pub struct TokenActivity {
+    window: Option<Duration>,
+    last_seen: Arc<DashMap<String, Instant>>,
+}
+
+impl TokenActivity {
+    /// Create new activity tracking, S1 candles are always written without window.
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            last_seen: Default::default(),
+        }
+    }
+
+    /// Record token creation, so its first trades get S1 candles.
+    pub fn record_create(&self, mint_acc: &str) {
+        if self.window.is_some() {
+            self.last_seen.insert(mint_acc.to_string(), Instant::now());
+        }
+    }
+
+    /// Record trade of a token at given time.
+    /// Returns starts of its candles for enabled resolutions it's active enough for.
+    pub fn trade_buckets(
+        &self,
+        mint_acc: &str,
+        datetime: DateTime<Utc>,
+    ) -> Vec<(Resolution, DateTime<Utc>)> {
+        self.buckets_of(mint_acc, datetime, Resolution::enabled())
+    }
+
+    /// Record trade of a token, returning starts of its candles among given resolutions.
+    fn buckets_of(
+        &self,
+        mint_acc: &str,
+        datetime: DateTime<Utc>,
+        resolutions: Vec<Resolution>,
+    ) -> Vec<(Resolution, DateTime<Utc>)> {
+        let active = match self.window {
+            Some(window) => self
+                .last_seen
+                .insert(mint_acc.to_string(), Instant::now())
+                .is_some_and(|last_seen| last_seen.elapsed() <= window),
+            None => true,
+        };
+
+        resolutions
+            .into_iter()
+            .filter(|resolution| active || *resolution != Resolution::S1)
+            .map(|resolution| (resolution, resolution.bucket_start(datetime)))
+            .collect()
+    }
+
+    /// Forget tokens inactive for longer than the window periodically,
+    /// so memory is bounded by recently active tokens.
+    pub async fn run(self) {
+        let Some(window) = self.window else {
+            return;
+        };
+
+        // Interval panics on zero period.
+        let mut interval = tokio::time::interval(window.max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            self.last_seen
+                .retain(|_, last_seen| last_seen.elapsed() <= window);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "So11111111111111111111111111111111111111112";
+
+    fn resolutions(buckets: &[(Resolution, DateTime<Utc>)]) -> Vec<Resolution> {
+        buckets.iter().map(|(resolution, _)| *resolution).collect()
+    }
+
+    #[test]
+    fn disabled_resolutions_are_not_written() {
+        let activity = TokenActivity::new(None);
+        let datetime = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let enabled = vec![Resolution::M1, Resolution::H1];
+
+        let buckets = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&buckets), enabled);
+        assert_eq!(
+            buckets[0].1,
+            DateTime::from_timestamp(1_699_999_980, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn seconds_are_written_only_for_active_tokens() {
+        let activity = TokenActivity::new(Some(Duration::from_secs(60)));
+        let datetime = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let enabled = vec![Resolution::S1, Resolution::M1];
+
+        // Unknown token is not active yet.
+        let first = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&first), [Resolution::M1]);
+        let second = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&second), enabled);
+
+        // Created token is active from the first trade.
+        let created = "11111111111111111111111111111111";
+        activity.record_create(created);
+        let buckets = activity.buckets_of(created, datetime, enabled.clone());
+        assert_eq!(resolutions(&buckets), enabled);
+    }
+
+    #[test]
+    fn seconds_stop_for_inactive_token_and_resume() {
+        let activity = TokenActivity::new(Some(Duration::from_millis(50)));
+        let datetime = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let enabled = vec![Resolution::S1, Resolution::M1];
+        activity.record_create(MINT);
+        let buckets = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&buckets), enabled);
+
+        std::thread::sleep(Duration::from_millis(80));
+        let inactive = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&inactive), [Resolution::M1]);
+        // The trade renews activity.
+        let renewed = activity.buckets_of(MINT, datetime, enabled.clone());
+        assert_eq!(resolutions(&renewed), enabled);
+    }
+
+    #[tokio::test]
+    async fn inactive_tokens_are_forgotten() {
+        let activity = TokenActivity::new(Some(Duration::from_millis(50)));
+        activity.record_create(MINT);
+        tokio::spawn(activity.clone().run());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(activity.last_seen.contains_key(MINT));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while activity.last_seen.contains_key(MINT) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("inactive token is forgotten");
+    }
+
+    #[test]
+    fn activity_is_not_tracked_without_window() {
+        let activity = TokenActivity::new(None);
+        activity.record_create(MINT);
+        let datetime = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        activity.buckets_of(MINT, datetime, vec![Resolution::S1]);
+        assert!(activity.last_seen.is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::model::TokenMetadata;
+use crate::storage::Storage;
+
+/// Tokens list with metadata.
+pub type TokensList = Arc<Vec<(String, TokenMetadata)>>;
+
+/// Short-living snapshot of the tokens list, so repeated list requests don't scan the table.
+/// Snapshot is dropped when a token is added or its metadata is resolved, and expires
+/// after TTL anyway.
+#[derive(Clone)]
+pub struct TokensCache {
+    ttl: Duration,
+    snapshot: Arc<Mutex<Option<(Instant, TokensList)>>>,
+}
+
+impl TokensCache {
+    /// Create new empty cache.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            snapshot: Default::default(),
+        }
+    }
+
+    /// Get tokens list with its age, loading it from storage if there is no fresh snapshot.
+    /// Concurrent requests wait for a single load.
+    pub async fn get(&self, storage: &Storage) -> anyhow::Result<(TokensList, Duration)> {
+        let mut snapshot = self.snapshot.lock().await;
+        if let Some((loaded_at, tokens)) = &*snapshot
+            && loaded_at.elapsed() < self.ttl
+        {
+            return Ok((tokens.clone(), loaded_at.elapsed()));
+        }
+
+        let tokens = Arc::new(storage.get_tokens().await?);
+        *snapshot = Some((Instant::now(), tokens.clone()));
+        Ok((tokens, Duration::ZERO))
+    }
+
+    /// Cache TTL.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Drop snapshot on new and updated tokens.
+    pub async fn run(self, mut tokens: broadcast::Receiver<(String, TokenMetadata)>) {
+        // Skipped tokens invalidate snapshot as well.
+        while let Ok(_) | Err(RecvError::Lagged(_)) = tokens.recv().await {
+            *self.snapshot.lock().await = None;
+        }
+
+        tracing::error!("Tokens cache invalidation stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hot_candles::HotCandles;
+
+    /// Insert token behind storage back, so only DB reads can see it.
+    async fn insert_unannounced(pool: &sqlx::PgPool, mint: &str) {
+        sqlx::query("INSERT INTO token (mint) VALUES ($1)")
+            .bind(mint)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn mints(tokens: &TokensList) -> Vec<&str> {
+        let mut mints: Vec<_> = tokens.iter().map(|(mint, _)| mint.as_str()).collect();
+        mints.sort_unstable();
+        mints
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn rapid_requests_read_db_once(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(
+            pool.clone(),
+            "redis://127.0.0.1:1",
+            HotCandles::new(None, 0),
+        )
+        .await;
+        let cache = TokensCache::new(Duration::from_secs(60));
+        insert_unannounced(&pool, "first").await;
+
+        let (tokens, age) = cache.get(&storage).await.unwrap();
+        assert_eq!(mints(&tokens), ["first"]);
+        assert_eq!(age, Duration::ZERO);
+
+        insert_unannounced(&pool, "second").await;
+        let (cached, _) = cache.get(&storage).await.unwrap();
+        assert!(Arc::ptr_eq(&tokens, &cached));
+        assert_eq!(mints(&cached), ["first"]);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn new_token_busts_snapshot(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(
+            pool.clone(),
+            "redis://127.0.0.1:1",
+            HotCandles::new(None, 0),
+        )
+        .await;
+        let cache = TokensCache::new(Duration::from_secs(60));
+        tokio::spawn(cache.clone().run(storage.subscribe_tokens()));
+        insert_unannounced(&pool, "first").await;
+        let (tokens, _) = cache.get(&storage).await.unwrap();
+        assert_eq!(mints(&tokens), ["first"]);
+
+        storage
+            .insert_token_metadata(String::from("second"), None)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while cache.get(&storage).await.unwrap().0.len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("snapshot is dropped on new token");
+        let (tokens, _) = cache.get(&storage).await.unwrap();
+        assert_eq!(mints(&tokens), ["first", "second"]);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn snapshot_expires_after_ttl(pool: sqlx::PgPool) {
+        let storage = Storage::for_tests(
+            pool.clone(),
+            "redis://127.0.0.1:1",
+            HotCandles::new(None, 0),
+        )
+        .await;
+        let cache = TokensCache::new(Duration::from_millis(50));
+        insert_unannounced(&pool, "first").await;
+        cache.get(&storage).await.unwrap();
+
+        insert_unannounced(&pool, "second").await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (tokens, age) = cache.get(&storage).await.unwrap();
+        assert_eq!(mints(&tokens), ["first", "second"]);
+        assert_eq!(age, Duration::ZERO);
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::model::BucketedTrade;
+use crate::storage::Storage;
+
+/// Trade waiting for its batch to be written.
+struct PendingTrade {
+    trade: BucketedTrade,
+    /// Receives error message of the trade insertion, if it failed.
+    written: oneshot::Sender<Result<(), String>>,
+}
+
+/// Collects trades into batches written to DB and cache in a single round trip each.
+///
+/// A batch is written once it has `max_size` trades or `max_delay` after its first trade,
+/// whichever comes first. Batches are written one at a time, trades arriving meanwhile
+/// make up the next one, so batches grow with load.
+#[derive(Clone)]
+pub struct TradeBatcher {
+    sender: mpsc::Sender<PendingTrade>,
+}
+
+impl TradeBatcher {
+    /// Start writing batches into storage.
+    pub fn start(storage: Storage, max_size: usize, max_delay: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel(max_size);
+        tokio::spawn(Self::run(storage, receiver, max_size, max_delay));
+        Self { sender }
+    }
+
+    /// Add trade to the current batch and wait until the batch is written.
+    /// Fails if the trade failed to be inserted into DB.
+    pub async fn insert(&self, trade: BucketedTrade) -> anyhow::Result<()> {
+        let (written, wait) = oneshot::channel();
+        self.sender
+            .send(PendingTrade { trade, written })
+            .await
+            .map_err(|_| anyhow::anyhow!("Trade batcher stopped"))?;
+        wait.await
+            .map_err(|_| anyhow::anyhow!("Trade batcher stopped"))?
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Collect and write batches until all batchers are dropped.
+    async fn run(
+        storage: Storage,
+        mut receiver: mpsc::Receiver<PendingTrade>,
+        max_size: usize,
+        max_delay: Duration,
+    ) {
+        let mut batch = Vec::with_capacity(max_size);
+        while collect_batch(&mut receiver, &mut batch, max_size, max_delay).await {
+            let (trades, written): (Vec<_>, Vec<_>) = batch
+                .drain(..)
+                .map(|pending| (pending.trade, pending.written))
+                .unzip();
+            // Every trade gets its own result, so only handlers of failed ones report errors.
+            let results = storage.insert_trades(trades).await;
+            for (written, result) in written.into_iter().zip(results) {
+                // Waiter may be gone, e.g. its handler was cancelled.
+                let _ = written.send(result.map_err(|e| format!("{e:#}")));
+            }
+        }
+    }
+}
+
+/// Wait for the first item, then collect items into the batch until it has `max_size` ones
+/// or `max_delay` passes. Returns `false` if all senders are dropped and nothing is left.
+async fn collect_batch<T>(
+    receiver: &mut mpsc::Receiver<T>,
+    batch: &mut Vec<T>,
+    max_size: usize,
+    max_delay: Duration,
+) -> bool {
+    if receiver.recv_many(batch, max_size).await == 0 {
+        return false;
+    }
+
+    let deadline = tokio::time::sleep(max_delay);
+    tokio::pin!(deadline);
+    while batch.len() < max_size {
+        let limit = max_size - batch.len();
+        tokio::select! {
+            received = receiver.recv_many(batch, limit) => {
+                if received == 0 {
+                    break;
+                }
+            }
+            _ = &mut deadline => break,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_is_flushed_when_full() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        for i in 0..5 {
+            sender.send(i).await.unwrap();
+        }
+
+        let mut batch = Vec::new();
+        let start = tokio::time::Instant::now();
+        assert!(collect_batch(&mut receiver, &mut batch, 3, Duration::from_secs(1)).await);
+        assert_eq!(batch, [0, 1, 2]);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        batch.clear();
+        assert!(collect_batch(&mut receiver, &mut batch, 3, Duration::from_secs(1)).await);
+        assert_eq!(batch, [3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_is_flushed_after_delay() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let max_delay = Duration::from_millis(100);
+        tokio::spawn(async move {
+            sender.send(0).await.unwrap();
+            tokio::time::sleep(max_delay / 2).await;
+            sender.send(1).await.unwrap();
+            tokio::time::sleep(max_delay).await;
+            sender.send(2).await.unwrap();
+        });
+
+        let mut batch = Vec::new();
+        let start = tokio::time::Instant::now();
+        assert!(collect_batch(&mut receiver, &mut batch, 10, max_delay).await);
+        assert_eq!(batch, [0, 1]);
+        assert_eq!(start.elapsed(), max_delay);
+
+        batch.clear();
+        assert!(collect_batch(&mut receiver, &mut batch, 10, max_delay).await);
+        assert_eq!(batch, [2]);
+    }
+
+    #[tokio::test]
+    async fn collection_stops_when_senders_are_dropped() {
+        let (sender, mut receiver) = mpsc::channel::<u8>(1);
+        drop(sender);
+
+        let mut batch = Vec::new();
+        assert!(!collect_batch(&mut receiver, &mut batch, 10, Duration::from_secs(1)).await);
+    }
+}
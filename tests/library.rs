@@ -0,0 +1,37 @@
+//! Indexer core used as a library, the way other services embed it.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use pumpfun_indexer::db::Db;
+use pumpfun_indexer::metrics::Metrics;
+use pumpfun_indexer::model::{Resolution, TradeInfo};
+use sqlx::PgPool;
+use sqlx::types::chrono::DateTime;
+
+const MINT: &str = "So11111111111111111111111111111111111111112";
+
+#[sqlx::test(migrations = "pg/migrations")]
+async fn trades_are_read_back_as_candles(pool: PgPool) {
+    let db = Db::from_pool(pool, Arc::new(Metrics::default()), Duration::from_secs(10));
+    db.insert_token(MINT.to_string(), None).await.unwrap();
+
+    let info = TradeInfo {
+        mint_acc: MINT.to_string(),
+        timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        sol_amount: 1_000,
+        token_amount: 2_000,
+        is_buy: true,
+        decimals: None,
+    };
+    let buckets = [Resolution::M1, Resolution::H1]
+        .map(|resolution| (resolution, resolution.bucket_start(info.timestamp)));
+    db.insert_trades(&[(buckets.to_vec(), info)]).await.unwrap();
+
+    for (resolution, bucket) in buckets {
+        let (datetime, candle) = db.last_trade(MINT, resolution).await.unwrap();
+        assert_eq!(datetime, bucket);
+        assert_eq!(candle.close, 0.5);
+        assert_eq!(candle.trade_count, 1);
+    }
+}